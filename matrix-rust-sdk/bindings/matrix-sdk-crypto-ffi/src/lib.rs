@@ -502,6 +502,7 @@ fn collect_sessions(
             imported: session.imported,
             backed_up: session.backed_up,
             history_visibility: None,
+            shared_history: false,
             algorithm: RustEventEncryptionAlgorithm::MegolmV1AesSha2,
         };
 
@@ -684,6 +685,7 @@ impl From<EncryptionSettings> for RustEncryptionSettings {
                 only_allow_trusted_devices: v.only_allow_trusted_devices,
                 error_on_verified_user_problem: v.error_on_verified_user_problem,
             },
+            ignored_devices: Default::default(),
         }
     }
 }
@@ -275,6 +275,10 @@ pub struct ClientBuilder {
 impl ClientBuilder {
     #[uniffi::constructor]
     pub fn new() -> Arc<Self> {
+        let mut encryption_settings = EncryptionSettings::default();
+        encryption_settings.backup_download_strategy =
+            BackupDownloadStrategy::AfterDecryptionFailure;
+
         Arc::new(Self {
             session_paths: None,
             username: None,
@@ -290,12 +294,7 @@ impl ClientBuilder {
             session_delegate: None,
             additional_root_certificates: Default::default(),
             disable_built_in_root_certificates: false,
-            encryption_settings: EncryptionSettings {
-                auto_enable_cross_signing: false,
-                backup_download_strategy:
-                    matrix_sdk::encryption::BackupDownloadStrategy::AfterDecryptionFailure,
-                auto_enable_backups: false,
-            },
+            encryption_settings,
             room_key_recipient_strategy: Default::default(),
             decryption_trust_requirement: TrustRequirement::Untrusted,
             request_config: Default::default(),
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{pin::pin, sync::Arc};
 
 use futures_util::StreamExt;
 use matrix_sdk::{
@@ -10,7 +10,10 @@ use tracing::{error, info};
 use zeroize::Zeroize;
 
 use super::RUNTIME;
-use crate::{client::Client, error::ClientError, ruma::AuthData, task_handle::TaskHandle};
+use crate::{
+    client::Client, error::ClientError, room::IdentityStatusChangeListener, ruma::AuthData,
+    task_handle::TaskHandle,
+};
 
 #[derive(uniffi::Object)]
 pub struct Encryption {
@@ -450,6 +453,52 @@ impl Encryption {
         let identity = self.inner.request_user_identity(user_id.as_str().try_into()?).await?;
         Ok(identity.map(|identity| Arc::new(UserIdentity { inner: identity })))
     }
+
+    /// Subscribe to changes in the identity status of the users we share a
+    /// room with, across all our rooms.
+    ///
+    /// The first call of the listener lists the identity changes that haven't
+    /// been acknowledged yet, with [`Encryption::pin_identity`] or
+    /// [`Encryption::withdraw_identity_verification`].
+    pub fn subscribe_to_identity_status_changes(
+        &self,
+        listener: Box<dyn IdentityStatusChangeListener>,
+    ) -> Arc<TaskHandle> {
+        let encryption = self.inner.clone();
+        Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            let status_changes = match encryption.subscribe_to_identity_status_changes().await {
+                Ok(status_changes) => status_changes,
+                Err(error) => {
+                    error!("Failed to subscribe to identity status changes: {error}");
+                    return;
+                }
+            };
+
+            let mut status_changes = pin!(status_changes);
+            while let Some(identity_status_changes) = status_changes.next().await {
+                listener.call(identity_status_changes.into_iter().map(Into::into).collect());
+            }
+        })))
+    }
+
+    /// Acknowledge the identity change of a user by pinning their new
+    /// identity.
+    ///
+    /// Returns `false` if we don't know the identity of the user.
+    pub async fn pin_identity(&self, user_id: String) -> Result<bool, ClientError> {
+        Ok(self.inner.pin_identity(user_id.as_str().try_into()?).await?)
+    }
+
+    /// Acknowledge that a previously verified user changed their identity, by
+    /// withdrawing the requirement for their identity to be verified.
+    ///
+    /// Returns `false` if we don't know the identity of the user.
+    pub async fn withdraw_identity_verification(
+        &self,
+        user_id: String,
+    ) -> Result<bool, ClientError> {
+        Ok(self.inner.withdraw_identity_verification(user_id.as_str().try_into()?).await?)
+    }
 }
 
 /// The E2EE identity of a user.
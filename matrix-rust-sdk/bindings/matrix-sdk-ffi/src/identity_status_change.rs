@@ -22,3 +22,9 @@ pub struct IdentityStatusChange {
     /// The new state of the identity of the user.
     pub changed_to: IdentityState,
 }
+
+impl From<matrix_sdk::crypto::IdentityStatusChange> for IdentityStatusChange {
+    fn from(change: matrix_sdk::crypto::IdentityStatusChange) -> Self {
+        Self { user_id: change.user_id.to_string(), changed_to: change.changed_to }
+    }
+}
@@ -643,15 +643,7 @@ impl Room {
                 // TODO: what to do with failures?
                 let mut status_changes = pin!(status_changes);
                 while let Some(identity_status_changes) = status_changes.next().await {
-                    listener.call(
-                        identity_status_changes
-                            .into_iter()
-                            .map(|change| {
-                                let user_id = change.user_id.to_string();
-                                IdentityStatusChange { user_id, changed_to: change.changed_to }
-                            })
-                            .collect(),
-                    );
+                    listener.call(identity_status_changes.into_iter().map(Into::into).collect());
                 }
             }
         })))
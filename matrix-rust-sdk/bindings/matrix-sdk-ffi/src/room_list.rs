@@ -36,6 +36,8 @@ use crate::{
 pub enum RoomListError {
     #[error("sliding sync error: {error}")]
     SlidingSync { error: String },
+    #[error("sync error: {error}")]
+    Sync { error: String },
     #[error("unknown list `{list_name}`")]
     UnknownList { list_name: String },
     #[error("input cannot be applied")]
@@ -62,6 +64,7 @@ impl From<matrix_sdk_ui::room_list_service::Error> for RoomListError {
 
         match value {
             SlidingSync(error) => Self::SlidingSync { error: error.to_string() },
+            Sync(error) => Self::Sync { error: error.to_string() },
             UnknownList(list_name) => Self::UnknownList { list_name },
             RoomNotFound(room_id) => Self::RoomNotFound { room_name: room_id.to_string() },
             TimelineAlreadyExists(room_id) => {
@@ -14,7 +14,7 @@
 // limitations under the License.
 
 #[cfg(feature = "e2e-encryption")]
-use std::sync::Arc;
+use std::sync::{Arc, RwLock as StdRwLock};
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
     fmt, iter,
@@ -37,6 +37,8 @@ use ruma::events::{
 };
 #[cfg(doc)]
 use ruma::DeviceId;
+#[cfg(feature = "e2e-encryption")]
+use ruma::OwnedDeviceId;
 use ruma::{
     api::client as api,
     events::{
@@ -121,6 +123,12 @@ pub struct BaseClient {
     #[cfg(feature = "e2e-encryption")]
     pub room_key_recipient_strategy: CollectStrategy,
 
+    /// Per-room overrides of [`Self::room_key_recipient_strategy`].
+    ///
+    /// This is only kept in memory.
+    #[cfg(feature = "e2e-encryption")]
+    room_key_recipient_strategy_overrides: Arc<StdRwLock<BTreeMap<OwnedRoomId, CollectStrategy>>>,
+
     /// The trust requirement to use for decrypting events.
     #[cfg(feature = "e2e-encryption")]
     pub decryption_trust_requirement: TrustRequirement,
@@ -159,6 +167,8 @@ impl BaseClient {
             #[cfg(feature = "e2e-encryption")]
             room_key_recipient_strategy: Default::default(),
             #[cfg(feature = "e2e-encryption")]
+            room_key_recipient_strategy_overrides: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
             decryption_trust_requirement: TrustRequirement::Untrusted,
         }
     }
@@ -188,6 +198,9 @@ impl BaseClient {
             ignore_user_list_changes: Default::default(),
             room_info_notable_update_sender: self.room_info_notable_update_sender.clone(),
             room_key_recipient_strategy: self.room_key_recipient_strategy.clone(),
+            room_key_recipient_strategy_overrides: self
+                .room_key_recipient_strategy_overrides
+                .clone(),
             decryption_trust_requirement: self.decryption_trust_requirement,
         };
 
@@ -931,6 +944,19 @@ impl BaseClient {
         self.store.sync_lock()
     }
 
+    /// Restore the snapshot of the state store with the given identifier, and
+    /// reload the rooms and the sync token from it.
+    ///
+    /// See [`StateStore::restore_snapshot`] for more details. Returns false if
+    /// there is no such snapshot, in which case nothing changes.
+    ///
+    /// [`StateStore::restore_snapshot`]: crate::store::StateStore::restore_snapshot
+    pub async fn restore_state_store_snapshot(&self, id: u64) -> Result<bool> {
+        let _sync_lock = self.sync_lock().lock().await;
+
+        Ok(self.store.restore_snapshot(id, &self.room_info_notable_update_sender).await?)
+    }
+
     /// Receive a response from a sync call.
     ///
     /// # Arguments
@@ -1048,6 +1074,18 @@ impl BaseClient {
                 )
                 .await?;
 
+            // Cache the latest decrypted event in room_info, like with sliding sync, so
+            // that room lists can be built on top of `/sync` too.
+            #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
+            crate::sliding_sync::cache_latest_events(
+                &room,
+                &mut room_info,
+                &timeline.events,
+                Some(&changes),
+                Some(&self.store),
+            )
+            .await;
+
             // Save the new `RoomInfo`.
             changes.add_room(room_info);
 
@@ -1226,15 +1264,9 @@ impl BaseClient {
 
         account_data_processor.apply(&mut changes, &self.store).await;
 
-        changes.presence = response
-            .presence
-            .events
-            .iter()
-            .filter_map(|e| {
-                let event = e.deserialize().ok()?;
-                Some((event.sender, e.clone()))
-            })
-            .collect();
+        for raw_event in &response.presence.events {
+            changes.add_received_presence_event(raw_event.clone());
+        }
 
         changes.ambiguity_maps = ambiguity_cache.cache;
 
@@ -1457,6 +1489,22 @@ impl BaseClient {
     /// Get a to-device request that will share a room key with users in a room.
     #[cfg(feature = "e2e-encryption")]
     pub async fn share_room_key(&self, room_id: &RoomId) -> Result<Vec<Arc<ToDeviceRequest>>> {
+        self.share_room_key_ignoring_devices(room_id, BTreeMap::new()).await
+    }
+
+    /// Get a to-device request that will share a room key with users in a
+    /// room, treating the given devices as if their local trust was
+    /// [`LocalTrust::Ignored`], without changing it.
+    ///
+    /// See [`EncryptionSettings::ignored_devices`].
+    ///
+    /// [`LocalTrust::Ignored`]: matrix_sdk_crypto::LocalTrust::Ignored
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn share_room_key_ignoring_devices(
+        &self,
+        room_id: &RoomId,
+        ignored_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
+    ) -> Result<Vec<Arc<ToDeviceRequest>>> {
         match self.olm_machine().await.as_ref() {
             Some(o) => {
                 let Some(room) = self.get_room(room_id) else {
@@ -1478,11 +1526,14 @@ impl BaseClient {
 
                 let members = self.store.get_user_ids(room_id, filter).await?;
 
-                let settings = EncryptionSettings::new(
-                    room_encryption_event,
-                    history_visibility,
-                    self.room_key_recipient_strategy.clone(),
-                );
+                let settings = EncryptionSettings {
+                    ignored_devices,
+                    ..EncryptionSettings::new(
+                        room_encryption_event,
+                        history_visibility,
+                        self.room_key_recipient_strategy_for_room(room_id),
+                    )
+                };
 
                 Ok(o.share_room_key(room_id, members.iter().map(Deref::deref), settings).await?)
             }
@@ -1490,6 +1541,46 @@ impl BaseClient {
         }
     }
 
+    /// Get the strategy used to pick the recipient devices of the room keys,
+    /// when sending an encrypted message in the given room.
+    ///
+    /// This is the room's override, if one has been set with
+    /// [`Self::set_room_key_recipient_strategy_for_room`], or the client-wide
+    /// [`Self::room_key_recipient_strategy`] otherwise.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn room_key_recipient_strategy_for_room(&self, room_id: &RoomId) -> CollectStrategy {
+        self.room_key_recipient_strategy_overrides
+            .read()
+            .unwrap()
+            .get(room_id)
+            .cloned()
+            .unwrap_or_else(|| self.room_key_recipient_strategy.clone())
+    }
+
+    /// Override the strategy used to pick the recipient devices of the room
+    /// keys for the given room.
+    ///
+    /// Passing `None` removes the override, so the client-wide
+    /// [`Self::room_key_recipient_strategy`] is used again. Overrides are only
+    /// kept in memory, and must be set again after a restart.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn set_room_key_recipient_strategy_for_room(
+        &self,
+        room_id: &RoomId,
+        strategy: Option<CollectStrategy>,
+    ) {
+        let mut overrides = self.room_key_recipient_strategy_overrides.write().unwrap();
+
+        match strategy {
+            Some(strategy) => {
+                overrides.insert(room_id.to_owned(), strategy);
+            }
+            None => {
+                overrides.remove(room_id);
+            }
+        }
+    }
+
     /// Get the room with the given room id.
     ///
     /// # Arguments
@@ -1908,6 +1999,81 @@ mod tests {
             .contains(RoomInfoNotableUpdateReasons::LATEST_EVENT));
     }
 
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_room_key_recipient_strategy_override() {
+        use matrix_sdk_crypto::CollectStrategy;
+
+        let room_id = room_id!("!a:b.c");
+        let other_room_id = room_id!("!d:e.f");
+
+        let client = BaseClient::with_store_config(StoreConfig::new(
+            "cross-process-store-locks-holder-name".to_owned(),
+        ));
+
+        // By default, all the rooms use the client-wide strategy.
+        assert_eq!(
+            client.room_key_recipient_strategy_for_room(room_id),
+            CollectStrategy::default()
+        );
+
+        // When I override the strategy for a room, only that room uses it.
+        client.set_room_key_recipient_strategy_for_room(
+            room_id,
+            Some(CollectStrategy::new_identity_based()),
+        );
+        assert_eq!(
+            client.room_key_recipient_strategy_for_room(room_id),
+            CollectStrategy::new_identity_based()
+        );
+        assert_eq!(
+            client.room_key_recipient_strategy_for_room(other_room_id),
+            CollectStrategy::default()
+        );
+
+        // And when I remove the override, the room uses the client-wide strategy again.
+        client.set_room_key_recipient_strategy_for_room(room_id, None);
+        assert_eq!(
+            client.room_key_recipient_strategy_for_room(room_id),
+            CollectStrategy::default()
+        );
+    }
+
+    #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
+    #[async_test]
+    async fn test_latest_event_is_cached_from_sync_response() {
+        use ruma::owned_event_id;
+
+        let user_id = user_id!("@u:u.to");
+        let room_id = room_id!("!r:u.to");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        // Given a room without a latest event,
+        let room = process_room_join_test_helper(&client, room_id, "$1", user_id).await;
+        assert!(room.latest_event().is_none());
+
+        // When a message is received with `/sync`,
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_timeline_event(
+                sync_timeline_event!({
+                    "content": {
+                        "body": "Hello",
+                        "msgtype": "m.text",
+                    },
+                    "event_id": "$2",
+                    "origin_server_ts": 1432135524679u64,
+                    "sender": user_id,
+                    "type": "m.room.message",
+                }),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // Then it's the latest event of the room.
+        assert_eq!(room.latest_event().unwrap().event_id(), Some(owned_event_id!("$2")));
+    }
+
     // TODO: I wanted to write more tests here for decrypt_latest_events but I got
     // lost trying to set up my OlmMachine to be able to encrypt and decrypt
     // events. In the meantime, there are tests for the most difficult logic
@@ -48,6 +48,7 @@ pub fn make_test_event(room_id: &RoomId, content: &str) -> SyncTimelineEvent {
             sender_claimed_keys: Default::default(),
         },
         verification_state: VerificationState::Verified,
+        session_id: Some("mysessionid".to_owned()),
     };
 
     let event = EventFactory::new()
@@ -85,6 +86,7 @@ pub fn check_test_event(event: &SyncTimelineEvent, text: &str) {
         assert_matches!(&d.encryption_info.algorithm_info, AlgorithmInfo::MegolmV1AesSha2 { curve25519_key, .. } => {
             assert_eq!(curve25519_key, "1337");
         });
+        assert_eq!(d.encryption_info.session_id.as_deref(), Some("mysessionid"));
 
         // Check event.
         let deserialized = d.event.deserialize().unwrap();
@@ -115,6 +115,26 @@
 //!   timeline, leading to incorrect results. We have to take that into account
 //!   by resetting the read counts *every* time we see an event that was the
 //!   target of the latest active read receipt.
+//!
+//! ## Threads
+//!
+//! The counts above consider the room as a whole. In addition to these, we
+//! keep a breakdown of the counts per thread, in [`ThreadReadReceipts`].
+//!
+//! For each thread, the latest active receipt is the latest (in sync order)
+//! among the threaded receipts for this thread, the unthreaded receipts, and
+//! the implicit receipts of events we sent in this thread. Since positions
+//! are those of the whole sync ordering, an unthreaded receipt applies to all
+//! the threads at once.
+//!
+//! When the latest active receipt of a thread is known, the counts are
+//! computed from all the events in the thread following it; otherwise, the
+//! new events in the thread are accumulated, until we see a receipt. Threads
+//! without anything unread are forgotten to keep the [`RoomInfo`] small,
+//! unless their latest active receipt is known, since it's needed to count
+//! their next events.
+//!
+//! [`RoomInfo`]: crate::RoomInfo
 #![allow(dead_code)] // too many different build configurations, I give up
 
 use std::{
@@ -176,6 +196,11 @@ pub struct RoomReadReceipts {
     /// not the event ids of the receipt events themselves.
     #[serde(default = "new_nonempty_ring_buffer")]
     pending: RingBuffer<OwnedEventId>,
+
+    /// Read receipts data for the threads that have unread events or a known
+    /// latest active receipt, keyed by the event id of their thread root.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    threads: BTreeMap<OwnedEventId, ThreadReadReceipts>,
 }
 
 impl Default for RoomReadReceipts {
@@ -186,40 +211,102 @@ impl Default for RoomReadReceipts {
             num_mentions: Default::default(),
             latest_active: Default::default(),
             pending: new_nonempty_ring_buffer(),
+            threads: Default::default(),
         }
     }
 }
 
+/// Read receipts data collected for a single thread of a room.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ThreadReadReceipts {
+    /// Does the thread have unread messages?
+    pub num_unread: u64,
+
+    /// Does the thread have unread events that should notify?
+    pub num_notifications: u64,
+
+    /// Does the thread have messages causing highlights for the users? (aka
+    /// mentions)
+    pub num_mentions: u64,
+
+    /// The latest read receipt (threaded for this thread, or unthreaded)
+    /// known for the thread.
+    #[serde(default)]
+    latest_active: Option<LatestReadReceipt>,
+}
+
+impl ThreadReadReceipts {
+    /// The id of the event the latest known read receipt for this thread
+    /// refers to, if any.
+    pub fn latest_receipt_event_id(&self) -> Option<&EventId> {
+        self.latest_active.as_ref().map(|receipt| &*receipt.event_id)
+    }
+
+    /// Update the [`ThreadReadReceipts`] unread counts according to the new
+    /// event.
+    fn process_event(&mut self, event: &SyncTimelineEvent, user_id: &UserId) {
+        let (marks_as_unread, notifies, mentions) = count_event(event, user_id);
+        self.num_unread += u64::from(marks_as_unread);
+        self.num_notifications += u64::from(notifies);
+        self.num_mentions += u64::from(mentions);
+    }
+
+    fn reset(&mut self) {
+        self.num_unread = 0;
+        self.num_notifications = 0;
+        self.num_mentions = 0;
+    }
+
+    /// Does this thread have anything unread?
+    fn has_unread(&self) -> bool {
+        self.num_unread > 0 || self.num_notifications > 0 || self.num_mentions > 0
+    }
+
+    /// Is there anything worth remembering about this thread?
+    fn is_empty(&self) -> bool {
+        !self.has_unread() && self.latest_active.is_none()
+    }
+}
+
 fn new_nonempty_ring_buffer() -> RingBuffer<OwnedEventId> {
     // 10 pending read receipts per room should be enough for everyone.
     // SAFETY: `unwrap` is safe because 10 is not zero.
     RingBuffer::new(NonZeroUsize::new(10).unwrap())
 }
 
+/// Returns whether the event marks the room as unread, whether it should
+/// notify, and whether it mentions the user.
+fn count_event(event: &SyncTimelineEvent, user_id: &UserId) -> (bool, bool, bool) {
+    let marks_as_unread = marks_as_unread(event.raw(), user_id);
+    let notifies = event.push_actions.iter().any(|action| action.should_notify());
+    let mentions = event.push_actions.iter().any(|action| action.is_highlight());
+    (marks_as_unread, notifies, mentions)
+}
+
 impl RoomReadReceipts {
+    /// Read receipts data for the threads of this room that have unread
+    /// events or a known latest read receipt, keyed by the event id of their
+    /// thread root.
+    pub fn threads(&self) -> &BTreeMap<OwnedEventId, ThreadReadReceipts> {
+        &self.threads
+    }
+
+    /// Read receipts data for the given thread, if it has unread events or a
+    /// known latest read receipt.
+    pub fn thread(&self, thread_root: &EventId) -> Option<&ThreadReadReceipts> {
+        self.threads.get(thread_root)
+    }
+
     /// Update the [`RoomReadReceipts`] unread counts according to the new
     /// event.
     ///
     /// Returns whether a new event triggered a new unread/notification/mention.
     #[inline(always)]
     fn process_event(&mut self, event: &SyncTimelineEvent, user_id: &UserId) {
-        if marks_as_unread(event.raw(), user_id) {
-            self.num_unread += 1;
-        }
-
-        let mut has_notify = false;
-        let mut has_mention = false;
-
-        for action in &event.push_actions {
-            if !has_notify && action.should_notify() {
-                self.num_notifications += 1;
-                has_notify = true;
-            }
-            if !has_mention && action.is_highlight() {
-                self.num_mentions += 1;
-                has_mention = true;
-            }
-        }
+        let (marks_as_unread, notifies, mentions) = count_event(event, user_id);
+        self.num_unread += u64::from(marks_as_unread);
+        self.num_notifications += u64::from(notifies);
+        self.num_mentions += u64::from(mentions);
     }
 
     #[inline(always)]
@@ -473,6 +560,14 @@ pub(crate) fn compute_unread_counts(
         all_events
     };
 
+    compute_thread_unread_counts(
+        user_id,
+        receipt_event,
+        &all_events,
+        new_events,
+        &mut read_receipts.threads,
+    );
+
     let new_receipt = {
         let mut selector = ReceiptSelector::new(
             &all_events,
@@ -523,6 +618,153 @@ pub(crate) fn compute_unread_counts(
     debug!(?read_receipts, "no better receipt, {} new events", new_events.len());
 }
 
+/// Returns the root of the thread the event belongs to, if any.
+fn thread_root(event: &Raw<AnySyncTimelineEvent>) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct RelatesTo {
+        rel_type: Option<String>,
+        event_id: Option<OwnedEventId>,
+    }
+
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<RelatesTo>,
+    }
+
+    let relates_to = event.get_field::<Content>("content").ok().flatten()?.relates_to?;
+    if relates_to.rel_type.as_deref() == Some("m.thread") {
+        relates_to.event_id
+    } else {
+        None
+    }
+}
+
+/// Given a set of events coming from sync, for a room, update the
+/// [`ThreadReadReceipts`]' counts of unread messages, notifications and
+/// highlights, for all the threads touched by these events or the new
+/// receipts.
+///
+/// See this module's documentation for more information.
+fn compute_thread_unread_counts(
+    user_id: &UserId,
+    receipt_event: Option<&ReceiptEventContent>,
+    all_events: &Vector<SyncTimelineEvent>,
+    new_events: &[SyncTimelineEvent],
+    threads: &mut BTreeMap<OwnedEventId, ThreadReadReceipts>,
+) {
+    let event_id_to_pos = ReceiptSelector::create_sync_index(all_events.iter());
+
+    // Group the new events by thread.
+    let mut new_events_by_thread = BTreeMap::<OwnedEventId, Vec<&SyncTimelineEvent>>::new();
+    for event in new_events {
+        if let Some(root) = thread_root(event.raw()) {
+            new_events_by_thread.entry(root).or_default().push(event);
+        }
+    }
+
+    // Collect the candidate receipts for each thread: explicit threaded receipts,
+    // unthreaded receipts which apply to all the threads, and implicit receipts
+    // for the events we sent.
+    let mut candidates = BTreeMap::<OwnedEventId, Vec<OwnedEventId>>::new();
+    let mut unthreaded = Vec::new();
+
+    if let Some(receipt_event) = receipt_event {
+        for (event_id, receipts) in &receipt_event.0 {
+            for ty in [ReceiptType::Read, ReceiptType::ReadPrivate] {
+                let Some(receipt) = receipts.get(&ty).and_then(|receipts| receipts.get(user_id))
+                else {
+                    continue;
+                };
+
+                match &receipt.thread {
+                    ReceiptThread::Thread(root) => {
+                        candidates.entry(root.clone()).or_default().push(event_id.clone());
+                    }
+                    ReceiptThread::Unthreaded => unthreaded.push(event_id.clone()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if !unthreaded.is_empty() {
+        for root in threads.keys().chain(new_events_by_thread.keys()) {
+            candidates.entry(root.clone()).or_default().extend(unthreaded.iter().cloned());
+        }
+    }
+
+    for (root, events) in &new_events_by_thread {
+        for event in events {
+            let Ok(Some(sender)) = event.raw().get_field::<OwnedUserId>("sender") else {
+                continue;
+            };
+            if sender == user_id {
+                if let Some(event_id) = event.event_id() {
+                    candidates.entry(root.clone()).or_default().push(event_id);
+                }
+            }
+        }
+    }
+
+    // Select the latest active receipt for each thread.
+    for (root, event_ids) in candidates {
+        let thread = threads.entry(root).or_default();
+
+        let mut latest_pos = thread
+            .latest_active
+            .as_ref()
+            .and_then(|receipt| event_id_to_pos.get(&receipt.event_id))
+            .copied();
+
+        for event_id in event_ids {
+            match (event_id_to_pos.get(&event_id).copied(), latest_pos) {
+                (Some(pos), Some(prev_pos)) if pos < prev_pos => {
+                    trace!(%event_id, "thread receipt not better, keeping previous");
+                }
+                (Some(pos), _) => {
+                    latest_pos = Some(pos);
+                    thread.latest_active = Some(LatestReadReceipt { event_id });
+                }
+                (None, None) => {
+                    // We can't order the receipt against the previous one, so trust the most
+                    // recent one we received.
+                    thread.latest_active = Some(LatestReadReceipt { event_id });
+                }
+                (None, Some(_)) => {
+                    trace!(%event_id, "thread receipt for an unknown event, keeping previous");
+                }
+            }
+        }
+    }
+
+    // Update the counts of the threads.
+    for (root, thread) in threads.iter_mut() {
+        let receipt_pos = thread
+            .latest_active
+            .as_ref()
+            .and_then(|receipt| event_id_to_pos.get(&receipt.event_id))
+            .copied();
+
+        if let Some(receipt_pos) = receipt_pos {
+            // Count all the events in the thread after the receipt.
+            thread.reset();
+            for event in all_events.iter().skip(receipt_pos + 1) {
+                if thread_root(event.raw()).as_ref() == Some(root) {
+                    thread.process_event(event, user_id);
+                }
+            }
+        } else if let Some(events) = new_events_by_thread.get(root) {
+            // Accumulate the new events in the thread, and wait for the next receipt.
+            for event in events {
+                thread.process_event(event, user_id);
+            }
+        }
+    }
+
+    threads.retain(|_, thread| !thread.is_empty());
+}
+
 /// Is the event worth marking a room as unread?
 fn marks_as_unread(event: &Raw<AnySyncTimelineEvent>, user_id: &UserId) -> bool {
     let event = match event.deserialize() {
@@ -630,7 +872,7 @@ mod tests {
         room_id, user_id, EventId, UserId,
     };
 
-    use super::compute_unread_counts;
+    use super::{compute_unread_counts, thread_root};
     use crate::read_receipts::{marks_as_unread, ReceiptSelector, RoomReadReceipts};
 
     #[test]
@@ -1619,4 +1861,159 @@ mod tests {
         // And the active receipt is the implicit one on my event.
         assert_eq!(read_receipts.latest_active.unwrap().event_id, event_id!("$6"));
     }
+
+    fn sync_thread_message(
+        sender: &UserId,
+        event_id: impl serde::Serialize,
+        thread_root: &EventId,
+    ) -> SyncTimelineEvent {
+        SyncTimelineEvent::new(sync_timeline_event!({
+            "sender": sender,
+            "type": "m.room.message",
+            "event_id": event_id,
+            "origin_server_ts": 42,
+            "content": {
+                "body": "In the thread",
+                "msgtype": "m.text",
+                "m.relates_to": { "rel_type": "m.thread", "event_id": thread_root },
+            },
+        }))
+    }
+
+    #[test]
+    fn test_thread_root() {
+        let bob = user_id!("@bob:example.org");
+        let root = event_id!("$root");
+
+        assert_eq!(thread_root(sync_thread_message(bob, "$1", root).raw()).as_deref(), Some(root));
+        assert!(thread_root(sync_timeline_message(bob, "$2", "A").raw()).is_none());
+    }
+
+    #[test]
+    fn test_compute_unread_counts_in_threads() {
+        let user_id = user_id!("@alice:example.org");
+        let bob = user_id!("@bob:example.org");
+        let room_id = room_id!("!room:example.org");
+        let root_a = event_id!("$root_a");
+        let root_b = event_id!("$root_b");
+
+        let events = vec![
+            sync_timeline_message(bob, root_a, "A"),
+            sync_timeline_message(bob, root_b, "B"),
+            sync_thread_message(bob, "$a1", root_a),
+            sync_thread_message(bob, "$a2", root_a),
+            sync_thread_message(bob, "$b1", root_b),
+        ];
+
+        // Without any receipt, the new events in the threads are counted.
+        let mut read_receipts = RoomReadReceipts::default();
+        compute_unread_counts(user_id, room_id, None, Vector::new(), &events, &mut read_receipts);
+
+        assert_eq!(read_receipts.num_unread, 5);
+        assert_eq!(read_receipts.threads().len(), 2);
+        assert_eq!(read_receipts.thread(root_a).unwrap().num_unread, 2);
+        assert_eq!(read_receipts.thread(root_b).unwrap().num_unread, 1);
+
+        // A threaded receipt only affects its thread.
+        let receipt_event = EventBuilder::new().make_receipt_event_content([(
+            owned_event_id!("$a1"),
+            ReceiptType::Read,
+            user_id.to_owned(),
+            ReceiptThread::Thread(root_a.to_owned()),
+        )]);
+
+        let previous_events: Vector<_> = events.into_iter().collect();
+        compute_unread_counts(
+            user_id,
+            room_id,
+            Some(&receipt_event),
+            previous_events.clone(),
+            &[],
+            &mut read_receipts,
+        );
+
+        let thread_a = read_receipts.thread(root_a).unwrap();
+        assert_eq!(thread_a.num_unread, 1);
+        assert_eq!(thread_a.latest_receipt_event_id(), Some(event_id!("$a1")));
+        assert_eq!(read_receipts.thread(root_b).unwrap().num_unread, 1);
+
+        // An unthreaded receipt applies to all the threads, which don't have anything
+        // unread anymore, but keep their receipt.
+        let receipt_event = EventBuilder::new().make_receipt_event_content([(
+            owned_event_id!("$b1"),
+            ReceiptType::Read,
+            user_id.to_owned(),
+            ReceiptThread::Unthreaded,
+        )]);
+
+        compute_unread_counts(
+            user_id,
+            room_id,
+            Some(&receipt_event),
+            previous_events.clone(),
+            &[],
+            &mut read_receipts,
+        );
+
+        assert_eq!(read_receipts.num_unread, 0);
+        assert_eq!(read_receipts.threads().len(), 2);
+        for root in [root_a, root_b] {
+            let thread = read_receipts.thread(root).unwrap();
+            assert_eq!(thread.num_unread, 0);
+            assert_eq!(thread.latest_receipt_event_id(), Some(event_id!("$b1")));
+        }
+
+        // The next events in the threads are counted from the kept receipt.
+        let new_events = [sync_thread_message(bob, "$a3", root_a)];
+        compute_unread_counts(
+            user_id,
+            room_id,
+            None,
+            previous_events,
+            &new_events,
+            &mut read_receipts,
+        );
+
+        assert_eq!(read_receipts.thread(root_a).unwrap().num_unread, 1);
+        assert_eq!(read_receipts.thread(root_b).unwrap().num_unread, 0);
+    }
+
+    #[test]
+    fn test_thread_without_unread_or_receipt_is_forgotten() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!room:example.org");
+        let root = event_id!("$root");
+
+        // A thread with nothing unread and no known receipt.
+        let mut read_receipts = RoomReadReceipts::default();
+        read_receipts.threads.insert(root.to_owned(), ThreadReadReceipts::default());
+
+        compute_unread_counts(user_id, room_id, None, Vector::new(), &[], &mut read_receipts);
+
+        assert!(read_receipts.threads().is_empty());
+    }
+
+    #[test]
+    fn test_compute_unread_counts_in_thread_with_implicit_receipt() {
+        let user_id = user_id!("@alice:example.org");
+        let bob = user_id!("@bob:example.org");
+        let room_id = room_id!("!room:example.org");
+        let root = event_id!("$root");
+
+        // Bob posted in the thread, then I replied, then Bob replied again.
+        let events = vec![
+            sync_timeline_message(bob, root, "A"),
+            sync_thread_message(bob, "$1", root),
+            sync_thread_message(user_id, "$2", root),
+            sync_thread_message(bob, "$3", root),
+        ];
+
+        let mut read_receipts = RoomReadReceipts::default();
+        compute_unread_counts(user_id, room_id, None, Vector::new(), &events, &mut read_receipts);
+
+        // Only Bob's last message in the thread is unread.
+        let thread = read_receipts.thread(root).unwrap();
+        assert_eq!(thread.num_unread, 1);
+        assert_eq!(thread.latest_receipt_event_id(), Some(event_id!("$2")));
+    }
 }
@@ -69,7 +69,7 @@ use crate::latest_event::LatestEvent;
 use crate::{
     deserialized_responses::{DisplayName, MemberEvent, RawSyncOrStrippedState},
     notification_settings::RoomNotificationMode,
-    read_receipts::RoomReadReceipts,
+    read_receipts::{RoomReadReceipts, ThreadReadReceipts},
     store::{DynStateStore, Result as StoreResult, StateStoreExt},
     sync::UnreadNotificationsCount,
     Error, MinimalStateEvent, OriginalMinimalStateEvent, RoomMemberships,
@@ -329,6 +329,15 @@ impl Room {
         self.inner.read().read_receipts.num_mentions
     }
 
+    /// Get the detailed information about read receipts for the given thread
+    /// of the room (computed client-side).
+    ///
+    /// Returns `None` if the thread doesn't have any unread event nor known
+    /// read receipt.
+    pub fn thread_read_receipts(&self, thread_root: &EventId) -> Option<ThreadReadReceipts> {
+        self.inner.read().read_receipts.thread(thread_root).cloned()
+    }
+
     /// Check if the room has its members fully synced.
     ///
     /// Members might be missing if lazy member loading was enabled for the
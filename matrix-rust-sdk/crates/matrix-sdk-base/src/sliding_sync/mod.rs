@@ -688,7 +688,7 @@ impl BaseClient {
 /// It is the responsibility of the caller to update the `RoomInfo` instance
 /// stored in the `Room`.
 #[cfg(feature = "e2e-encryption")]
-async fn cache_latest_events(
+pub(crate) async fn cache_latest_events(
     room: &Room,
     room_info: &mut RoomInfo,
     events: &[SyncTimelineEvent],
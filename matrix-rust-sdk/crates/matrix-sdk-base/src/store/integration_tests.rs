@@ -94,6 +94,8 @@ pub trait StateStoreIntegrationTests {
     async fn test_update_send_queue_dependent(&self);
     /// Test saving/restoring server capabilities.
     async fn test_server_capabilities_saving(&self);
+    /// Test saving, restoring and removing snapshots.
+    async fn test_snapshots(&self) -> Result<()>;
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -1052,6 +1054,75 @@ impl StateStoreIntegrationTests for DynStateStore {
         Ok(())
     }
 
+    async fn test_snapshots(&self) -> Result<()> {
+        let room_id = room_id();
+        let user_id = user_id();
+        let custom_key = b"snapshot_test_key";
+
+        assert!(!self.restore_snapshot(1).await?, "restored a missing snapshot");
+
+        self.populate().await?;
+
+        // One request is waiting to be sent when the snapshot is saved, and is
+        // sent after it. Another one is queued after the snapshot.
+        let send_queue_room_id = room_id!("!snapshot_send_queue:localhost");
+        let sent_txn = TransactionId::new();
+        let queued_txn = TransactionId::new();
+        let content =
+            SerializableEventContent::new(&RoomMessageEventContent::text_plain("sup").into())
+                .unwrap();
+        self.save_send_queue_request(
+            send_queue_room_id,
+            sent_txn.clone(),
+            content.clone().into(),
+            0,
+        )
+        .await?;
+
+        self.save_snapshot(1).await?;
+
+        // Change the store after the snapshot.
+        assert!(self.remove_send_queue_request(send_queue_room_id, &sent_txn).await?);
+        self.save_send_queue_request(send_queue_room_id, queued_txn.clone(), content.into(), 0)
+            .await?;
+        self.remove_room(room_id).await?;
+        self.set_custom_value(custom_key, b"value".to_vec()).await?;
+        self.set_kv_data(
+            StateStoreDataKey::SyncToken,
+            StateStoreDataValue::SyncToken("s_after_snapshot".to_owned()),
+        )
+        .await?;
+        assert_eq!(self.get_room_infos().await?.len(), 1);
+
+        // Restoring the snapshot brings back the data of the store at that time.
+        assert!(self.restore_snapshot(1).await?);
+        assert_eq!(self.get_room_infos().await?.len(), 2);
+        assert!(self.get_member_event(room_id, user_id).await?.is_some());
+        assert!(self.get_custom_value(custom_key).await?.is_none());
+        assert_let!(
+            Some(StateStoreDataValue::SyncToken(sync_token)) =
+                self.get_kv_data(StateStoreDataKey::SyncToken).await?
+        );
+        assert_eq!(sync_token, "t392-516_47314_0_7_1_1_1_11444_1");
+
+        // But the send queue isn't restored: the sent request isn't sent again,
+        // and the queued one isn't dropped.
+        let requests = self.load_send_queue_requests(send_queue_room_id).await?;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].transaction_id, queued_txn);
+
+        // The snapshot can be restored again, until it's removed.
+        self.remove_room(room_id).await?;
+        assert!(self.restore_snapshot(1).await?);
+        assert_eq!(self.get_room_infos().await?.len(), 2);
+
+        self.remove_snapshot(1).await?;
+        assert!(!self.restore_snapshot(1).await?, "restored a removed snapshot");
+        assert_eq!(self.get_room_infos().await?.len(), 2);
+
+        Ok(())
+    }
+
     async fn test_profile_removal(&self) -> Result<()> {
         let room_id = room_id();
 
@@ -1762,6 +1833,12 @@ macro_rules! statestore_integration_tests {
                 let store = get_store().await.expect("creating store failed").into_state_store();
                 store.test_update_send_queue_dependent().await;
             }
+
+            #[async_test]
+            async fn test_snapshots() -> StoreResult<()> {
+                let store = get_store().await?.into_state_store();
+                store.test_snapshots().await
+            }
         }
     };
 }
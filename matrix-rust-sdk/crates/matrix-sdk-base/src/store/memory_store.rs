@@ -91,6 +91,8 @@ pub struct MemoryStore {
     custom: StdRwLock<HashMap<Vec<u8>, Vec<u8>>>,
     send_queue_events: StdRwLock<BTreeMap<OwnedRoomId, Vec<QueuedRequest>>>,
     dependent_send_queue_events: StdRwLock<BTreeMap<OwnedRoomId, Vec<DependentQueuedRequest>>>,
+    /// The snapshots of the data above, see [`StateStore::save_snapshot`].
+    snapshots: StdRwLock<HashMap<u64, MemoryStore>>,
 }
 
 impl MemoryStore {
@@ -99,6 +101,37 @@ impl MemoryStore {
         Self::default()
     }
 
+    /// Replace all the data of this store, except the snapshots and the send
+    /// queues, with a copy of the data of `other`.
+    fn copy_data_from(&self, other: &MemoryStore) {
+        *self.recently_visited_rooms.write().unwrap() =
+            other.recently_visited_rooms.read().unwrap().clone();
+        *self.composer_drafts.write().unwrap() = other.composer_drafts.read().unwrap().clone();
+        *self.user_avatar_url.write().unwrap() = other.user_avatar_url.read().unwrap().clone();
+        *self.sync_token.write().unwrap() = other.sync_token.read().unwrap().clone();
+        *self.server_capabilities.write().unwrap() =
+            other.server_capabilities.read().unwrap().clone();
+        *self.filters.write().unwrap() = other.filters.read().unwrap().clone();
+        *self.utd_hook_manager_data.write().unwrap() =
+            other.utd_hook_manager_data.read().unwrap().clone();
+        *self.account_data.write().unwrap() = other.account_data.read().unwrap().clone();
+        *self.profiles.write().unwrap() = other.profiles.read().unwrap().clone();
+        *self.display_names.write().unwrap() = other.display_names.read().unwrap().clone();
+        *self.members.write().unwrap() = other.members.read().unwrap().clone();
+        *self.room_info.write().unwrap() = other.room_info.read().unwrap().clone();
+        *self.room_state.write().unwrap() = other.room_state.read().unwrap().clone();
+        *self.room_account_data.write().unwrap() = other.room_account_data.read().unwrap().clone();
+        *self.stripped_room_state.write().unwrap() =
+            other.stripped_room_state.read().unwrap().clone();
+        *self.stripped_members.write().unwrap() = other.stripped_members.read().unwrap().clone();
+        *self.presence.write().unwrap() = other.presence.read().unwrap().clone();
+        *self.room_user_receipts.write().unwrap() =
+            other.room_user_receipts.read().unwrap().clone();
+        *self.room_event_receipts.write().unwrap() =
+            other.room_event_receipts.read().unwrap().clone();
+        *self.custom.write().unwrap() = other.custom.read().unwrap().clone();
+    }
+
     fn get_user_room_receipt_event_impl(
         &self,
         room_id: &RoomId,
@@ -802,6 +835,31 @@ impl StateStore for MemoryStore {
         Ok(())
     }
 
+    async fn save_snapshot(&self, id: u64) -> Result<()> {
+        let snapshot = MemoryStore::new();
+        snapshot.copy_data_from(self);
+        self.snapshots.write().unwrap().insert(id, snapshot);
+
+        Ok(())
+    }
+
+    async fn restore_snapshot(&self, id: u64) -> Result<bool> {
+        let snapshots = self.snapshots.read().unwrap();
+
+        let Some(snapshot) = snapshots.get(&id) else {
+            return Ok(false);
+        };
+        self.copy_data_from(snapshot);
+
+        Ok(true)
+    }
+
+    async fn remove_snapshot(&self, id: u64) -> Result<()> {
+        self.snapshots.write().unwrap().remove(&id);
+
+        Ok(())
+    }
+
     async fn save_send_queue_request(
         &self,
         room_id: &RoomId,
@@ -51,8 +51,8 @@ use ruma::{
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
         AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
-    serde::Raw,
-    EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    serde::{JsonObject, Raw},
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
 use tokio::sync::{broadcast, Mutex, RwLock};
 use tracing::warn;
@@ -60,7 +60,10 @@ use tracing::warn;
 use crate::{
     deserialized_responses::DisplayName,
     event_cache::store as event_cache_store,
-    rooms::{normal::RoomInfoNotableUpdate, RoomInfo, RoomState},
+    rooms::{
+        normal::{RoomInfoNotableUpdate, RoomInfoNotableUpdateReasons},
+        RoomInfo, RoomState,
+    },
     MinimalRoomMemberEvent, Room, RoomStateFilter, SessionMeta,
 };
 
@@ -243,6 +246,59 @@ impl Store {
         Ok(())
     }
 
+    /// Restore the snapshot of the inner `StateStore` with the given
+    /// identifier, then reload the rooms and the sync token from it.
+    ///
+    /// Returns false if there is no such snapshot.
+    pub(crate) async fn restore_snapshot(
+        &self,
+        id: u64,
+        room_info_notable_update_sender: &broadcast::Sender<RoomInfoNotableUpdate>,
+    ) -> Result<bool> {
+        if !self.inner.restore_snapshot(id).await? {
+            return Ok(false);
+        }
+
+        let room_infos = self.load_room_infos().await?;
+
+        if let Some(session_meta) = self.session_meta() {
+            let mut rooms = self.rooms.write().unwrap();
+
+            // Forget about the rooms that weren't known at the time of the snapshot.
+            let restored_room_ids: BTreeSet<_> =
+                room_infos.iter().map(|room_info| room_info.room_id().to_owned()).collect();
+            let removed_room_ids: Vec<_> = rooms
+                .iter()
+                .map(|room| room.room_id().to_owned())
+                .filter(|room_id| !restored_room_ids.contains(room_id))
+                .collect();
+
+            for room_id in removed_room_ids {
+                rooms.remove(&room_id);
+            }
+
+            for room_info in room_infos {
+                if let Some(room) = rooms.get(room_info.room_id()) {
+                    room.set_room_info(room_info, RoomInfoNotableUpdateReasons::all());
+                } else {
+                    let room = Room::restore(
+                        &session_meta.user_id,
+                        self.inner.clone(),
+                        room_info,
+                        room_info_notable_update_sender.clone(),
+                    );
+                    rooms.insert(room.room_id().to_owned(), room);
+                }
+            }
+        }
+
+        let token =
+            self.get_kv_data(StateStoreDataKey::SyncToken).await?.and_then(|s| s.into_sync_token());
+        *self.sync_token.write().await = token;
+
+        Ok(true)
+    }
+
     /// The current [`SessionMeta`] containing our user ID and device ID.
     pub fn session_meta(&self) -> Option<&SessionMeta> {
         self.session_meta.get()
@@ -339,6 +395,12 @@ impl Deref for Store {
     }
 }
 
+/// The field of the presence events saved in the state store containing the
+/// time at which they were received, if it's known.
+///
+/// See [`StateChanges::add_received_presence_event`].
+pub const PRESENCE_RECEIVED_AT_FIELD: &str = "org.matrix.sdk.received_at";
+
 /// Store state changes and pass them to the StateStore.
 #[derive(Clone, Debug, Default)]
 pub struct StateChanges {
@@ -399,6 +461,31 @@ impl StateChanges {
         self.presence.insert(event.sender, raw_event);
     }
 
+    /// Update the `StateChanges` struct with the given `PresenceEvent` that
+    /// was just received.
+    ///
+    /// The `last_active_ago` of a presence event is relative to the time at
+    /// which it was received, so this time is added to the saved event under
+    /// [`PRESENCE_RECEIVED_AT_FIELD`].
+    pub fn add_received_presence_event(&mut self, raw_event: Raw<PresenceEvent>) {
+        let Ok(event) = raw_event.deserialize() else {
+            return;
+        };
+
+        let raw_event = raw_event
+            .deserialize_as::<JsonObject>()
+            .ok()
+            .and_then(|mut object| {
+                let received_at = serde_json::to_value(MilliSecondsSinceUnixEpoch::now()).ok()?;
+                object.insert(PRESENCE_RECEIVED_AT_FIELD.to_owned(), received_at);
+                Raw::new(&object).ok()
+            })
+            .map(Raw::cast)
+            .unwrap_or(raw_event);
+
+        self.presence.insert(event.sender, raw_event);
+    }
+
     /// Update the `StateChanges` struct with the given `RoomInfo`.
     pub fn add_room(&mut self, room: RoomInfo) {
         self.room_infos.insert(room.room_id.clone(), room);
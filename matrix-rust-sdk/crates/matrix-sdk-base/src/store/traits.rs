@@ -346,6 +346,27 @@ pub trait StateStore: AsyncTraitDeps {
     /// * `room_id` - The `RoomId` of the room to delete.
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error>;
 
+    /// Save a snapshot of all the data of the store, under the given
+    /// identifier.
+    ///
+    /// A previous snapshot with the same identifier is replaced. Snapshots
+    /// aren't part of other snapshots, and neither are the send queues, which
+    /// are left untouched when a snapshot is restored.
+    ///
+    /// This is meant for debugging, to be able to go back in time with
+    /// [`StateStore::restore_snapshot`].
+    async fn save_snapshot(&self, id: u64) -> Result<(), Self::Error>;
+
+    /// Replace all the data of the store with the snapshot saved under the
+    /// given identifier.
+    ///
+    /// The snapshot is kept. Returns false if there is no such snapshot, in
+    /// which case the store is left untouched.
+    async fn restore_snapshot(&self, id: u64) -> Result<bool, Self::Error>;
+
+    /// Remove the snapshot saved under the given identifier, if any.
+    async fn remove_snapshot(&self, id: u64) -> Result<(), Self::Error>;
+
     /// Save a request to be sent by a send queue later (e.g. sending an event).
     ///
     /// # Arguments
@@ -653,6 +674,18 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.remove_room(room_id).await.map_err(Into::into)
     }
 
+    async fn save_snapshot(&self, id: u64) -> Result<(), Self::Error> {
+        self.0.save_snapshot(id).await.map_err(Into::into)
+    }
+
+    async fn restore_snapshot(&self, id: u64) -> Result<bool, Self::Error> {
+        self.0.restore_snapshot(id).await.map_err(Into::into)
+    }
+
+    async fn remove_snapshot(&self, id: u64) -> Result<(), Self::Error> {
+        self.0.remove_snapshot(id).await.map_err(Into::into)
+    }
+
     async fn save_send_queue_request(
         &self,
         room_id: &RoomId,
@@ -298,6 +298,10 @@ pub struct EncryptionInfo {
     /// Callers that persist this should mark the state as dirty when a device
     /// change is received down the sync.
     pub verification_state: VerificationState,
+    /// The ID of the Megolm session that was used to decrypt the event, if
+    /// known.
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 /// Represents a matrix room event that has been returned from `/sync`,
@@ -1009,6 +1013,7 @@ mod tests {
                         sender_claimed_keys: Default::default(),
                     },
                     verification_state: VerificationState::Verified,
+                    session_id: Some("mysessionid".to_owned()),
                 },
                 unsigned_encryption_info: Some(BTreeMap::from([(
                     UnsignedEventLocation::RelationsReplace,
@@ -1047,6 +1052,7 @@ mod tests {
                                 }
                             },
                             "verification_state": "Verified",
+                            "session_id": "mysessionid",
                         },
                         "unsigned_encryption_info": {
                             "RelationsReplace": {"UnableToDecrypt": {
@@ -1095,6 +1101,7 @@ mod tests {
             event.encryption_info().unwrap().algorithm_info,
             AlgorithmInfo::MegolmV1AesSha2 { .. }
         );
+        assert!(event.encryption_info().unwrap().session_id.is_none());
 
         // Test that the previous format, with an undecryptable unsigned event, can also
         // be deserialized.
@@ -22,29 +22,41 @@
 
 use std::{
     collections::{btree_map::Entry, BTreeMap, BTreeSet},
-    mem,
+    future, mem,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, RwLock as StdRwLock,
     },
+    time::Duration,
 };
 
+use futures_core::Stream;
+use futures_util::StreamExt;
+use matrix_sdk_common::deserialized_responses::WithheldCode;
 use ruma::{
     api::client::keys::claim_keys::v3::Request as KeysClaimRequest,
     events::secret::request::{
         RequestAction, SecretName, ToDeviceSecretRequestEvent as SecretRequestEvent,
     },
-    DeviceId, OneTimeKeyAlgorithm, OwnedDeviceId, OwnedTransactionId, OwnedUserId, RoomId,
-    TransactionId, UserId,
+    DeviceId, MilliSecondsSinceUnixEpoch, OneTimeKeyAlgorithm, OwnedDeviceId, OwnedRoomId,
+    OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UInt, UserId,
 };
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tracing::{debug, field::debug, info, instrument, trace, warn, Span};
 use vodozemac::{megolm::SessionOrdering, Curve25519PublicKey};
 
-use super::{GossipRequest, GossippedSecret, RequestEvent, RequestInfo, SecretInfo, WaitQueue};
+use super::{
+    GossipRequest, GossippedSecret, KeyRequestPolicy, KeyRequestState, KeyRequestUpdate,
+    RequestEvent, RequestInfo, SecretInfo, WaitQueue,
+};
 use crate::{
     error::{EventError, OlmError, OlmResult},
     identities::IdentityManager,
-    olm::{InboundGroupSession, Session},
+    olm::{
+        sender_data_finder::SessionDeviceCheckError, InboundGroupSession, SenderDataFinder, Session,
+    },
     session_manager::GroupSessionCache,
     store::{Changes, CryptoStoreError, SecretImportError, Store, StoreCache},
     types::{
@@ -52,7 +64,8 @@ use crate::{
             forwarded_room_key::ForwardedRoomKeyContent,
             olm_v1::{DecryptedForwardedRoomKeyEvent, DecryptedSecretSendEvent},
             room::encrypted::EncryptedEvent,
-            room_key_request::RoomKeyRequestEvent,
+            room_key_request::{RoomKeyRequestEvent, SupportedKeyInfo},
+            room_key_withheld::CommonWithheldCodeContent,
             secret_send::SecretSendContent,
             EventType,
         },
@@ -82,9 +95,63 @@ pub(crate) struct GossipMachineInner {
     /// Whether we should send out `m.room_key_request` messages.
     room_key_requests_enabled: AtomicBool,
 
+    /// The policy used to retry the `m.room_key_request` messages we send out.
+    key_request_policy: StdRwLock<KeyRequestPolicy>,
+
+    /// The `m.room_key_request` messages waiting for an answer, by room and
+    /// session ID.
+    pending_key_requests: StdRwLock<BTreeMap<(OwnedRoomId, String), PendingKeyRequest>>,
+
+    /// The sender side of a broadcast channel notified of the lifecycle of
+    /// our `m.room_key_request` messages.
+    key_request_updates: broadcast::Sender<KeyRequestUpdate>,
+
     identity_manager: IdentityManager,
 }
 
+/// The key under which the `m.room_key_request` messages waiting for an
+/// answer are saved in the custom values of the store.
+const PENDING_KEY_REQUESTS_KEY: &str = "pending_key_requests";
+
+/// An `m.room_key_request` waiting for an answer.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct PendingKeyRequest {
+    request: GossipRequest,
+    /// How many times the request was sent out.
+    attempts: u32,
+    /// When the request should be sent out again, or for the first time if
+    /// it's waiting for the key backup, see
+    /// [`KeyRequestPolicy::prefer_backup`].
+    #[serde(default = "MilliSecondsSinceUnixEpoch::now")]
+    next_attempt: MilliSecondsSinceUnixEpoch,
+    /// Whether we already had a session with this ID when the request was
+    /// created, e.g. one that doesn't start early enough.
+    had_session: bool,
+}
+
+/// The key under which the room keys shared as part of the history of the
+/// given room are held in the custom values of the store, until the invite to
+/// the room is accepted.
+fn shared_history_keys_key(room_id: &RoomId) -> String {
+    format!("shared_history_keys:{room_id}")
+}
+
+/// A room key shared as part of the history of a room, see
+/// [`GossipMachine::accept_shared_history_keys`].
+#[derive(Debug, Deserialize, Serialize)]
+struct HeldSharedHistoryKey {
+    /// The Curve25519 key of the device that sent the room key.
+    sender_key: Curve25519PublicKey,
+    /// The decrypted `m.forwarded_room_key` event, serialized as JSON.
+    event: String,
+}
+
+/// The point in time when the given delay from now is elapsed.
+fn after(delay: Duration) -> MilliSecondsSinceUnixEpoch {
+    let delay = UInt::new_saturating(delay.as_millis().try_into().unwrap_or(u64::MAX));
+    MilliSecondsSinceUnixEpoch(MilliSecondsSinceUnixEpoch::now().0.saturating_add(delay))
+}
+
 impl GossipMachine {
     pub fn new(
         store: Store,
@@ -109,6 +176,9 @@ impl GossipMachine {
                 users_for_key_claim,
                 room_key_forwarding_enabled,
                 room_key_requests_enabled,
+                key_request_policy: Default::default(),
+                pending_key_requests: Default::default(),
+                key_request_updates: broadcast::Sender::new(100),
                 identity_manager,
             }),
         }
@@ -140,19 +210,178 @@ impl GossipMachine {
         self.inner.room_key_requests_enabled.load(Ordering::SeqCst)
     }
 
+    /// Set the policy used to retry the outgoing `m.room_key_request`s.
+    pub fn set_key_request_policy(&self, policy: KeyRequestPolicy) {
+        *self.inner.key_request_policy.write().unwrap() = policy;
+    }
+
+    /// Get the policy used to retry the outgoing `m.room_key_request`s.
+    pub fn key_request_policy(&self) -> KeyRequestPolicy {
+        *self.inner.key_request_policy.read().unwrap()
+    }
+
+    /// Receive the updates about the lifecycle of our outgoing
+    /// `m.room_key_request`s.
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn key_request_updates_stream(&self) -> impl Stream<Item = KeyRequestUpdate> {
+        let stream = BroadcastStream::new(self.inner.key_request_updates.subscribe());
+
+        stream.filter_map(|result| {
+            future::ready(match result {
+                Ok(update) => Some(update),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("key_request_updates_stream missed {lag} updates");
+                    None
+                }
+            })
+        })
+    }
+
+    fn send_key_request_update(&self, request: &GossipRequest, state: KeyRequestState) {
+        if let SecretInfo::KeyRequest(info) = &request.info {
+            trace!(request_id = ?request.request_id, ?state, "Key request state changed");
+
+            // There might be no listeners, that's fine.
+            let _ = self.inner.key_request_updates.send(KeyRequestUpdate {
+                request_id: request.request_id.clone(),
+                room_id: info.room_id().to_owned(),
+                session_id: info.session_id().to_owned(),
+                state,
+            });
+        }
+    }
+
+    /// Load the `m.room_key_request` messages waiting for an answer that were
+    /// saved by a previous instance, so they keep being retried and counted.
+    pub async fn load_pending_key_requests(&self) -> Result<(), CryptoStoreError> {
+        let pending: Vec<PendingKeyRequest> =
+            self.inner.store.get_value(PENDING_KEY_REQUESTS_KEY).await?.unwrap_or_default();
+
+        let mut pending_key_requests = self.inner.pending_key_requests.write().unwrap();
+
+        for pending in pending {
+            if let SecretInfo::KeyRequest(info) = &pending.request.info {
+                let key = (info.room_id().to_owned(), info.session_id().to_owned());
+                pending_key_requests.insert(key, pending);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save the `m.room_key_request` messages waiting for an answer, to retry
+    /// them after a restart.
+    async fn save_pending_key_requests(&self) -> Result<(), CryptoStoreError> {
+        let pending: Vec<_> =
+            self.inner.pending_key_requests.read().unwrap().values().cloned().collect();
+        self.inner.store.set_value(PENDING_KEY_REQUESTS_KEY, &pending).await
+    }
+
     /// Load stored outgoing requests that were not yet sent out.
+    ///
+    /// Key requests held back by the [`KeyRequestPolicy`] are skipped.
     async fn load_outgoing_requests(&self) -> Result<Vec<OutgoingRequest>, CryptoStoreError> {
+        let now = MilliSecondsSinceUnixEpoch::now();
+        let held_back: BTreeSet<OwnedTransactionId> = self
+            .inner
+            .pending_key_requests
+            .read()
+            .unwrap()
+            .values()
+            .filter(|pending| pending.attempts == 0 && pending.next_attempt > now)
+            .map(|pending| pending.request.request_id.clone())
+            .collect();
+
         Ok(self
             .inner
             .store
             .get_unsent_secret_requests()
             .await?
             .into_iter()
-            .filter(|i| !i.sent_out)
+            .filter(|i| !i.sent_out && !held_back.contains(&i.request_id))
             .map(|info| info.to_request(self.device_id()))
             .collect())
     }
 
+    /// Apply the [`KeyRequestPolicy`] to the key requests waiting for an
+    /// answer.
+    ///
+    /// Requests for a room key we received in the meantime, e.g. from the key
+    /// backup, are cancelled, unanswered requests are sent again with a new
+    /// request ID, and we give up on the ones sent too many times.
+    async fn retry_key_requests(&self) -> Result<(), CryptoStoreError> {
+        let policy = self.key_request_policy();
+        let now = MilliSecondsSinceUnixEpoch::now();
+
+        let due: Vec<_> = self
+            .inner
+            .pending_key_requests
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, pending)| pending.next_attempt <= now)
+            .map(|(key, pending)| {
+                (key.clone(), pending.request.clone(), pending.attempts, pending.had_session)
+            })
+            .collect();
+
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        for ((room_id, session_id), request, attempts, had_session) in due {
+            let store = &self.inner.store;
+            let received = !had_session
+                && store.get_inbound_group_session(&room_id, &session_id).await?.is_some();
+
+            if received || (request.sent_out && attempts >= policy.max_attempts) {
+                if received {
+                    debug!(?room_id, session_id, "Received the requested room key, cancelling");
+                } else {
+                    info!(?room_id, session_id, attempts, "Giving up on an unanswered key request");
+                }
+
+                self.inner.pending_key_requests.write().unwrap().remove(&(room_id, session_id));
+                self.cancel_key_request(&request).await?;
+                self.send_key_request_update(&request, KeyRequestState::Cancelled);
+            } else if request.sent_out {
+                debug!(?room_id, session_id, attempts, "Sending an unanswered key request again");
+
+                self.cancel_key_request(&request).await?;
+
+                let new_request =
+                    GossipRequest { request_id: TransactionId::new(), sent_out: false, ..request };
+                self.save_outgoing_key_info(new_request.clone()).await?;
+
+                if let Some(pending) =
+                    self.inner.pending_key_requests.write().unwrap().get_mut(&(room_id, session_id))
+                {
+                    pending.request = new_request.clone();
+                    pending.next_attempt = after(policy.retry_delay(attempts));
+                }
+
+                self.send_key_request_update(&new_request, KeyRequestState::Created);
+            }
+        }
+
+        self.save_pending_key_requests().await
+    }
+
+    /// Delete the given key request, and queue up its cancellation if it was
+    /// sent out.
+    async fn cancel_key_request(&self, request: &GossipRequest) -> Result<(), CryptoStoreError> {
+        self.delete_key_info(request).await?;
+
+        if request.sent_out {
+            let cancel = request.to_cancellation(self.device_id());
+            self.inner.outgoing_requests.write().unwrap().insert(cancel.request_id.clone(), cancel);
+        }
+
+        Ok(())
+    }
+
     /// Our own user id.
     pub fn user_id(&self) -> &UserId {
         &self.inner.store.static_account().user_id
@@ -166,6 +395,8 @@ impl GossipMachine {
     pub async fn outgoing_to_device_requests(
         &self,
     ) -> Result<Vec<OutgoingRequest>, CryptoStoreError> {
+        self.retry_key_requests().await?;
+
         let mut key_requests = self.load_outgoing_requests().await?;
         let key_forwards: Vec<OutgoingRequest> =
             self.inner.outgoing_requests.read().unwrap().values().cloned().collect();
@@ -671,9 +902,18 @@ impl GossipMachine {
         if self.inner.room_key_requests_enabled.load(Ordering::SeqCst) {
             let request = self.inner.store.get_secret_request_by_info(key_info).await?;
 
+            let max_outstanding_requests = self.key_request_policy().max_outstanding_requests;
+
             // Don't send out duplicate requests, users can re-request them if they
             // think a second request might succeed.
-            if request.is_none() {
+            if request.is_some() {
+                Ok(false)
+            } else if self.inner.pending_key_requests.read().unwrap().len()
+                >= max_outstanding_requests
+            {
+                debug!("Too many outstanding key requests, not requesting the key");
+                Ok(false)
+            } else {
                 let devices = self.inner.store.get_user_devices(self.user_id()).await?;
 
                 // Devices will only respond to key requests if the devices are
@@ -681,8 +921,6 @@ impl GossipMachine {
                 // we're verified by them either. Don't request keys if there isn't
                 // at least one verified device.
                 Ok(devices.is_any_verified())
-            } else {
-                Ok(false)
             }
         } else {
             Ok(false)
@@ -756,7 +994,34 @@ impl GossipMachine {
         };
 
         let outgoing_request = request.to_request(self.device_id());
-        self.save_outgoing_key_info(request).await?;
+        self.save_outgoing_key_info(request.clone()).await?;
+
+        if let SecretInfo::KeyRequest(info) = &request.info {
+            let policy = self.key_request_policy();
+            let had_session = self
+                .inner
+                .store
+                .get_inbound_group_session(info.room_id(), info.session_id())
+                .await?
+                .is_some();
+            let next_attempt = if policy.prefer_backup {
+                after(policy.backup_timeout)
+            } else {
+                MilliSecondsSinceUnixEpoch::now()
+            };
+
+            self.inner.pending_key_requests.write().unwrap().insert(
+                (info.room_id().to_owned(), info.session_id().to_owned()),
+                PendingKeyRequest {
+                    request: request.clone(),
+                    attempts: 0,
+                    next_attempt,
+                    had_session,
+                },
+            );
+            self.save_pending_key_requests().await?;
+            self.send_key_request_update(&request, KeyRequestState::Created);
+        }
 
         Ok(outgoing_request)
     }
@@ -821,7 +1086,25 @@ impl GossipMachine {
                 "Marking outgoing secret request as sent"
             );
             info.sent_out = true;
-            self.save_outgoing_key_info(info).await?;
+            self.save_outgoing_key_info(info.clone()).await?;
+
+            if let SecretInfo::KeyRequest(key_info) = &info.info {
+                let key = (key_info.room_id().to_owned(), key_info.session_id().to_owned());
+                let policy = self.key_request_policy();
+
+                let attempt =
+                    self.inner.pending_key_requests.write().unwrap().get_mut(&key).map(|pending| {
+                        pending.request = info.clone();
+                        pending.attempts += 1;
+                        pending.next_attempt = after(policy.retry_delay(pending.attempts));
+                        pending.attempts
+                    });
+
+                if let Some(attempt) = attempt {
+                    self.save_pending_key_requests().await?;
+                    self.send_key_request_update(&info, KeyRequestState::Sent { attempt });
+                }
+            }
         }
 
         self.inner.outgoing_requests.write().unwrap().remove(id);
@@ -848,6 +1131,103 @@ impl GossipMachine {
         let request = key_info.to_cancellation(self.device_id());
         self.inner.outgoing_requests.write().unwrap().insert(request.request_id.clone(), request);
 
+        if let SecretInfo::KeyRequest(info) = &key_info.info {
+            let key = (info.room_id().to_owned(), info.session_id().to_owned());
+            self.inner.pending_key_requests.write().unwrap().remove(&key);
+            self.save_pending_key_requests().await?;
+            self.send_key_request_update(key_info, KeyRequestState::Answered);
+        }
+
+        Ok(())
+    }
+
+    /// Receive an `m.room_key.withheld` for the given session.
+    ///
+    /// If we requested the room key, the request is cancelled and the
+    /// listeners of [`GossipMachine::key_request_updates_stream()`] are
+    /// notified.
+    ///
+    /// The notice is only accepted from a device the request was sent to, i.e.
+    /// one of our own devices, or from the device which created the session.
+    pub async fn receive_withheld(
+        &self,
+        sender: &UserId,
+        content: &CommonWithheldCodeContent,
+        code: &WithheldCode,
+    ) -> Result<(), CryptoStoreError> {
+        let key = (content.room_id.clone(), content.session_id.clone());
+        let Some(pending) = self.inner.pending_key_requests.read().unwrap().get(&key).cloned()
+        else {
+            return Ok(());
+        };
+
+        if !self.is_withheld_sender_allowed(sender, content, &pending.request).await? {
+            warn!(
+                ?sender,
+                room_id = ?content.room_id,
+                session_id = content.session_id,
+                "Ignoring a withheld notice for a key request from an unexpected sender"
+            );
+            return Ok(());
+        }
+
+        self.inner.pending_key_requests.write().unwrap().remove(&key);
+        self.inner.outgoing_requests.write().unwrap().remove(&pending.request.request_id);
+        self.cancel_key_request(&pending.request).await?;
+        self.save_pending_key_requests().await?;
+        self.send_key_request_update(&pending.request, KeyRequestState::Withheld(code.clone()));
+
+        Ok(())
+    }
+
+    /// Check that an `m.room_key.withheld` for one of our key requests comes
+    /// from a device the request was sent to, or from the device which created
+    /// the session.
+    async fn is_withheld_sender_allowed(
+        &self,
+        sender: &UserId,
+        content: &CommonWithheldCodeContent,
+        request: &GossipRequest,
+    ) -> Result<bool, CryptoStoreError> {
+        if sender == request.request_recipient {
+            return Ok(true);
+        }
+
+        // Only the megolm v1 requests tell us which device created the session.
+        let SecretInfo::KeyRequest(SupportedKeyInfo::MegolmV1AesSha2(info)) = &request.info else {
+            return Ok(false);
+        };
+
+        Ok(content.sender_key == info.sender_key
+            && self.inner.store.get_device_from_curve_key(sender, info.sender_key).await?.is_some())
+    }
+
+    /// Release the room key request held back for the given room key by
+    /// [`KeyRequestPolicy::prefer_backup`], now that the key backup was
+    /// queried for it.
+    ///
+    /// The request is cancelled with the next outgoing requests if the key
+    /// backup provided the room key, and sent out otherwise.
+    pub async fn backup_download_finished(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<(), CryptoStoreError> {
+        let released = self
+            .inner
+            .pending_key_requests
+            .write()
+            .unwrap()
+            .get_mut(&(room_id.to_owned(), session_id.to_owned()))
+            .filter(|pending| pending.attempts == 0 && !pending.request.sent_out)
+            .map(|pending| pending.next_attempt = MilliSecondsSinceUnixEpoch::now())
+            .is_some();
+
+        if released {
+            trace!(?room_id, session_id, "Releasing a key request after a backup download");
+            self.save_pending_key_requests().await?;
+        }
+
         Ok(())
     }
 
@@ -1005,6 +1385,170 @@ impl GossipMachine {
         }
     }
 
+    /// Hold a room key that was shared with us as part of the history of a
+    /// room we were invited to, as opposed to being sent as a response to one
+    /// of our room key requests, until we accept the invite. See [MSC3061].
+    ///
+    /// The room membership of the sender isn't known when the key is received,
+    /// so it's only accepted by [`GossipMachine::accept_shared_history_keys`],
+    /// if it was shared by the user who invited us.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    async fn hold_shared_history_key(
+        &self,
+        room_id: &RoomId,
+        sender_key: Curve25519PublicKey,
+        event: &DecryptedForwardedRoomKeyEvent,
+    ) -> Result<(), CryptoStoreError> {
+        info!(
+            ?sender_key,
+            sender = ?event.sender,
+            ?room_id,
+            "Received a room key shared as part of the room history, holding it until the \
+             invite is accepted",
+        );
+
+        let key = shared_history_keys_key(room_id);
+        let mut keys: Vec<HeldSharedHistoryKey> =
+            self.inner.store.get_value(&key).await?.unwrap_or_default();
+        keys.push(HeldSharedHistoryKey { sender_key, event: serde_json::to_string(event)? });
+
+        self.inner.store.set_value(&key, &keys).await
+    }
+
+    /// Accept the room keys that were shared with us as part of the history of
+    /// the given room, by the user who invited us to it. See [MSC3061].
+    ///
+    /// The keys that were shared by other users are discarded, since we can't
+    /// know whether they are members of the room.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    pub async fn accept_shared_history_keys(
+        &self,
+        room_id: &RoomId,
+        inviter: &UserId,
+    ) -> Result<Vec<InboundGroupSession>, CryptoStoreError> {
+        let key = shared_history_keys_key(room_id);
+        let Some(keys) = self.inner.store.get_value::<Vec<HeldSharedHistoryKey>>(&key).await?
+        else {
+            return Ok(Vec::new());
+        };
+
+        let mut sessions = Vec::new();
+
+        for held_key in keys {
+            let event: DecryptedForwardedRoomKeyEvent = serde_json::from_str(&held_key.event)?;
+
+            if event.sender != inviter {
+                warn!(
+                    sender_key = ?held_key.sender_key,
+                    sender = ?event.sender,
+                    ?room_id,
+                    "Discarding a room key shared as part of the room history by a user who \
+                     didn't invite us",
+                );
+                continue;
+            }
+
+            if let Some(session) =
+                self.receive_shared_history_key(held_key.sender_key, &event).await?
+            {
+                sessions.push(session);
+            }
+        }
+
+        self.inner.store.remove_custom_value(&key).await?;
+
+        Ok(sessions)
+    }
+
+    /// Receive a room key that was shared with us as part of the history of a
+    /// room, by the user who invited us to it. See [MSC3061].
+    ///
+    /// Such keys are only accepted from a verified device of that user, and are
+    /// never used to replace a better version of the same session.
+    ///
+    /// The [`SenderData`] of the session is computed like for a room key sent
+    /// by its creator: if the forwarding device didn't create the session, it
+    /// is marked as coming from an insecure source, until it's computed again
+    /// when decrypting an event of the actual creator.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    /// [`SenderData`]: crate::olm::SenderData
+    async fn receive_shared_history_key(
+        &self,
+        sender_key: Curve25519PublicKey,
+        event: &DecryptedForwardedRoomKeyEvent,
+    ) -> Result<Option<InboundGroupSession>, CryptoStoreError> {
+        let Some(device) =
+            self.inner.store.get_device_from_curve_key(&event.sender, sender_key).await?
+        else {
+            warn!(
+                ?sender_key,
+                sender = ?event.sender,
+                "Received a room key shared as part of the room history from an unknown device",
+            );
+
+            return Ok(None);
+        };
+
+        if !device.is_verified() {
+            warn!(
+                ?sender_key,
+                sender = ?event.sender,
+                device_id = ?device.device_id(),
+                "Received a room key shared as part of the room history from an unverified device",
+            );
+
+            return Ok(None);
+        }
+
+        let mut session = match InboundGroupSession::try_from(event) {
+            Ok(session) => session,
+            Err(e) => {
+                warn!(?sender_key, "Couldn't create a group session from a received room key");
+                return Err(e.into());
+            }
+        };
+
+        session.sender_data = match SenderDataFinder::find_using_curve_key(
+            &self.inner.store,
+            sender_key,
+            &event.sender,
+            &session,
+        )
+        .await
+        {
+            Ok(sender_data) => sender_data,
+            Err(SessionDeviceCheckError::CryptoStoreError(e)) => return Err(e),
+            Err(SessionDeviceCheckError::MismatchedIdentityKeys(e)) => {
+                warn!(
+                    ?sender_key,
+                    session_id = session.session_id(),
+                    "Received a room key shared as part of the room history with mismatched \
+                     identity keys: {e}",
+                );
+
+                return Ok(None);
+            }
+        };
+
+        if self.inner.store.compare_group_session(&session).await? == SessionOrdering::Better {
+            info!(
+                ?sender_key,
+                claimed_sender_key = ?session.sender_key(),
+                room_id = ?session.room_id(),
+                session_id = session.session_id(),
+                sender_data = ?session.sender_data.to_type(),
+                "Received a room key shared as part of the room history",
+            );
+
+            Ok(Some(session))
+        } else {
+            Ok(None)
+        }
+    }
+
     async fn should_accept_forward(
         &self,
         info: &GossipRequest,
@@ -1039,6 +1583,11 @@ impl GossipMachine {
         let Some(request) =
             self.inner.store.get_secret_request_by_info(&info.clone().into()).await?
         else {
+            if event.content.shared_history() {
+                self.hold_shared_history_key(info.room_id(), sender_key, event).await?;
+                return Ok(None);
+            }
+
             warn!(
                 sender_key = ?sender_key,
                 room_id = ?info.room_id(),
@@ -1095,7 +1644,7 @@ impl GossipMachine {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
 
     #[cfg(feature = "automatic-room-key-forwarding")]
     use assert_matches::assert_matches;
@@ -1128,6 +1677,7 @@ mod tests {
         EncryptionSettings,
     };
     use crate::{
+        gossiping::{KeyRequestPolicy, KeyRequestState},
         identities::{DeviceData, IdentityManager, LocalTrust},
         olm::{Account, PrivateCrossSigningIdentity},
         session_manager::GroupSessionCache,
@@ -1451,6 +2001,319 @@ mod tests {
         assert!(machine.outgoing_to_device_requests().await.unwrap().is_empty());
     }
 
+    #[test]
+    fn test_key_request_retry_delay() {
+        let policy = KeyRequestPolicy {
+            initial_retry_delay: Duration::from_secs(10),
+            max_retry_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        assert_eq!(policy.retry_delay(1), Duration::from_secs(10));
+        assert_eq!(policy.retry_delay(2), Duration::from_secs(20));
+        assert_eq!(policy.retry_delay(3), Duration::from_secs(40));
+        assert_eq!(policy.retry_delay(4), Duration::from_secs(60));
+        assert_eq!(policy.retry_delay(100), Duration::from_secs(60));
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_request_retry() {
+        use futures_util::{pin_mut, FutureExt, StreamExt};
+        use ruma::events::ToDeviceEventType;
+
+        let machine = get_machine_test_helper().await;
+        let account = account();
+        let alice_device = DeviceData::from_account(&alice_2_account());
+        alice_device.set_trust_state(LocalTrust::Verified);
+        machine.inner.store.save_device_data(&[alice_device]).await.unwrap();
+
+        // Retry right away, and give up after the second attempt.
+        machine.set_key_request_policy(KeyRequestPolicy {
+            initial_retry_delay: Duration::ZERO,
+            max_attempts: 2,
+            ..Default::default()
+        });
+
+        let stream = machine.key_request_updates_stream();
+        pin_mut!(stream);
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Created);
+        assert_eq!(update.session_id, session.session_id());
+
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        let first_request_id = requests[0].request_id.clone();
+        assert_eq!(update.request_id, first_request_id);
+
+        machine.mark_outgoing_request_as_sent(&first_request_id).await.unwrap();
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Sent { attempt: 1 });
+
+        // The unanswered request is cancelled, and sent again with a new ID.
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Created);
+        assert_ne!(update.request_id, first_request_id);
+        assert!(requests.iter().any(|r| r.request_id == update.request_id));
+
+        // The new request isn't sent again until it was sent out.
+        assert_eq!(machine.outgoing_to_device_requests().await.unwrap().len(), 2);
+        assert!(stream.next().now_or_never().is_none());
+
+        for request in requests {
+            machine.mark_outgoing_request_as_sent(&request.request_id).await.unwrap();
+        }
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Sent { attempt: 2 });
+
+        // We give up after the second attempt, only the cancellation is left.
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_matches!(
+            requests[0].request.as_ref(),
+            AnyOutgoingRequest::ToDeviceRequest(r) if r.event_type == ToDeviceEventType::RoomKeyRequest
+        );
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Cancelled);
+        assert!(machine.inner.pending_key_requests.read().unwrap().is_empty());
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_request_prefer_backup() {
+        use futures_util::{pin_mut, FutureExt, StreamExt};
+
+        let machine = get_machine_test_helper().await;
+        let account = account();
+        let alice_device = DeviceData::from_account(&alice_2_account());
+        alice_device.set_trust_state(LocalTrust::Verified);
+        machine.inner.store.save_device_data(&[alice_device]).await.unwrap();
+
+        machine.set_key_request_policy(KeyRequestPolicy {
+            prefer_backup: true,
+            backup_timeout: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        let stream = machine.key_request_updates_stream();
+        pin_mut!(stream);
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+
+        // The request is held back while we wait for the key backup.
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+        assert!(machine.outgoing_to_device_requests().await.unwrap().is_empty());
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Created);
+
+        // The key backup didn't have the room key, the request is sent out.
+        machine.backup_download_finished(session.room_id(), session.session_id()).await.unwrap();
+        assert_eq!(machine.outgoing_to_device_requests().await.unwrap().len(), 1);
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Created);
+        let held_back_request_id = update.request_id;
+
+        // The key backup provided the room key, the request is cancelled without
+        // ever being sent.
+        machine.inner.store.save_inbound_group_sessions(&[session.clone()]).await.unwrap();
+        machine.backup_download_finished(session.room_id(), session.session_id()).await.unwrap();
+
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        assert!(requests.iter().all(|r| r.request_id != held_back_request_id));
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Cancelled);
+        assert_eq!(update.request_id, held_back_request_id);
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_request_backup_timeout_survives_restart() {
+        let machine = get_machine_test_helper().await;
+        let account = account();
+        let alice_device = DeviceData::from_account(&alice_2_account());
+        alice_device.set_trust_state(LocalTrust::Verified);
+        machine.inner.store.save_device_data(&[alice_device]).await.unwrap();
+
+        machine.set_key_request_policy(KeyRequestPolicy {
+            prefer_backup: true,
+            backup_timeout: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+
+        // A new machine using the same store still waits for the key backup.
+        let store = machine.inner.store.clone();
+        let restarted = GossipMachine::new(
+            store.clone(),
+            IdentityManager::new(store.clone()),
+            GroupSessionCache::new(store),
+            Default::default(),
+        );
+        restarted.load_pending_key_requests().await.unwrap();
+
+        assert!(restarted.outgoing_to_device_requests().await.unwrap().is_empty());
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_request_max_outstanding_requests() {
+        let machine = get_machine_test_helper().await;
+        let account = account();
+        let alice_device = DeviceData::from_account(&alice_2_account());
+        alice_device.set_trust_state(LocalTrust::Verified);
+        machine.inner.store.save_device_data(&[alice_device]).await.unwrap();
+
+        machine.set_key_request_policy(KeyRequestPolicy {
+            max_outstanding_requests: 1,
+            ..Default::default()
+        });
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+        assert!(!machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+
+        assert_eq!(machine.outgoing_to_device_requests().await.unwrap().len(), 1);
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_request_withheld() {
+        use futures_util::{pin_mut, FutureExt, StreamExt};
+        use matrix_sdk_common::deserialized_responses::WithheldCode;
+        use ruma::events::ToDeviceEventType;
+
+        use crate::types::events::room_key_withheld::CommonWithheldCodeContent;
+
+        let machine = get_machine_test_helper().await;
+        let account = account();
+        let alice_device = DeviceData::from_account(&alice_2_account());
+        alice_device.set_trust_state(LocalTrust::Verified);
+        machine.inner.store.save_device_data(&[alice_device]).await.unwrap();
+
+        machine.set_key_request_policy(KeyRequestPolicy {
+            initial_retry_delay: Duration::ZERO,
+            ..Default::default()
+        });
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        machine.mark_outgoing_request_as_sent(&requests[0].request_id).await.unwrap();
+
+        let stream = machine.key_request_updates_stream();
+        pin_mut!(stream);
+
+        let content = CommonWithheldCodeContent::new(
+            session.room_id().to_owned(),
+            session.session_id().to_owned(),
+            session.sender_key(),
+            bob_device_id().to_owned(),
+        );
+
+        // A withheld notice from a user who neither received the request nor
+        // created the session is ignored.
+        machine.receive_withheld(bob_id(), &content, &WithheldCode::Unverified).await.unwrap();
+        assert!(stream.next().now_or_never().is_none());
+        assert_eq!(machine.inner.pending_key_requests.read().unwrap().len(), 1);
+
+        machine
+            .receive_withheld(machine.user_id(), &content, &WithheldCode::Unverified)
+            .await
+            .unwrap();
+
+        let update = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(update.state, KeyRequestState::Withheld(WithheldCode::Unverified));
+
+        // The request isn't retried anymore, it's deleted and only its
+        // cancellation is left.
+        assert!(machine.inner.pending_key_requests.read().unwrap().is_empty());
+        assert!(machine
+            .inner
+            .store
+            .get_outgoing_secret_requests(&requests[0].request_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_matches!(
+            requests[0].request.as_ref(),
+            AnyOutgoingRequest::ToDeviceRequest(r) if r.event_type == ToDeviceEventType::RoomKeyRequest
+        );
+        assert!(stream.next().now_or_never().is_none());
+
+        // The room key can be requested again.
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_key_request_retried_after_restart() {
+        let machine = get_machine_test_helper().await;
+        let account = account();
+        let alice_device = DeviceData::from_account(&alice_2_account());
+        alice_device.set_trust_state(LocalTrust::Verified);
+        machine.inner.store.save_device_data(&[alice_device]).await.unwrap();
+
+        let (outbound, session) = account.create_group_session_pair_with_defaults(room_id()).await;
+        let content = outbound.encrypt("m.dummy", &message_like_event_content!({})).await;
+        let event = wrap_encrypted_content(machine.user_id(), content);
+
+        assert!(machine.create_outgoing_key_request(session.room_id(), &event).await.unwrap());
+        let requests = machine.outgoing_to_device_requests().await.unwrap();
+        machine.mark_outgoing_request_as_sent(&requests[0].request_id).await.unwrap();
+
+        // A new machine using the same store picks up the unanswered request.
+        let store = machine.inner.store.clone();
+        let restarted = GossipMachine::new(
+            store.clone(),
+            IdentityManager::new(store.clone()),
+            GroupSessionCache::new(store),
+            Default::default(),
+        );
+        restarted.load_pending_key_requests().await.unwrap();
+
+        {
+            let pending_key_requests = restarted.inner.pending_key_requests.read().unwrap();
+            assert_eq!(pending_key_requests.len(), 1);
+            assert_eq!(pending_key_requests.values().next().unwrap().attempts, 1);
+        }
+
+        // It's due right away: it's cancelled and sent again with a new ID.
+        let requests = restarted.outgoing_to_device_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+    }
+
     #[async_test]
     #[cfg(feature = "automatic-room-key-forwarding")]
     async fn test_receive_forwarded_key() {
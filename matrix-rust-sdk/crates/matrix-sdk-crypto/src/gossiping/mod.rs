@@ -17,9 +17,11 @@ mod machine;
 use std::{
     collections::{BTreeMap, BTreeSet},
     sync::{Arc, RwLock as StdRwLock},
+    time::Duration,
 };
 
 pub(crate) use machine::GossipMachine;
+use matrix_sdk_common::deserialized_responses::WithheldCode;
 use ruma::{
     events::{
         room_key_request::{Action, ToDeviceRoomKeyRequestEventContent},
@@ -31,7 +33,7 @@ use ruma::{
     },
     serde::Raw,
     to_device::DeviceIdOrAllDevices,
-    DeviceId, OwnedDeviceId, OwnedTransactionId, OwnedUserId, TransactionId, UserId,
+    DeviceId, OwnedDeviceId, OwnedRoomId, OwnedTransactionId, OwnedUserId, TransactionId, UserId,
 };
 use serde::{Deserialize, Serialize};
 
@@ -97,6 +99,116 @@ pub struct GossipRequest {
     pub sent_out: bool,
 }
 
+/// The policy used for the room key requests we send out to our other devices
+/// when we fail to decrypt an event.
+///
+/// See [`OlmMachine::set_key_request_policy()`].
+///
+/// [`OlmMachine::set_key_request_policy()`]: crate::OlmMachine::set_key_request_policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyRequestPolicy {
+    /// How long to wait for an answer before sending a room key request again.
+    ///
+    /// The delay doubles after each attempt, up to `max_retry_delay`.
+    pub initial_retry_delay: Duration,
+
+    /// The maximum delay between two attempts of a room key request.
+    pub max_retry_delay: Duration,
+
+    /// How many times a room key request is sent before giving up on it.
+    pub max_attempts: u32,
+
+    /// The maximum number of room key requests waiting for an answer at the
+    /// same time. No new room key request is created once it's reached.
+    pub max_outstanding_requests: usize,
+
+    /// Whether to give the key backup a chance to provide the room key before
+    /// sending a new room key request.
+    ///
+    /// If set, a new request is held back until
+    /// [`OlmMachine::backup_download_finished()`] is called for its room key,
+    /// or for `backup_timeout` at most. The request is cancelled if the room
+    /// key is received in the meantime.
+    ///
+    /// [`OlmMachine::backup_download_finished()`]: crate::OlmMachine::backup_download_finished
+    pub prefer_backup: bool,
+
+    /// How long a new room key request waits for the key backup at most, if
+    /// `prefer_backup` is set.
+    pub backup_timeout: Duration,
+}
+
+impl Default for KeyRequestPolicy {
+    fn default() -> Self {
+        Self {
+            initial_retry_delay: Duration::from_secs(60),
+            max_retry_delay: Duration::from_secs(60 * 60),
+            max_attempts: 5,
+            max_outstanding_requests: 100,
+            prefer_backup: false,
+            backup_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl KeyRequestPolicy {
+    /// How long to wait before retrying a room key request that was sent out
+    /// `attempts` times.
+    fn retry_delay(&self, attempts: u32) -> Duration {
+        let factor = 1u32 << attempts.saturating_sub(1).min(31);
+        self.initial_retry_delay
+            .checked_mul(factor)
+            .map_or(self.max_retry_delay, |delay| delay.min(self.max_retry_delay))
+    }
+}
+
+/// The state of one of our room key requests, see [`KeyRequestUpdate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyRequestState {
+    /// The request was created, and will be sent out with the next outgoing
+    /// requests.
+    Created,
+
+    /// The request was sent out to our other devices.
+    Sent {
+        /// How many times the request was sent, starting at 1.
+        attempt: u32,
+    },
+
+    /// One of our devices answered the request with the room key.
+    Answered,
+
+    /// The owner of the room key told us that they withheld it from us.
+    Withheld(WithheldCode),
+
+    /// The request was cancelled, because we received the room key by other
+    /// means, or we gave up after too many attempts.
+    Cancelled,
+}
+
+/// An update about one of the room key requests we send out to our other
+/// devices.
+///
+/// See [`OlmMachine::key_request_updates_stream()`].
+///
+/// [`OlmMachine::key_request_updates_stream()`]: crate::OlmMachine::key_request_updates_stream
+#[derive(Clone, Debug)]
+pub struct KeyRequestUpdate {
+    /// The ID of the request.
+    ///
+    /// A new ID is used each time a request is sent again.
+    pub request_id: OwnedTransactionId,
+
+    /// The room the requested room key is used in.
+    pub room_id: OwnedRoomId,
+
+    /// The ID of the requested session.
+    pub session_id: String,
+
+    /// The new state of the request.
+    pub state: KeyRequestState,
+}
+
 /// An enum over the various secret request types we can have.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum SecretInfo {
@@ -29,8 +29,9 @@ use crate::store::IdentityUpdates;
 /// Something that can answer questions about the membership of a room and the
 /// identities of users.
 ///
-/// This is implemented by `matrix_sdk::Room` and is a trait here so we can
-/// supply a mock when needed.
+/// This is implemented by `matrix_sdk::Room`, and in `matrix_sdk` by all the
+/// rooms we share with other users for the client-wide stream of identity
+/// changes. It is a trait here so we can supply a mock when needed.
 #[async_trait]
 pub trait RoomIdentityProvider: core::fmt::Debug {
     /// Is the user with the supplied ID a member of this room?
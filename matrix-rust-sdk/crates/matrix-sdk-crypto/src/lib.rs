@@ -85,7 +85,9 @@ pub use file_encryption::{
     decrypt_room_key_export, encrypt_room_key_export, AttachmentDecryptor, AttachmentEncryptor,
     DecryptorError, KeyExportError, MediaEncryptionInfo,
 };
-pub use gossiping::{GossipRequest, GossippedSecret};
+pub use gossiping::{
+    GossipRequest, GossippedSecret, KeyRequestPolicy, KeyRequestState, KeyRequestUpdate,
+};
 pub use identities::{
     Device, DeviceData, LocalTrust, OtherUserIdentity, OtherUserIdentityData, OwnUserIdentity,
     OwnUserIdentityData, UserDevices, UserIdentity, UserIdentityData,
@@ -18,6 +18,7 @@ use std::{
     time::Duration,
 };
 
+use futures_core::Stream;
 use itertools::Itertools;
 use matrix_sdk_common::{
     deserialized_responses::{
@@ -63,7 +64,7 @@ use crate::{
     backups::{BackupMachine, MegolmV1BackupKey},
     dehydrated_devices::{DehydratedDevices, DehydrationError},
     error::{EventError, MegolmError, MegolmResult, OlmError, OlmResult, SetRoomSettingsError},
-    gossiping::GossipMachine,
+    gossiping::{GossipMachine, KeyRequestPolicy, KeyRequestUpdate},
     identities::{user::UserIdentity, Device, IdentityManager, UserDevices},
     olm::{
         Account, CrossSigningStatus, EncryptionSettings, IdentityKeys, InboundGroupSession,
@@ -73,8 +74,8 @@ use crate::{
     session_manager::{GroupSessionManager, SessionManager},
     store::{
         Changes, CryptoStoreWrapper, DeviceChanges, IdentityChanges, IntoCryptoStore, MemoryStore,
-        PendingChanges, Result as StoreResult, RoomKeyInfo, RoomSettings, SecretImportError, Store,
-        StoreCache, StoreTransaction,
+        PendingChanges, Result as StoreResult, RoomKeyInfo, RoomKeyRotationPolicy, RoomSettings,
+        SecretImportError, Store, StoreCache, StoreTransaction,
     },
     types::{
         events::{
@@ -87,7 +88,7 @@ use crate::{
             room_key_withheld::{
                 MegolmV1AesSha2WithheldContent, RoomKeyWithheldContent, RoomKeyWithheldEvent,
             },
-            ToDeviceEvents,
+            EventType, ToDeviceEvents,
         },
         requests::{
             AnyIncomingResponse, KeysQueryRequest, OutgoingRequest, ToDeviceRequest,
@@ -97,8 +98,8 @@ use crate::{
     },
     utilities::timestamp_to_iso8601,
     verification::{Verification, VerificationMachine, VerificationRequest},
-    CrossSigningKeyExport, CryptoStoreError, DecryptionSettings, DeviceData, LocalTrust,
-    RoomEventDecryptionResult, SignatureError, TrustRequirement,
+    CollectStrategy, CrossSigningKeyExport, CryptoStoreError, DecryptionSettings, DeviceData,
+    LocalTrust, RoomEventDecryptionResult, SignatureError, TrustRequirement,
 };
 
 /// State machine implementation of the Olm/Megolm encryption protocol used for
@@ -384,14 +385,17 @@ impl OlmMachine {
         // mechanism (at the store wrapper layer).
         Self::migration_post_verified_latch_support(&store, &identity_manager).await?;
 
-        Ok(Self::new_helper(
+        let machine = Self::new_helper(
             device_id,
             store,
             verification_machine,
             identity_manager,
             identity,
             maybe_backup_key,
-        ))
+        );
+        machine.inner.key_request_machine.load_pending_key_requests().await?;
+
+        Ok(machine)
     }
 
     // The sdk now support verified identity change detection.
@@ -482,6 +486,47 @@ impl OlmMachine {
         self.inner.key_request_machine.are_room_key_requests_enabled()
     }
 
+    /// Set the policy used to retry the outgoing `m.room_key_request`s we
+    /// send when we fail to decrypt an event.
+    ///
+    /// See also [`OlmMachine::set_room_key_requests_enabled`].
+    pub fn set_key_request_policy(&self, policy: KeyRequestPolicy) {
+        self.inner.key_request_machine.set_key_request_policy(policy)
+    }
+
+    /// Get the policy used to retry the outgoing `m.room_key_request`s.
+    ///
+    /// See also [`OlmMachine::set_key_request_policy`].
+    pub fn key_request_policy(&self) -> KeyRequestPolicy {
+        self.inner.key_request_machine.key_request_policy()
+    }
+
+    /// Tell the machine that we're done trying to download the given room key
+    /// from the key backup, successfully or not.
+    ///
+    /// If [`KeyRequestPolicy::prefer_backup`] is set, the room key request
+    /// waiting for the key backup is released: it's cancelled if the key
+    /// backup provided the room key, and sent out otherwise.
+    pub async fn backup_download_finished(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> StoreResult<()> {
+        self.inner.key_request_machine.backup_download_finished(room_id, session_id).await
+    }
+
+    /// Receive notifications of the lifecycle of our outgoing
+    /// `m.room_key_request`s as a [`Stream`].
+    ///
+    /// An update is sent when a request is created, sent out, answered with
+    /// the room key, withheld by the owner of the room key, or cancelled.
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    pub fn key_request_updates_stream(&self) -> impl Stream<Item = KeyRequestUpdate> {
+        self.inner.key_request_machine.key_request_updates_stream()
+    }
+
     /// Enable or disable room key forwarding.
     ///
     /// If room key forwarding is enabled, we will automatically reply to
@@ -890,6 +935,7 @@ impl OlmMachine {
                         .await?;
 
                 session.sender_data = sender_data;
+                session.set_shared_history(content.shared_history);
 
                 match self.store().compare_group_session(&session).await? {
                     SessionOrdering::Better => {
@@ -939,9 +985,26 @@ impl OlmMachine {
         }
     }
 
-    fn add_withheld_info(&self, changes: &mut Changes, event: &RoomKeyWithheldEvent) {
+    async fn add_withheld_info(&self, changes: &mut Changes, event: &RoomKeyWithheldEvent) {
         debug!(?event.content, "Processing `m.room_key.withheld` event");
 
+        if let RoomKeyWithheldContent::MegolmV1AesSha2(
+            MegolmV1AesSha2WithheldContent::BlackListed(c)
+            | MegolmV1AesSha2WithheldContent::Unverified(c)
+            | MegolmV1AesSha2WithheldContent::Unauthorised(c)
+            | MegolmV1AesSha2WithheldContent::Unavailable(c),
+        ) = &event.content
+        {
+            if let Err(e) = self
+                .inner
+                .key_request_machine
+                .receive_withheld(&event.sender, c, &event.content.withheld_code())
+                .await
+            {
+                error!("Error handling a withheld room key for a key request: {e:?}");
+            }
+        }
+
         if let RoomKeyWithheldContent::MegolmV1AesSha2(
             MegolmV1AesSha2WithheldContent::BlackListed(c)
             | MegolmV1AesSha2WithheldContent::Unverified(c),
@@ -1090,6 +1153,141 @@ impl OlmMachine {
         self.inner.group_session_manager.share_room_key(room_id, users, encryption_settings).await
     }
 
+    /// Get to-device requests to share the history of a room with a user who
+    /// was invited to it, as described in [MSC3061].
+    ///
+    /// Only the room keys of the sessions that were created while the history
+    /// of the room was shared are sent, to the devices of the user that the
+    /// room keys of the room would be shared with: the non-blacklisted devices
+    /// allowed by the given sharing strategy, and only the verified ones if the
+    /// room only allows trusted devices.
+    ///
+    /// Olm sessions need to be established with the devices of the user
+    /// beforehand, using [`OlmMachine::get_missing_sessions`]. The devices
+    /// without an Olm session are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room whose history should be shared.
+    ///
+    /// `user_id` - The user who was invited to the room.
+    ///
+    /// `sharing_strategy` - The strategy used to share the room keys of the
+    /// room.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    #[instrument(skip(self, sharing_strategy))]
+    pub async fn share_room_key_history(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+        sharing_strategy: &CollectStrategy,
+    ) -> OlmResult<Vec<ToDeviceRequest>> {
+        let mut sessions = self.store().get_inbound_group_sessions_for_room(room_id).await?;
+        sessions.retain(|s| s.shared_history());
+
+        if sessions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let room_only_allows_trusted_devices = self
+            .store()
+            .get_room_settings(room_id)
+            .await?
+            .is_some_and(|settings| settings.only_allow_trusted_devices);
+        let only_allow_trusted_devices = room_only_allows_trusted_devices
+            || matches!(
+                sharing_strategy,
+                CollectStrategy::DeviceBasedStrategy { only_allow_trusted_devices: true, .. }
+            );
+
+        let should_share_with = |device: &Device| {
+            if device.is_blacklisted() {
+                false
+            } else if device.local_trust_state() == LocalTrust::Ignored {
+                // Ignore the trust state of that device and share, like for
+                // the room keys.
+                true
+            } else if only_allow_trusted_devices {
+                device.is_verified()
+            } else if let CollectStrategy::IdentityBasedStrategy = sharing_strategy {
+                device.is_cross_signed_by_owner()
+            } else {
+                true
+            }
+        };
+
+        let devices = self.get_user_devices(user_id, None).await?;
+        let mut changes = Changes::default();
+        let mut requests = Vec::new();
+
+        for device in devices.devices().filter(should_share_with) {
+            for session in &sessions {
+                match device.encrypt_room_key_for_forwarding(session.clone(), None).await {
+                    Ok((used_session, content)) => {
+                        changes.sessions.push(used_session);
+                        requests.push(ToDeviceRequest::new(
+                            user_id,
+                            device.device_id().to_owned(),
+                            content.event_type(),
+                            content.cast(),
+                        ));
+                    }
+                    Err(OlmError::MissingSession) => {
+                        debug!(
+                            device_id = ?device.device_id(),
+                            "Not sharing the room history with a device, no Olm session found",
+                        );
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        self.store().save_changes(changes).await?;
+
+        info!(num_sessions = sessions.len(), num_requests = requests.len(), "Sharing room history");
+
+        Ok(requests)
+    }
+
+    /// Accept the room keys that were shared with us as part of the history of
+    /// a room, as described in [MSC3061], once we accepted the invite to the
+    /// room.
+    ///
+    /// The room keys shared as part of the history of a room are held until
+    /// this method is called, since the room membership of their sender isn't
+    /// known when they are received. Only the room keys that were shared by
+    /// the user who invited us, from one of their verified devices, are
+    /// accepted, the others are discarded.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room whose history was shared.
+    ///
+    /// `inviter` - The user who invited us to the room.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    #[instrument(skip(self))]
+    pub async fn accept_room_key_history(
+        &self,
+        room_id: &RoomId,
+        inviter: &UserId,
+    ) -> StoreResult<()> {
+        let sessions =
+            self.inner.key_request_machine.accept_shared_history_keys(room_id, inviter).await?;
+
+        if !sessions.is_empty() {
+            info!(num_sessions = sessions.len(), "Accepting the shared room history");
+
+            let changes = Changes { inbound_group_sessions: sessions, ..Default::default() };
+            self.store().save_changes(changes).await?;
+        }
+
+        Ok(())
+    }
+
     /// Receive an unencrypted verification event.
     ///
     /// This method can be used to pass verification events that are happening
@@ -1217,7 +1415,7 @@ impl OlmMachine {
         match event {
             RoomKeyRequest(e) => self.inner.key_request_machine.receive_incoming_key_request(e),
             SecretRequest(e) => self.inner.key_request_machine.receive_incoming_secret_request(e),
-            RoomKeyWithheld(e) => self.add_withheld_info(changes, e),
+            RoomKeyWithheld(e) => self.add_withheld_info(changes, e).await,
             KeyVerificationAccept(..)
             | KeyVerificationCancel(..)
             | KeyVerificationKey(..)
@@ -1600,6 +1798,7 @@ impl OlmMachine {
                     .collect(),
             },
             verification_state,
+            session_id: Some(session.session_id().to_owned()),
         })
     }
 
@@ -2461,6 +2660,42 @@ impl OlmMachine {
         Ok(())
     }
 
+    /// Get the local room key rotation policy of the given room.
+    ///
+    /// Returns the default policy, which doesn't add anything to the room's
+    /// encryption settings, if none was set with
+    /// [`OlmMachine::set_room_key_rotation_policy`].
+    pub async fn room_key_rotation_policy(
+        &self,
+        room_id: &RoomId,
+    ) -> StoreResult<RoomKeyRotationPolicy> {
+        self.inner.store.get_room_key_rotation_policy(room_id).await
+    }
+
+    /// Store the local room key rotation policy of the given room.
+    ///
+    /// The policy is taken into account the next time a room key is shared in
+    /// the room. It can only make the rotation stricter than what the room's
+    /// encryption settings ask for, so unlike
+    /// [`OlmMachine::set_room_settings`] it can be changed at any time. It's
+    /// stored apart from the [`RoomSettings`] of the room, which are left
+    /// untouched.
+    ///
+    /// A policy with a zero rotation period is rejected with
+    /// [`SetRoomSettingsError::InvalidSettings`].
+    pub async fn set_room_key_rotation_policy(
+        &self,
+        room_id: &RoomId,
+        policy: RoomKeyRotationPolicy,
+    ) -> Result<(), SetRoomSettingsError> {
+        if !policy.is_valid() {
+            warn!(?room_id, ?policy, "Rejecting a room key rotation policy with a zero period");
+            return Err(SetRoomSettingsError::InvalidSettings);
+        }
+
+        Ok(self.inner.store.set_room_key_rotation_policy(room_id, &policy).await?)
+    }
+
     /// Returns whether this `OlmMachine` is the same another one.
     ///
     /// Useful for testing purposes only.
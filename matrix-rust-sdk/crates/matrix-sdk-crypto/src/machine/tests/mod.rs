@@ -29,8 +29,11 @@ use ruma::{
     },
     device_id,
     events::{
-        room::message::{
-            AddMentions, MessageType, Relation, ReplyWithinThread, RoomMessageEventContent,
+        room::{
+            history_visibility::HistoryVisibility,
+            message::{
+                AddMentions, MessageType, Relation, ReplyWithinThread, RoomMessageEventContent,
+            },
         },
         AnyMessageLikeEvent, AnyMessageLikeEventContent, AnyToDeviceEvent, MessageLikeEvent,
         OriginalMessageLikeEvent,
@@ -38,7 +41,7 @@ use ruma::{
     room_id,
     serde::Raw,
     uint, user_id, DeviceId, DeviceKeyAlgorithm, DeviceKeyId, MilliSecondsSinceUnixEpoch,
-    OneTimeKeyAlgorithm, TransactionId, UserId,
+    OneTimeKeyAlgorithm, RoomId, TransactionId, UserId,
 };
 use serde_json::json;
 use vodozemac::{
@@ -56,9 +59,9 @@ use crate::{
         },
         EncryptionSyncChanges, OlmMachine,
     },
-    olm::{BackedUpRoomKey, ExportedRoomKey, SenderData, VerifyJson},
+    olm::{BackedUpRoomKey, ExportedRoomKey, SenderData, SenderDataType, VerifyJson},
     session_manager::CollectStrategy,
-    store::{BackupDecryptionKey, Changes, CryptoStore, MemoryStore},
+    store::{BackupDecryptionKey, Changes, CryptoStore, MemoryStore, RoomSettings},
     types::{
         events::{
             room::encrypted::{EncryptedToDeviceEvent, ToDeviceEncryptedEventContent},
@@ -70,7 +73,7 @@ use crate::{
     },
     utilities::json_convert,
     verification::tests::bob_id,
-    Account, DecryptionSettings, DeviceData, EncryptionSettings, MegolmError, OlmError,
+    Account, DecryptionSettings, DeviceData, EncryptionSettings, LocalTrust, MegolmError, OlmError,
     RoomEventDecryptionResult, TrustRequirement,
 };
 
@@ -434,6 +437,175 @@ async fn test_room_key_sharing() {
     assert_eq!(room_key_updates[0].session_id, alice_session.session_id());
 }
 
+#[async_test]
+async fn test_room_key_history_sharing() {
+    let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+    let room_id = room_id!("!test:example.org");
+
+    // Alice creates a room key while the history of the room is shared, and a
+    // second one once it isn't anymore.
+    alice.share_room_key(room_id, iter::empty(), EncryptionSettings::default()).await.unwrap();
+    let shared_session =
+        alice.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+    assert!(alice.discard_room_key(room_id).await.unwrap());
+
+    let settings =
+        EncryptionSettings { history_visibility: HistoryVisibility::Joined, ..Default::default() };
+    alice.share_room_key(room_id, iter::empty(), settings).await.unwrap();
+    let private_session =
+        alice.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+
+    // Bob has verified the device of Alice.
+    bob.get_device(alice.user_id(), alice.device_id(), None)
+        .await
+        .unwrap()
+        .unwrap()
+        .set_local_trust(LocalTrust::Verified)
+        .await
+        .unwrap();
+
+    // Then she invites Bob and shares the history of the room with him, and he
+    // accepts the invite.
+    share_room_key_history_test_helper(&alice, &bob, room_id, alice.user_id()).await;
+
+    // Bob accepted the room key even though he never requested it, but only
+    // received the one that was created while the history was shared.
+    let session = bob
+        .store()
+        .get_inbound_group_session(room_id, shared_session.session_id())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(session.shared_history());
+
+    // Alice created the session herself, with a device that isn't cross-signed.
+    assert_eq!(session.sender_data_type(), SenderDataType::DeviceInfo);
+
+    let session =
+        bob.store().get_inbound_group_session(room_id, private_session.session_id()).await;
+    assert!(session.unwrap().is_none());
+}
+
+#[async_test]
+async fn test_room_key_history_from_unverified_device() {
+    let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+    let room_id = room_id!("!test:example.org");
+
+    alice.share_room_key(room_id, iter::empty(), EncryptionSettings::default()).await.unwrap();
+    let shared_session =
+        alice.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+
+    // Bob didn't verify the device of Alice, so he rejects the room key.
+    share_room_key_history_test_helper(&alice, &bob, room_id, alice.user_id()).await;
+
+    let session = bob.store().get_inbound_group_session(room_id, shared_session.session_id()).await;
+    assert!(session.unwrap().is_none());
+}
+
+#[async_test]
+async fn test_room_key_history_from_other_user_than_inviter() {
+    let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+    let room_id = room_id!("!test:example.org");
+
+    alice.share_room_key(room_id, iter::empty(), EncryptionSettings::default()).await.unwrap();
+    let shared_session =
+        alice.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+
+    bob.get_device(alice.user_id(), alice.device_id(), None)
+        .await
+        .unwrap()
+        .unwrap()
+        .set_local_trust(LocalTrust::Verified)
+        .await
+        .unwrap();
+
+    // Carol invited Bob instead of Alice, so he can't know whether Alice
+    // is a member of the room and discards the room key.
+    share_room_key_history_test_helper(&alice, &bob, room_id, user_id!("@carol:example.org")).await;
+
+    let session = bob.store().get_inbound_group_session(room_id, shared_session.session_id()).await;
+    assert!(session.unwrap().is_none());
+
+    // The room key isn't held anymore.
+    bob.accept_room_key_history(room_id, alice.user_id()).await.unwrap();
+    let session = bob.store().get_inbound_group_session(room_id, shared_session.session_id()).await;
+    assert!(session.unwrap().is_none());
+}
+
+#[async_test]
+async fn test_room_key_history_with_untrusted_devices() {
+    let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+    let room_id = room_id!("!test:example.org");
+
+    alice.share_room_key(room_id, iter::empty(), EncryptionSettings::default()).await.unwrap();
+
+    // The device of Bob isn't verified, so the history isn't shared with it if
+    // the sharing strategy only allows trusted devices.
+    let strategy = CollectStrategy::DeviceBasedStrategy {
+        only_allow_trusted_devices: true,
+        error_on_verified_user_problem: false,
+    };
+    let requests = alice.share_room_key_history(room_id, bob.user_id(), &strategy).await.unwrap();
+    assert!(requests.is_empty());
+
+    let requests = alice
+        .share_room_key_history(room_id, bob.user_id(), &CollectStrategy::new_identity_based())
+        .await
+        .unwrap();
+    assert!(requests.is_empty());
+
+    // Nor if the room only allows trusted devices.
+    alice
+        .set_room_settings(
+            room_id,
+            &RoomSettings { only_allow_trusted_devices: true, ..Default::default() },
+        )
+        .await
+        .unwrap();
+    let requests = alice
+        .share_room_key_history(room_id, bob.user_id(), &CollectStrategy::default())
+        .await
+        .unwrap();
+    assert!(requests.is_empty());
+}
+
+/// Share the history of the room from Alice with Bob, and let Bob accept it
+/// once he accepted the invite of `inviter`.
+async fn share_room_key_history_test_helper(
+    alice: &OlmMachine,
+    bob: &OlmMachine,
+    room_id: &RoomId,
+    inviter: &UserId,
+) {
+    let requests = alice
+        .share_room_key_history(room_id, bob.user_id(), &CollectStrategy::default())
+        .await
+        .unwrap();
+    assert_eq!(requests.len(), 1);
+
+    let event = ToDeviceEvent::new(
+        alice.user_id().to_owned(),
+        to_device_requests_to_content(requests.into_iter().map(Arc::new).collect()),
+    );
+    let event = json_convert(&event).unwrap();
+
+    let (_, room_key_updates) = bob
+        .receive_sync_changes(EncryptionSyncChanges {
+            to_device_events: vec![event],
+            changed_devices: &Default::default(),
+            one_time_keys_counts: &Default::default(),
+            unused_fallback_keys: None,
+            next_batch_token: None,
+        })
+        .await
+        .unwrap();
+
+    // The room key is held until Bob accepts the invite.
+    assert!(room_key_updates.is_empty());
+
+    bob.accept_room_key_history(room_id, inviter).await.unwrap();
+}
+
 #[async_test]
 async fn test_request_missing_secrets() {
     let (alice, _) = get_machine_pair_with_session(alice_id(), bob_id(), false).await;
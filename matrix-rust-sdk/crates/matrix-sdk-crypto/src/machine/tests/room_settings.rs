@@ -5,8 +5,10 @@ use matrix_sdk_test::async_test;
 use ruma::room_id;
 
 use crate::{
-    machine::tests, store::RoomSettings, types::EventEncryptionAlgorithm, OlmMachine,
-    SetRoomSettingsError,
+    machine::tests,
+    store::{RoomKeyRotationPolicy, RoomSettings},
+    types::EventEncryptionAlgorithm,
+    OlmMachine, SetRoomSettingsError,
 };
 
 #[async_test]
@@ -99,3 +101,96 @@ async fn test_set_room_settings_accepts_noop_changes() {
         .await
         .unwrap();
 }
+
+#[async_test]
+async fn test_set_room_settings_rejects_changes_from_default_settings() {
+    let machine = OlmMachine::new(tests::user_id(), tests::alice_device_id()).await;
+    let room_id = room_id!("!test:localhost");
+
+    // Initial settings, which are the default ones.
+    machine.set_room_settings(room_id, &RoomSettings::default()).await.unwrap();
+
+    // Now, modifying the settings should be rejected, even with a rotation
+    // policy set for the room.
+    machine
+        .set_room_key_rotation_policy(
+            room_id,
+            RoomKeyRotationPolicy { rotate_on_new_recipients: true, ..Default::default() },
+        )
+        .await
+        .unwrap();
+
+    let err = machine
+        .set_room_settings(
+            room_id,
+            &RoomSettings { session_rotation_period_messages: Some(1000), ..Default::default() },
+        )
+        .await
+        .unwrap_err();
+
+    assert_matches!(err, SetRoomSettingsError::EncryptionDowngrade);
+    assert_eq!(machine.room_settings(room_id).await.unwrap(), Some(RoomSettings::default()));
+}
+
+#[async_test]
+async fn test_rotation_policy_is_independent_from_room_settings() {
+    let machine = OlmMachine::new(tests::user_id(), tests::alice_device_id()).await;
+    let room_id = room_id!("!test:localhost");
+
+    let policy = RoomKeyRotationPolicy {
+        rotation_period: Some(Duration::from_secs(60 * 60)),
+        rotation_period_msgs: Some(10),
+        rotate_on_new_recipients: true,
+    };
+
+    // There's no policy by default.
+    assert_eq!(
+        machine.room_key_rotation_policy(room_id).await.unwrap(),
+        RoomKeyRotationPolicy::default()
+    );
+
+    // Setting a policy doesn't create any room settings, which can still be set
+    // later.
+    machine.set_room_key_rotation_policy(room_id, policy.clone()).await.unwrap();
+    assert!(machine.room_settings(room_id).await.unwrap().is_none());
+
+    machine
+        .set_room_settings(
+            room_id,
+            &RoomSettings { session_rotation_period_messages: Some(100), ..Default::default() },
+        )
+        .await
+        .unwrap();
+    assert_eq!(machine.room_key_rotation_policy(room_id).await.unwrap(), policy);
+
+    // And the policy can be changed at any time.
+    machine.set_room_key_rotation_policy(room_id, RoomKeyRotationPolicy::default()).await.unwrap();
+
+    let settings = machine.room_settings(room_id).await.unwrap().unwrap();
+    assert_eq!(settings.session_rotation_period_messages, Some(100));
+    assert_eq!(
+        machine.room_key_rotation_policy(room_id).await.unwrap(),
+        RoomKeyRotationPolicy::default()
+    );
+}
+
+#[async_test]
+async fn test_rotation_policy_with_zero_period_is_rejected() {
+    let machine = OlmMachine::new(tests::user_id(), tests::alice_device_id()).await;
+    let room_id = room_id!("!test:localhost");
+
+    let policy = RoomKeyRotationPolicy { rotation_period_msgs: Some(0), ..Default::default() };
+    let result = machine.set_room_key_rotation_policy(room_id, policy).await;
+    assert_matches!(result, Err(SetRoomSettingsError::InvalidSettings));
+
+    let policy =
+        RoomKeyRotationPolicy { rotation_period: Some(Duration::ZERO), ..Default::default() };
+    let result = machine.set_room_key_rotation_policy(room_id, policy).await;
+    assert_matches!(result, Err(SetRoomSettingsError::InvalidSettings));
+
+    // Nothing was saved.
+    assert_eq!(
+        machine.room_key_rotation_policy(room_id).await.unwrap(),
+        RoomKeyRotationPolicy::default()
+    );
+}
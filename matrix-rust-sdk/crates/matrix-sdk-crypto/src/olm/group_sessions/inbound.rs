@@ -151,6 +151,12 @@ pub struct InboundGroupSession {
     /// created.
     history_visibility: Arc<Option<HistoryVisibility>>,
 
+    /// Can this room key be shared with the users who are invited to the room
+    /// later on? See [MSC3061].
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    shared_history: bool,
+
     /// Was this room key backed up to the server.
     backed_up: Arc<AtomicBool>,
 }
@@ -193,9 +199,12 @@ impl InboundGroupSession {
         let mut keys = SigningKeys::new();
         keys.insert(DeviceKeyAlgorithm::Ed25519, signing_key.into());
 
+        let shared_history = history_visibility.as_ref().is_some_and(is_history_shareable);
+
         Ok(InboundGroupSession {
             inner: Arc::new(Mutex::new(session)),
             history_visibility: history_visibility.into(),
+            shared_history,
             session_id: session_id.into(),
             first_known_index,
             creator_info: SessionCreatorInfo {
@@ -239,6 +248,7 @@ impl InboundGroupSession {
             forwarding_curve25519_key_chain: vec![],
             session_key: backup.session_key,
             sender_claimed_keys: backup.sender_claimed_keys,
+            shared_history: false,
         })
     }
 
@@ -260,6 +270,7 @@ impl InboundGroupSession {
             imported: self.imported,
             backed_up: self.backed_up(),
             history_visibility: self.history_visibility.as_ref().clone(),
+            shared_history: self.shared_history,
             algorithm: (*self.algorithm).to_owned(),
         }
     }
@@ -313,6 +324,7 @@ impl InboundGroupSession {
             forwarding_curve25519_key_chain: vec![],
             sender_claimed_keys: (*self.creator_info.signing_keys).clone(),
             session_key,
+            shared_history: self.shared_history,
         }
     }
 
@@ -341,6 +353,7 @@ impl InboundGroupSession {
             },
             sender_data: pickle.sender_data,
             history_visibility: pickle.history_visibility.into(),
+            shared_history: pickle.shared_history,
             first_known_index,
             room_id: (*pickle.room_id).into(),
             backed_up: AtomicBool::from(pickle.backed_up).into(),
@@ -376,6 +389,21 @@ impl InboundGroupSession {
         self.imported
     }
 
+    /// Can this session be shared with the users who are invited to the room
+    /// later on?
+    ///
+    /// This is the case if the history of the room was visible to new members
+    /// when the session was created.
+    pub fn shared_history(&self) -> bool {
+        self.shared_history
+    }
+
+    /// Set whether this session can be shared with the users who are invited
+    /// to the room later on, as told by the sender of the room key.
+    pub(crate) fn set_shared_history(&mut self, shared_history: bool) {
+        self.shared_history = shared_history;
+    }
+
     /// Check if the [`InboundGroupSession`] is better than the given other
     /// [`InboundGroupSession`]
     pub async fn compare(&self, other: &InboundGroupSession) -> SessionOrdering {
@@ -537,6 +565,10 @@ pub struct PickledInboundGroupSession {
     pub backed_up: bool,
     /// History visibility of the room when the session was created.
     pub history_visibility: Option<HistoryVisibility>,
+    /// Flag remembering if the session can be shared with the users who are
+    /// invited to the room later on.
+    #[serde(default)]
+    pub shared_history: bool,
     /// The algorithm of this inbound group session.
     #[serde(default = "default_algorithm")]
     pub algorithm: EventEncryptionAlgorithm,
@@ -546,6 +578,12 @@ fn default_algorithm() -> EventEncryptionAlgorithm {
     EventEncryptionAlgorithm::MegolmV1AesSha2
 }
 
+/// Can the room keys created with the given history visibility be shared with
+/// the users who are invited to the room later on?
+pub(super) fn is_history_shareable(history_visibility: &HistoryVisibility) -> bool {
+    matches!(history_visibility, HistoryVisibility::Shared | HistoryVisibility::WorldReadable)
+}
+
 impl TryFrom<&ExportedRoomKey> for InboundGroupSession {
     type Error = SessionCreationError;
 
@@ -565,6 +603,7 @@ impl TryFrom<&ExportedRoomKey> for InboundGroupSession {
             // See https://github.com/matrix-org/matrix-rust-sdk/issues/3548
             sender_data: SenderData::default(),
             history_visibility: None.into(),
+            shared_history: key.shared_history,
             first_known_index,
             room_id: key.room_id.to_owned(),
             imported: true,
@@ -595,6 +634,7 @@ impl From<&ForwardedMegolmV1AesSha2Content> for InboundGroupSession {
             // See https://github.com/matrix-org/matrix-rust-sdk/issues/3548
             sender_data: SenderData::default(),
             history_visibility: None.into(),
+            shared_history: value.shared_history,
             first_known_index,
             room_id: value.room_id.to_owned(),
             imported: true,
@@ -621,6 +661,7 @@ impl From<&ForwardedMegolmV2AesSha2Content> for InboundGroupSession {
             // See https://github.com/matrix-org/matrix-rust-sdk/issues/3548
             sender_data: SenderData::default(),
             history_visibility: None.into(),
+            shared_history: value.shared_history,
             first_known_index,
             room_id: value.room_id.to_owned(),
             imported: true,
@@ -789,6 +830,7 @@ mod tests {
                 "imported":false,
                 "backed_up":false,
                 "history_visibility":"shared",
+                "shared_history":true,
                 "algorithm":"m.megolm.v1.aes-sha2"
             })
         );
@@ -857,6 +899,39 @@ mod tests {
         assert!(!owner_check_failed);
     }
 
+    #[async_test]
+    async fn test_shared_history_follows_history_visibility() {
+        let new_session = |history_visibility| {
+            InboundGroupSession::new(
+                Curve25519PublicKey::from_base64("AmM1DvVJarsNNXVuX7OarzfT481N37GtDwvDVF0RcR8")
+                    .unwrap(),
+                Ed25519PublicKey::from_base64("wTRTdz4rn4EY+68cKPzpMdQ6RAlg7T8cbTmEjaXuUww")
+                    .unwrap(),
+                room_id!("!test:localhost"),
+                &create_session_key(),
+                SenderData::unknown(),
+                EventEncryptionAlgorithm::MegolmV1AesSha2,
+                history_visibility,
+            )
+            .unwrap()
+        };
+
+        assert!(new_session(Some(HistoryVisibility::Shared)).shared_history());
+        assert!(new_session(Some(HistoryVisibility::WorldReadable)).shared_history());
+        assert!(!new_session(Some(HistoryVisibility::Joined)).shared_history());
+        assert!(!new_session(Some(HistoryVisibility::Invited)).shared_history());
+        assert!(!new_session(None).shared_history());
+
+        // The flag survives a pickle roundtrip and an export.
+        let session = new_session(Some(HistoryVisibility::Shared));
+        let unpickled = InboundGroupSession::from_pickle(session.pickle().await).unwrap();
+        assert!(unpickled.shared_history());
+
+        let export = session.export().await;
+        assert!(export.shared_history);
+        assert!(InboundGroupSession::from_export(&export).unwrap().shared_history());
+    }
+
     #[async_test]
     async fn test_session_comparison() {
         let alice = Account::with_device_id(alice_id(), alice_device_id());
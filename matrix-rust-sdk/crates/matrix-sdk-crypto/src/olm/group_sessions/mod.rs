@@ -98,6 +98,18 @@ pub struct ExportedRoomKey {
         serialize_with = "serialize_curve_key_vec"
     )]
     pub forwarding_curve25519_key_chain: Vec<Curve25519PublicKey>,
+
+    /// Whether the key can be shared with the users who are invited to the
+    /// room later on, as defined in [MSC3061].
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    #[serde(
+        default,
+        rename = "org.matrix.msc3061.shared_history",
+        alias = "shared_history",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub shared_history: bool,
 }
 
 impl ExportedRoomKey {
@@ -117,6 +129,7 @@ impl ExportedRoomKey {
             session_key: room_key.session_key,
             sender_claimed_keys: room_key.sender_claimed_keys,
             forwarding_curve25519_key_chain: room_key.forwarding_curve25519_key_chain,
+            shared_history: false,
         }
     }
 }
@@ -180,6 +193,7 @@ impl TryFrom<ExportedRoomKey> for ForwardedRoomKeyContent {
                             forwarding_curve25519_key_chain: room_key
                                 .forwarding_curve25519_key_chain
                                 .clone(),
+                            shared_history: room_key.shared_history,
                             other: Default::default(),
                         }
                         .into(),
@@ -197,6 +211,7 @@ impl TryFrom<ExportedRoomKey> for ForwardedRoomKeyContent {
                         session_key: room_key.session_key,
                         claimed_sender_key: room_key.sender_key,
                         claimed_signing_keys: room_key.sender_claimed_keys,
+                        shared_history: room_key.shared_history,
                         other: Default::default(),
                     }
                     .into(),
@@ -240,6 +255,7 @@ impl TryFrom<ForwardedRoomKeyContent> for ExportedRoomKey {
                     sender_claimed_keys,
                     sender_key: content.claimed_sender_key,
                     session_key: content.session_key,
+                    shared_history: content.shared_history,
                 })
             }
             #[cfg(feature = "experimental-algorithms")]
@@ -251,6 +267,7 @@ impl TryFrom<ForwardedRoomKeyContent> for ExportedRoomKey {
                 sender_claimed_keys: content.claimed_signing_keys,
                 sender_key: content.claimed_sender_key,
                 session_key: content.session_key,
+                shared_history: content.shared_history,
             }),
             ForwardedRoomKeyContent::Unknown(c) => Err(SessionExportError::Algorithm(c.algorithm)),
         }
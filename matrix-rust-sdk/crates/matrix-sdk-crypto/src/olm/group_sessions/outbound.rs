@@ -43,7 +43,7 @@ pub use vodozemac::{
     PickleError,
 };
 
-use super::SessionCreationError;
+use super::{inbound::is_history_shareable, SessionCreationError};
 #[cfg(feature = "experimental-algorithms")]
 use crate::types::events::room::encrypted::MegolmV2AesSha2Content;
 use crate::{
@@ -102,6 +102,18 @@ pub struct EncryptionSettings {
     /// Default will send to all devices.
     #[serde(default)]
     pub sharing_strategy: CollectStrategy,
+    /// Devices that receive the room key as if their local trust was
+    /// [`LocalTrust::Ignored`], without changing it.
+    ///
+    /// This only applies to the sharing of the room key these settings are
+    /// used for, so it isn't persisted with the session. It allows sending a
+    /// single message to the unsigned devices of verified users, despite the
+    /// `error_on_verified_user_problem` option of
+    /// [`CollectStrategy::DeviceBasedStrategy`].
+    ///
+    /// [`LocalTrust::Ignored`]: crate::LocalTrust::Ignored
+    #[serde(skip)]
+    pub ignored_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
 }
 
 impl Default for EncryptionSettings {
@@ -112,6 +124,7 @@ impl Default for EncryptionSettings {
             rotation_period_msgs: ROTATION_MESSAGES,
             history_visibility: HistoryVisibility::Shared,
             sharing_strategy: CollectStrategy::default(),
+            ignored_devices: BTreeMap::new(),
         }
     }
 }
@@ -135,6 +148,7 @@ impl EncryptionSettings {
             rotation_period_msgs,
             history_visibility,
             sharing_strategy,
+            ignored_devices: BTreeMap::new(),
         }
     }
 }
@@ -434,27 +448,27 @@ impl OutboundGroupSession {
         Raw::new(&content).expect("m.room.encrypted event content can always be serialized")
     }
 
-    fn elapsed(&self) -> bool {
+    fn elapsed(&self, settings: &EncryptionSettings) -> bool {
         let creation_time = Duration::from_secs(self.creation_time.get().into());
         let now = Duration::from_secs(SecondsSinceUnixEpoch::now().get().into());
         now.checked_sub(creation_time)
-            .map(|elapsed| elapsed >= self.safe_rotation_period())
+            .map(|elapsed| elapsed >= Self::safe_rotation_period(settings))
             .unwrap_or(true)
     }
 
-    /// Returns the rotation_period_ms that was set for this session, clamped
-    /// to be no less than one hour.
+    /// Returns the rotation_period_ms of the given settings, clamped to be no
+    /// less than one hour.
     ///
     /// This is to prevent a malicious or careless user causing sessions to be
     /// rotated very frequently.
     ///
     /// The feature flag `_disable-minimum-rotation-period-ms` can
     /// be used to prevent this behaviour (which can be useful for tests).
-    fn safe_rotation_period(&self) -> Duration {
+    fn safe_rotation_period(settings: &EncryptionSettings) -> Duration {
         if cfg!(feature = "_disable-minimum-rotation-period-ms") {
-            self.settings.rotation_period
+            settings.rotation_period
         } else {
-            max(self.settings.rotation_period, ONE_HOUR)
+            max(settings.rotation_period, ONE_HOUR)
         }
     }
 
@@ -463,15 +477,26 @@ impl OutboundGroupSession {
     /// A session will expire after some time or if enough messages have been
     /// encrypted using it.
     pub fn expired(&self) -> bool {
+        self.expired_with(&self.settings)
+    }
+
+    /// Check if the session has expired according to the rotation periods of
+    /// the given settings, instead of the ones it was created with.
+    ///
+    /// This is useful to know if a session should be rotated because of a
+    /// stricter local [`RoomKeyRotationPolicy`].
+    ///
+    /// [`RoomKeyRotationPolicy`]: crate::store::RoomKeyRotationPolicy
+    pub fn expired_with(&self, settings: &EncryptionSettings) -> bool {
         let count = self.message_count.load(Ordering::SeqCst);
         // We clamp the rotation period for message counts to be between 1 and
         // 10000. The Megolm session should be usable for at least 1 message,
         // and at most 10000 messages. Realistically Megolm uses u32 for it's
         // internal counter and one could use the Megolm session for up to
         // u32::MAX messages, but we're staying on the safe side of things.
-        let rotation_period_msgs = self.settings.rotation_period_msgs.clamp(1, 10_000);
+        let rotation_period_msgs = settings.rotation_period_msgs.clamp(1, 10_000);
 
-        count >= rotation_period_msgs || self.elapsed()
+        count >= rotation_period_msgs || self.elapsed(settings)
     }
 
     /// Has the session been invalidated.
@@ -524,17 +549,40 @@ impl OutboundGroupSession {
         session.message_index()
     }
 
+    /// Get the time at which this session was created.
+    pub fn creation_time(&self) -> SecondsSinceUnixEpoch {
+        self.creation_time
+    }
+
+    /// Get the number of messages that were encrypted with this session.
+    pub fn message_count(&self) -> u64 {
+        self.message_count.load(Ordering::SeqCst)
+    }
+
+    /// Get the devices this session was shared with, grouped by user.
+    ///
+    /// This doesn't include the devices for which the requests sharing the
+    /// session haven't been sent yet.
+    pub fn shared_with(&self) -> BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>> {
+        self.shared_with_set
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(user_id, devices)| (user_id.clone(), devices.keys().cloned().collect()))
+            .collect()
+    }
+
     pub(crate) async fn as_content(&self) -> RoomKeyContent {
         let session_key = self.session_key().await;
 
-        RoomKeyContent::MegolmV1AesSha2(
-            MegolmV1AesSha2RoomKeyContent::new(
-                self.room_id().to_owned(),
-                self.session_id().to_owned(),
-                session_key,
-            )
-            .into(),
-        )
+        let mut content = MegolmV1AesSha2RoomKeyContent::new(
+            self.room_id().to_owned(),
+            self.session_id().to_owned(),
+            session_key,
+        );
+        content.shared_history = is_history_shareable(&self.settings.history_visibility);
+
+        RoomKeyContent::MegolmV1AesSha2(content.into())
     }
 
     /// Has or will the session be shared with the given user/device pair.
@@ -1013,6 +1061,21 @@ mod tests {
             assert!(session.expired());
         }
 
+        #[async_test]
+        async fn test_session_exposes_its_usage() {
+            let session = create_session(EncryptionSettings::default()).await;
+            assert_eq!(session.message_count(), 0);
+            assert!(session.shared_with().is_empty());
+
+            let user_id = user_id!("@bob:example.org");
+            session.mark_shared_with(user_id, device_id!("BOBDEVICE"), session.sender_key()).await;
+            session.message_count.store(3, Ordering::SeqCst);
+
+            assert_eq!(session.message_count(), 3);
+            assert_eq!(session.shared_with()[user_id].len(), 1);
+            assert!(session.creation_time() <= SecondsSinceUnixEpoch::now());
+        }
+
         async fn create_session(settings: EncryptionSettings) -> OutboundGroupSession {
             let account =
                 Account::with_device_id(user_id!("@alice:example.org"), device_id!("DEVICEID"))
@@ -40,7 +40,7 @@ use crate::{
         InboundGroupSession, OutboundGroupSession, SenderData, SenderDataFinder, Session,
         ShareInfo, ShareState,
     },
-    store::{Changes, CryptoStoreWrapper, Result as StoreResult, Store},
+    store::{Changes, CryptoStoreWrapper, Result as StoreResult, RoomKeyRotationPolicy, Store},
     types::{events::room::encrypted::RoomEncryptedEventContent, requests::ToDeviceRequest},
     Device, DeviceData, EncryptionSettings, OlmError,
 };
@@ -372,6 +372,47 @@ impl GroupSessionManager {
         self.sessions.clone()
     }
 
+    /// Check if the local rotation policy of a room requires the given
+    /// outbound session to be rotated before it gets shared with the given
+    /// devices.
+    fn rotation_policy_requires_rotation(
+        policy: &RoomKeyRotationPolicy,
+        encryption_settings: &EncryptionSettings,
+        outbound: &OutboundGroupSession,
+        devices: &BTreeMap<OwnedUserId, Vec<DeviceData>>,
+    ) -> bool {
+        if outbound.expired_with(encryption_settings) {
+            debug!(
+                session_id = outbound.session_id(),
+                "The room key has expired according to the local rotation policy"
+            );
+
+            return true;
+        }
+
+        // A session that was never shared with anyone can safely be sent to
+        // newcomers. Devices to which we couldn't send the session, because we
+        // have no Olm session with them, don't count as new recipients either,
+        // otherwise we would keep on rotating the session for them.
+        let has_new_recipients = outbound.shared()
+            && devices.values().flatten().any(|device| {
+                outbound.is_shared_with(device) == ShareState::NotShared
+                    && !device.was_withheld_code_sent()
+            });
+
+        if policy.rotate_on_new_recipients && has_new_recipients {
+            debug!(
+                session_id = outbound.session_id(),
+                "New devices need to receive the room key, and the local rotation policy asks \
+                 for a rotation in this case"
+            );
+
+            true
+        } else {
+            false
+        }
+    }
+
     async fn maybe_rotate_group_session(
         &self,
         should_rotate: bool,
@@ -411,8 +452,8 @@ impl GroupSessionManager {
                 old_session_id = old_session_id,
                 session_id = outbound.session_id(),
                 "A user or device has left the room since we last sent a \
-                message, or the encryption settings have changed. Rotating the \
-                room key.",
+                message, the encryption settings have changed, or the local \
+                rotation policy requires it. Rotating the room key.",
             );
 
             outbound
@@ -648,7 +689,13 @@ impl GroupSessionManager {
         let account = self.store.static_account();
         let device = self.store.get_device(account.user_id(), account.device_id()).await?;
 
-        let encryption_settings = encryption_settings.into();
+        // Apply the local rotation policy of the room, if any, on top of the
+        // room's own encryption settings.
+        let rotation_policy = self.store.get_room_key_rotation_policy(room_id).await?;
+
+        let mut encryption_settings = encryption_settings.into();
+        rotation_policy.apply(&mut encryption_settings);
+
         let mut changes = Changes::default();
 
         // Try to get an existing session or create a new one.
@@ -690,6 +737,14 @@ impl GroupSessionManager {
         let CollectRecipientsResult { should_rotate, devices, mut withheld_devices } =
             self.collect_session_recipients(users, &encryption_settings, &outbound).await?;
 
+        let should_rotate = should_rotate
+            || Self::rotation_policy_requires_rotation(
+                &rotation_policy,
+                &encryption_settings,
+                &outbound,
+                &devices,
+            );
+
         let outbound = self
             .maybe_rotate_group_session(
                 should_rotate,
@@ -789,6 +844,7 @@ mod tests {
         device_id,
         events::room::history_visibility::HistoryVisibility,
         room_id,
+        serde::Raw,
         to_device::DeviceIdOrAllDevices,
         user_id, DeviceId, OneTimeKeyAlgorithm, TransactionId, UInt, UserId,
     };
@@ -799,6 +855,7 @@ mod tests {
         machine::EncryptionSyncChanges,
         olm::{Account, SenderData},
         session_manager::{group_sessions::CollectRecipientsResult, CollectStrategy},
+        store::RoomKeyRotationPolicy,
         types::{
             events::{
                 room::encrypted::EncryptedToDeviceEvent,
@@ -1073,6 +1130,82 @@ mod tests {
         assert!(!outbound.pending_requests().is_empty());
     }
 
+    #[async_test]
+    async fn test_rotation_policy_rotates_on_new_recipients() {
+        let machine = machine_with_shared_room_key_test_helper().await;
+
+        let room_id = room_id!("!test:localhost");
+        let late_joiner = user_id!("@bob:localhost");
+        let keys_claim = keys_claim_response();
+
+        let policy = RoomKeyRotationPolicy { rotate_on_new_recipients: true, ..Default::default() };
+        machine.set_room_key_rotation_policy(room_id, policy).await.unwrap();
+
+        let first_session =
+            machine.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+
+        // Sharing the room key with the same users doesn't rotate it.
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        let requests =
+            machine.share_room_key(room_id, users, EncryptionSettings::default()).await.unwrap();
+        assert!(requests.is_empty());
+
+        let outbound =
+            machine.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+        assert_eq!(outbound.session_id(), first_session.session_id());
+
+        // But a late joiner gets a new room key, which is shared with everyone.
+        let mut users: BTreeSet<_> = keys_claim.one_time_keys.keys().map(Deref::deref).collect();
+        users.insert(late_joiner);
+
+        let requests = machine
+            .share_room_key(room_id, users.into_iter(), EncryptionSettings::default())
+            .await
+            .unwrap();
+
+        let event_count: usize = requests
+            .iter()
+            .filter(|r| r.event_type == "m.room.encrypted".into())
+            .map(|r| r.message_count())
+            .sum();
+        let outbound =
+            machine.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+
+        assert_ne!(outbound.session_id(), first_session.session_id());
+        assert_eq!(event_count, 149);
+    }
+
+    #[async_test]
+    async fn test_rotation_policy_is_stricter_than_the_room_settings() {
+        let machine = machine_with_shared_room_key_test_helper().await;
+
+        let room_id = room_id!("!test:localhost");
+        let keys_claim = keys_claim_response();
+        let content = Raw::new(&json!({"body": "Hello", "msgtype": "m.text"})).unwrap().cast();
+
+        let policy = RoomKeyRotationPolicy { rotation_period_msgs: Some(1), ..Default::default() };
+        machine.set_room_key_rotation_policy(room_id, policy).await.unwrap();
+
+        let first_session =
+            machine.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+        machine.encrypt_room_event_raw(room_id, "m.room.message", &content).await.unwrap();
+
+        // The room settings allow 100 messages per room key, but the policy
+        // only allows one.
+        assert!(!first_session.expired());
+        let mut settings = EncryptionSettings::default();
+        machine.room_key_rotation_policy(room_id).await.unwrap().apply(&mut settings);
+        assert!(first_session.expired_with(&settings));
+
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        machine.share_room_key(room_id, users, EncryptionSettings::default()).await.unwrap();
+
+        let outbound =
+            machine.inner.group_session_manager.get_outbound_group_session(room_id).unwrap();
+        assert_ne!(outbound.session_id(), first_session.session_id());
+        assert_eq!(outbound.settings().rotation_period_msgs, 1);
+    }
+
     #[async_test]
     async fn test_changing_encryption_settings() {
         let machine = machine_with_shared_room_key_test_helper().await;
@@ -185,6 +185,7 @@ pub(crate) async fn collect_session_recipients(
                     user_devices,
                     &own_identity,
                     &device_owner_identity,
+                    settings.ignored_devices.get(user_id).map(Vec::as_slice).unwrap_or_default(),
                     only_allow_trusted_devices,
                     error_on_verified_user_problem,
                 );
@@ -395,10 +396,14 @@ struct DeviceBasedRecipientDevices {
 ///    the transmission to fail due to being unsigned. (If
 ///    `error_on_verified_user_problem` is unset, these devices are otherwise
 ///    partitioned into `allowed_devices`.)
+///
+/// The `ignored_devices` are treated as if their local trust was
+/// [`LocalTrust::Ignored`].
 fn split_devices_for_user(
     user_devices: HashMap<OwnedDeviceId, DeviceData>,
     own_identity: &Option<OwnUserIdentityData>,
     device_owner_identity: &Option<UserIdentityData>,
+    ignored_devices: &[OwnedDeviceId],
     only_allow_trusted_devices: bool,
     error_on_verified_user_problem: bool,
 ) -> DeviceBasedRecipientDevices {
@@ -406,7 +411,9 @@ fn split_devices_for_user(
     for d in user_devices.into_values() {
         if d.is_blacklisted() {
             recipient_devices.denied_devices_with_code.push((d, WithheldCode::Blacklisted));
-        } else if d.local_trust_state() == LocalTrust::Ignored {
+        } else if d.local_trust_state() == LocalTrust::Ignored
+            || ignored_devices.iter().any(|device_id| device_id == d.device_id())
+        {
             // Ignore the trust state of that device and share
             recipient_devices.allowed_devices.push(d);
         } else if only_allow_trusted_devices && !d.is_verified(own_identity, device_owner_identity)
@@ -799,6 +806,63 @@ mod tests {
         assert_eq!(0, share_result.withheld_devices.len());
     }
 
+    /// Test that we can share a key with the unsigned device, despite
+    /// `error_on_verified_user_problem`, by ignoring it in the encryption
+    /// settings, without changing its local trust.
+    #[async_test]
+    async fn test_error_on_unsigned_of_verified_resolve_by_ignoring_in_settings() {
+        use VerificationViolationTestData as DataSet;
+
+        let machine = unsigned_of_verified_setup().await;
+
+        let encryption_settings = EncryptionSettings {
+            ignored_devices: BTreeMap::from([(
+                DataSet::bob_id().to_owned(),
+                vec![DataSet::bob_device_2_id().to_owned()],
+            )]),
+            ..error_on_verification_problem_encryption_settings()
+        };
+        let group_session = create_test_outbound_group_session(&machine, &encryption_settings);
+
+        // We should be able to share a key, and it should include the unsigned device.
+        let share_result = collect_session_recipients(
+            machine.store(),
+            iter::once(DataSet::bob_id()),
+            &encryption_settings,
+            &group_session,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(2, share_result.devices.get(DataSet::bob_id()).unwrap().len());
+        assert_eq!(0, share_result.withheld_devices.len());
+
+        // The local trust of the device didn't change.
+        let device = machine
+            .get_device(DataSet::bob_id(), DataSet::bob_device_2_id(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(device.local_trust_state(), LocalTrust::Unset);
+
+        // So without the override, sharing fails again.
+        let encryption_settings = error_on_verification_problem_encryption_settings();
+        let share_result = collect_session_recipients(
+            machine.store(),
+            iter::once(DataSet::bob_id()),
+            &encryption_settings,
+            &group_session,
+        )
+        .await;
+
+        assert_matches!(
+            share_result,
+            Err(OlmError::SessionRecipientCollectionError(
+                SessionRecipientCollectionError::VerifiedUserHasUnsignedDevice(_)
+            ))
+        );
+    }
+
     /// Test that we can resolve errors from
     /// `error_on_verified_user_problem` by blacklisting the
     /// device.
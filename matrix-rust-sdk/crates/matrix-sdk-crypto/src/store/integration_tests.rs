@@ -237,6 +237,29 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(&session, &loaded_session, "The loaded session should be the same one we put into the store.");
             }
 
+            #[async_test]
+            async fn test_load_all_sessions() {
+                let store = get_store("load_all_sessions", None, true).await;
+                let (account, session) = get_account_and_session().await;
+                store
+                    .save_pending_changes(PendingChanges { account: Some(account.deep_clone()) })
+                    .await
+                    .expect("Can't save account");
+
+                assert!(store.get_all_sessions().await.unwrap().is_empty());
+
+                let changes = Changes {
+                    sessions: vec![session.clone()],
+                    devices: DeviceChanges { new: vec![DeviceData::from_account(&account)], ..Default::default() },
+                    ..Default::default()
+                };
+
+                store.save_changes(changes).await.unwrap();
+
+                let sessions = store.get_all_sessions().await.expect("Can't load all the sessions");
+                assert_eq!(sessions, vec![session]);
+            }
+
             #[async_test]
             async fn test_add_and_save_session() {
                 let store_name = "add_and_save_session";
@@ -338,6 +361,42 @@ macro_rules! cryptostore_integration_tests {
                 );
             }
 
+            #[async_test]
+            async fn test_load_all_outbound_group_sessions() {
+                let (account, store) = get_loaded_store("load_all_outbound_group_sessions").await;
+                assert!(store.get_all_outbound_group_sessions().await.unwrap().is_empty());
+
+                let (session_1, _) = account
+                    .create_group_session_pair_with_defaults(room_id!("!test_1:localhost"))
+                    .await;
+                let (session_2, _) = account
+                    .create_group_session_pair_with_defaults(room_id!("!test_2:localhost"))
+                    .await;
+
+                let changes = Changes {
+                    outbound_group_sessions: vec![session_1.clone(), session_2.clone()],
+                    ..Default::default()
+                };
+                store.save_changes(changes).await.expect("Can't save group sessions");
+
+                let mut session_ids: Vec<_> = store
+                    .get_all_outbound_group_sessions()
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|s| (s.room_id().to_owned(), s.session_id().to_owned()))
+                    .collect();
+                session_ids.sort();
+
+                let mut expected = vec![
+                    (session_1.room_id().to_owned(), session_1.session_id().to_owned()),
+                    (session_2.room_id().to_owned(), session_2.session_id().to_owned()),
+                ];
+                expected.sort();
+
+                assert_eq!(session_ids, expected);
+            }
+
             /// Test that we can import an inbound group session via [`CryptoStore::save_changes`]
             #[async_test]
             async fn test_save_changes_save_inbound_group_session() {
@@ -563,6 +622,36 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(store.inbound_group_session_counts(None).await.unwrap().total, 1);
             }
 
+            #[async_test]
+            async fn test_load_inbound_group_sessions_for_room() {
+                let (account, store) = get_loaded_store("load_inbound_group_sessions_for_room").await;
+
+                let room_id = room_id!("!test:localhost");
+                let other_room_id = room_id!("!other:localhost");
+                let (_, session) = account.create_group_session_pair_with_defaults(room_id).await;
+                let (_, other_session) =
+                    account.create_group_session_pair_with_defaults(other_room_id).await;
+
+                let changes = Changes {
+                    inbound_group_sessions: vec![session.clone(), other_session.clone()],
+                    ..Default::default()
+                };
+                store.save_changes(changes).await.expect("Can't save group sessions");
+
+                let sessions = store.get_inbound_group_sessions_for_room(room_id).await.unwrap();
+                assert_eq!(sessions, vec![session]);
+
+                let sessions =
+                    store.get_inbound_group_sessions_for_room(other_room_id).await.unwrap();
+                assert_eq!(sessions, vec![other_session]);
+
+                let sessions = store
+                    .get_inbound_group_sessions_for_room(room_id!("!unknown:localhost"))
+                    .await
+                    .unwrap();
+                assert!(sessions.is_empty());
+            }
+
             #[async_test]
             async fn test_fetch_inbound_group_sessions_for_device() {
                 // Given a store exists, containing inbound group sessions from different devices
@@ -1193,6 +1282,31 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(None, loaded_settings_3);
             }
 
+            #[async_test]
+            async fn test_all_room_settings_loading() {
+                let (_, store) = get_loaded_store("all_room_settings_loading").await;
+                assert!(store.get_all_room_settings().await.unwrap().is_empty());
+
+                let room_settings = HashMap::from([
+                    (
+                        room_id!("!test_1:localhost").to_owned(),
+                        RoomSettings { only_allow_trusted_devices: true, ..Default::default() },
+                    ),
+                    (
+                        room_id!("!test_2:localhost").to_owned(),
+                        RoomSettings {
+                            session_rotation_period_messages: Some(10),
+                            ..Default::default()
+                        },
+                    ),
+                ]);
+
+                let changes = Changes { room_settings: room_settings.clone(), ..Default::default() };
+                store.save_changes(changes).await.unwrap();
+
+                assert_eq!(store.get_all_room_settings().await.unwrap(), room_settings);
+            }
+
             #[async_test]
             async fn test_backup_keys_saving() {
                 let (_account, store) = get_loaded_store("backup_keys_saving").await;
@@ -336,6 +336,10 @@ impl CryptoStore for MemoryStore {
         Ok(self.sessions.read().unwrap().get(sender_key).cloned())
     }
 
+    async fn get_all_sessions(&self) -> Result<Vec<Session>> {
+        Ok(self.sessions.read().unwrap().values().flatten().cloned().collect())
+    }
+
     async fn get_inbound_group_session(
         &self,
         room_id: &RoomId,
@@ -361,6 +365,13 @@ impl CryptoStore for MemoryStore {
         Ok(self.inbound_group_sessions.get_all())
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>> {
+        Ok(self.inbound_group_sessions.get_for_room(room_id))
+    }
+
     async fn inbound_group_session_counts(
         &self,
         backup_version: Option<&str>,
@@ -493,6 +504,10 @@ impl CryptoStore for MemoryStore {
         Ok(self.outbound_group_sessions.read().unwrap().get(room_id).cloned())
     }
 
+    async fn get_all_outbound_group_sessions(&self) -> Result<Vec<OutboundGroupSession>> {
+        Ok(self.outbound_group_sessions.read().unwrap().values().cloned().collect())
+    }
+
     async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>> {
         Ok(self.tracked_users.read().unwrap().values().cloned().collect())
     }
@@ -612,6 +627,10 @@ impl CryptoStore for MemoryStore {
         Ok(self.room_settings.read().unwrap().get(room_id).cloned())
     }
 
+    async fn get_all_room_settings(&self) -> Result<HashMap<OwnedRoomId, RoomSettings>> {
+        Ok(self.room_settings.read().unwrap().clone())
+    }
+
     async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>> {
         Ok(self.custom_values.read().unwrap().get(key).cloned())
     }
@@ -1114,7 +1133,8 @@ mod integration_tests {
 
     use async_trait::async_trait;
     use ruma::{
-        events::secret::request::SecretName, DeviceId, OwnedDeviceId, RoomId, TransactionId, UserId,
+        events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, RoomId,
+        TransactionId, UserId,
     };
     use vodozemac::Curve25519PublicKey;
 
@@ -1216,6 +1236,10 @@ mod integration_tests {
             self.0.get_sessions(sender_key).await
         }
 
+        async fn get_all_sessions(&self) -> Result<Vec<Session>, Self::Error> {
+            self.0.get_all_sessions().await
+        }
+
         async fn get_inbound_group_session(
             &self,
             room_id: &RoomId,
@@ -1238,6 +1262,13 @@ mod integration_tests {
             self.0.get_inbound_group_sessions().await
         }
 
+        async fn get_inbound_group_sessions_for_room(
+            &self,
+            room_id: &RoomId,
+        ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+            self.0.get_inbound_group_sessions_for_room(room_id).await
+        }
+
         async fn inbound_group_session_counts(
             &self,
             backup_version: Option<&str>,
@@ -1295,6 +1326,12 @@ mod integration_tests {
             self.0.get_outbound_group_session(room_id).await
         }
 
+        async fn get_all_outbound_group_sessions(
+            &self,
+        ) -> Result<Vec<OutboundGroupSession>, Self::Error> {
+            self.0.get_all_outbound_group_sessions().await
+        }
+
         async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>, Self::Error> {
             self.0.load_tracked_users().await
         }
@@ -1382,6 +1419,12 @@ mod integration_tests {
             self.0.get_room_settings(room_id).await
         }
 
+        async fn get_all_room_settings(
+            &self,
+        ) -> Result<HashMap<OwnedRoomId, RoomSettings>, Self::Error> {
+            self.0.get_all_room_settings().await
+        }
+
         async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
             self.0.get_custom_value(key).await
         }
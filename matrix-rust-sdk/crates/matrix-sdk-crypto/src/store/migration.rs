@@ -0,0 +1,612 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Migration of the data of a [`CryptoStore`] to another one, possibly using a
+//! different backend.
+//!
+//! [`CryptoStore`]: super::CryptoStore
+
+use std::collections::{BTreeSet, HashMap};
+
+use ruma::{events::secret::request::SecretName, OwnedRoomId, OwnedUserId};
+use thiserror::Error;
+use tracing::{debug, info};
+use vodozemac::Ed25519PublicKey;
+
+use super::{
+    BackupKeys, Changes, CryptoStoreError, DeviceChanges, DynCryptoStore, IdentityChanges,
+    IntoCryptoStore, PendingChanges, RoomKeyCounts, RoomSettings, TrackedUser,
+};
+use crate::{
+    olm::{Account, OutboundGroupSession, PrivateCrossSigningIdentity, Session},
+    GossipRequest, GossippedSecret,
+};
+
+/// The number of inbound group sessions saved at once in the destination
+/// store.
+const INBOUND_GROUP_SESSIONS_BATCH_SIZE: usize = 1000;
+
+/// The names of the secrets that might be waiting in the secret inbox of a
+/// store.
+const INBOX_SECRET_NAMES: [SecretName; 4] = [
+    SecretName::CrossSigningMasterKey,
+    SecretName::CrossSigningSelfSigningKey,
+    SecretName::CrossSigningUserSigningKey,
+    SecretName::RecoveryKey,
+];
+
+/// Error type for [`migrate`].
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    /// One of the stores returned an error.
+    #[error(transparent)]
+    Store(#[from] CryptoStoreError),
+
+    /// The source store doesn't contain an account, there is nothing to
+    /// migrate.
+    #[error("the source store doesn't contain an account")]
+    MissingAccount,
+
+    /// The destination store already contains an account, migrating to it
+    /// would mix the data of two accounts.
+    #[error("the destination store isn't empty")]
+    DestinationNotEmpty,
+
+    /// The data read back from the destination store doesn't match the data of
+    /// the source store.
+    #[error("the migrated {what} don't match: expected {expected}, found {found}")]
+    VerificationFailed {
+        /// The kind of data that doesn't match.
+        what: &'static str,
+        /// The value found in the source store.
+        expected: String,
+        /// The value found in the destination store.
+        found: String,
+    },
+}
+
+/// A step of a [`migrate`] operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationStep {
+    /// The private cross-signing identity and the backup keys are being
+    /// migrated. The account itself is only saved once everything else has
+    /// been migrated.
+    Account,
+    /// The tracked users, their devices and their identities are being
+    /// migrated.
+    Devices,
+    /// The Olm sessions are being migrated.
+    OlmSessions,
+    /// The inbound group sessions are being migrated.
+    InboundGroupSessions,
+    /// The room settings and the outbound group sessions are being migrated.
+    Rooms,
+    /// The outgoing secret requests and the secret inbox are being migrated.
+    Secrets,
+    /// The migrated data is being compared to the data of the source store.
+    Verification,
+}
+
+/// The progress of a [`migrate`] operation, reported to its listener.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationProgress {
+    /// The current step of the migration.
+    pub step: MigrationStep,
+    /// The number of items of the current step that were migrated.
+    pub done: usize,
+    /// The total number of items of the current step.
+    pub total: usize,
+}
+
+/// A summary of the data migrated by [`migrate`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// The number of users whose devices are tracked.
+    pub tracked_users: usize,
+    /// The number of devices.
+    pub devices: usize,
+    /// The number of user identities.
+    pub identities: usize,
+    /// The number of Olm sessions.
+    pub olm_sessions: usize,
+    /// The number of inbound group sessions, and how many of them are backed
+    /// up.
+    pub inbound_group_sessions: RoomKeyCounts,
+    /// The number of outbound group sessions.
+    pub outbound_group_sessions: usize,
+    /// The number of rooms with custom settings.
+    pub room_settings: usize,
+    /// The number of unsent secret requests.
+    pub secret_requests: usize,
+    /// The number of secrets waiting in the secret inbox.
+    pub secrets: usize,
+}
+
+/// Copy all the data of a crypto store to another one, possibly using a
+/// different backend.
+///
+/// The destination store must not contain an account. The account is saved
+/// last, so a migration that failed before it can be retried with the same
+/// destination store. Once everything has been copied, the data is read back
+/// from the destination store and compared to the data of the source store.
+///
+/// The `progress_listener` is called before each step of the migration, and
+/// after each batch of inbound group sessions.
+///
+/// Some data can't be enumerated through the [`CryptoStore`] trait, and is
+/// thus not migrated: the hashes of the Olm messages we received, the withheld
+/// room key notices, the secret requests that were already sent out, and the
+/// custom values. Losing them is harmless: a message could be decrypted twice,
+/// or a room key could be requested again.
+///
+/// The source store is left untouched.
+///
+/// [`CryptoStore`]: super::CryptoStore
+pub async fn migrate(
+    from: impl IntoCryptoStore,
+    to: impl IntoCryptoStore,
+    progress_listener: impl Fn(MigrationProgress),
+) -> Result<MigrationReport, MigrationError> {
+    let from = from.into_crypto_store();
+    let to = to.into_crypto_store();
+
+    let report_step = |step, total| progress_listener(MigrationProgress { step, done: 0, total });
+
+    if to.load_account().await?.is_some() {
+        return Err(MigrationError::DestinationNotEmpty);
+    }
+
+    let mut report = MigrationReport::default();
+
+    // All the data that doesn't depend on a user or a room. The account itself
+    // is saved at the very end.
+    report_step(MigrationStep::Account, 1);
+
+    let account = from.load_account().await?.ok_or(MigrationError::MissingAccount)?;
+    let own_user_id = account.user_id().to_owned();
+    info!(user_id = ?own_user_id, device_id = ?account.device_id(), "Migrating a crypto store");
+
+    let BackupKeys { decryption_key, backup_version } = from.load_backup_keys().await?;
+    to.save_changes(Changes {
+        private_identity: from.load_identity().await?,
+        backup_version: backup_version.clone(),
+        backup_decryption_key: decryption_key,
+        next_batch_token: from.next_batch_token().await?,
+        ..Default::default()
+    })
+    .await?;
+
+    // The tracked users, with their devices and their identity.
+    let tracked_users = from.load_tracked_users().await?;
+    report_step(MigrationStep::Devices, tracked_users.len());
+
+    let tracked: Vec<_> = tracked_users.iter().map(|u| (u.user_id.as_ref(), u.dirty)).collect();
+    to.save_tracked_users(&tracked).await?;
+    report.tracked_users = tracked_users.len();
+
+    // Our own devices are needed even if we don't track our own user, e.g.
+    // because the store was never synced.
+    let mut user_ids: BTreeSet<OwnedUserId> =
+        tracked_users.into_iter().map(|u| u.user_id).collect();
+    user_ids.insert(own_user_id);
+
+    let mut devices = Vec::new();
+    let mut identities = Vec::new();
+
+    for user_id in &user_ids {
+        devices.extend(from.get_user_devices(user_id).await?.into_values());
+        identities.extend(from.get_user_identity(user_id).await?);
+    }
+
+    report.devices = devices.len();
+    report.identities = identities.len();
+
+    to.save_changes(Changes {
+        devices: DeviceChanges { new: devices, ..Default::default() },
+        identities: IdentityChanges { new: identities, ..Default::default() },
+        ..Default::default()
+    })
+    .await?;
+
+    // All the Olm sessions, including the ones with devices we don't track
+    // anymore.
+    let sessions = from.get_all_sessions().await?;
+    report_step(MigrationStep::OlmSessions, sessions.len());
+
+    report.olm_sessions = sessions.len();
+    to.save_changes(Changes { sessions, ..Default::default() }).await?;
+
+    // The inbound group sessions, in batches, keeping track of which ones are
+    // backed up.
+    let inbound_group_sessions = from.get_inbound_group_sessions().await?;
+    let total = inbound_group_sessions.len();
+    report_step(MigrationStep::InboundGroupSessions, total);
+
+    let mut room_ids: BTreeSet<OwnedRoomId> =
+        inbound_group_sessions.iter().map(|s| s.room_id().to_owned()).collect();
+
+    let (backed_up, not_backed_up): (Vec<_>, Vec<_>) =
+        inbound_group_sessions.into_iter().partition(|s| s.backed_up());
+    let batches = backed_up
+        .chunks(INBOUND_GROUP_SESSIONS_BATCH_SIZE)
+        .map(|batch| (batch, backup_version.as_deref()))
+        .chain(not_backed_up.chunks(INBOUND_GROUP_SESSIONS_BATCH_SIZE).map(|batch| (batch, None)));
+
+    let mut done = 0;
+
+    for (batch, backed_up_to_version) in batches {
+        to.save_inbound_group_sessions(batch.to_vec(), backed_up_to_version).await?;
+
+        done += batch.len();
+        debug!(done, total, "Migrated a batch of inbound group sessions");
+        progress_listener(MigrationProgress {
+            step: MigrationStep::InboundGroupSessions,
+            done,
+            total,
+        });
+    }
+
+    // The outbound group sessions and the room settings. Stores might not be
+    // able to list the settings saved by an older version of the store, so we
+    // also look them up for all the rooms we have a room key for.
+    let outbound_group_sessions = from.get_all_outbound_group_sessions().await?;
+    let mut room_settings = from.get_all_room_settings().await?;
+
+    room_ids.extend(outbound_group_sessions.iter().map(|s| s.room_id().to_owned()));
+    room_ids.retain(|room_id| !room_settings.contains_key(room_id));
+    report_step(MigrationStep::Rooms, outbound_group_sessions.len() + room_ids.len());
+
+    for room_id in room_ids {
+        if let Some(settings) = from.get_room_settings(&room_id).await? {
+            room_settings.insert(room_id, settings);
+        }
+    }
+
+    report.room_settings = room_settings.len();
+    report.outbound_group_sessions = outbound_group_sessions.len();
+
+    to.save_changes(Changes {
+        room_settings: room_settings.clone(),
+        outbound_group_sessions,
+        ..Default::default()
+    })
+    .await?;
+
+    // The secret requests and the secrets waiting in the inbox.
+    let key_requests = from.get_unsent_secret_requests().await?;
+    report_step(MigrationStep::Secrets, key_requests.len());
+
+    let mut secrets = Vec::new();
+    for secret_name in &INBOX_SECRET_NAMES {
+        secrets.extend(from.get_secrets_from_inbox(secret_name).await?);
+    }
+
+    report.secret_requests = key_requests.len();
+    report.secrets = secrets.len();
+
+    to.save_changes(Changes { key_requests, secrets, ..Default::default() }).await?;
+
+    // Everything else made it to the destination store, it's now safe to mark
+    // it as non-empty.
+    to.save_pending_changes(PendingChanges { account: Some(account) }).await?;
+
+    // Finally, make sure that everything made it to the destination store.
+    report_step(MigrationStep::Verification, 1);
+
+    report.inbound_group_sessions =
+        verify(&*from, &*to, &user_ids, backup_version.as_deref(), room_settings).await?;
+
+    info!(?report, "Crypto store migrated");
+
+    Ok(report)
+}
+
+/// Compare the data of the destination store to the data of the source store,
+/// and return the counts of inbound group sessions.
+async fn verify(
+    from: &DynCryptoStore,
+    to: &DynCryptoStore,
+    user_ids: &BTreeSet<OwnedUserId>,
+    backup_version: Option<&str>,
+    room_settings: HashMap<OwnedRoomId, RoomSettings>,
+) -> Result<RoomKeyCounts, MigrationError> {
+    fn check<T: PartialEq + std::fmt::Debug>(
+        what: &'static str,
+        expected: T,
+        found: T,
+    ) -> Result<(), MigrationError> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(MigrationError::VerificationFailed {
+                what,
+                expected: format!("{expected:?}"),
+                found: format!("{found:?}"),
+            })
+        }
+    }
+
+    let identity_keys = |account: Option<Account>| account.map(|a| a.identity_keys());
+    check(
+        "identity keys",
+        identity_keys(from.load_account().await?),
+        identity_keys(to.load_account().await?),
+    )?;
+
+    // Only compare the public parts of the keys, the error must not contain any
+    // secret.
+    async fn cross_signing_keys(
+        identity: Option<PrivateCrossSigningIdentity>,
+    ) -> Option<[Option<Ed25519PublicKey>; 3]> {
+        let identity = identity?;
+
+        Some([
+            identity.master_public_key().await.and_then(|k| k.get_first_key()),
+            identity.self_signing_public_key().await.and_then(|k| k.get_first_key()),
+            identity.user_signing_public_key().await.and_then(|k| k.get_first_key()),
+        ])
+    }
+    check(
+        "cross-signing keys",
+        cross_signing_keys(from.load_identity().await?).await,
+        cross_signing_keys(to.load_identity().await?).await,
+    )?;
+
+    let backup_keys = |keys: BackupKeys| {
+        (keys.backup_version, keys.decryption_key.map(|k| k.megolm_v1_public_key().to_base64()))
+    };
+    check(
+        "backup keys",
+        backup_keys(from.load_backup_keys().await?),
+        backup_keys(to.load_backup_keys().await?),
+    )?;
+
+    let tracked_users = |users: Vec<TrackedUser>| {
+        users.into_iter().map(|u| (u.user_id, u.dirty)).collect::<BTreeSet<_>>()
+    };
+    check(
+        "tracked users",
+        tracked_users(from.load_tracked_users().await?),
+        tracked_users(to.load_tracked_users().await?),
+    )?;
+
+    for user_id in user_ids {
+        let device_ids = |devices: HashMap<_, _>| devices.into_keys().collect::<BTreeSet<_>>();
+        check(
+            "devices",
+            device_ids(from.get_user_devices(user_id).await?),
+            device_ids(to.get_user_devices(user_id).await?),
+        )?;
+    }
+
+    let session_ids = |sessions: Vec<Session>| {
+        sessions
+            .into_iter()
+            .map(|s| (s.sender_key().to_base64(), s.session_id))
+            .collect::<BTreeSet<_>>()
+    };
+    check(
+        "Olm sessions",
+        session_ids(from.get_all_sessions().await?),
+        session_ids(to.get_all_sessions().await?),
+    )?;
+
+    let expected = from.inbound_group_session_counts(backup_version).await?;
+    let found = to.inbound_group_session_counts(backup_version).await?;
+    check("inbound group sessions", expected.total, found.total)?;
+    check("backed up inbound group sessions", expected.backed_up, found.backed_up)?;
+
+    let outbound_session_ids = |sessions: Vec<OutboundGroupSession>| {
+        sessions
+            .into_iter()
+            .map(|s| (s.room_id().to_owned(), s.session_id().to_owned()))
+            .collect::<BTreeSet<_>>()
+    };
+    check(
+        "outbound group sessions",
+        outbound_session_ids(from.get_all_outbound_group_sessions().await?),
+        outbound_session_ids(to.get_all_outbound_group_sessions().await?),
+    )?;
+
+    check("room settings", room_settings, to.get_all_room_settings().await?)?;
+
+    let request_ids = |requests: Vec<GossipRequest>| {
+        requests.into_iter().map(|r| r.request_id).collect::<BTreeSet<_>>()
+    };
+    check(
+        "secret requests",
+        request_ids(from.get_unsent_secret_requests().await?),
+        request_ids(to.get_unsent_secret_requests().await?),
+    )?;
+
+    for secret_name in &INBOX_SECRET_NAMES {
+        let secret_ids = |secrets: Vec<GossippedSecret>| {
+            secrets.iter().map(|s| s.event.content.request_id.clone()).collect::<BTreeSet<_>>()
+        };
+        check(
+            "secrets",
+            secret_ids(from.get_secrets_from_inbox(secret_name).await?),
+            secret_ids(to.get_secrets_from_inbox(secret_name).await?),
+        )?;
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use matrix_sdk_test::async_test;
+    use ruma::{device_id, room_id, user_id};
+    use vodozemac::olm::SessionConfig;
+
+    use super::{migrate, MigrationError, MigrationProgress, MigrationStep};
+    use crate::{
+        identities::DeviceData,
+        olm::{Account, PrivateCrossSigningIdentity},
+        store::{
+            BackupDecryptionKey, Changes, CryptoStore, DeviceChanges, MemoryStore, PendingChanges,
+            RoomSettings,
+        },
+    };
+
+    /// Fill a store with an account, a private cross-signing identity, backup
+    /// keys, a tracked user with a device and an Olm session, an Olm session
+    /// with an unknown device, a room key, an outbound group session and room
+    /// settings for rooms we don't have a room key for.
+    async fn populated_store() -> MemoryStore {
+        let store = MemoryStore::new();
+
+        let alice = Account::with_device_id(user_id!("@alice:localhost"), device_id!("ALICE"));
+
+        let mut sessions = Vec::new();
+        for (user_id, device_id, tracked) in [
+            (user_id!("@bob:localhost"), device_id!("BOB"), true),
+            (user_id!("@carol:localhost"), device_id!("CAROL"), false),
+        ] {
+            let mut other = Account::with_device_id(user_id, device_id);
+            other.generate_one_time_keys(1);
+            let one_time_key = *other.one_time_keys().values().next().unwrap();
+
+            sessions.push(alice.create_outbound_session_helper(
+                SessionConfig::default(),
+                other.identity_keys().curve25519,
+                one_time_key,
+                false,
+                alice.device_keys(),
+            ));
+
+            if tracked {
+                store.save_tracked_users(&[(user_id, false)]).await.unwrap();
+                store.save_devices(vec![DeviceData::from_account(&other)]);
+            }
+        }
+
+        let (_, inbound) =
+            alice.create_group_session_pair_with_defaults(room_id!("!test:localhost")).await;
+        let (outbound, _) =
+            alice.create_group_session_pair_with_defaults(room_id!("!outbound:localhost")).await;
+
+        store
+            .save_changes(Changes {
+                private_identity: Some(PrivateCrossSigningIdentity::new(
+                    alice.user_id().to_owned(),
+                )),
+                backup_decryption_key: Some(BackupDecryptionKey::new().unwrap()),
+                backup_version: Some("1".to_owned()),
+                devices: DeviceChanges {
+                    new: vec![DeviceData::from_account(&alice)],
+                    ..Default::default()
+                },
+                sessions,
+                inbound_group_sessions: vec![inbound],
+                outbound_group_sessions: vec![outbound],
+                room_settings: HashMap::from([(
+                    room_id!("!settings:localhost").to_owned(),
+                    RoomSettings { only_allow_trusted_devices: true, ..Default::default() },
+                )]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        store.save_pending_changes(PendingChanges { account: Some(alice) }).await.unwrap();
+
+        store
+    }
+
+    #[async_test]
+    async fn test_migrate_copies_everything() {
+        let from = populated_store().await;
+        let to = Arc::new(MemoryStore::new());
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let report = migrate(from, to.clone(), {
+            let progress = progress.clone();
+            move |update: MigrationProgress| progress.lock().unwrap().push(update)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(report.tracked_users, 1);
+        assert_eq!(report.devices, 2);
+        assert_eq!(report.olm_sessions, 2);
+        assert_eq!(report.inbound_group_sessions.total, 1);
+        assert_eq!(report.outbound_group_sessions, 1);
+        assert_eq!(report.room_settings, 1);
+
+        let account = to.load_account().await.unwrap().unwrap();
+        assert_eq!(account.user_id(), "@alice:localhost");
+        assert!(to
+            .get_device(user_id!("@bob:localhost"), device_id!("BOB"))
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(to.get_all_sessions().await.unwrap().len(), 2);
+        assert_eq!(to.get_inbound_group_sessions().await.unwrap().len(), 1);
+        assert!(to
+            .get_outbound_group_session(room_id!("!outbound:localhost"))
+            .await
+            .unwrap()
+            .is_some());
+        assert!(to.get_room_settings(room_id!("!settings:localhost")).await.unwrap().is_some());
+        assert!(to.load_identity().await.unwrap().is_some());
+        assert!(to.load_backup_keys().await.unwrap().decryption_key.is_some());
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.first().unwrap().step, MigrationStep::Account);
+        assert_eq!(progress.last().unwrap().step, MigrationStep::Verification);
+        assert!(progress.contains(&MigrationProgress {
+            step: MigrationStep::InboundGroupSessions,
+            done: 1,
+            total: 1,
+        }));
+    }
+
+    #[async_test]
+    async fn test_migrate_can_be_retried() {
+        let from = Arc::new(populated_store().await);
+        let to = Arc::new(MemoryStore::new());
+
+        // An interrupted migration leaves some data, but not the account, in
+        // the destination store.
+        to.save_tracked_users(&[(user_id!("@bob:localhost"), false)]).await.unwrap();
+        to.save_inbound_group_sessions(from.get_inbound_group_sessions().await.unwrap(), None)
+            .await
+            .unwrap();
+
+        let report = migrate(from, to.clone(), |_| {}).await.unwrap();
+
+        assert_eq!(report.inbound_group_sessions.total, 1);
+        assert!(to.load_account().await.unwrap().is_some());
+    }
+
+    #[async_test]
+    async fn test_migrate_refuses_a_non_empty_destination() {
+        let from = populated_store().await;
+        let to = populated_store().await;
+
+        let result = migrate(from, to, |_| {}).await;
+        assert!(matches!(result, Err(MigrationError::DestinationNotEmpty)));
+    }
+
+    #[async_test]
+    async fn test_migrate_requires_an_account() {
+        let result = migrate(MemoryStore::new(), MemoryStore::new(), |_| {}).await;
+        assert!(matches!(result, Err(MigrationError::MissingAccount)));
+    }
+}
@@ -52,7 +52,7 @@ use futures_core::Stream;
 use futures_util::StreamExt;
 use ruma::{
     encryption::KeyUsage, events::secret::request::SecretName, DeviceId, OwnedDeviceId,
-    OwnedRoomId, OwnedUserId, UserId,
+    OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
@@ -75,13 +75,14 @@ use crate::{
         EventEncryptionAlgorithm, MegolmBackupV1Curve25519AesSha2Secrets, SecretsBundle,
     },
     verification::VerificationMachine,
-    CrossSigningStatus, OwnUserIdentityData, RoomKeyImportResult,
+    CrossSigningStatus, EncryptionSettings, OwnUserIdentityData, RoomKeyImportResult,
 };
 
 pub mod caches;
 mod crypto_store_wrapper;
 mod error;
 mod memorystore;
+mod migration;
 mod traits;
 
 #[cfg(any(test, feature = "testing"))]
@@ -94,6 +95,7 @@ pub(crate) use crypto_store_wrapper::CryptoStoreWrapper;
 pub use error::{CryptoStoreError, Result};
 use matrix_sdk_common::{store_locks::CrossProcessStoreLock, timeout::timeout};
 pub use memorystore::MemoryStore;
+pub use migration::{migrate, MigrationError, MigrationProgress, MigrationReport, MigrationStep};
 pub use traits::{CryptoStore, DynCryptoStore, IntoCryptoStore};
 
 pub use crate::gossiping::{GossipRequest, SecretInfo};
@@ -626,6 +628,12 @@ pub struct DeviceChanges {
     pub deleted: Vec<DeviceData>,
 }
 
+/// The key of the custom value holding the local room key rotation policy of a
+/// room.
+fn room_key_rotation_policy_key(room_id: &RoomId) -> String {
+    format!("room_key_rotation_policy:{room_id}")
+}
+
 /// Convert the devices and vectors contained in the [`DeviceChanges`] into
 /// a [`DeviceUpdates`] struct.
 ///
@@ -763,7 +771,7 @@ impl DeviceChanges {
 }
 
 /// Struct holding info about how many room keys the store has.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct RoomKeyCounts {
     /// The total number of room keys the store has.
     pub total: usize,
@@ -884,6 +892,55 @@ impl Default for RoomSettings {
     }
 }
 
+/// A local policy controlling when the room key of a room gets rotated.
+///
+/// The policy can only make the rotation stricter: a room key is rotated as
+/// soon as either the room's encryption settings or this policy ask for it.
+/// Regardless of the policy, the room key is always rotated when a user leaves
+/// the room or when one of the recipient devices gets deleted or blacklisted.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RoomKeyRotationPolicy {
+    /// The maximum time a room key should be used for, before it is rotated.
+    ///
+    /// It can't be zero.
+    pub rotation_period: Option<Duration>,
+
+    /// The maximum number of messages a room key should be used for, before
+    /// it is rotated.
+    ///
+    /// It can't be zero.
+    pub rotation_period_msgs: Option<u64>,
+
+    /// Should the room key be rotated when a new device, or a new user, needs
+    /// to receive it, so that newcomers can't decrypt the messages sent
+    /// before they joined.
+    #[serde(default)]
+    pub rotate_on_new_recipients: bool,
+}
+
+impl RoomKeyRotationPolicy {
+    /// Apply this policy to the given encryption settings, keeping the
+    /// strictest of the rotation periods.
+    ///
+    /// Invalid zero rotation periods are ignored.
+    pub fn apply(&self, settings: &mut EncryptionSettings) {
+        if let Some(period) = self.rotation_period.filter(|period| !period.is_zero()) {
+            settings.rotation_period = settings.rotation_period.min(period);
+        }
+
+        if let Some(msgs) = self.rotation_period_msgs.filter(|msgs| *msgs > 0) {
+            settings.rotation_period_msgs = settings.rotation_period_msgs.min(msgs);
+        }
+    }
+
+    /// Whether this policy can be used, i.e. none of its rotation periods is
+    /// zero.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.rotation_period.map_or(true, |period| !period.is_zero())
+            && self.rotation_period_msgs.map_or(true, |msgs| msgs > 0)
+    }
+}
+
 /// Information on a room key that has been received or imported.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct RoomKeyInfo {
@@ -1484,6 +1541,29 @@ impl Store {
         self.set_value("only_allow_trusted_devices", &block_untrusted_devices).await
     }
 
+    /// Get the local room key rotation policy of the given room.
+    ///
+    /// Returns the default policy if none was set.
+    pub async fn get_room_key_rotation_policy(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<RoomKeyRotationPolicy> {
+        let key = room_key_rotation_policy_key(room_id);
+        Ok(self.get_value(&key).await?.unwrap_or_default())
+    }
+
+    /// Set the local room key rotation policy of the given room.
+    ///
+    /// It's stored apart from the [`RoomSettings`] of the room, which come from
+    /// its `m.room.encryption` state event and can't be downgraded.
+    pub async fn set_room_key_rotation_policy(
+        &self,
+        room_id: &RoomId,
+        policy: &RoomKeyRotationPolicy,
+    ) -> Result<()> {
+        self.set_value(&room_key_rotation_policy_key(room_id), policy).await
+    }
+
     /// Get custom stored value associated with a key
     pub async fn get_value<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
         let Some(value) = self.get_custom_value(key).await? else {
@@ -1939,13 +2019,17 @@ impl matrix_sdk_common::store_locks::BackingStore for LockableCryptoStore {
 
 #[cfg(test)]
 mod tests {
-    use std::pin::pin;
+    use std::{pin::pin, time::Duration};
 
     use futures_util::StreamExt;
     use matrix_sdk_test::async_test;
     use ruma::{room_id, user_id};
 
-    use crate::{machine::test_helpers::get_machine_pair, types::EventEncryptionAlgorithm};
+    use super::RoomKeyRotationPolicy;
+    use crate::{
+        machine::test_helpers::get_machine_pair, types::EventEncryptionAlgorithm,
+        EncryptionSettings,
+    };
 
     #[async_test]
     async fn test_import_room_keys_notifies_stream() {
@@ -2082,4 +2166,21 @@ mod tests {
 
         assert!(status.is_complete(), "We should have imported all the cross-signing keys");
     }
+
+    #[test]
+    fn test_rotation_policy_ignores_zero_periods() {
+        let policy = RoomKeyRotationPolicy {
+            rotation_period: Some(Duration::ZERO),
+            rotation_period_msgs: Some(0),
+            rotate_on_new_recipients: false,
+        };
+        assert!(!policy.is_valid());
+
+        let mut settings = EncryptionSettings::default();
+        policy.apply(&mut settings);
+
+        let default_settings = EncryptionSettings::default();
+        assert_eq!(settings.rotation_period, default_settings.rotation_period);
+        assert_eq!(settings.rotation_period_msgs, default_settings.rotation_period_msgs);
+    }
 }
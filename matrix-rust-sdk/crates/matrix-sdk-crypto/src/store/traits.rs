@@ -17,7 +17,8 @@ use std::{collections::HashMap, fmt, sync::Arc};
 use async_trait::async_trait;
 use matrix_sdk_common::AsyncTraitDeps;
 use ruma::{
-    events::secret::request::SecretName, DeviceId, OwnedDeviceId, RoomId, TransactionId, UserId,
+    events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, RoomId,
+    TransactionId, UserId,
 };
 use vodozemac::Curve25519PublicKey;
 
@@ -89,6 +90,9 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// * `sender_key` - The sender key that was used to establish the sessions.
     async fn get_sessions(&self, sender_key: &str) -> Result<Option<Vec<Session>>, Self::Error>;
 
+    /// Get all the Olm sessions we have stored, whatever their sender key.
+    async fn get_all_sessions(&self) -> Result<Vec<Session>, Self::Error>;
+
     /// Get the inbound group session from our store.
     ///
     /// # Arguments
@@ -117,6 +121,12 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Get all the inbound group sessions we have stored.
     async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>, Self::Error>;
 
+    /// Get all the inbound group sessions of the given room.
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error>;
+
     /// Get the number inbound group sessions we have and how many of them are
     /// backed up.
     async fn inbound_group_session_counts(
@@ -202,6 +212,11 @@ pub trait CryptoStore: AsyncTraitDeps {
         room_id: &RoomId,
     ) -> Result<Option<OutboundGroupSession>, Self::Error>;
 
+    /// Get all the outbound group sessions we have stored.
+    async fn get_all_outbound_group_sessions(
+        &self,
+    ) -> Result<Vec<OutboundGroupSession>, Self::Error>;
+
     /// Provide the list of users whose devices we are keeping track of, and
     /// whether they are considered dirty/outdated.
     async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>, Self::Error>;
@@ -314,6 +329,14 @@ pub trait CryptoStore: AsyncTraitDeps {
         room_id: &RoomId,
     ) -> Result<Option<RoomSettings>, Self::Error>;
 
+    /// Get the settings of all the rooms we have settings for.
+    ///
+    /// Stores that can't recover the room id from their keys might miss the
+    /// settings that were saved by an older version of the store.
+    async fn get_all_room_settings(
+        &self,
+    ) -> Result<HashMap<OwnedRoomId, RoomSettings>, Self::Error>;
+
     /// Get arbitrary data from the store
     ///
     /// # Arguments
@@ -402,6 +425,10 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_sessions(sender_key).await.map_err(Into::into)
     }
 
+    async fn get_all_sessions(&self) -> Result<Vec<Session>> {
+        self.0.get_all_sessions().await.map_err(Into::into)
+    }
+
     async fn get_inbound_group_session(
         &self,
         room_id: &RoomId,
@@ -414,6 +441,13 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_inbound_group_sessions().await.map_err(Into::into)
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>> {
+        self.0.get_inbound_group_sessions_for_room(room_id).await.map_err(Into::into)
+    }
+
     async fn get_inbound_group_sessions_for_device_batch(
         &self,
         curve_key: Curve25519PublicKey,
@@ -472,6 +506,10 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_outbound_group_session(room_id).await.map_err(Into::into)
     }
 
+    async fn get_all_outbound_group_sessions(&self) -> Result<Vec<OutboundGroupSession>> {
+        self.0.get_all_outbound_group_sessions().await.map_err(Into::into)
+    }
+
     async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>> {
         self.0.load_tracked_users().await.map_err(Into::into)
     }
@@ -552,6 +590,10 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.get_room_settings(room_id).await.map_err(Into::into)
     }
 
+    async fn get_all_room_settings(&self) -> Result<HashMap<OwnedRoomId, RoomSettings>> {
+        self.0.get_all_room_settings().await.map_err(Into::into)
+    }
+
     async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
         self.0.get_custom_value(key).await.map_err(Into::into)
     }
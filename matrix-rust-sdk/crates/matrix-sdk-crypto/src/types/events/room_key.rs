@@ -74,6 +74,11 @@ impl RoomKeyContent {
             pub room_id: &'a RoomId,
             pub session_id: &'a str,
             pub session_key: &'a str,
+            #[serde(
+                rename = "org.matrix.msc3061.shared_history",
+                skip_serializing_if = "std::ops::Not::not"
+            )]
+            pub shared_history: bool,
             #[serde(flatten)]
             other: &'a BTreeMap<String, Value>,
         }
@@ -83,6 +88,7 @@ impl RoomKeyContent {
                 room_id: &content.room_id,
                 session_id: &content.session_id,
                 session_key: "",
+                shared_history: content.shared_history,
                 other: &content.other,
             };
 
@@ -113,6 +119,17 @@ pub struct MegolmV1AesSha2Content {
     ///
     /// [`InboundGroupSession`]: vodozemac::megolm::InboundGroupSession
     pub session_key: SessionKey,
+    /// Whether the key can be shared with the users who are invited to the
+    /// room later on, as defined in [MSC3061].
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    #[serde(
+        default,
+        rename = "org.matrix.msc3061.shared_history",
+        alias = "shared_history",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub shared_history: bool,
     /// Any other, custom and non-specced fields of the content.
     #[serde(flatten)]
     other: BTreeMap<String, Value>,
@@ -121,7 +138,7 @@ pub struct MegolmV1AesSha2Content {
 impl MegolmV1AesSha2Content {
     /// Create a new `m.megolm.v1.aes-sha2` `m.room_key` content.
     pub fn new(room_id: OwnedRoomId, session_id: String, session_key: SessionKey) -> Self {
-        Self { room_id, session_id, session_key, other: Default::default() }
+        Self { room_id, session_id, session_key, shared_history: false, other: Default::default() }
     }
 }
 
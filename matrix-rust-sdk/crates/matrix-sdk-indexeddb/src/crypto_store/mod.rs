@@ -39,7 +39,7 @@ use matrix_sdk_crypto::{
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
     events::secret::request::SecretName, DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
-    RoomId, TransactionId, UserId,
+    OwnedRoomId, RoomId, TransactionId, UserId,
 };
 use sha2::Sha256;
 use tokio::sync::Mutex;
@@ -615,7 +615,10 @@ impl IndexeddbCryptoStore {
 
             for (room_id, settings) in room_settings_changes {
                 let key = self.serializer.encode_key(keys::ROOM_SETTINGS, room_id);
-                let value = self.serializer.serialize_value(&settings)?;
+                let value = self.serializer.serialize_value(&RoomSettingsIndexedDbObject {
+                    room_id: Some(room_id.clone()),
+                    settings: settings.clone(),
+                })?;
                 settings_store.put(key, value);
             }
         }
@@ -802,6 +805,30 @@ impl_crypto_store! {
         }
     }
 
+    async fn get_all_outbound_group_sessions(&self) -> Result<Vec<OutboundGroupSession>> {
+        let account_info = self.get_static_account().ok_or(CryptoStoreError::AccountUnset)?;
+
+        self
+            .inner
+            .transaction_on_one_with_mode(
+                keys::OUTBOUND_GROUP_SESSIONS,
+                IdbTransactionMode::Readonly,
+            )?
+            .object_store(keys::OUTBOUND_GROUP_SESSIONS)?
+            .get_all()?
+            .await?
+            .iter()
+            .map(|value| -> Result<_> {
+                Ok(OutboundGroupSession::from_pickle(
+                    account_info.device_id.clone(),
+                    account_info.identity_keys.clone(),
+                    self.serializer.deserialize_value(value)?,
+                )
+                .map_err(CryptoStoreError::from)?)
+            })
+            .collect()
+    }
+
     async fn get_outgoing_secret_requests(
         &self,
         request_id: &TransactionId,
@@ -890,7 +917,7 @@ impl_crypto_store! {
                         device_keys.clone(),
                         p,
                     )
-                        .map_err(|_| IndexeddbCryptoStoreError::CryptoStoreError(CryptoStoreError::AccountUnset))
+                        .map_err(|e| IndexeddbCryptoStoreError::CryptoStoreError(CryptoStoreError::backend(e)))
                 }))
                 .collect::<Result<Vec<Session>>>()?;
 
@@ -901,6 +928,23 @@ impl_crypto_store! {
         }
     }
 
+    async fn get_all_sessions(&self) -> Result<Vec<Session>> {
+        let device_keys = self.get_own_device().await?.as_device_keys().clone();
+
+        self
+            .inner
+            .transaction_on_one_with_mode(keys::SESSION, IdbTransactionMode::Readonly)?
+            .object_store(keys::SESSION)?
+            .get_all()?
+            .await?
+            .iter()
+            .map(|value| {
+                Session::from_pickle(device_keys.clone(), self.serializer.deserialize_value(value)?)
+                    .map_err(|e| CryptoStoreError::backend(e).into())
+            })
+            .collect()
+    }
+
     async fn get_inbound_group_session(
         &self,
         room_id: &RoomId,
@@ -942,6 +986,25 @@ impl_crypto_store! {
         ).await
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>> {
+        let range = self.serializer.encode_to_range(keys::INBOUND_GROUP_SESSIONS_V3, room_id)?;
+
+        self.inner
+            .transaction_on_one_with_mode(
+                keys::INBOUND_GROUP_SESSIONS_V3,
+                IdbTransactionMode::Readonly,
+            )?
+            .object_store(keys::INBOUND_GROUP_SESSIONS_V3)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .map(|value| self.deserialize_inbound_group_session(value))
+            .collect()
+    }
+
     async fn get_inbound_group_sessions_for_device_batch(
         &self,
         sender_key: Curve25519PublicKey,
@@ -1322,10 +1385,36 @@ impl_crypto_store! {
             .object_store(keys::ROOM_SETTINGS)?
             .get(&key)?
             .await?
-            .map(|v| self.serializer.deserialize_value(v))
+            .map(|v| -> Result<_> {
+                let RoomSettingsIndexedDbObject { settings, .. } =
+                    self.serializer.deserialize_value(v)?;
+                Ok(settings)
+            })
             .transpose()
     }
 
+    async fn get_all_room_settings(&self) -> Result<HashMap<OwnedRoomId, RoomSettings>> {
+        let values = self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_SETTINGS, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_SETTINGS)?
+            .get_all()?
+            .await?;
+
+        let mut all_settings = HashMap::new();
+
+        for value in values.iter() {
+            let RoomSettingsIndexedDbObject { room_id, settings } =
+                self.serializer.deserialize_value(value)?;
+
+            if let Some(room_id) = room_id {
+                all_settings.insert(room_id, settings);
+            }
+        }
+
+        Ok(all_settings)
+    }
+
     async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>> {
         self
             .inner
@@ -1631,6 +1720,21 @@ struct GossipRequestIndexedDbObject {
     unsent: bool,
 }
 
+/// The objects we store in the room_settings indexeddb object store
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RoomSettingsIndexedDbObject {
+    /// The ID of the room these settings belong to, since the key of the
+    /// object store might be hashed.
+    ///
+    /// Missing from the settings that were saved before it was added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    room_id: Option<OwnedRoomId>,
+
+    /// The settings themselves.
+    #[serde(flatten)]
+    settings: RoomSettings,
+}
+
 /// The objects we store in the inbound_group_sessions3 indexeddb object store
 #[derive(serde::Serialize, serde::Deserialize)]
 struct InboundGroupSessionIndexedDbObject {
@@ -13,7 +13,9 @@
 // limitations under the License.
 
 use std::{
+    cell::Cell,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    rc::Rc,
     sync::Arc,
 };
 
@@ -21,7 +23,7 @@ use anyhow::anyhow;
 use async_trait::async_trait;
 use gloo_utils::format::JsValueSerdeExt;
 use growable_bloom_filter::GrowableBloom;
-use indexed_db_futures::prelude::*;
+use indexed_db_futures::{prelude::*, request::OpenDbRequest, IdbVersionChangeEvent};
 use matrix_sdk_base::{
     deserialized_responses::{DisplayName, RawAnySyncOrStrippedState},
     store::{
@@ -203,6 +205,57 @@ where
     .map_err(|e| IndexeddbStateStoreError::StoreError(StoreError::Backend(anyhow!(e).into())))
 }
 
+/// The stores whose data is copied in the snapshots.
+///
+/// The send queues aren't part of them, so that restoring a snapshot neither
+/// drops the requests waiting to be sent, nor sends again the ones which have
+/// already been sent.
+const SNAPSHOT_STORES: &[&str] = &[
+    keys::ACCOUNT_DATA,
+    keys::PROFILES,
+    keys::DISPLAY_NAMES,
+    keys::USER_IDS,
+    keys::ROOM_STATE,
+    keys::ROOM_INFOS,
+    keys::PRESENCE,
+    keys::ROOM_ACCOUNT_DATA,
+    keys::STRIPPED_ROOM_STATE,
+    keys::STRIPPED_USER_IDS,
+    keys::ROOM_USER_RECEIPTS,
+    keys::ROOM_EVENT_RECEIPTS,
+    keys::CUSTOM,
+    keys::KV,
+];
+
+/// Replace the data of the [`SNAPSHOT_STORES`] of `target` with the data of
+/// `source`.
+async fn copy_stores(source: &IdbDatabase, target: &IdbDatabase) -> Result<()> {
+    let mut data = Vec::with_capacity(SNAPSHOT_STORES.len());
+
+    for name in SNAPSHOT_STORES {
+        let tx = source.transaction_on_one_with_mode(name, IdbTransactionMode::Readonly)?;
+        let values = match tx.object_store(name)?.open_cursor()?.await? {
+            Some(cursor) => cursor.into_vec(0).await?,
+            None => Vec::new(),
+        };
+        data.push((*name, values));
+    }
+
+    let tx =
+        target.transaction_on_multi_with_mode(SNAPSHOT_STORES, IdbTransactionMode::Readwrite)?;
+
+    for (name, values) in data {
+        let store = tx.object_store(name)?;
+        store.clear()?;
+
+        for kv in values {
+            store.put_key_val(kv.key(), kv.value())?;
+        }
+    }
+
+    tx.await.into_result().map_err(|e| e.into())
+}
+
 /// Builder for [`IndexeddbStateStore`].
 #[derive(Debug)]
 pub struct IndexeddbStateStoreBuilder {
@@ -310,6 +363,35 @@ impl IndexeddbStateStore {
             .and_then(|c| c.value().as_string()))
     }
 
+    /// The name of the database holding the snapshot with the given
+    /// identifier.
+    fn snapshot_name(&self, id: u64) -> String {
+        format!("{}::snapshot-{id}", self.name)
+    }
+
+    /// Open the database holding the snapshot with the given identifier.
+    ///
+    /// Returns the database and whether it has just been created.
+    async fn open_snapshot(&self, id: u64) -> Result<(IdbDatabase, bool)> {
+        let created = Rc::new(Cell::new(false));
+
+        let mut db_req: OpenDbRequest = IdbDatabase::open_u32(&self.snapshot_name(id), 1)?;
+        db_req.set_on_upgrade_needed(Some({
+            let created = created.clone();
+            move |evt: &IdbVersionChangeEvent| -> Result<(), JsValue> {
+                let db = evt.db();
+                for name in ALL_STORES {
+                    db.create_object_store(name)?;
+                }
+                created.set(true);
+                Ok(())
+            }
+        }));
+        let db = db_req.await?;
+
+        Ok((db, created.get()))
+    }
+
     /// Encrypt (if needs be) then JSON-serialize a value.
     fn serialize_value(&self, event: &impl Serialize) -> Result<JsValue> {
         serialize_value(self.store_cipher.as_deref(), event)
@@ -1342,6 +1424,35 @@ impl_state_store!({
         tx.await.into_result().map_err(|e| e.into())
     }
 
+    async fn save_snapshot(&self, id: u64) -> Result<()> {
+        let (snapshot, _) = self.open_snapshot(id).await?;
+        let result = copy_stores(&self.inner, &snapshot).await;
+        snapshot.close();
+
+        result
+    }
+
+    async fn restore_snapshot(&self, id: u64) -> Result<bool> {
+        let (snapshot, created) = self.open_snapshot(id).await?;
+
+        if created {
+            // There was no such snapshot, don't keep the empty one around.
+            snapshot.delete()?.await?;
+            return Ok(false);
+        }
+
+        let result = copy_stores(&snapshot, &self.inner).await;
+        snapshot.close();
+
+        result.map(|()| true)
+    }
+
+    async fn remove_snapshot(&self, id: u64) -> Result<()> {
+        IdbDatabase::delete_by_name(&self.snapshot_name(id))?.await?;
+
+        Ok(())
+    }
+
     async fn get_user_ids(
         &self,
         room_id: &RoomId,
@@ -15,7 +15,7 @@
 use std::{
     borrow::Cow,
     collections::HashMap,
-    fmt,
+    fmt, io,
     path::Path,
     sync::{Arc, RwLock},
 };
@@ -34,10 +34,10 @@ use matrix_sdk_crypto::{
 use matrix_sdk_store_encryption::StoreCipher;
 use ruma::{
     events::secret::request::SecretName, DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
-    RoomId, TransactionId, UserId,
+    OwnedRoomId, RoomId, TransactionId, UserId,
 };
 use rusqlite::{named_params, params_from_iter, OptionalExtension};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{fs, sync::Mutex};
 use tracing::{debug, instrument, warn};
 use vodozemac::Curve25519PublicKey;
@@ -48,6 +48,7 @@ use crate::{
         repeat_vars, Key, SqliteAsyncConnExt, SqliteKeyValueStoreAsyncConnExt,
         SqliteKeyValueStoreConnExt,
     },
+    wipe::{close_pool, CLOSE_TIMEOUT, CRYPTO_STORE_DATABASE_NAME},
     OpenStoreError,
 };
 
@@ -78,7 +79,7 @@ impl SqliteCryptoStore {
     ) -> Result<Self, OpenStoreError> {
         let path = path.as_ref();
         fs::create_dir_all(path).await.map_err(OpenStoreError::CreateDir)?;
-        let cfg = deadpool_sqlite::Config::new(path.join("matrix-sdk-crypto.sqlite3"));
+        let cfg = deadpool_sqlite::Config::new(path.join(CRYPTO_STORE_DATABASE_NAME));
         let pool = cfg.create_pool(Runtime::Tokio1)?;
 
         Self::open_with_pool(pool, passphrase).await
@@ -106,6 +107,16 @@ impl SqliteCryptoStore {
         })
     }
 
+    /// Close the connections to the database.
+    ///
+    /// The store can't be used anymore afterwards: all its operations fail.
+    /// The connections that are in use are closed once they are released,
+    /// which this waits for. An error of kind [`io::ErrorKind::TimedOut`] is
+    /// returned if they aren't released after 10 seconds.
+    pub async fn close(&self) -> io::Result<()> {
+        close_pool(&self.pool, CLOSE_TIMEOUT).await
+    }
+
     fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
         if let Some(key) = &self.store_cipher {
             let encrypted = key.encrypt_value_data(value)?;
@@ -187,6 +198,19 @@ impl SqliteCryptoStore {
     }
 }
 
+/// The value stored in the `room_settings` table.
+///
+/// The room id is stored next to the settings because the key of the table
+/// is hashed when the store is encrypted. Values saved by older versions of
+/// the store don't contain it.
+#[derive(Deserialize, Serialize)]
+struct StoredRoomSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    room_id: Option<OwnedRoomId>,
+    #[serde(flatten)]
+    settings: RoomSettings,
+}
+
 const DATABASE_VERSION: u8 = 9;
 
 /// Run migrations for the given version of the database.
@@ -467,6 +491,14 @@ trait SqliteObjectCryptoStoreExt: SqliteAsyncConnExt {
             .await?)
     }
 
+    async fn get_all_sessions(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM session", |mut stmt| {
+                stmt.query(())?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
     async fn get_inbound_group_session(
         &self,
         session_id: Key,
@@ -489,6 +521,20 @@ trait SqliteObjectCryptoStoreExt: SqliteAsyncConnExt {
             .await?)
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: Key,
+    ) -> Result<Vec<(Vec<u8>, bool)>> {
+        Ok(self
+            .prepare(
+                "SELECT data, backed_up FROM inbound_group_session WHERE room_id = ?",
+                |mut stmt| {
+                    stmt.query((room_id,))?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+                },
+            )
+            .await?)
+    }
+
     async fn get_inbound_group_session_counts(
         &self,
         _backup_version: Option<&str>,
@@ -590,6 +636,14 @@ trait SqliteObjectCryptoStoreExt: SqliteAsyncConnExt {
             .optional()?)
     }
 
+    async fn get_all_outbound_group_sessions(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM outbound_group_session", |mut stmt| {
+                stmt.query(())?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
+
     async fn get_device(&self, user_id: Key, device_id: Key) -> Result<Option<Vec<u8>>> {
         Ok(self
             .query_row(
@@ -721,6 +775,14 @@ trait SqliteObjectCryptoStoreExt: SqliteAsyncConnExt {
             .await
             .optional()?)
     }
+
+    async fn get_all_room_settings(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .prepare("SELECT data FROM room_settings", |mut stmt| {
+                stmt.query(())?.mapped(|row| row.get(0)).collect()
+            })
+            .await?)
+    }
 }
 
 #[async_trait]
@@ -908,9 +970,12 @@ impl CryptoStore for SqliteCryptoStore {
                 }
 
                 for (room_id, settings) in changes.room_settings {
-                    let room_id = this.encode_key("room_settings", room_id.as_bytes());
-                    let value = this.serialize_value(&settings)?;
-                    txn.set_room_settings(&room_id, &value)?;
+                    let key = this.encode_key("room_settings", room_id.as_bytes());
+                    let value = this.serialize_value(&StoredRoomSettings {
+                        room_id: Some(room_id),
+                        settings,
+                    })?;
+                    txn.set_room_settings(&key, &value)?;
                 }
 
                 for secret in changes.secrets {
@@ -970,6 +1035,21 @@ impl CryptoStore for SqliteCryptoStore {
         }
     }
 
+    async fn get_all_sessions(&self) -> Result<Vec<Session>> {
+        let device_keys = self.get_own_device().await?.as_device_keys().clone();
+
+        self.acquire()
+            .await?
+            .get_all_sessions()
+            .await?
+            .into_iter()
+            .map(|bytes| {
+                let pickle = self.deserialize_value(&bytes)?;
+                Session::from_pickle(device_keys.clone(), pickle).map_err(|_| Error::AccountUnset)
+            })
+            .collect()
+    }
+
     #[instrument(skip(self))]
     async fn get_inbound_group_session(
         &self,
@@ -1004,6 +1084,23 @@ impl CryptoStore for SqliteCryptoStore {
             .collect()
     }
 
+    async fn get_inbound_group_sessions_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<InboundGroupSession>> {
+        let room_id = self.encode_key("inbound_group_session", room_id.as_bytes());
+
+        self.acquire()
+            .await?
+            .get_inbound_group_sessions_for_room(room_id)
+            .await?
+            .into_iter()
+            .map(|(value, backed_up)| {
+                self.deserialize_and_unpickle_inbound_group_session(value, backed_up)
+            })
+            .collect()
+    }
+
     async fn get_inbound_group_sessions_for_device_batch(
         &self,
         sender_key: Curve25519PublicKey,
@@ -1113,6 +1210,26 @@ impl CryptoStore for SqliteCryptoStore {
         return Ok(Some(session));
     }
 
+    async fn get_all_outbound_group_sessions(&self) -> Result<Vec<OutboundGroupSession>> {
+        let account_info = self.get_static_account().ok_or(Error::AccountUnset)?;
+
+        self.acquire()
+            .await?
+            .get_all_outbound_group_sessions()
+            .await?
+            .into_iter()
+            .map(|value| {
+                let pickle = self.deserialize_json(&value)?;
+                OutboundGroupSession::from_pickle(
+                    account_info.device_id.clone(),
+                    account_info.identity_keys.clone(),
+                    pickle,
+                )
+                .map_err(|_| Error::Unpickle)
+            })
+            .collect()
+    }
+
     async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>> {
         self.acquire()
             .await?
@@ -1289,11 +1406,25 @@ impl CryptoStore for SqliteCryptoStore {
             return Ok(None);
         };
 
-        let settings = self.deserialize_value(&value)?;
+        let StoredRoomSettings { settings, .. } = self.deserialize_value(&value)?;
 
         return Ok(Some(settings));
     }
 
+    async fn get_all_room_settings(&self) -> Result<HashMap<OwnedRoomId, RoomSettings>> {
+        let mut all_settings = HashMap::new();
+
+        for value in self.acquire().await?.get_all_room_settings().await? {
+            let StoredRoomSettings { room_id, settings } = self.deserialize_value(&value)?;
+
+            if let Some(room_id) = room_id {
+                all_settings.insert(room_id, settings);
+            }
+        }
+
+        Ok(all_settings)
+    }
+
     async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>> {
         let Some(serialized) = self.acquire().await?.get_kv(key).await? else {
             return Ok(None);
@@ -16,7 +16,7 @@
 
 #![allow(dead_code)] // Most of the unused code may be used soonish.
 
-use std::{borrow::Cow, fmt, path::Path, sync::Arc};
+use std::{borrow::Cow, fmt, io, path::Path, sync::Arc};
 
 use async_trait::async_trait;
 use deadpool_sqlite::{Object as SqliteAsyncConn, Pool as SqlitePool, Runtime};
@@ -37,6 +37,7 @@ use tracing::{debug, trace};
 use crate::{
     error::{Error, Result},
     utils::{Key, SqliteAsyncConnExt, SqliteKeyValueStoreAsyncConnExt, SqliteKeyValueStoreConnExt},
+    wipe::{close_pool, CLOSE_TIMEOUT, EVENT_CACHE_STORE_DATABASE_NAME},
     OpenStoreError,
 };
 
@@ -104,6 +105,16 @@ impl SqliteEventCacheStore {
         Ok(Self { store_cipher, pool })
     }
 
+    /// Close the connections to the database.
+    ///
+    /// The store can't be used anymore afterwards: all its operations fail.
+    /// The connections that are in use are closed once they are released,
+    /// which this waits for. An error of kind [`io::ErrorKind::TimedOut`] is
+    /// returned if they aren't released after 10 seconds.
+    pub async fn close(&self) -> io::Result<()> {
+        close_pool(&self.pool, CLOSE_TIMEOUT).await
+    }
+
     fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
         if let Some(key) = &self.store_cipher {
             let encrypted = key.encrypt_value_data(value)?;
@@ -327,7 +338,7 @@ struct RawLinkedChunk {
 
 async fn create_pool(path: &Path) -> Result<SqlitePool, OpenStoreError> {
     fs::create_dir_all(path).await.map_err(OpenStoreError::CreateDir)?;
-    let cfg = deadpool_sqlite::Config::new(path.join("matrix-sdk-event-cache.sqlite3"));
+    let cfg = deadpool_sqlite::Config::new(path.join(EVENT_CACHE_STORE_DATABASE_NAME));
     Ok(cfg.create_pool(Runtime::Tokio1)?)
 }
 
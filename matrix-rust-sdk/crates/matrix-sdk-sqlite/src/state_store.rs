@@ -1,7 +1,7 @@
 use std::{
     borrow::Cow,
     collections::{BTreeMap, BTreeSet, HashMap},
-    fmt, iter,
+    fmt, io, iter,
     path::Path,
     sync::Arc,
 };
@@ -46,6 +46,7 @@ use crate::{
         repeat_vars, Key, SqliteAsyncConnExt, SqliteKeyValueStoreAsyncConnExt,
         SqliteKeyValueStoreConnExt,
     },
+    wipe::{close_pool, CLOSE_TIMEOUT, STATE_STORE_DATABASE_NAME},
     OpenStoreError,
 };
 
@@ -64,6 +65,30 @@ mod keys {
     pub const DEPENDENTS_SEND_QUEUE: &str = "dependent_send_queue_events";
 }
 
+/// The tables holding the data of the store, which are copied in the
+/// snapshots.
+///
+/// The send queues aren't part of them, so that restoring a snapshot neither
+/// drops the requests waiting to be sent, nor sends again the ones which have
+/// already been sent.
+const SNAPSHOT_TABLES: &[&str] = &[
+    keys::KV_BLOB,
+    keys::ROOM_INFO,
+    keys::STATE_EVENT,
+    keys::GLOBAL_ACCOUNT_DATA,
+    keys::ROOM_ACCOUNT_DATA,
+    keys::MEMBER,
+    keys::PROFILE,
+    keys::RECEIPT,
+    keys::DISPLAY_NAME,
+];
+
+/// The name of the table holding the copy of `table` in the snapshot with the
+/// given identifier.
+fn snapshot_table_name(id: u64, table: &str) -> String {
+    format!("snapshot_{id}_{table}")
+}
+
 /// Identifier of the latest database version.
 ///
 /// This is used to figure whether the sqlite database requires a migration.
@@ -121,6 +146,16 @@ impl SqliteStateStore {
         Ok(this)
     }
 
+    /// Close the connections to the database.
+    ///
+    /// The store can't be used anymore afterwards: all its operations fail.
+    /// The connections that are in use are closed once they are released,
+    /// which this waits for. An error of kind [`io::ErrorKind::TimedOut`] is
+    /// returned if they aren't released after 10 seconds.
+    pub async fn close(&self) -> io::Result<()> {
+        close_pool(&self.pool, CLOSE_TIMEOUT).await
+    }
+
     /// Run database migrations from the given `from` version to the given `to`
     /// version
     ///
@@ -425,7 +460,7 @@ impl SqliteStateStore {
 
 async fn create_pool(path: &Path) -> Result<SqlitePool, OpenStoreError> {
     fs::create_dir_all(path).await.map_err(OpenStoreError::CreateDir)?;
-    let cfg = deadpool_sqlite::Config::new(path.join("matrix-sdk-state.sqlite3"));
+    let cfg = deadpool_sqlite::Config::new(path.join(STATE_STORE_DATABASE_NAME));
     Ok(cfg.create_pool(Runtime::Tokio1)?)
 }
 
@@ -1742,6 +1777,64 @@ impl StateStore for SqliteStateStore {
             .await
     }
 
+    async fn save_snapshot(&self, id: u64) -> Result<()> {
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                for table in SNAPSHOT_TABLES {
+                    let snapshot = snapshot_table_name(id, table);
+                    txn.execute_batch(&format!(
+                        "DROP TABLE IF EXISTS \"{snapshot}\"; \
+                         CREATE TABLE \"{snapshot}\" AS SELECT * FROM \"{table}\";"
+                    ))?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn restore_snapshot(&self, id: u64) -> Result<bool> {
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                let exists: bool = txn.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)",
+                    (snapshot_table_name(id, keys::KV_BLOB),),
+                    |row| row.get(0),
+                )?;
+
+                if !exists {
+                    return Ok(false);
+                }
+
+                for table in SNAPSHOT_TABLES {
+                    let snapshot = snapshot_table_name(id, table);
+                    txn.execute_batch(&format!(
+                        "DELETE FROM \"{table}\"; \
+                         INSERT INTO \"{table}\" SELECT * FROM \"{snapshot}\";"
+                    ))?;
+                }
+
+                Ok(true)
+            })
+            .await
+    }
+
+    async fn remove_snapshot(&self, id: u64) -> Result<()> {
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                for table in SNAPSHOT_TABLES {
+                    let snapshot = snapshot_table_name(id, table);
+                    txn.execute_batch(&format!("DROP TABLE IF EXISTS \"{snapshot}\";"))?;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
     async fn save_send_queue_request(
         &self,
         room_id: &RoomId,
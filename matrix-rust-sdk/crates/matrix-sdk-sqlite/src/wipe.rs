@@ -0,0 +1,233 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use deadpool_sqlite::Pool as SqlitePool;
+use tokio::{
+    fs,
+    time::{sleep, timeout},
+};
+
+/// The name of the database file of the state store.
+pub(crate) const STATE_STORE_DATABASE_NAME: &str = "matrix-sdk-state.sqlite3";
+
+/// The name of the database file of the crypto store.
+pub(crate) const CRYPTO_STORE_DATABASE_NAME: &str = "matrix-sdk-crypto.sqlite3";
+
+/// The name of the database file of the event cache store.
+pub(crate) const EVENT_CACHE_STORE_DATABASE_NAME: &str = "matrix-sdk-event-cache.sqlite3";
+
+/// The suffixes of the files that SQLite creates next to a database.
+const DATABASE_FILE_SUFFIXES: [&str; 4] = ["", "-wal", "-shm", "-journal"];
+
+/// How long to wait for the connections in use to be released when closing a
+/// store.
+pub(crate) const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Close the connections of the given pool, and wait until they are all
+/// closed, including the ones that were in use.
+///
+/// Returns an error of kind [`io::ErrorKind::TimedOut`] if some connections
+/// are still in use after `max_wait`. The pool is closed anyway, so they're
+/// closed as soon as they're released.
+pub(crate) async fn close_pool(pool: &SqlitePool, max_wait: Duration) -> io::Result<()> {
+    pool.close();
+
+    // The connections that are in use are only dropped once they are released.
+    let released = async {
+        while pool.status().size > 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    timeout(max_wait, released).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "{} connections to the database were still in use after {max_wait:?}",
+                pool.status().size
+            ),
+        )
+    })
+}
+
+/// All the files of the databases of the stores in the given directory.
+fn database_files(path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    [STATE_STORE_DATABASE_NAME, CRYPTO_STORE_DATABASE_NAME, EVENT_CACHE_STORE_DATABASE_NAME]
+        .into_iter()
+        .flat_map(|name| DATABASE_FILE_SUFFIXES.map(|suffix| format!("{name}{suffix}")))
+        .map(|file_name| path.join(file_name))
+}
+
+/// Delete the databases of the SQLite stores in the given directory.
+///
+/// The databases of the state store, the crypto store and the event cache
+/// store are deleted, with the temporary files of SQLite, whether they are
+/// encrypted or not. The directory itself is removed if it's empty afterwards.
+///
+/// The stores using these databases must be closed first, e.g. with
+/// [`SqliteStateStore::close`]. An error is returned if a file was created
+/// again during the deletion, by a connection that was still open.
+///
+/// Returns the paths of the deleted files.
+///
+/// [`SqliteStateStore::close`]: crate::SqliteStateStore::close
+pub async fn delete_stores(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let mut deleted = Vec::new();
+
+    for file in database_files(path) {
+        match fs::remove_file(&file).await {
+            Ok(()) => deleted.push(file),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    for file in database_files(path) {
+        if fs::try_exists(&file).await? {
+            return Err(io::Error::other(format!(
+                "{} was created again, is a store still open?",
+                file.display()
+            )));
+        }
+    }
+
+    // The directory might be shared with other files, only remove it if the
+    // stores were the only thing in it.
+    match fs::read_dir(path).await {
+        Ok(mut entries) => {
+            if entries.next_entry().await?.is_none() {
+                fs::remove_dir(path).await?;
+            }
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(all(test, feature = "state-store", feature = "event-cache"))]
+mod tests {
+    use std::{io, time::Duration};
+
+    use deadpool_sqlite::{Config, Runtime};
+    use matrix_sdk_base::{StateStore, StateStoreDataKey, StateStoreDataValue};
+    use matrix_sdk_test::async_test;
+    use tempfile::tempdir;
+    use tokio::{spawn, time::sleep};
+
+    use super::{
+        close_pool, delete_stores, CLOSE_TIMEOUT, EVENT_CACHE_STORE_DATABASE_NAME,
+        STATE_STORE_DATABASE_NAME,
+    };
+    use crate::{SqliteEventCacheStore, SqliteStateStore};
+
+    #[async_test]
+    async fn test_delete_stores() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("stores");
+
+        let state_store = SqliteStateStore::open(&path, Some("passphrase")).await.unwrap();
+        let event_cache_store = SqliteEventCacheStore::open(&path, None).await.unwrap();
+        state_store
+            .set_kv_data(
+                StateStoreDataKey::SyncToken,
+                StateStoreDataValue::SyncToken("s1".to_owned()),
+            )
+            .await
+            .unwrap();
+
+        state_store.close().await.unwrap();
+        event_cache_store.close().await.unwrap();
+
+        // A closed store can't be used anymore.
+        state_store.get_kv_data(StateStoreDataKey::SyncToken).await.unwrap_err();
+
+        let deleted = delete_stores(&path).await.unwrap();
+        assert!(deleted.contains(&path.join(STATE_STORE_DATABASE_NAME)));
+        assert!(deleted.contains(&path.join(EVENT_CACHE_STORE_DATABASE_NAME)));
+
+        // No file remains, not even the directory.
+        assert!(!path.exists());
+
+        // Deleting the stores again is a no-op.
+        assert!(delete_stores(&path).await.unwrap().is_empty());
+    }
+
+    #[async_test]
+    async fn test_delete_stores_keeps_other_files() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("stores");
+
+        let state_store = SqliteStateStore::open(&path, None).await.unwrap();
+        state_store.close().await.unwrap();
+        std::fs::write(path.join("session.yaml"), "").unwrap();
+
+        delete_stores(&path).await.unwrap();
+
+        let remaining: Vec<_> =
+            std::fs::read_dir(&path).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(remaining, ["session.yaml"]);
+    }
+
+    #[async_test]
+    async fn test_close_pool_waits_for_connections_in_use() {
+        let tmpdir = tempdir().unwrap();
+        let pool =
+            Config::new(tmpdir.path().join("test.sqlite3")).create_pool(Runtime::Tokio1).unwrap();
+
+        let conn = pool.get().await.unwrap();
+
+        let close = spawn({
+            let pool = pool.clone();
+            async move { close_pool(&pool, CLOSE_TIMEOUT).await }
+        });
+
+        // The connection is still in use, so the pool isn't closed yet.
+        sleep(Duration::from_millis(50)).await;
+        assert!(!close.is_finished());
+        assert!(pool.is_closed());
+
+        // Once it's released, it's closed.
+        drop(conn);
+        close.await.unwrap().unwrap();
+        assert_eq!(pool.status().size, 0);
+    }
+
+    #[async_test]
+    async fn test_close_pool_times_out() {
+        let tmpdir = tempdir().unwrap();
+        let pool =
+            Config::new(tmpdir.path().join("test.sqlite3")).create_pool(Runtime::Tokio1).unwrap();
+
+        let conn = pool.get().await.unwrap();
+
+        // The connection isn't released in time.
+        let error = close_pool(&pool, Duration::from_millis(50)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(pool.is_closed());
+
+        // It's still closed once it's released.
+        drop(conn);
+        close_pool(&pool, CLOSE_TIMEOUT).await.unwrap();
+        assert_eq!(pool.status().size, 0);
+    }
+}
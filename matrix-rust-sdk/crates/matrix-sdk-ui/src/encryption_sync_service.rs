@@ -31,7 +31,7 @@ use std::{pin::Pin, time::Duration};
 use async_stream::stream;
 use futures_core::stream::Stream;
 use futures_util::{pin_mut, StreamExt};
-use matrix_sdk::{Client, SlidingSync, LEASE_DURATION_MS};
+use matrix_sdk::{sync_checkpoints::SyncCheckpoint, Client, SlidingSync, LEASE_DURATION_MS};
 use matrix_sdk_base::sliding_sync::http;
 use ruma::assign;
 use tokio::sync::OwnedMutexGuard;
@@ -311,6 +311,17 @@ impl EncryptionSyncService {
     pub(crate) async fn expire_sync_session(&self) {
         self.sliding_sync.expire_session().await;
     }
+
+    /// Resume from the position of the given sync checkpoint, once it has been
+    /// restored with [`Client::restore_checkpoint`].
+    ///
+    /// Returns false if the checkpoint has no position for the encryption
+    /// sync, in which case nothing changes.
+    ///
+    /// This method **MUST** be called when the sync loop is stopped.
+    pub async fn restore_checkpoint_position(&self, checkpoint: &SyncCheckpoint) -> bool {
+        self.sliding_sync.restore_checkpoint_position(checkpoint).await
+    }
 }
 
 /// Errors for the [`EncryptionSyncService`].
@@ -0,0 +1,318 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for that specific language governing permissions and
+// limitations under the License.
+
+//! Builder and list definitions for the `RoomListService`.
+
+use std::sync::{atomic::AtomicBool, Arc};
+
+use matrix_sdk::{
+    sliding_sync::{Range, Version},
+    Client, Room, RoomState, SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
+};
+use matrix_sdk_base::sliding_sync::http;
+use ruma::{assign, directory::RoomTypeFilter, events::StateEventType};
+
+use super::{
+    classic_sync::ClassicSync, subscriptions::RoomSubscriptions, Error, RoomListService,
+    StateMachine, ALL_ROOMS_DEFAULT_GROWING_BATCH_SIZE, ALL_ROOMS_DEFAULT_SELECTIVE_RANGE,
+    ALL_ROOMS_LIST_NAME, DEFAULT_REQUIRED_STATE,
+};
+
+/// The definition of a sliding sync list managed by the [`RoomListService`].
+///
+/// Like the `all_rooms` list, every list starts with a
+/// [`SlidingSyncMode::Selective`] sync-mode over
+/// [`Self::selective_range`], and then switches to a
+/// [`SlidingSyncMode::Growing`] sync-mode with [`Self::growing_batch_size`]
+/// once the first rooms have been synced.
+#[derive(Clone, Debug)]
+pub struct RoomListDefinition {
+    pub(super) name: String,
+    required_state: Vec<(StateEventType, String)>,
+    timeline_limit: u32,
+    include_heroes: bool,
+    filters: Option<http::request::ListFilters>,
+    pub(super) selective_range: Range,
+    pub(super) growing_batch_size: u32,
+}
+
+impl RoomListDefinition {
+    /// Create a new list definition with the given name, and the same
+    /// parameters as the `all_rooms` list, except its filters.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            required_state: DEFAULT_REQUIRED_STATE
+                .iter()
+                .map(|(state_event, value)| (state_event.clone(), (*value).to_owned()))
+                .collect(),
+            timeline_limit: 1,
+            include_heroes: true,
+            filters: None,
+            selective_range: ALL_ROOMS_DEFAULT_SELECTIVE_RANGE,
+            growing_batch_size: ALL_ROOMS_DEFAULT_GROWING_BATCH_SIZE,
+        }
+    }
+
+    /// The definition of the `all_rooms` list, referred by the constant
+    /// [`ALL_ROOMS_LIST_NAME`].
+    pub fn all_rooms() -> Self {
+        Self::new(ALL_ROOMS_LIST_NAME).filters(Some(assign!(
+            http::request::ListFilters::default(),
+            {
+                // As defined in the [SlidingSync MSC](https://github.com/matrix-org/matrix-spec-proposals/blob/9450ced7fb9cf5ea9077d029b3adf36aebfa8709/proposals/3575-sync.md?plain=1#L444)
+                // If unset, both invited and joined rooms are returned. If false, no invited rooms are
+                // returned. If true, only invited rooms are returned.
+                is_invite: None,
+                not_room_types: vec![RoomTypeFilter::Space],
+            }
+        )))
+    }
+
+    /// The name of the list.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the `required_state` of the rooms of this list.
+    pub fn required_state(mut self, required_state: Vec<(StateEventType, String)>) -> Self {
+        self.required_state = required_state;
+        self
+    }
+
+    /// Set the `timeline_limit` of the rooms of this list.
+    pub fn timeline_limit(mut self, timeline_limit: u32) -> Self {
+        self.timeline_limit = timeline_limit;
+        self
+    }
+
+    /// Set whether the heroes of the rooms of this list must be included.
+    pub fn include_heroes(mut self, include_heroes: bool) -> Self {
+        self.include_heroes = include_heroes;
+        self
+    }
+
+    /// Set the filters of this list, see [`SlidingSyncListBuilder::filters`].
+    pub fn filters(mut self, filters: Option<http::request::ListFilters>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Set the range used by the selective sync-mode, i.e. the rooms loaded
+    /// first.
+    pub fn selective_range(mut self, range: Range) -> Self {
+        self.selective_range = range;
+        self
+    }
+
+    /// Set the batch size used by the growing sync-mode, i.e. when loading the
+    /// remaining rooms.
+    pub fn growing_batch_size(mut self, batch_size: u32) -> Self {
+        self.growing_batch_size = batch_size;
+        self
+    }
+
+    /// Whether the given room belongs to this list, according to its filters.
+    ///
+    /// It's only used when falling back on `/sync`, to count the rooms of the
+    /// list, since the homeserver doesn't count them.
+    pub(super) fn contains(&self, room: &Room) -> bool {
+        let is_invite = self.filters.as_ref().and_then(|filters| filters.is_invite);
+
+        let has_state = match room.state() {
+            RoomState::Joined => is_invite != Some(true),
+            RoomState::Invited => is_invite != Some(false),
+            RoomState::Left | RoomState::Knocked => false,
+        };
+
+        let room_type = RoomTypeFilter::from(room.room_type());
+        let has_type = self.filters.as_ref().map_or(true, |filters| {
+            !filters.not_room_types.iter().any(|filter| filter.as_str() == room_type.as_str())
+        });
+
+        has_state && has_type
+    }
+
+    fn sliding_sync_list_builder(&self) -> SlidingSyncListBuilder {
+        SlidingSyncList::builder(&self.name)
+            .sync_mode(SlidingSyncMode::new_selective().add_range(self.selective_range.clone()))
+            .timeline_limit(self.timeline_limit)
+            .required_state(self.required_state.clone())
+            .include_heroes(Some(self.include_heroes))
+            .filters(self.filters.clone())
+    }
+}
+
+/// A builder for a [`RoomListService`].
+///
+/// By default, the service has a single `all_rooms` list, and enables the
+/// account data, receipt and typing extensions.
+#[derive(Clone, Debug)]
+pub struct RoomListServiceBuilder {
+    client: Client,
+    lists: Vec<RoomListDefinition>,
+    account_data_extension: bool,
+    receipt_extension: bool,
+    typing_extension: bool,
+    presence_extension: bool,
+    max_room_subscriptions: Option<usize>,
+    classic_sync: bool,
+}
+
+impl RoomListServiceBuilder {
+    pub(super) fn new(client: Client) -> Self {
+        Self {
+            client,
+            lists: vec![RoomListDefinition::all_rooms()],
+            account_data_extension: true,
+            receipt_extension: true,
+            typing_extension: true,
+            presence_extension: false,
+            max_room_subscriptions: None,
+            classic_sync: false,
+        }
+    }
+
+    /// Add a list to the service.
+    ///
+    /// A list with the same name replaces the previous one, including the
+    /// `all_rooms` list.
+    pub fn add_list(mut self, list: RoomListDefinition) -> Self {
+        match self.lists.iter_mut().find(|existing| existing.name == list.name) {
+            Some(existing) => *existing = list,
+            None => self.lists.push(list),
+        }
+        self
+    }
+
+    /// Enable or disable the account data extension.
+    pub fn account_data_extension(mut self, enabled: bool) -> Self {
+        self.account_data_extension = enabled;
+        self
+    }
+
+    /// Enable or disable the receipt extension, for all the subscribed rooms.
+    pub fn receipt_extension(mut self, enabled: bool) -> Self {
+        self.receipt_extension = enabled;
+        self
+    }
+
+    /// Enable or disable the typing extension.
+    pub fn typing_extension(mut self, enabled: bool) -> Self {
+        self.typing_extension = enabled;
+        self
+    }
+
+    /// Enable or disable the presence extension, which feeds
+    /// [`Client::presence`].
+    ///
+    /// It's disabled by default, because it isn't supported by all the
+    /// servers yet, see [`Presence::sliding_sync_extension`].
+    ///
+    /// [`Presence::sliding_sync_extension`]: matrix_sdk::Presence::sliding_sync_extension
+    pub fn presence_extension(mut self, enabled: bool) -> Self {
+        self.presence_extension = enabled;
+        self
+    }
+
+    /// Set the maximum number of room subscriptions.
+    ///
+    /// When a new room subscription would exceed this maximum, the least
+    /// recently used subscription without a handle, see
+    /// [`RoomListService::subscribe_to_rooms`], is evicted. The rooms with live
+    /// handles are never evicted, so the maximum can be exceeded if all the
+    /// subscriptions have handles. By default, there is no maximum.
+    pub fn max_room_subscriptions(mut self, max: usize) -> Self {
+        self.max_room_subscriptions = Some(max);
+        self
+    }
+
+    /// Fall back on `/sync` v2 instead of sliding sync from the start, because
+    /// the homeserver is known not to support it.
+    ///
+    /// See [`RoomListService::fall_back_on_classic_sync`] to fall back later.
+    pub(crate) fn with_classic_sync(mut self) -> Self {
+        self.classic_sync = true;
+        self
+    }
+
+    /// Build the [`RoomListService`].
+    ///
+    /// All the lists are cached, and reloaded from the cache if possible.
+    pub async fn build(self) -> Result<RoomListService, Error> {
+        let mut builder = self.client.sliding_sync("room-list").map_err(Error::SlidingSync)?;
+
+        if self.account_data_extension {
+            builder = builder.with_account_data_extension(
+                assign!(http::request::AccountData::default(), { enabled: Some(true) }),
+            );
+        }
+
+        if self.receipt_extension {
+            builder = builder.with_receipt_extension(assign!(http::request::Receipts::default(), {
+                enabled: Some(true),
+                rooms: Some(vec![http::request::ReceiptsRoom::AllSubscribed])
+            }));
+        }
+
+        if self.typing_extension {
+            builder = builder.with_typing_extension(assign!(http::request::Typing::default(), {
+                enabled: Some(true),
+            }));
+        }
+
+        if self.presence_extension {
+            builder =
+                builder.with_custom_extension(self.client.presence().sliding_sync_extension());
+        }
+
+        if self.classic_sync {
+            // The sliding sync is never run, but it still holds the lists and the room
+            // subscriptions, so it must be built even without a sliding sync version.
+            builder = builder.version(Version::Native);
+        }
+
+        // TODO: Re-enable once we know it creates slowness.
+        // // We don't deal with encryption device messages here so this is safe
+        // .share_pos();
+
+        for list in &self.lists {
+            builder = builder
+                .add_cached_list(list.sliding_sync_list_builder())
+                .await
+                .map_err(Error::SlidingSync)?;
+        }
+
+        let sliding_sync = builder.build().await.map(Arc::new).map_err(Error::SlidingSync)?;
+
+        // Eagerly subscribe the event cache to sync responses.
+        self.client.event_cache().subscribe()?;
+
+        let state_machine = StateMachine::new(self.lists);
+        let subscriptions = Arc::new(RoomSubscriptions::new(
+            sliding_sync.clone(),
+            state_machine.subscribe(),
+            self.max_room_subscriptions,
+        ));
+
+        Ok(RoomListService {
+            classic_sync: ClassicSync::new(self.client.clone()),
+            uses_classic_sync: AtomicBool::new(self.classic_sync),
+            client: self.client,
+            sliding_sync,
+            state_machine,
+            subscriptions,
+        })
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A fallback on `/sync` v2 for the `RoomListService`, for homeservers that
+//! don't support sliding sync.
+
+use std::{sync::Mutex, time::Duration};
+
+use async_stream::stream;
+use futures_util::{pin_mut, Stream, StreamExt as _};
+use matrix_sdk::{config::SyncSettings, Client, Error};
+use ruma::{
+    api::client::{filter::FilterDefinition, sync::sync_events::v3::Filter},
+    UInt,
+};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// The name of the filter uploaded for the `/sync` requests.
+const FILTER_NAME: &str = "room-list-service";
+
+/// The `timeline_limit` of the rooms, the same as the one of the room
+/// subscriptions.
+const TIMELINE_LIMIT: u32 = 20;
+
+/// How long the homeserver can wait before responding to a `/sync` request.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs `/sync` v2 in a loop, instead of sliding sync.
+///
+/// The responses are processed by the client like any other sync response, so
+/// the rooms, their latest events and the end-to-end encryption are kept up to
+/// date the same way.
+#[derive(Debug)]
+pub(super) struct ClassicSync {
+    client: Client,
+
+    /// The cancellation of the latest run of the sync loop, cancelled by
+    /// [`Self::stop`].
+    run: Mutex<CancellationToken>,
+}
+
+impl ClassicSync {
+    pub(super) fn new(client: Client) -> Self {
+        Self { client, run: Mutex::new(CancellationToken::new()) }
+    }
+
+    /// Prepare a new run of the sync loop, that [`Self::stop`] stops from now
+    /// on, even if [`Self::sync`] hasn't been called with it yet.
+    pub(super) fn new_run(&self) -> CancellationToken {
+        let run = CancellationToken::new();
+        *self.run.lock().unwrap() = run.clone();
+        run
+    }
+
+    /// Run the sync loop, until the run is cancelled by [`Self::stop`] or an
+    /// error happens.
+    ///
+    /// The sync resumes from the last sync token of the client.
+    pub(super) fn sync(
+        &self,
+        run: CancellationToken,
+    ) -> impl Stream<Item = Result<(), Error>> + '_ {
+        stream! {
+            if run.is_cancelled() {
+                debug!("The sync loop has been stopped before it started");
+                return;
+            }
+
+            let settings = match self.sync_settings().await {
+                Ok(settings) => settings,
+                Err(error) => {
+                    yield Err(error);
+                    return;
+                }
+            };
+
+            let sync = self.client.sync_stream(settings).await;
+            pin_mut!(sync);
+
+            loop {
+                let response = select! {
+                    biased;
+
+                    _ = run.cancelled() => {
+                        debug!("The sync loop has been stopped");
+                        None
+                    }
+
+                    response = sync.next() => response,
+                };
+
+                match response {
+                    Some(Ok(_response)) => yield Ok(()),
+
+                    Some(Err(error)) => {
+                        yield Err(error);
+                        break;
+                    }
+
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Stop the latest run of the sync loop, cancelling any in-flight request.
+    ///
+    /// A run that has already finished isn't affected, and neither are the
+    /// next ones.
+    pub(super) fn stop(&self) {
+        self.run.lock().unwrap().cancel();
+    }
+
+    /// Get the settings of the `/sync` requests, uploading the filter if
+    /// needed.
+    async fn sync_settings(&self) -> Result<SyncSettings, Error> {
+        let mut filter = FilterDefinition::with_lazy_loading();
+        filter.room.timeline.limit = Some(UInt::from(TIMELINE_LIMIT));
+
+        let filter_id = self.client.get_or_upload_filter(FILTER_NAME, filter).await?;
+
+        Ok(SyncSettings::new().timeout(SYNC_TIMEOUT).filter(Filter::FilterId(filter_id)))
+    }
+}
@@ -70,6 +70,8 @@ use std::sync::Arc;
 pub use all::new_filter as new_filter_all;
 pub use any::new_filter as new_filter_any;
 pub use category::{new_filter as new_filter_category, RoomCategory};
+#[cfg(test)]
+use eyeball::SharedObservable;
 pub use favourite::new_filter as new_filter_favourite;
 pub use fuzzy_match_room_name::new_filter as new_filter_fuzzy_match_room_name;
 pub use invite::new_filter as new_filter_invite;
@@ -93,6 +95,8 @@ use wiremock::{
 };
 
 use super::Room;
+#[cfg(test)]
+use super::{subscriptions::RoomSubscriptions, State};
 
 /// A trait “alias” that represents a _filter_.
 ///
@@ -135,7 +139,14 @@ pub(super) async fn new_rooms<const N: usize>(
 
     let _response = client.sync_once(Default::default()).await.unwrap();
 
-    room_ids.map(|room_id| Room::new(client.get_room(room_id).unwrap(), sliding_sync))
+    let subscriptions = Arc::new(RoomSubscriptions::new(
+        sliding_sync.clone(),
+        SharedObservable::new(State::Init).subscribe(),
+        None,
+    ));
+
+    room_ids
+        .map(|room_id| Room::new(client.get_room(room_id).unwrap(), sliding_sync, &subscriptions))
 }
 
 #[cfg(test)]
@@ -43,6 +43,14 @@
 //! This behavior has proven to be empirically satisfying to provide a fast and
 //! fluid user experience for a Matrix client.
 //!
+//! [`SlidingSyncMode::Selective`]: matrix_sdk::SlidingSyncMode::Selective
+//! [`SlidingSyncMode::Growing`]: matrix_sdk::SlidingSyncMode::Growing
+//!
+//! More lists can be defined with [`RoomListService::builder`], e.g. to only
+//! sync the invites, with their own `required_state`, timeline limit and
+//! filters. They follow the same selective-then-growing sync-modes, and can be
+//! obtained with [`RoomListService::list`].
+//!
 //! [`RoomListService::all_rooms`] provides a way to get a [`RoomList`] for all
 //! the rooms. From that, calling [`RoomList::entries_with_dynamic_adapters`]
 //! provides a way to get a stream of rooms. This stream is sorted, can be
@@ -51,28 +59,45 @@
 //! [`RoomListService::state`] provides a way to get a stream of the state
 //! machine's state, which can be pretty helpful for the client app.
 
+mod builder;
+mod classic_sync;
 pub mod filters;
 mod room;
 mod room_list;
 pub mod sorters;
 mod state;
-
-use std::{sync::Arc, time::Duration};
+mod subscriptions;
+
+use std::{
+    future::ready,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_stream::stream;
+pub use builder::*;
+use classic_sync::ClassicSync;
 use eyeball::Subscriber;
-use futures_util::{pin_mut, Stream, StreamExt};
+use futures_util::{future::Either, pin_mut, Stream, StreamExt};
 use matrix_sdk::{
-    event_cache::EventCacheError, Client, Error as SlidingSyncError, SlidingSync, SlidingSyncList,
-    SlidingSyncMode,
+    event_cache::EventCacheError, sync_checkpoints::SyncCheckpoint, Client,
+    Error as SlidingSyncError, SlidingSync,
 };
 use matrix_sdk_base::sliding_sync::http;
 pub use room::*;
 pub use room_list::*;
-use ruma::{assign, directory::RoomTypeFilter, events::StateEventType, OwnedRoomId, RoomId, UInt};
+use ruma::{
+    api::client::error::ErrorKind, assign, events::StateEventType, OwnedRoomId, RoomId, UInt,
+};
 pub use state::*;
+pub use subscriptions::RoomSubscriptionHandle;
+use subscriptions::RoomSubscriptions;
 use thiserror::Error;
 use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
 use crate::timeline;
@@ -102,6 +127,22 @@ const DEFAULT_ROOM_SUBSCRIPTION_EXTRA_REQUIRED_STATE: &[(StateEventType, &str)]
 /// The default `timeline_limit` value when used with room subscriptions.
 const DEFAULT_ROOM_SUBSCRIPTION_TIMELINE_LIMIT: u32 = 20;
 
+/// The default settings of a room subscription.
+fn default_room_subscription() -> http::request::RoomSubscription {
+    assign!(http::request::RoomSubscription::default(), {
+        required_state: DEFAULT_REQUIRED_STATE.iter().map(|(state_event, value)| {
+            (state_event.clone(), (*value).to_owned())
+        })
+        .chain(
+            DEFAULT_ROOM_SUBSCRIPTION_EXTRA_REQUIRED_STATE.iter().map(|(state_event, value)| {
+                (state_event.clone(), (*value).to_owned())
+            })
+        )
+        .collect(),
+        timeline_limit: UInt::from(DEFAULT_ROOM_SUBSCRIPTION_TIMELINE_LIMIT),
+    })
+}
+
 /// The [`RoomListService`] type. See the module's documentation to learn more.
 #[derive(Debug)]
 pub struct RoomListService {
@@ -115,6 +156,16 @@ pub struct RoomListService {
     ///
     /// `RoomListService` is a simple state-machine.
     state_machine: StateMachine,
+
+    /// The room subscriptions, with their handles.
+    subscriptions: Arc<RoomSubscriptions>,
+
+    /// The fallback on `/sync` v2, used instead of the sliding sync when the
+    /// homeserver doesn't support it.
+    classic_sync: ClassicSync,
+
+    /// Whether [`Self::classic_sync`] is used instead of the sliding sync.
+    uses_classic_sync: AtomicBool,
 }
 
 impl RoomListService {
@@ -127,57 +178,16 @@ impl RoomListService {
     /// to create one in this case using
     /// [`EncryptionSyncService`][crate::encryption_sync_service::EncryptionSyncService].
     pub async fn new(client: Client) -> Result<Self, Error> {
-        let builder = client
-            .sliding_sync("room-list")
-            .map_err(Error::SlidingSync)?
-            .with_account_data_extension(
-                assign!(http::request::AccountData::default(), { enabled: Some(true) }),
-            )
-            .with_receipt_extension(assign!(http::request::Receipts::default(), {
-                enabled: Some(true),
-                rooms: Some(vec![http::request::ReceiptsRoom::AllSubscribed])
-            }))
-            .with_typing_extension(assign!(http::request::Typing::default(), {
-                enabled: Some(true),
-            }));
-        // TODO: Re-enable once we know it creates slowness.
-        // // We don't deal with encryption device messages here so this is safe
-        // .share_pos();
-
-        let sliding_sync = builder
-            .add_cached_list(
-                SlidingSyncList::builder(ALL_ROOMS_LIST_NAME)
-                    .sync_mode(
-                        SlidingSyncMode::new_selective()
-                            .add_range(ALL_ROOMS_DEFAULT_SELECTIVE_RANGE),
-                    )
-                    .timeline_limit(1)
-                    .required_state(
-                        DEFAULT_REQUIRED_STATE
-                            .iter()
-                            .map(|(state_event, value)| (state_event.clone(), (*value).to_owned()))
-                            .collect(),
-                    )
-                    .include_heroes(Some(true))
-                    .filters(Some(assign!(http::request::ListFilters::default(), {
-                        // As defined in the [SlidingSync MSC](https://github.com/matrix-org/matrix-spec-proposals/blob/9450ced7fb9cf5ea9077d029b3adf36aebfa8709/proposals/3575-sync.md?plain=1#L444)
-                        // If unset, both invited and joined rooms are returned. If false, no invited rooms are
-                        // returned. If true, only invited rooms are returned.
-                        is_invite: None,
-                        not_room_types: vec![RoomTypeFilter::Space],
-                    }))),
-            )
-            .await
-            .map_err(Error::SlidingSync)?
-            .build()
-            .await
-            .map(Arc::new)
-            .map_err(Error::SlidingSync)?;
-
-        // Eagerly subscribe the event cache to sync responses.
-        client.event_cache().subscribe()?;
+        Self::builder(client).build().await
+    }
 
-        Ok(Self { client, sliding_sync, state_machine: StateMachine::new() })
+    /// Create a [`RoomListServiceBuilder`], to configure the lists and the
+    /// extensions of the `RoomListService`.
+    ///
+    /// By default, the service has a single `all_rooms` list, like with
+    /// [`Self::new`].
+    pub fn builder(client: Client) -> RoomListServiceBuilder {
+        RoomListServiceBuilder::new(client)
     }
 
     /// Start to sync the room list.
@@ -198,8 +208,32 @@ impl RoomListService {
     /// using the [`SyncService`] instead.
     #[doc(hidden)]
     pub fn sync(&self) -> impl Stream<Item = Result<(), Error>> + '_ {
+        self.sync_run(self.new_sync_run())
+    }
+
+    /// Prepare a run of [`Self::sync_run`].
+    ///
+    /// [`Self::stop_sync`] stops the run from now on, even if the stream
+    /// returned by [`Self::sync_run`] hasn't been polled yet.
+    pub(crate) fn new_sync_run(&self) -> CancellationToken {
+        self.classic_sync.new_run()
+    }
+
+    /// Like [`Self::sync`], for a run prepared with [`Self::new_sync_run`].
+    pub(crate) fn sync_run(
+        &self,
+        run: CancellationToken,
+    ) -> impl Stream<Item = Result<(), Error>> + '_ {
         stream! {
-            let sync = self.sliding_sync.sync();
+            // Fall back on `/sync` if the homeserver doesn't support sliding sync.
+            let uses_classic_sync = self.uses_classic_sync();
+            let sync = if uses_classic_sync {
+                Either::Left(self.classic_sync.sync(run).map(|result| result.map_err(Error::Sync)))
+            } else {
+                Either::Right(self.sliding_sync.sync().map(|result| {
+                    result.map(|_update_summary| ()).map_err(Error::SlidingSync)
+                }))
+            };
             pin_mut!(sync);
 
             // This is a state machine implementation.
@@ -218,9 +252,13 @@ impl RoomListService {
                 // Do the sync.
                 match sync.next().await {
                     // Got a successful result while syncing.
-                    Some(Ok(_update_summary)) => {
+                    Some(Ok(())) => {
                         debug!(state = ?next_state, "New state");
 
+                        if uses_classic_sync {
+                            self.update_room_counts().await;
+                        }
+
                         // Update the state.
                         self.state_machine.set(next_state);
 
@@ -234,7 +272,14 @@ impl RoomListService {
                         let next_state = State::Error { from: Box::new(next_state) };
                         self.state_machine.set(next_state);
 
-                        yield Err(Error::SlidingSync(error));
+                        // If the session has expired, the room subscriptions have been cleared.
+                        if let Error::SlidingSync(error) = &error {
+                            if error.client_api_error_kind() == Some(&ErrorKind::UnknownPos) {
+                                self.subscriptions.restore();
+                            }
+                        }
+
+                        yield Err(error);
 
                         break;
                     }
@@ -273,9 +318,47 @@ impl RoomListService {
     /// using the [`SyncService`] instead.
     #[doc(hidden)]
     pub fn stop_sync(&self) -> Result<(), Error> {
+        if self.uses_classic_sync() {
+            self.classic_sync.stop();
+            return Ok(());
+        }
+
         self.sliding_sync.stop_sync().map_err(Error::SlidingSync)
     }
 
+    /// Whether `/sync` v2 is used instead of the sliding sync, because the
+    /// homeserver doesn't support it.
+    pub(crate) fn uses_classic_sync(&self) -> bool {
+        self.uses_classic_sync.load(Ordering::SeqCst)
+    }
+
+    /// Fall back on `/sync` v2 instead of the sliding sync, because the
+    /// homeserver turned out not to support it.
+    ///
+    /// **Warning**: This method **must not** be called while the sync loop is
+    /// running!
+    pub(crate) fn fall_back_on_classic_sync(&self) {
+        self.uses_classic_sync.store(true, Ordering::SeqCst);
+    }
+
+    /// Update the number of rooms of the lists from the rooms known by the
+    /// client, since the `/sync` v2 responses don't have them.
+    async fn update_room_counts(&self) {
+        let rooms = self.client.rooms();
+
+        for definition in self.state_machine.lists() {
+            let count = rooms.iter().filter(|room| definition.contains(room)).count();
+            let count = u32::try_from(count).unwrap_or(u32::MAX);
+
+            self.sliding_sync
+                .on_list(definition.name(), |list| {
+                    list.set_maximum_number_of_rooms(Some(count));
+                    ready(())
+                })
+                .await;
+        }
+    }
+
     /// Force the sliding sync session to expire.
     ///
     /// This is used by [`SyncService`][crate::SyncService].
@@ -285,6 +368,10 @@ impl RoomListService {
     pub(crate) async fn expire_sync_session(&self) {
         self.sliding_sync.expire_session().await;
 
+        // The room subscriptions have been cleared, but the ones with live handles are
+        // still wanted.
+        self.subscriptions.restore();
+
         // Usually, when the session expires, it leads the state to be `Error`,
         // thus some actions (like refreshing the lists) are executed. However,
         // if the sync loop has been stopped manually, the state is `Terminated`, and
@@ -364,7 +451,14 @@ impl RoomListService {
     }
 
     async fn list_for(&self, sliding_sync_list_name: &str) -> Result<RoomList, Error> {
-        RoomList::new(&self.client, &self.sliding_sync, sliding_sync_list_name, self.state()).await
+        RoomList::new(
+            &self.client,
+            &self.sliding_sync,
+            &self.subscriptions,
+            sliding_sync_list_name,
+            self.state(),
+        )
+        .await
     }
 
     /// Get a [`RoomList`] for all rooms.
@@ -372,11 +466,23 @@ impl RoomListService {
         self.list_for(ALL_ROOMS_LIST_NAME).await
     }
 
+    /// Get a [`RoomList`] for the list with the given name, as defined with
+    /// [`RoomListServiceBuilder::add_list`].
+    pub async fn list(&self, name: &str) -> Result<RoomList, Error> {
+        self.list_for(name).await
+    }
+
+    /// Get the names of all the lists of this service.
+    pub fn list_names(&self) -> impl Iterator<Item = &str> {
+        self.state_machine.lists().iter().map(RoomListDefinition::name)
+    }
+
     /// Get a [`Room`] if it exists.
     pub fn room(&self, room_id: &RoomId) -> Result<Room, Error> {
         Ok(Room::new(
             self.client.get_room(room_id).ok_or_else(|| Error::RoomNotFound(room_id.to_owned()))?,
             &self.sliding_sync,
+            &self.subscriptions,
         ))
     }
 
@@ -384,28 +490,63 @@ impl RoomListService {
     ///
     /// It means that all events from these rooms will be received every time,
     /// no matter how the `RoomList` is configured.
+    ///
+    /// These subscriptions have no handle: they are only removed when they are
+    /// evicted, see [`RoomListServiceBuilder::max_room_subscriptions`]. Prefer
+    /// [`Self::subscribe_to_room`].
     pub fn subscribe_to_rooms(&self, room_ids: &[&RoomId]) {
-        let settings = assign!(http::request::RoomSubscription::default(), {
-            required_state: DEFAULT_REQUIRED_STATE.iter().map(|(state_event, value)| {
-                (state_event.clone(), (*value).to_owned())
-            })
-            .chain(
-                DEFAULT_ROOM_SUBSCRIPTION_EXTRA_REQUIRED_STATE.iter().map(|(state_event, value)| {
-                    (state_event.clone(), (*value).to_owned())
-                })
-            )
-            .collect(),
-            timeline_limit: UInt::from(DEFAULT_ROOM_SUBSCRIPTION_TIMELINE_LIMIT),
-        });
-
-        let cancel_in_flight_request = match self.state_machine.get() {
-            State::Init | State::Recovering | State::Error { .. } | State::Terminated { .. } => {
-                false
-            }
-            State::SettingUp | State::Running => true,
-        };
+        for room_id in room_ids {
+            self.subscriptions.subscribe(room_id, default_room_subscription(), true);
+        }
+    }
+
+    /// Subscribe to a room, and get a handle on the subscription.
+    ///
+    /// All events from this room will be received every time, no matter how
+    /// the `RoomList` is configured, as long as at least one handle on the
+    /// subscription is alive. When the last handle is dropped, the room is
+    /// unsubscribed, so that it stops costing bandwidth.
+    ///
+    /// `settings` defines the `timeline_limit` and the `required_state` of the
+    /// subscription; if `None`, the same defaults as
+    /// [`Self::subscribe_to_rooms`] are used. If the room is already
+    /// subscribed, the settings of all the live handles are merged: the
+    /// highest `timeline_limit` is used, along with all the `required_state`s.
+    ///
+    /// If the maximum number of room subscriptions is reached, the least
+    /// recently used subscription without a handle is evicted. A room with
+    /// live handles is never evicted.
+    pub fn subscribe_to_room(
+        &self,
+        room_id: &RoomId,
+        settings: Option<http::request::RoomSubscription>,
+    ) -> RoomSubscriptionHandle {
+        RoomSubscriptionHandle::new(
+            &self.subscriptions,
+            room_id,
+            settings.unwrap_or_else(default_room_subscription),
+        )
+    }
 
-        self.sliding_sync.subscribe_to_rooms(room_ids, Some(settings), cancel_in_flight_request)
+    /// Get the IDs of the subscribed rooms.
+    pub fn subscribed_rooms(&self) -> Vec<OwnedRoomId> {
+        self.subscriptions.room_ids()
+    }
+
+    /// Resume from the position of the given sync checkpoint, once it has been
+    /// restored with [`Client::restore_checkpoint`].
+    ///
+    /// Returns false if the checkpoint has no position for the room list
+    /// sync, in which case nothing changes. When falling back on `/sync`,
+    /// there's nothing to do: its position is restored along with the store.
+    ///
+    /// This method **MUST** be called when the sync loop is stopped.
+    pub async fn restore_checkpoint_position(&self, checkpoint: &SyncCheckpoint) -> bool {
+        if self.uses_classic_sync() {
+            return false;
+        }
+
+        self.sliding_sync.restore_checkpoint_position(checkpoint).await
     }
 
     #[cfg(test)]
@@ -421,6 +562,10 @@ pub enum Error {
     #[error(transparent)]
     SlidingSync(SlidingSyncError),
 
+    /// Error from `/sync`, when the homeserver doesn't support sliding sync.
+    #[error(transparent)]
+    Sync(SlidingSyncError),
+
     /// An operation has been requested on an unknown list.
     #[error("Unknown list `{0}`")]
     UnknownList(String),
@@ -19,10 +19,13 @@ use std::{ops::Deref, sync::Arc};
 
 use async_once_cell::OnceCell as AsyncOnceCell;
 use matrix_sdk::SlidingSync;
+use matrix_sdk_base::sliding_sync::http;
 use ruma::RoomId;
 use tracing::info;
 
-use super::Error;
+use super::{
+    default_room_subscription, subscriptions::RoomSubscriptions, Error, RoomSubscriptionHandle,
+};
 use crate::{
     timeline::{EventTimelineItem, TimelineBuilder},
     Timeline,
@@ -49,6 +52,9 @@ struct RoomInner {
     /// The underlying client room.
     room: matrix_sdk::Room,
 
+    /// The room subscriptions of the `RoomListService`.
+    subscriptions: Arc<RoomSubscriptions>,
+
     /// The timeline of the room.
     timeline: AsyncOnceCell<Arc<Timeline>>,
 }
@@ -63,11 +69,16 @@ impl Deref for Room {
 
 impl Room {
     /// Create a new `Room`.
-    pub(super) fn new(room: matrix_sdk::Room, sliding_sync: &Arc<SlidingSync>) -> Self {
+    pub(super) fn new(
+        room: matrix_sdk::Room,
+        sliding_sync: &Arc<SlidingSync>,
+        subscriptions: &Arc<RoomSubscriptions>,
+    ) -> Self {
         Self {
             inner: Arc::new(RoomInner {
                 sliding_sync: sliding_sync.clone(),
                 room,
+                subscriptions: subscriptions.clone(),
                 timeline: AsyncOnceCell::new(),
             }),
         }
@@ -101,19 +112,38 @@ impl Room {
     /// Initialize the timeline of the room with an event type filter so only
     /// some events are returned. If a previous timeline exists, it'll
     /// return an error. Otherwise, a Timeline will be returned.
+    ///
+    /// The room is subscribed as long as the timeline is alive, even if this
+    /// `Room` is dropped in the meantime.
     pub async fn init_timeline_with_builder(&self, builder: TimelineBuilder) -> Result<(), Error> {
         if self.inner.timeline.get().is_some() {
             Err(Error::TimelineAlreadyExists(self.inner.room.room_id().to_owned()))
         } else {
+            let builder = builder.with_room_subscription(self.subscribe(None));
+
             self.inner
                 .timeline
                 .get_or_try_init(async { Ok(Arc::new(builder.build().await?)) })
                 .await
                 .map_err(Error::InitializingTimeline)?;
+
             Ok(())
         }
     }
 
+    /// Subscribe to this room, see
+    /// [`RoomListService::subscribe_to_room`](super::RoomListService::subscribe_to_room).
+    pub fn subscribe(
+        &self,
+        settings: Option<http::request::RoomSubscription>,
+    ) -> RoomSubscriptionHandle {
+        RoomSubscriptionHandle::new(
+            &self.inner.subscriptions,
+            self.id(),
+            settings.unwrap_or_else(default_room_subscription),
+        )
+    }
+
     /// Get the latest event in the timeline.
     ///
     /// The latest event comes first from the `Timeline`, it can be a local or a
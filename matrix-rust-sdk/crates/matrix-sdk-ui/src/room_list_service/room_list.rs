@@ -35,6 +35,7 @@ use tracing::{error, trace};
 use super::{
     filters::BoxedFilterFn,
     sorters::{new_sorter_lexicographic, new_sorter_name, new_sorter_recency},
+    subscriptions::RoomSubscriptions,
     Error, Room, State,
 };
 
@@ -44,6 +45,7 @@ use super::{
 pub struct RoomList {
     client: Client,
     sliding_sync: Arc<SlidingSync>,
+    subscriptions: Arc<RoomSubscriptions>,
     sliding_sync_list: SlidingSyncList,
     loading_state: SharedObservable<RoomListLoadingState>,
     loading_state_task: JoinHandle<()>,
@@ -59,6 +61,7 @@ impl RoomList {
     pub(super) async fn new(
         client: &Client,
         sliding_sync: &Arc<SlidingSync>,
+        subscriptions: &Arc<RoomSubscriptions>,
         sliding_sync_list_name: &str,
         room_list_service_state: Subscriber<State>,
     ) -> Result<Self, Error> {
@@ -78,6 +81,7 @@ impl RoomList {
         Ok(Self {
             client: client.clone(),
             sliding_sync: sliding_sync.clone(),
+            subscriptions: subscriptions.clone(),
             sliding_sync_list: sliding_sync_list.clone(),
             loading_state: loading_state.clone(),
             loading_state_task: spawn(async move {
@@ -124,7 +128,7 @@ impl RoomList {
     fn entries(&self) -> (Vector<Room>, impl Stream<Item = Vec<VectorDiff<Room>>> + '_) {
         let (rooms, stream) = self.client.rooms_stream();
 
-        let map_room = |room| Room::new(room, &self.sliding_sync);
+        let map_room = |room| Room::new(room, &self.sliding_sync, &self.subscriptions);
 
         (
             rooms.into_iter().map(map_room).collect(),
@@ -23,7 +23,7 @@ use std::{
 use eyeball::{SharedObservable, Subscriber};
 use matrix_sdk::{sliding_sync::Range, SlidingSync, SlidingSyncMode};
 
-use super::Error;
+use super::{Error, RoomListDefinition};
 
 pub const ALL_ROOMS_LIST_NAME: &str = "all_rooms";
 
@@ -79,14 +79,19 @@ pub struct StateMachine {
     ///
     /// To be used in coordination with `Self::last_state_update_time`.
     state_lifespan: Duration,
+
+    /// The definitions of the lists whose sync-mode is managed by the state
+    /// machine.
+    lists: Vec<RoomListDefinition>,
 }
 
 impl StateMachine {
-    pub(super) fn new() -> Self {
+    pub(super) fn new(lists: Vec<RoomListDefinition>) -> Self {
         StateMachine {
             state: SharedObservable::new(State::Init),
             last_state_update_time: Mutex::new(Instant::now()),
             state_lifespan: DEFAULT_STATE_LIFESPAN,
+            lists,
         }
     }
 
@@ -105,6 +110,11 @@ impl StateMachine {
         self.state.set(state);
     }
 
+    /// The definitions of the lists managed by the state machine.
+    pub(super) fn lists(&self) -> &[RoomListDefinition] {
+        &self.lists
+    }
+
     /// Subscribe to state updates.
     pub fn subscribe(&self) -> Subscriber<State> {
         self.state.subscribe()
@@ -119,7 +129,7 @@ impl StateMachine {
             Init => SettingUp,
 
             SettingUp | Recovering => {
-                set_all_rooms_to_growing_sync_mode(sliding_sync).await?;
+                set_lists_to_growing_sync_mode(sliding_sync, &self.lists).await?;
                 Running
             }
 
@@ -128,7 +138,7 @@ impl StateMachine {
                 // requesting potentially large data. See `Self::last_state_update` to learn
                 // the details.
                 if self.last_state_update_time.lock().unwrap().elapsed() > self.state_lifespan {
-                    set_all_rooms_to_selective_sync_mode(sliding_sync).await?;
+                    set_lists_to_selective_sync_mode(sliding_sync, &self.lists).await?;
 
                     Recovering
                 } else {
@@ -147,7 +157,7 @@ impl StateMachine {
 
                     // If the previous state was `Running`, we enter the `Recovering` state.
                     Running => {
-                        set_all_rooms_to_selective_sync_mode(sliding_sync).await?;
+                        set_lists_to_selective_sync_mode(sliding_sync, &self.lists).await?;
                         Recovering
                     }
 
@@ -161,28 +171,42 @@ impl StateMachine {
     }
 }
 
-async fn set_all_rooms_to_growing_sync_mode(sliding_sync: &SlidingSync) -> Result<(), Error> {
-    sliding_sync
-        .on_list(ALL_ROOMS_LIST_NAME, |list| {
-            list.set_sync_mode(SlidingSyncMode::new_growing(ALL_ROOMS_DEFAULT_GROWING_BATCH_SIZE));
+async fn set_lists_to_growing_sync_mode(
+    sliding_sync: &SlidingSync,
+    lists: &[RoomListDefinition],
+) -> Result<(), Error> {
+    for definition in lists {
+        sliding_sync
+            .on_list(&definition.name, |list| {
+                list.set_sync_mode(SlidingSyncMode::new_growing(definition.growing_batch_size));
+
+                ready(())
+            })
+            .await
+            .ok_or_else(|| Error::UnknownList(definition.name.clone()))?;
+    }
 
-            ready(())
-        })
-        .await
-        .ok_or_else(|| Error::UnknownList(ALL_ROOMS_LIST_NAME.to_owned()))
+    Ok(())
 }
 
-async fn set_all_rooms_to_selective_sync_mode(sliding_sync: &SlidingSync) -> Result<(), Error> {
-    sliding_sync
-        .on_list(ALL_ROOMS_LIST_NAME, |list| {
-            list.set_sync_mode(
-                SlidingSyncMode::new_selective().add_range(ALL_ROOMS_DEFAULT_SELECTIVE_RANGE),
-            );
-
-            ready(())
-        })
-        .await
-        .ok_or_else(|| Error::UnknownList(ALL_ROOMS_LIST_NAME.to_owned()))
+async fn set_lists_to_selective_sync_mode(
+    sliding_sync: &SlidingSync,
+    lists: &[RoomListDefinition],
+) -> Result<(), Error> {
+    for definition in lists {
+        sliding_sync
+            .on_list(&definition.name, |list| {
+                list.set_sync_mode(
+                    SlidingSyncMode::new_selective().add_range(definition.selective_range.clone()),
+                );
+
+                ready(())
+            })
+            .await
+            .ok_or_else(|| Error::UnknownList(definition.name.clone()))?;
+    }
+
+    Ok(())
 }
 
 /// Default `batch_size` for the selective sync-mode of the
@@ -205,7 +229,7 @@ mod tests {
         let room_list = new_room_list().await?;
         let sliding_sync = room_list.sliding_sync();
 
-        let state_machine = StateMachine::new();
+        let state_machine = StateMachine::new(vec![RoomListDefinition::all_rooms()]);
 
         // Hypothetical error.
         {
@@ -364,6 +388,7 @@ mod tests {
     {
         let room_list = new_room_list().await?;
         let sliding_sync = room_list.sliding_sync();
+        let lists = [RoomListDefinition::all_rooms()];
 
         // List is present, in Selective mode.
         assert_eq!(
@@ -377,7 +402,7 @@ mod tests {
         );
 
         // Run the action!
-        set_all_rooms_to_growing_sync_mode(sliding_sync).await.unwrap();
+        set_lists_to_growing_sync_mode(sliding_sync, &lists).await.unwrap();
 
         // List is still present, in Growing mode.
         assert_eq!(
@@ -393,7 +418,7 @@ mod tests {
         );
 
         // Run the other action!
-        set_all_rooms_to_selective_sync_mode(sliding_sync).await.unwrap();
+        set_lists_to_selective_sync_mode(sliding_sync, &lists).await.unwrap();
 
         // List is still present, in Selective mode.
         assert_eq!(
@@ -0,0 +1,336 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for that specific language governing permissions and
+// limitations under the License.
+
+//! Reference-counted room subscriptions for the `RoomListService`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use eyeball::Subscriber;
+use matrix_sdk::SlidingSync;
+use matrix_sdk_base::sliding_sync::http;
+use ruma::{OwnedRoomId, RoomId};
+use tracing::warn;
+
+use super::State;
+
+/// A handle on a room subscription, created by
+/// [`RoomListService::subscribe_to_room`].
+///
+/// The room stays subscribed as long as at least one handle on it is alive.
+/// When the last handle is dropped, the room is unsubscribed, and when any
+/// handle is dropped, the settings of the subscription are computed again
+/// from the remaining handles.
+///
+/// [`RoomListService::subscribe_to_room`]: super::RoomListService::subscribe_to_room
+#[derive(Debug)]
+pub struct RoomSubscriptionHandle {
+    subscriptions: Arc<RoomSubscriptions>,
+    room_id: OwnedRoomId,
+    key: u64,
+}
+
+impl RoomSubscriptionHandle {
+    pub(super) fn new(
+        subscriptions: &Arc<RoomSubscriptions>,
+        room_id: &RoomId,
+        settings: http::request::RoomSubscription,
+    ) -> Self {
+        let key = subscriptions.subscribe(room_id, settings, false);
+        Self { subscriptions: subscriptions.clone(), room_id: room_id.to_owned(), key }
+    }
+
+    /// The ID of the subscribed room.
+    pub fn room_id(&self) -> &RoomId {
+        &self.room_id
+    }
+}
+
+impl Drop for RoomSubscriptionHandle {
+    fn drop(&mut self) {
+        self.subscriptions.release(&self.room_id, self.key);
+    }
+}
+
+#[derive(Debug)]
+struct SubscribedRoom {
+    /// The settings asked by each live [`RoomSubscriptionHandle`], by key.
+    handles: BTreeMap<u64, http::request::RoomSubscription>,
+
+    /// The settings of the subscription without a handle, made with
+    /// [`super::RoomListService::subscribe_to_rooms`], if any. Such a
+    /// subscription is only removed by an eviction.
+    pinned: Option<http::request::RoomSubscription>,
+
+    /// The last time the subscription was used, for the LRU eviction.
+    last_used: u64,
+
+    /// The settings of the subscription, i.e. the merge of the settings of the
+    /// live handles and of the pinned subscription.
+    settings: http::request::RoomSubscription,
+}
+
+impl SubscribedRoom {
+    /// Compute the settings of the subscription from the live handles and the
+    /// pinned subscription.
+    fn compute_settings(&self) -> http::request::RoomSubscription {
+        let mut settings = http::request::RoomSubscription::default();
+
+        for other in self.pinned.iter().chain(self.handles.values()) {
+            merge_settings(&mut settings, other.clone());
+        }
+
+        settings
+    }
+
+    /// Compute the settings again, and return them if they have changed.
+    fn update_settings(&mut self) -> Option<http::request::RoomSubscription> {
+        let settings = self.compute_settings();
+
+        if same_settings(&settings, &self.settings) {
+            return None;
+        }
+
+        self.settings = settings.clone();
+
+        Some(settings)
+    }
+}
+
+#[derive(Debug, Default)]
+struct RoomSubscriptionsInner {
+    rooms: HashMap<OwnedRoomId, SubscribedRoom>,
+
+    /// A logical clock, incremented every time a subscription is used. Its
+    /// value also identifies the handles.
+    clock: u64,
+}
+
+/// The room subscriptions of a [`super::RoomListService`].
+#[derive(Debug)]
+pub(super) struct RoomSubscriptions {
+    sliding_sync: Arc<SlidingSync>,
+
+    /// The state of the `RoomListService`, to know whether the in-flight
+    /// request must be cancelled when subscribing to a room.
+    state: Subscriber<State>,
+
+    /// The maximum number of subscribed rooms, if any.
+    max_subscriptions: Option<usize>,
+
+    /// This mutex is only taken for short periods of time, so it's sync.
+    inner: Mutex<RoomSubscriptionsInner>,
+}
+
+impl RoomSubscriptions {
+    pub(super) fn new(
+        sliding_sync: Arc<SlidingSync>,
+        state: Subscriber<State>,
+        max_subscriptions: Option<usize>,
+    ) -> Self {
+        Self { sliding_sync, state, max_subscriptions, inner: Default::default() }
+    }
+
+    /// Subscribe to a room, with a handle if `pinned` is false.
+    ///
+    /// If the room is already subscribed, the settings are merged with the
+    /// ones of the subscription, see [`merge_settings`]. Returns the key of
+    /// the handle.
+    pub(super) fn subscribe(
+        &self,
+        room_id: &RoomId,
+        settings: http::request::RoomSubscription,
+        pinned: bool,
+    ) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let now = inner.clock;
+
+        if let Some(room) = inner.rooms.get_mut(room_id) {
+            room.last_used = now;
+
+            if pinned {
+                match &mut room.pinned {
+                    Some(pinned) => {
+                        merge_settings(pinned, settings);
+                    }
+                    None => room.pinned = Some(settings),
+                }
+            } else {
+                room.handles.insert(now, settings);
+            }
+
+            if let Some(settings) = room.update_settings() {
+                self.sliding_sync.update_room_subscription(
+                    room_id,
+                    settings,
+                    self.cancel_in_flight_request(),
+                );
+            }
+
+            return now;
+        }
+
+        let mut room = SubscribedRoom {
+            handles: BTreeMap::new(),
+            pinned: None,
+            last_used: now,
+            settings: Default::default(),
+        };
+
+        if pinned {
+            room.pinned = Some(settings);
+        } else {
+            room.handles.insert(now, settings);
+        }
+
+        room.settings = room.compute_settings();
+
+        self.sliding_sync.subscribe_to_rooms(
+            &[room_id],
+            Some(room.settings.clone()),
+            self.cancel_in_flight_request(),
+        );
+
+        inner.rooms.insert(room_id.to_owned(), room);
+
+        // Evict the least recently used subscriptions if there are too many of them.
+        if let Some(max_subscriptions) = self.max_subscriptions {
+            let mut evicted = Vec::new();
+
+            while inner.rooms.len() > max_subscriptions {
+                // Only the subscriptions without a handle can be evicted: the rooms with live
+                // handles are still wanted.
+                let Some(lru) = inner
+                    .rooms
+                    .iter()
+                    .filter(|(id, room)| *id != room_id && room.handles.is_empty())
+                    .min_by_key(|(_, room)| room.last_used)
+                    .map(|(id, _)| id.clone())
+                else {
+                    warn!(
+                        max_subscriptions,
+                        subscriptions = inner.rooms.len(),
+                        "Too many room subscriptions, but all of them have live handles"
+                    );
+                    break;
+                };
+
+                inner.rooms.remove(&lru);
+                evicted.push(lru);
+            }
+
+            if !evicted.is_empty() {
+                let evicted = evicted.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+                self.sliding_sync.unsubscribe_from_rooms(&evicted, false);
+            }
+        }
+
+        now
+    }
+
+    /// Whether the in-flight request must be cancelled to send a change of the
+    /// room subscriptions right away.
+    fn cancel_in_flight_request(&self) -> bool {
+        match self.state.get() {
+            State::Init | State::Recovering | State::Error { .. } | State::Terminated { .. } => {
+                false
+            }
+            State::SettingUp | State::Running => true,
+        }
+    }
+
+    /// Release the handle with the given key.
+    ///
+    /// The room is unsubscribed if it was its last handle and the
+    /// subscription isn't pinned, otherwise the settings of the subscription
+    /// are computed again without the ones of this handle.
+    fn release(&self, room_id: &RoomId, key: u64) {
+        let mut inner = self.inner.lock().unwrap();
+
+        let Some(room) = inner.rooms.get_mut(room_id) else {
+            return;
+        };
+
+        if room.handles.remove(&key).is_none() {
+            return;
+        }
+
+        if room.handles.is_empty() && room.pinned.is_none() {
+            inner.rooms.remove(room_id);
+            self.sliding_sync.unsubscribe_from_rooms(&[room_id], false);
+        } else if let Some(settings) = room.update_settings() {
+            self.sliding_sync.update_room_subscription(room_id, settings, false);
+        }
+    }
+
+    /// Subscribe again to the rooms that have live handles, after the session
+    /// of the sliding sync has expired.
+    ///
+    /// When its session expires, the sliding sync clears its room
+    /// subscriptions on purpose, so that they aren't all sent again. The rooms
+    /// that still have live handles are wanted though, so they are subscribed
+    /// again, but the pinned subscriptions are dropped, like the sliding sync
+    /// does.
+    pub(super) fn restore(&self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.rooms.retain(|_, room| !room.handles.is_empty());
+
+        for (room_id, room) in &mut inner.rooms {
+            room.pinned = None;
+            room.settings = room.compute_settings();
+            self.sliding_sync.subscribe_to_rooms(&[room_id], Some(room.settings.clone()), false);
+        }
+    }
+
+    /// The IDs of the subscribed rooms.
+    pub(super) fn room_ids(&self) -> Vec<OwnedRoomId> {
+        self.inner.lock().unwrap().rooms.keys().cloned().collect()
+    }
+}
+
+/// Merge the `other` settings of a room subscription into `settings`, so that
+/// the subscription satisfies both.
+///
+/// The highest `timeline_limit` is kept, the `required_state`s are merged, and
+/// the heroes are included if one of the settings asks for them.
+fn merge_settings(
+    settings: &mut http::request::RoomSubscription,
+    other: http::request::RoomSubscription,
+) {
+    settings.timeline_limit = settings.timeline_limit.max(other.timeline_limit);
+
+    for required_state in other.required_state {
+        if !settings.required_state.contains(&required_state) {
+            settings.required_state.push(required_state);
+        }
+    }
+
+    if other.include_heroes == Some(true) {
+        settings.include_heroes = Some(true);
+    }
+}
+
+/// Whether two settings of a room subscription are the same.
+fn same_settings(
+    settings: &http::request::RoomSubscription,
+    other: &http::request::RoomSubscription,
+) -> bool {
+    settings.timeline_limit == other.timeline_limit
+        && settings.required_state == other.required_state
+        && settings.include_heroes == other.include_heroes
+}
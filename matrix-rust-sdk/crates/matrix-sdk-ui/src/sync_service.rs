@@ -22,26 +22,50 @@
 //! [`state`](SyncService::state) that the user
 //! MUST observe. Whenever an error/termination is observed, the user MUST call
 //! [`SyncService::start()`] again to restart the room list sync.
+//!
+//! If the homeserver doesn't support sliding sync, the sync service falls back
+//! on `/sync` v2, which drives the [`RoomListService`] and takes care of the
+//! end-to-end encryption on its own. See [`SyncService::uses_classic_sync()`].
+//!
+//! If the offline mode is enabled with
+//! [`SyncServiceBuilder::with_offline_mode()`], network errors don't need to
+//! be handled by the user: after a few network errors in a row, the sync
+//! service goes into the [`State::Offline`] state, and restarts the syncs on
+//! its own once the homeserver can be reached again.
 
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use eyeball::{SharedObservable, Subscriber};
 use futures_core::Future;
 use futures_util::{pin_mut, StreamExt as _};
-use matrix_sdk::Client;
+use matrix_sdk::{
+    sliding_sync::Version as SlidingSyncVersion, sync_checkpoints::SyncCheckpoint, Client,
+    HttpError,
+};
+use matrix_sdk_base::StateStoreDataKey;
+use ruma::api::client::discovery::get_supported_versions;
 use thiserror::Error;
 use tokio::{
+    select,
     sync::{
         mpsc::{Receiver, Sender},
-        Mutex as AsyncMutex, OwnedMutexGuard,
+        Mutex as AsyncMutex, Notify, OwnedMutexGuard,
     },
     task::{spawn, JoinHandle},
+    time::sleep,
 };
-use tracing::{error, info, instrument, trace, warn, Instrument, Level};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument, trace, warn, Instrument, Level};
 
 use crate::{
     encryption_sync_service::{self, EncryptionSyncPermit, EncryptionSyncService, WithLocking},
-    room_list_service::{self, RoomListService},
+    room_list_service::{self, RoomListService, RoomListServiceBuilder},
 };
 
 /// Current state of the application.
@@ -54,6 +78,10 @@ use crate::{
 /// It is the responsibility of the caller to restart the application using the
 /// [`SyncService::start`] method, in case it terminated, gracefully or not.
 ///
+/// When the offline mode is enabled, network errors lead to the `Offline`
+/// state instead of the `Error` state, from which the service recovers on its
+/// own.
+///
 /// This can be observed with [`SyncService::state`].
 #[derive(Clone, Debug, PartialEq)]
 pub enum State {
@@ -65,14 +93,38 @@ pub enum State {
     Terminated,
     /// Any of the underlying syncs has ran into an error.
     Error,
+    /// The homeserver can't be reached, because of repeated network errors or
+    /// because the application signalled it with
+    /// [`SyncService::set_network_reachable`].
+    ///
+    /// The underlying syncs are stopped, and will be restarted automatically
+    /// once the homeserver can be reached again. Only set when the offline
+    /// mode is enabled.
+    Offline,
 }
 
+/// How many network errors in a row the underlying syncs can run into before
+/// the sync service goes offline.
+const MAX_NETWORK_FAILURES: usize = 3;
+
+/// The delay before checking again whether the homeserver can be reached,
+/// when offline. It's doubled after each unsuccessful check, up to
+/// [`MAX_OFFLINE_CHECK_DELAY`].
+const INITIAL_OFFLINE_CHECK_DELAY: Duration = Duration::from_secs(1);
+
+/// The maximum delay between two checks of whether the homeserver can be
+/// reached, when offline.
+const MAX_OFFLINE_CHECK_DELAY: Duration = Duration::from_secs(30);
+
 pub struct SyncService {
     /// Room list service used to synchronize the rooms state.
     room_list_service: Arc<RoomListService>,
 
     /// Encryption sync taking care of e2ee events.
-    encryption_sync_service: Arc<EncryptionSyncService>,
+    ///
+    /// `None` when falling back on `/sync`, which takes care of them on its
+    /// own.
+    encryption_sync_service: Option<Arc<EncryptionSyncService>>,
 
     /// What's the state of this sync service?
     state: SharedObservable<State>,
@@ -108,7 +160,20 @@ pub struct SyncService {
     /// `TerminationReport` sender for the [`Self::stop()`] function.
     ///
     /// This is set at the same time as all the tasks in [`Self::start()`].
-    scheduler_sender: Mutex<Option<Sender<TerminationReport>>>,
+    scheduler_sender: Arc<Mutex<Option<Sender<TerminationReport>>>>,
+
+    /// Whether the homeserver supports sliding sync, once it's known.
+    sliding_sync_support: SharedObservable<Option<bool>>,
+
+    /// Task discovering whether the homeserver supports sliding sync, when it
+    /// wasn't known when building the service.
+    sliding_sync_discovery_task: Option<JoinHandle<()>>,
+
+    /// How many network errors in a row the underlying syncs ran into.
+    network_failures: Arc<AtomicUsize>,
+
+    /// The offline mode, if it's enabled.
+    offline_mode: Option<Arc<OfflineMode>>,
 }
 
 impl SyncService {
@@ -128,82 +193,170 @@ impl SyncService {
         self.state.subscribe()
     }
 
+    /// Whether the sync service falls back on `/sync` v2, because the
+    /// homeserver doesn't support sliding sync.
+    ///
+    /// In this case, there is no encryption sync: `/sync` takes care of the
+    /// end-to-end encryption.
+    ///
+    /// If it wasn't known whether the homeserver supports sliding sync when
+    /// the service was built, this switches to `true` when the syncs (re)start
+    /// after it's been discovered that it doesn't. Running syncs are restarted
+    /// right away.
+    pub fn uses_classic_sync(&self) -> bool {
+        self.room_list_service.uses_classic_sync()
+    }
+
+    /// Resume the room list and the encryption syncs from their positions in
+    /// the given sync checkpoint, once it has been restored with
+    /// [`Client::restore_checkpoint`].
+    ///
+    /// Returns false if the checkpoint has no position for one of the syncs,
+    /// which then starts a new session. When falling back on `/sync`, there's
+    /// nothing to do and this returns false: its position is restored along
+    /// with the store.
+    ///
+    /// This method **MUST** be called when the syncs are stopped.
+    pub async fn restore_checkpoint_position(&self, checkpoint: &SyncCheckpoint) -> bool {
+        let room_list_restored =
+            self.room_list_service.restore_checkpoint_position(checkpoint).await;
+
+        if self.uses_classic_sync() {
+            return false;
+        }
+
+        let encryption_restored = match &self.encryption_sync_service {
+            Some(encryption_sync_service) => {
+                encryption_sync_service.restore_checkpoint_position(checkpoint).await
+            }
+            None => true,
+        };
+
+        room_list_restored && encryption_restored
+    }
+
+    /// Get what's needed to (re)start the underlying syncs.
+    fn sync_tasks(&self) -> SyncTasks {
+        SyncTasks {
+            room_list_service: self.room_list_service.clone(),
+            encryption_sync_service: self.encryption_sync_service.clone(),
+            room_list_task: self.room_list_task.clone(),
+            encryption_sync_task: self.encryption_sync_task.clone(),
+            encryption_sync_permit: self.encryption_sync_permit.clone(),
+            network_failures: self.network_failures.clone(),
+            sliding_sync_support: self.sliding_sync_support.clone(),
+        }
+    }
+
     /// The role of the scheduler task is to wait for a termination message
     /// (`TerminationReport`), sent either because we wanted to stop both
     /// syncs, or because one of the syncs failed (in which case we'll stop
     /// the other one too).
+    ///
+    /// When the offline mode is enabled, the scheduler task also restarts the
+    /// syncs after network errors, and takes care of the offline state.
     fn spawn_scheduler_task(
         &self,
         mut receiver: Receiver<TerminationReport>,
+        sender: Sender<TerminationReport>,
     ) -> impl Future<Output = ()> {
-        let encryption_sync_task = self.encryption_sync_task.clone();
-        let encryption_sync = self.encryption_sync_service.clone();
-        let room_list_service = self.room_list_service.clone();
-        let room_list_task = self.room_list_task.clone();
+        let sync_tasks = self.sync_tasks();
+        let offline_mode = self.offline_mode.clone();
         let state = self.state.clone();
 
         async move {
-            let Some(report) = receiver.recv().await else {
-                info!("internal channel has been closed?");
-                return;
-            };
-
-            // If one service failed, make sure to request stopping the other one.
-            let (stop_room_list, stop_encryption) = match &report.origin {
-                TerminationOrigin::EncryptionSync => (true, false),
-                TerminationOrigin::RoomList => (false, true),
-                TerminationOrigin::Scheduler => (true, true),
-            };
-
-            // Stop both services, and wait for the streams to properly finish: at some
-            // point they'll return `None` and will exit their infinite loops,
-            // and their tasks will gracefully terminate.
-
-            if stop_room_list {
-                if let Err(err) = room_list_service.stop_sync() {
-                    warn!(?report, "unable to stop room list service: {err:#}");
+            loop {
+                let Some(report) = receiver.recv().await else {
+                    info!("internal channel has been closed?");
+                    return;
+                };
+
+                sync_tasks.stop(&report).await;
+
+                // The sync that wasn't stopped on purpose may have run into an error too,
+                // and sent its own report in the meantime; it's obsolete now. Only keep
+                // track of a request to stop the service.
+                let mut stop_requested = matches!(report.origin, TerminationOrigin::Scheduler);
+                while let Ok(report) = receiver.try_recv() {
+                    stop_requested |= matches!(report.origin, TerminationOrigin::Scheduler);
                 }
-            }
 
-            {
-                let task = room_list_task.lock().unwrap().take();
-                if let Some(task) = task {
-                    if let Err(err) = task.await {
-                        error!("when awaiting room list service: {err:#}");
+                if let Some(offline_mode) = offline_mode.as_ref() {
+                    if report.is_network_error && !stop_requested {
+                        let failures =
+                            sync_tasks.network_failures.fetch_add(1, Ordering::SeqCst) + 1;
+
+                        if failures < MAX_NETWORK_FAILURES
+                            && !matches!(report.origin, TerminationOrigin::Connectivity)
+                        {
+                            debug!(failures, "restarting the syncs after a network error");
+                            sync_tasks.start(sender.clone()).await;
+                            continue;
+                        }
+
+                        info!("the homeserver can't be reached, going offline");
+                        offline_mode.pause_send_queue().await;
+                        state.set(State::Offline);
+
+                        let is_reachable = offline_mode.wait_until_reachable(&mut receiver).await;
+                        offline_mode.resume_send_queue().await;
+
+                        if is_reachable {
+                            info!("the homeserver can be reached again, restarting the syncs");
+                            sync_tasks.network_failures.store(0, Ordering::SeqCst);
+                            sync_tasks.start(sender.clone()).await;
+                            state.set(State::Running);
+                            continue;
+                        }
+
+                        state.set(State::Idle);
+                        return;
                     }
                 }
-            }
 
-            if stop_encryption {
-                if let Err(err) = encryption_sync.stop_sync() {
-                    warn!(?report, "unable to stop encryption sync: {err:#}");
-                }
-            }
+                // If the homeserver doesn't support sliding sync, the sliding syncs may fail
+                // before the discovery of its support has finished: in this case, wait for
+                // the discovery, to fall back on `/sync` instead of reporting an error.
+                if report.is_error
+                    && !report.is_network_error
+                    && !stop_requested
+                    && sync_tasks.sliding_sync_support.get().is_none()
+                {
+                    debug!(
+                        "a sync failed while discovering the sliding sync support, waiting for it"
+                    );
 
-            {
-                let task = encryption_sync_task.lock().unwrap().take();
-                if let Some(task) = task {
-                    if let Err(err) = task.await {
-                        error!("when awaiting encryption sync: {err:#}");
+                    match sync_tasks.wait_for_sliding_sync_support(&mut receiver).await {
+                        Some(false) => {
+                            debug!("restarting the syncs to fall back on `/sync`");
+                            sync_tasks.start(sender.clone()).await;
+                            continue;
+                        }
+                        // Sliding sync is supported, so the error is a real one.
+                        Some(true) => {}
+                        None => stop_requested = true,
                     }
                 }
-            }
 
-            if report.is_error {
-                if report.has_expired {
-                    if stop_room_list {
-                        room_list_service.expire_sync_session().await;
-                    }
-                    if stop_encryption {
-                        encryption_sync.expire_sync_session().await;
+                if matches!(report.origin, TerminationOrigin::Fallback) && !stop_requested {
+                    debug!("restarting the syncs to fall back on `/sync`");
+                    sync_tasks.start(sender.clone()).await;
+                    continue;
+                }
+
+                if stop_requested {
+                    state.set(State::Idle);
+                } else if report.is_error {
+                    if report.has_expired {
+                        sync_tasks.expire_sync_sessions(&report).await;
                     }
+
+                    state.set(State::Error);
+                } else {
+                    state.set(State::Terminated);
                 }
 
-                state.set(State::Error);
-            } else if matches!(report.origin, TerminationOrigin::Scheduler) {
-                state.set(State::Idle);
-            } else {
-                state.set(State::Terminated);
+                return;
             }
         }
         .instrument(tracing::span!(Level::WARN, "scheduler task"))
@@ -217,7 +370,7 @@ impl SyncService {
         let encryption_sync_stream = encryption_sync.sync(sync_permit_guard);
         pin_mut!(encryption_sync_stream);
 
-        let (is_error, has_expired) = loop {
+        let (is_error, has_expired, is_network_error) = loop {
             let res = encryption_sync_stream.next().await;
             match res {
                 Some(Ok(())) => {
@@ -226,21 +379,24 @@ impl SyncService {
                 Some(Err(err)) => {
                     // If the encryption sync error was an expired session, also expire the
                     // room list sync.
-                    let has_expired = if let encryption_sync_service::Error::SlidingSync(err) = &err
-                    {
-                        err.client_api_error_kind()
-                            == Some(&ruma::api::client::error::ErrorKind::UnknownPos)
-                    } else {
-                        false
-                    };
+                    let (has_expired, is_network_error) =
+                        if let encryption_sync_service::Error::SlidingSync(err) = &err {
+                            (
+                                err.client_api_error_kind()
+                                    == Some(&ruma::api::client::error::ErrorKind::UnknownPos),
+                                is_network_error(err),
+                            )
+                        } else {
+                            (false, false)
+                        };
                     if !has_expired {
                         error!("Error while processing encryption in sync service: {err:#}");
                     }
-                    break (true, has_expired);
+                    break (true, has_expired, is_network_error);
                 }
                 None => {
                     // The stream has ended.
-                    break (false, false);
+                    break (false, false, false);
                 }
             }
         };
@@ -249,6 +405,7 @@ impl SyncService {
             .send(TerminationReport {
                 is_error,
                 has_expired,
+                is_network_error,
                 origin: TerminationOrigin::EncryptionSync,
             })
             .await
@@ -259,40 +416,62 @@ impl SyncService {
 
     async fn room_list_sync_task(
         room_list_service: Arc<RoomListService>,
+        sync_run: CancellationToken,
         sender: Sender<TerminationReport>,
+        network_failures: Arc<AtomicUsize>,
+        sync_permit_guard: Option<OwnedMutexGuard<EncryptionSyncPermit>>,
     ) {
-        let room_list_stream = room_list_service.sync();
+        // When falling back on `/sync`, the room list sync also takes care of the
+        // end-to-end encryption, so it holds the permit.
+        let _sync_permit_guard = sync_permit_guard;
+
+        // The run has been prepared before spawning this task, so that stopping the
+        // room list sync before the task is first polled still stops it.
+        let room_list_stream = room_list_service.sync_run(sync_run);
         pin_mut!(room_list_stream);
 
-        let (is_error, has_expired) = loop {
+        let (is_error, has_expired, is_network_error) = loop {
             let res = room_list_stream.next().await;
             match res {
                 Some(Ok(())) => {
-                    // Carry on.
+                    // The homeserver could be reached, so reset the count of network errors in
+                    // a row, and carry on.
+                    network_failures.store(0, Ordering::SeqCst);
                 }
                 Some(Err(err)) => {
                     // If the room list error was an expired session, also expire the
                     // encryption sync.
-                    let has_expired = if let room_list_service::Error::SlidingSync(err) = &err {
-                        err.client_api_error_kind()
-                            == Some(&ruma::api::client::error::ErrorKind::UnknownPos)
-                    } else {
-                        false
-                    };
+                    let (has_expired, is_network_error) =
+                        if let room_list_service::Error::SlidingSync(err)
+                        | room_list_service::Error::Sync(err) = &err
+                        {
+                            (
+                                err.client_api_error_kind()
+                                    == Some(&ruma::api::client::error::ErrorKind::UnknownPos),
+                                is_network_error(err),
+                            )
+                        } else {
+                            (false, false)
+                        };
                     if !has_expired {
                         error!("Error while processing room list in sync service: {err:#}");
                     }
-                    break (true, has_expired);
+                    break (true, has_expired, is_network_error);
                 }
                 None => {
                     // The stream has ended.
-                    break (false, false);
+                    break (false, false, false);
                 }
             }
         };
 
         if let Err(err) = sender
-            .send(TerminationReport { is_error, has_expired, origin: TerminationOrigin::RoomList })
+            .send(TerminationReport {
+                is_error,
+                has_expired,
+                is_network_error,
+                origin: TerminationOrigin::RoomList,
+            })
             .await
         {
             error!("Error while sending termination report: {err:#}");
@@ -305,34 +484,41 @@ impl SyncService {
     /// - if the stream is still properly running, it won't be restarted.
     /// - if the stream has been aborted before, it will be properly cleaned up
     ///   and restarted.
+    /// - if the service is offline, it will check right away whether the
+    ///   homeserver can be reached again.
     pub async fn start(&self) {
         let _guard = self.modifying_state.lock().await;
 
         // Only (re)start the tasks if any was stopped.
-        if matches!(self.state.get(), State::Running) {
-            // It was already true, so we can skip the restart.
-            return;
+        match self.state.get() {
+            State::Running => {
+                // It was already true, so we can skip the restart.
+                return;
+            }
+            State::Offline => {
+                // The scheduler task will restart the syncs on its own.
+                if let Some(offline_mode) = &self.offline_mode {
+                    offline_mode.reachable.notify_one();
+                }
+                return;
+            }
+            State::Idle | State::Terminated | State::Error => {}
         }
 
         trace!("starting sync service");
 
         let (sender, receiver) = tokio::sync::mpsc::channel(16);
 
-        // First, take care of the room list.
-        *self.room_list_task.lock().unwrap() =
-            Some(spawn(Self::room_list_sync_task(self.room_list_service.clone(), sender.clone())));
+        // Publish the sender before starting the syncs, so that the sliding sync
+        // support discovery can't miss them.
+        *self.scheduler_sender.lock().unwrap() = Some(sender.clone());
 
-        // Then, take care of the encryption sync.
-        let sync_permit_guard = self.encryption_sync_permit.clone().lock_owned().await;
-        *self.encryption_sync_task.lock().unwrap() = Some(spawn(Self::encryption_sync_task(
-            self.encryption_sync_service.clone(),
-            sender.clone(),
-            sync_permit_guard,
-        )));
+        // Take care of the room list and the encryption sync.
+        self.sync_tasks().start(sender.clone()).await;
 
         // Spawn the scheduler task.
-        *self.scheduler_sender.lock().unwrap() = Some(sender);
-        *self.scheduler_task.lock().unwrap() = Some(spawn(self.spawn_scheduler_task(receiver)));
+        *self.scheduler_task.lock().unwrap() =
+            Some(spawn(self.spawn_scheduler_task(receiver, sender)));
 
         self.state.set(State::Running);
     }
@@ -351,7 +537,7 @@ impl SyncService {
                 // No need to stop if we were not running.
                 return Ok(());
             }
-            State::Running => {}
+            State::Running | State::Offline => {}
         };
 
         trace!("pausing sync service");
@@ -359,37 +545,81 @@ impl SyncService {
         // First, request to stop the two underlying syncs; we'll look at the results
         // later, so that we're in a clean state independently of the request to
         // stop.
+        self.send_termination_report(TerminationReport {
+            is_error: false,
+            has_expired: false,
+            is_network_error: false,
+            origin: TerminationOrigin::Scheduler,
+        })
+        .await?;
 
-        let sender = self.scheduler_sender.lock().unwrap().clone();
-        sender
+        let scheduler_task = self.scheduler_task.lock().unwrap().take();
+        scheduler_task
             .ok_or_else(|| {
-                error!("missing sender");
+                error!("missing scheduler task");
                 Error::InternalSchedulerError
             })?
-            .send(TerminationReport {
-                is_error: false,
-                has_expired: false,
-                origin: TerminationOrigin::Scheduler,
-            })
             .await
             .map_err(|err| {
-                error!("when sending termination report: {err}");
+                error!("couldn't finish scheduler task: {err}");
                 Error::InternalSchedulerError
             })?;
 
-        let scheduler_task = self.scheduler_task.lock().unwrap().take();
-        scheduler_task
+        Ok(())
+    }
+
+    /// Signal that the network became reachable or unreachable, as detected by
+    /// the platform.
+    ///
+    /// When the network becomes unreachable, the underlying syncs are stopped
+    /// and the service goes into the [`State::Offline`] state right away,
+    /// instead of waiting for repeated network errors. When it becomes
+    /// reachable again, the service checks right away whether the homeserver
+    /// can be reached, instead of waiting for the next periodic check.
+    ///
+    /// This has no effect if the offline mode isn't enabled.
+    #[instrument(skip(self))]
+    pub async fn set_network_reachable(&self, reachable: bool) -> Result<(), Error> {
+        let Some(offline_mode) = &self.offline_mode else {
+            warn!("the offline mode isn't enabled, ignoring the connectivity change");
+            return Ok(());
+        };
+
+        let _guard = self.modifying_state.lock().await;
+
+        match (self.state.get(), reachable) {
+            (State::Running, false) => {
+                self.send_termination_report(TerminationReport {
+                    is_error: true,
+                    has_expired: false,
+                    is_network_error: true,
+                    origin: TerminationOrigin::Connectivity,
+                })
+                .await?;
+            }
+            (State::Offline, true) => {
+                offline_mode.reachable.notify_one();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Send a `TerminationReport` to the scheduler task.
+    async fn send_termination_report(&self, report: TerminationReport) -> Result<(), Error> {
+        let sender = self.scheduler_sender.lock().unwrap().clone();
+        sender
             .ok_or_else(|| {
-                error!("missing scheduler task");
+                error!("missing sender");
                 Error::InternalSchedulerError
             })?
+            .send(report)
             .await
             .map_err(|err| {
-                error!("couldn't finish scheduler task: {err}");
+                error!("when sending termination report: {err}");
                 Error::InternalSchedulerError
-            })?;
-
-        Ok(())
+            })
     }
 
     /// Attempt to get a permit to use an `EncryptionSyncService` at a given
@@ -402,17 +632,351 @@ impl SyncService {
     }
 }
 
+impl Drop for SyncService {
+    fn drop(&mut self) {
+        if let Some(task) = &self.sliding_sync_discovery_task {
+            task.abort();
+        }
+    }
+}
+
+/// The unstable feature advertising the support of the native sliding sync.
+const NATIVE_SLIDING_SYNC_FEATURE: &str = "org.matrix.simplified_msc3575";
+
+/// Returns whether the homeserver supports the sliding sync version of the
+/// client, if it's known without any request to the homeserver.
+///
+/// The native sliding sync must be advertised by the homeserver, which is only
+/// known if its features are cached in the store.
+async fn cached_sliding_sync_support(client: &Client) -> Option<bool> {
+    match client.sliding_sync_version() {
+        SlidingSyncVersion::None => Some(false),
+        SlidingSyncVersion::Proxy { .. } => Some(true),
+        SlidingSyncVersion::Native => {
+            let capabilities =
+                match client.store().get_kv_data(StateStoreDataKey::ServerCapabilities).await {
+                    Ok(capabilities) => capabilities?.into_server_capabilities()?,
+                    Err(err) => {
+                        warn!("couldn't load the cached features of the homeserver: {err}");
+                        return None;
+                    }
+                };
+
+            Some(
+                capabilities
+                    .unstable_features
+                    .get(NATIVE_SLIDING_SYNC_FEATURE)
+                    .copied()
+                    .unwrap_or(false),
+            )
+        }
+    }
+}
+
+/// Discover whether the homeserver supports the native sliding sync, by
+/// fetching its features until it succeeds.
+///
+/// If it doesn't, the syncs are restarted if they are running, so that they
+/// fall back on `/sync` right away.
+async fn discover_sliding_sync_support(
+    client: Client,
+    sliding_sync_support: SharedObservable<Option<bool>>,
+    scheduler_sender: Arc<Mutex<Option<Sender<TerminationReport>>>>,
+) {
+    let mut delay = INITIAL_OFFLINE_CHECK_DELAY;
+
+    let supported = loop {
+        match client.unstable_features().await {
+            Ok(unstable_features) => {
+                break unstable_features.get(NATIVE_SLIDING_SYNC_FEATURE).copied().unwrap_or(false);
+            }
+            Err(err) => {
+                debug!(?delay, "couldn't get the features of the homeserver: {err}");
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_OFFLINE_CHECK_DELAY);
+            }
+        }
+    };
+
+    sliding_sync_support.set(Some(supported));
+
+    if supported {
+        return;
+    }
+
+    info!("the homeserver doesn't support sliding sync, falling back on `/sync`");
+
+    let sender = scheduler_sender.lock().unwrap().clone();
+    if let Some(sender) = sender {
+        let report = TerminationReport {
+            is_error: false,
+            has_expired: false,
+            is_network_error: false,
+            origin: TerminationOrigin::Fallback,
+        };
+
+        // If the scheduler task has stopped, the syncs aren't running: they'll fall
+        // back on `/sync` the next time they start.
+        if sender.send(report).await.is_err() {
+            debug!("the syncs aren't running, not restarting them");
+        }
+    }
+}
+
+/// Returns whether the error happened at the network layer, i.e. the
+/// homeserver couldn't be reached at all.
+fn is_network_error(err: &matrix_sdk::Error) -> bool {
+    matches!(err, matrix_sdk::Error::Http(HttpError::Reqwest(_)))
+}
+
+/// Everything needed to start and stop the underlying syncs, so that the
+/// scheduler task can restart them on its own.
+struct SyncTasks {
+    room_list_service: Arc<RoomListService>,
+    encryption_sync_service: Option<Arc<EncryptionSyncService>>,
+    room_list_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    encryption_sync_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    encryption_sync_permit: Arc<AsyncMutex<EncryptionSyncPermit>>,
+    network_failures: Arc<AtomicUsize>,
+    sliding_sync_support: SharedObservable<Option<bool>>,
+}
+
+impl SyncTasks {
+    /// Spawn the tasks running the room list and the encryption syncs.
+    ///
+    /// If it was discovered in the meantime that the homeserver doesn't
+    /// support sliding sync, only the room list sync is started, falling back
+    /// on `/sync`.
+    async fn start(&self, sender: Sender<TerminationReport>) {
+        if self.sliding_sync_support.get() == Some(false) {
+            self.room_list_service.fall_back_on_classic_sync();
+        }
+
+        let encryption_sync_service = self
+            .encryption_sync_service
+            .as_ref()
+            .filter(|_| !self.room_list_service.uses_classic_sync());
+
+        let Some(encryption_sync_service) = encryption_sync_service else {
+            // Falling back on `/sync`: there is only the room list sync.
+            let sync_permit_guard = self.encryption_sync_permit.clone().lock_owned().await;
+            *self.room_list_task.lock().unwrap() = Some(spawn(SyncService::room_list_sync_task(
+                self.room_list_service.clone(),
+                self.room_list_service.new_sync_run(),
+                sender,
+                self.network_failures.clone(),
+                Some(sync_permit_guard),
+            )));
+
+            return;
+        };
+
+        // First, take care of the room list.
+        *self.room_list_task.lock().unwrap() = Some(spawn(SyncService::room_list_sync_task(
+            self.room_list_service.clone(),
+            self.room_list_service.new_sync_run(),
+            sender.clone(),
+            self.network_failures.clone(),
+            None,
+        )));
+
+        // Then, take care of the encryption sync.
+        let sync_permit_guard = self.encryption_sync_permit.clone().lock_owned().await;
+        *self.encryption_sync_task.lock().unwrap() =
+            Some(spawn(SyncService::encryption_sync_task(
+                encryption_sync_service.clone(),
+                sender,
+                sync_permit_guard,
+            )));
+    }
+
+    /// Wait until it's known whether the homeserver supports sliding sync,
+    /// once the syncs have stopped.
+    ///
+    /// If it doesn't, the discovery sends a report to fall back on `/sync`,
+    /// which is consumed here. Returns `None` if the service was stopped in
+    /// the meantime.
+    async fn wait_for_sliding_sync_support(
+        &self,
+        receiver: &mut Receiver<TerminationReport>,
+    ) -> Option<bool> {
+        let mut sliding_sync_support = self.sliding_sync_support.subscribe();
+
+        loop {
+            if sliding_sync_support.get() == Some(true) {
+                return Some(true);
+            }
+
+            select! {
+                report = receiver.recv() => match report {
+                    Some(TerminationReport { origin: TerminationOrigin::Scheduler, .. }) | None => {
+                        return None;
+                    }
+                    Some(TerminationReport { origin: TerminationOrigin::Fallback, .. }) => {
+                        return Some(false);
+                    }
+                    // The syncs are already stopped, ignore any other report.
+                    Some(_) => continue,
+                },
+                // If the homeserver doesn't support sliding sync, keep waiting for the report
+                // of the discovery.
+                _ = sliding_sync_support.next() => {}
+            }
+        }
+    }
+
+    /// Stop both syncs, and wait for the streams to properly finish: at some
+    /// point they'll return `None` and will exit their infinite loops, and
+    /// their tasks will gracefully terminate.
+    async fn stop(&self, report: &TerminationReport) {
+        let (stop_room_list, stop_encryption) = report.origin.syncs_to_stop();
+
+        if stop_room_list {
+            if let Err(err) = self.room_list_service.stop_sync() {
+                warn!(?report, "unable to stop room list service: {err:#}");
+            }
+        }
+
+        {
+            let task = self.room_list_task.lock().unwrap().take();
+            if let Some(task) = task {
+                if let Err(err) = task.await {
+                    error!("when awaiting room list service: {err:#}");
+                }
+            }
+        }
+
+        if stop_encryption && !self.room_list_service.uses_classic_sync() {
+            if let Some(encryption_sync_service) = &self.encryption_sync_service {
+                if let Err(err) = encryption_sync_service.stop_sync() {
+                    warn!(?report, "unable to stop encryption sync: {err:#}");
+                }
+            }
+        }
+
+        {
+            let task = self.encryption_sync_task.lock().unwrap().take();
+            if let Some(task) = task {
+                if let Err(err) = task.await {
+                    error!("when awaiting encryption sync: {err:#}");
+                }
+            }
+        }
+    }
+
+    /// Expire the sessions of the syncs that didn't run into the expiration
+    /// error themselves.
+    async fn expire_sync_sessions(&self, report: &TerminationReport) {
+        let (stop_room_list, stop_encryption) = report.origin.syncs_to_stop();
+
+        if stop_room_list {
+            self.room_list_service.expire_sync_session().await;
+        }
+        if stop_encryption && !self.room_list_service.uses_classic_sync() {
+            if let Some(encryption_sync_service) = &self.encryption_sync_service {
+                encryption_sync_service.expire_sync_session().await;
+            }
+        }
+    }
+}
+
+/// The state of the offline mode of the sync service.
+struct OfflineMode {
+    /// SDK client, used to check whether the homeserver can be reached.
+    client: Client,
+
+    /// Notified when the application signals that the network became
+    /// reachable, or asks to start the service again.
+    reachable: Notify,
+
+    /// Whether the send queue was enabled before going offline, so that it's
+    /// only enabled again if it was.
+    send_queue_was_enabled: Mutex<bool>,
+}
+
+impl OfflineMode {
+    fn new(client: Client) -> Self {
+        Self { client, reachable: Notify::new(), send_queue_was_enabled: Mutex::new(false) }
+    }
+
+    /// Pause the send queue, remembering whether it was enabled.
+    async fn pause_send_queue(&self) {
+        let send_queue = self.client.send_queue();
+        *self.send_queue_was_enabled.lock().unwrap() = send_queue.is_enabled();
+        send_queue.set_enabled(false).await;
+    }
+
+    /// Resume the send queue, if it was enabled before going offline.
+    async fn resume_send_queue(&self) {
+        let was_enabled = std::mem::take(&mut *self.send_queue_was_enabled.lock().unwrap());
+        if was_enabled {
+            self.client.send_queue().set_enabled(true).await;
+        }
+    }
+
+    /// Wait until the homeserver can be reached, by checking its `/versions`
+    /// endpoint with an exponential backoff.
+    ///
+    /// Returns `false` if the service was stopped in the meantime.
+    async fn wait_until_reachable(&self, receiver: &mut Receiver<TerminationReport>) -> bool {
+        let mut delay = INITIAL_OFFLINE_CHECK_DELAY;
+
+        loop {
+            select! {
+                report = receiver.recv() => match report {
+                    Some(TerminationReport { origin: TerminationOrigin::Scheduler, .. }) | None => {
+                        return false;
+                    }
+                    // The syncs are already stopped, ignore any other report.
+                    Some(_) => continue,
+                },
+                _ = sleep(delay) => {}
+                _ = self.reachable.notified() => {}
+            }
+
+            let request = get_supported_versions::Request::new();
+            match self.client.send(request, None).await {
+                Ok(_) => return true,
+                Err(err) => {
+                    debug!(?delay, "the homeserver still can't be reached: {err}");
+                    delay = (delay * 2).min(MAX_OFFLINE_CHECK_DELAY);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 enum TerminationOrigin {
     EncryptionSync,
     RoomList,
     Scheduler,
+    /// The application signalled that the network became unreachable.
+    Connectivity,
+    /// The homeserver turned out not to support sliding sync, so the syncs
+    /// must be restarted to fall back on `/sync`.
+    Fallback,
+}
+
+impl TerminationOrigin {
+    /// Which of the room list and encryption syncs must be requested to stop,
+    /// the other one having stopped on its own.
+    fn syncs_to_stop(&self) -> (bool, bool) {
+        match self {
+            TerminationOrigin::EncryptionSync => (true, false),
+            TerminationOrigin::RoomList => (false, true),
+            TerminationOrigin::Scheduler
+            | TerminationOrigin::Connectivity
+            | TerminationOrigin::Fallback => (true, true),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct TerminationReport {
     is_error: bool,
     has_expired: bool,
+    is_network_error: bool,
     origin: TerminationOrigin,
 }
 
@@ -435,11 +999,22 @@ pub struct SyncServiceBuilder {
 
     /// Is the cross-process lock for the crypto store enabled?
     with_cross_process_lock: bool,
+
+    /// Is the offline mode enabled?
+    with_offline_mode: bool,
+
+    /// The builder of the room list service, if it isn't the default one.
+    room_list_service_builder: Option<RoomListServiceBuilder>,
 }
 
 impl SyncServiceBuilder {
     fn new(client: Client) -> Self {
-        Self { client, with_cross_process_lock: false }
+        Self {
+            client,
+            with_cross_process_lock: false,
+            with_offline_mode: false,
+            room_list_service_builder: None,
+        }
     }
 
     /// Enables the cross-process lock, if the sync service is being built in a
@@ -457,24 +1032,87 @@ impl SyncServiceBuilder {
         self
     }
 
+    /// Enables the offline mode.
+    ///
+    /// Instead of going into the [`State::Error`] state after a network error,
+    /// the sync service restarts the underlying syncs. After a few network
+    /// errors in a row, it goes into the [`State::Offline`] state, pauses the
+    /// send queue, and regularly checks whether the homeserver can be reached
+    /// again. Once it can, the syncs are restarted and the send queue is
+    /// resumed, without the user having to call [`SyncService::start()`].
+    pub fn with_offline_mode(mut self) -> Self {
+        self.with_offline_mode = true;
+        self
+    }
+
+    /// Use the given builder to create the room list service, e.g. to add
+    /// custom lists to it.
+    ///
+    /// By default, the room list service is created with
+    /// [`RoomListService::new`].
+    pub fn with_room_list_service_builder(mut self, builder: RoomListServiceBuilder) -> Self {
+        self.room_list_service_builder = Some(builder);
+        self
+    }
+
     /// Finish setting up the `SyncService`.
     ///
     /// This creates the underlying sliding syncs, and will *not* start them in
     /// the background. The resulting `SyncService` must be kept alive as
     /// long as the sliding syncs are supposed to run.
+    ///
+    /// If the homeserver doesn't support sliding sync, the `SyncService` falls
+    /// back on `/sync` v2 instead. No request is sent to the homeserver to
+    /// know it: if its features aren't cached, the sliding sync is used until
+    /// they are fetched in the background, and the syncs fall back on `/sync`
+    /// once it turns out that the homeserver doesn't support it.
     pub async fn build(self) -> Result<SyncService, Error> {
         let encryption_sync_permit = Arc::new(AsyncMutex::new(EncryptionSyncPermit::new()));
 
-        let room_list = RoomListService::new(self.client.clone()).await?;
+        let sliding_sync_support = cached_sliding_sync_support(&self.client).await;
+        let uses_classic_sync = sliding_sync_support == Some(false);
+
+        if uses_classic_sync {
+            info!("the homeserver doesn't support sliding sync, falling back on `/sync`");
+        }
+
+        let room_list_service_builder = self
+            .room_list_service_builder
+            .unwrap_or_else(|| RoomListService::builder(self.client.clone()));
 
-        let encryption_sync = Arc::new(
-            EncryptionSyncService::new(
+        let room_list = if uses_classic_sync {
+            room_list_service_builder.with_classic_sync().build().await?
+        } else {
+            room_list_service_builder.build().await?
+        };
+
+        let offline_mode =
+            self.with_offline_mode.then(|| Arc::new(OfflineMode::new(self.client.clone())));
+
+        let encryption_sync = if uses_classic_sync {
+            None
+        } else {
+            Some(Arc::new(
+                EncryptionSyncService::new(
+                    self.client.clone(),
+                    None,
+                    WithLocking::from(self.with_cross_process_lock),
+                )
+                .await?,
+            ))
+        };
+
+        let needs_discovery = sliding_sync_support.is_none();
+        let sliding_sync_support = SharedObservable::new(sliding_sync_support);
+        let scheduler_sender = Arc::new(Mutex::new(None));
+
+        let sliding_sync_discovery_task = needs_discovery.then(|| {
+            spawn(discover_sliding_sync_support(
                 self.client,
-                None,
-                WithLocking::from(self.with_cross_process_lock),
-            )
-            .await?,
-        );
+                sliding_sync_support.clone(),
+                scheduler_sender.clone(),
+            ))
+        });
 
         Ok(SyncService {
             room_list_service: Arc::new(room_list),
@@ -482,10 +1120,14 @@ impl SyncServiceBuilder {
             encryption_sync_task: Arc::new(Mutex::new(None)),
             room_list_task: Arc::new(Mutex::new(None)),
             scheduler_task: Arc::new(Mutex::new(None)),
-            scheduler_sender: Mutex::new(None),
+            scheduler_sender,
+            sliding_sync_support,
+            sliding_sync_discovery_task,
             state: SharedObservable::new(State::Idle),
             modifying_state: AsyncMutex::new(()),
             encryption_sync_permit,
+            network_failures: Default::default(),
+            offline_mode,
         })
     }
 }
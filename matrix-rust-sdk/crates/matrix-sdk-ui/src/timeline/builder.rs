@@ -31,8 +31,9 @@ use super::{
     Error, Timeline, TimelineDropHandle, TimelineFocus,
 };
 use crate::{
+    room_list_service::RoomSubscriptionHandle,
     timeline::{controller::TimelineNewItemPosition, event_item::RemoteEventOrigin},
-    unable_to_decrypt_hook::UtdHookManager,
+    unable_to_decrypt_hook::{UtdHookManager, UtdKeySource},
 };
 
 /// Builder that allows creating and configuring various parts of a
@@ -50,6 +51,9 @@ pub struct TimelineBuilder {
 
     /// An optional prefix for internal IDs.
     internal_id_prefix: Option<String>,
+
+    /// An optional room subscription, held as long as the timeline is alive.
+    room_subscription: Option<RoomSubscriptionHandle>,
 }
 
 impl TimelineBuilder {
@@ -60,6 +64,7 @@ impl TimelineBuilder {
             unable_to_decrypt_hook: None,
             focus: TimelineFocus::Live,
             internal_id_prefix: None,
+            room_subscription: None,
         }
     }
 
@@ -89,6 +94,13 @@ impl TimelineBuilder {
         self
     }
 
+    /// Keep the given room subscription as long as the timeline, or any of its
+    /// streams, is alive.
+    pub(crate) fn with_room_subscription(mut self, handle: RoomSubscriptionHandle) -> Self {
+        self.room_subscription = Some(handle);
+        self
+    }
+
     /// Enable tracking of the fully-read marker and the read receipts on the
     /// timeline.
     pub fn track_read_marker_and_receipts(mut self) -> Self {
@@ -146,7 +158,14 @@ impl TimelineBuilder {
         )
     )]
     pub async fn build(self) -> Result<Timeline, Error> {
-        let Self { room, settings, unable_to_decrypt_hook, focus, internal_id_prefix } = self;
+        let Self {
+            room,
+            settings,
+            unable_to_decrypt_hook,
+            focus,
+            internal_id_prefix,
+            room_subscription,
+        } = self;
 
         let client = room.client();
         let event_cache = client.event_cache();
@@ -379,10 +398,17 @@ impl TimelineBuilder {
                                 session_ids.extend(set);
                             }
 
-                            inner.retry_event_decryption(room, Some(session_ids)).await;
+                            inner
+                                .retry_event_decryption(
+                                    room,
+                                    Some(session_ids),
+                                    Some(UtdKeySource::Backup),
+                                )
+                                .await;
                         }
-                        // We lagged, so retry every event.
-                        Err(_) => inner.retry_event_decryption(room, None).await,
+                        // We lagged, so retry every event. The keys may have come from anywhere,
+                        // so their source is unknown.
+                        Err(_) => inner.retry_event_decryption(room, None, None).await,
                     }
                 }
             })
@@ -407,9 +433,12 @@ impl TimelineBuilder {
                         // 2. It will fail to decrypt the event, but try to download the room key to
                         //    decrypt it if the `BackupDownloadStrategy` has been set to
                         //    `AfterDecryptionFailure`.
+                        //
+                        // No room key has been received from the backup yet, so the source of the
+                        // keys decrypting the events is unknown.
                         Ok(BackupState::Enabled) | Err(_) => {
                             let room = inner.room();
-                            inner.retry_event_decryption(room, None).await;
+                            inner.retry_event_decryption(room, None, None).await;
                         }
                         // The other states aren't interesting since they are either still enabling
                         // the backup or have the backup in the disabled state.
@@ -439,6 +468,7 @@ impl TimelineBuilder {
                 local_echo_listener_handle,
                 _event_cache_drop_handle: event_cache_drop,
                 encryption_changes_handle,
+                _room_subscription: room_subscription,
             }),
         };
 
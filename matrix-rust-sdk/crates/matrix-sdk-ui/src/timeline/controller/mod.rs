@@ -74,7 +74,7 @@ use crate::{
         util::rfind_event_by_item_id,
         TimelineEventFilterFn,
     },
-    unable_to_decrypt_hook::UtdHookManager,
+    unable_to_decrypt_hook::{UtdHookManager, UtdKeySource},
 };
 
 mod state;
@@ -268,6 +268,7 @@ impl<P: RoomDataProvider> TimelineController<P> {
 
         let state = TimelineState::new(
             focus_kind,
+            room_data_provider.room_info().get().room_id().to_owned(),
             room_data_provider.own_user_id().to_owned(),
             room_data_provider.room_version(),
             internal_id_prefix,
@@ -977,8 +978,9 @@ impl<P: RoomDataProvider> TimelineController<P> {
         &self,
         room: &Room,
         session_ids: Option<BTreeSet<String>>,
+        key_source: Option<UtdKeySource>,
     ) {
-        self.retry_event_decryption_inner(room.to_owned(), session_ids).await
+        self.retry_event_decryption_inner(room.to_owned(), session_ids, key_source).await
     }
 
     #[cfg(test)]
@@ -988,13 +990,15 @@ impl<P: RoomDataProvider> TimelineController<P> {
         olm_machine: OlmMachine,
         session_ids: Option<BTreeSet<String>>,
     ) {
-        self.retry_event_decryption_inner((olm_machine, room_id.to_owned()), session_ids).await
+        self.retry_event_decryption_inner((olm_machine, room_id.to_owned()), session_ids, None)
+            .await
     }
 
     async fn retry_event_decryption_inner(
         &self,
         decryptor: impl Decryptor,
         session_ids: Option<BTreeSet<String>>,
+        key_source: Option<UtdKeySource>,
     ) {
         use super::EncryptedMessage;
 
@@ -1082,7 +1086,12 @@ impl<P: RoomDataProvider> TimelineController<P> {
                             } else {
                                 // Notify observers that we managed to eventually decrypt an event.
                                 if let Some(hook) = unable_to_decrypt_hook {
-                                    hook.on_late_decrypt(&remote_event.event_id, *utd_cause).await;
+                                    hook.on_late_decrypt(
+                                        &remote_event.event_id,
+                                        *utd_cause,
+                                        key_source,
+                                    )
+                                    .await;
                                 }
 
                                 Some(event)
@@ -1470,9 +1479,8 @@ impl TimelineController {
         thread: &ReceiptThread,
         event_id: &EventId,
     ) -> bool {
-        // We don't support threaded receipts yet.
         if *thread != ReceiptThread::Unthreaded {
-            return true;
+            return self.should_send_threaded_receipt(receipt_type, thread, event_id).await;
         }
 
         let own_user_id = self.room().own_user_id();
@@ -1525,6 +1533,50 @@ impl TimelineController {
         true
     }
 
+    /// Check whether the given threaded receipt should be sent.
+    ///
+    /// Threaded receipts are only compared to our previous receipts in the same
+    /// thread. Returns `false` if the given receipt is older than one of them.
+    async fn should_send_threaded_receipt(
+        &self,
+        receipt_type: &SendReceiptType,
+        thread: &ReceiptThread,
+        event_id: &EventId,
+    ) -> bool {
+        let previous_receipt_types: &[ReceiptType] = match receipt_type {
+            SendReceiptType::Read => &[ReceiptType::Read],
+            // It doesn't make sense to have a private read receipt behind a public one.
+            SendReceiptType::ReadPrivate => &[ReceiptType::Read, ReceiptType::ReadPrivate],
+            // Let the server handle the other receipts.
+            _ => return true,
+        };
+
+        let own_user_id = self.room().own_user_id();
+        let state = self.state.read().await;
+
+        for previous_receipt_type in previous_receipt_types {
+            let previous_receipt = self
+                .room()
+                .load_user_receipt(previous_receipt_type.clone(), thread.clone(), own_user_id)
+                .await;
+
+            let Ok(Some((previous_event_id, _))) = previous_receipt else {
+                continue;
+            };
+
+            trace!(%previous_event_id, ?thread, "found a previous threaded receipt");
+            if let Some(relative_pos) =
+                state.meta.compare_events_positions(&previous_event_id, event_id)
+            {
+                if relative_pos != RelativePosition::After {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
     /// Returns the latest event identifier, even if it's not visible, or if
     /// it's folded into another timeline item.
     pub(crate) async fn latest_event_id(&self) -> Option<OwnedEventId> {
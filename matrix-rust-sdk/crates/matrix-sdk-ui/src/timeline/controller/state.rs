@@ -39,8 +39,8 @@ use ruma::{
     },
     push::Action,
     serde::Raw,
-    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
-    RoomVersionId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedTransactionId,
+    OwnedUserId, RoomVersionId, UserId,
 };
 use tracing::{debug, instrument, trace, warn};
 
@@ -99,6 +99,7 @@ pub(in crate::timeline) struct TimelineState {
 impl TimelineState {
     pub(super) fn new(
         timeline_focus: TimelineFocusKind,
+        room_id: OwnedRoomId,
         own_user_id: OwnedUserId,
         room_version: RoomVersionId,
         internal_id_prefix: Option<String>,
@@ -111,6 +112,7 @@ impl TimelineState {
             // small enough.
             items: ObservableVector::with_capacity(32),
             meta: TimelineMetadata::new(
+                room_id,
                 own_user_id,
                 room_version,
                 internal_id_prefix,
@@ -887,6 +889,11 @@ pub(in crate::timeline) struct TimelineMetadata {
     /// This value is constant over the lifetime of the metadata.
     internal_id_prefix: Option<String>,
 
+    /// The ID of the room the timeline is attached to.
+    ///
+    /// This value is constant over the lifetime of the metadata.
+    pub(crate) room_id: OwnedRoomId,
+
     /// The hook to call whenever we run into a unable-to-decrypt event.
     ///
     /// This value is constant over the lifetime of the metadata.
@@ -957,6 +964,7 @@ const MAX_NUM_STASHED_PENDING_EDITS: NonZeroUsize = unsafe { NonZeroUsize::new_u
 
 impl TimelineMetadata {
     pub(crate) fn new(
+        room_id: OwnedRoomId,
         own_user_id: OwnedUserId,
         room_version: RoomVersionId,
         internal_id_prefix: Option<String>,
@@ -964,6 +972,7 @@ impl TimelineMetadata {
         is_room_encrypted: Option<bool>,
     ) -> Self {
         Self {
+            room_id,
             own_user_id,
             all_remote_events: Default::default(),
             next_internal_id: Default::default(),
@@ -609,7 +609,7 @@ enum DayDividerInsertError {
 mod tests {
     use assert_matches2::assert_let;
     use eyeball_im::ObservableVector;
-    use ruma::{owned_event_id, owned_user_id, uint, MilliSecondsSinceUnixEpoch};
+    use ruma::{owned_event_id, owned_room_id, owned_user_id, uint, MilliSecondsSinceUnixEpoch};
 
     use super::DayDividerAdjuster;
     use crate::timeline::{
@@ -644,6 +644,7 @@ mod tests {
 
     fn test_metadata() -> TimelineMetadata {
         TimelineMetadata::new(
+            owned_room_id!("!a:b.c"),
             owned_user_id!("@a:b.c"),
             ruma::RoomVersionId::V11,
             None,
@@ -37,7 +37,7 @@ use ruma::{
         receipt::Receipt,
         relation::Replacement,
         room::{
-            encrypted::RoomEncryptedEventContent,
+            encrypted::{EncryptedEventScheme, RoomEncryptedEventContent},
             member::RoomMemberEventContent,
             message::{self, RoomMessageEventContent, RoomMessageEventContentWithoutRelation},
         },
@@ -73,6 +73,7 @@ use crate::{
         traits::RoomDataProvider,
         RepliedToEvent,
     },
+    unable_to_decrypt_hook::UtdEventOrigin,
 };
 
 /// When adding an event, useful information related to the source of the event.
@@ -442,6 +443,16 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
             },
 
             TimelineEventKind::UnableToDecrypt { content, utd_cause } => {
+                #[allow(deprecated)]
+                let sender_device = as_variant!(
+                    &content.scheme,
+                    EncryptedEventScheme::MegolmV1AesSha2(c) => c.device_id.clone()
+                );
+                let session_id = as_variant!(
+                    &content.scheme,
+                    EncryptedEventScheme::MegolmV1AesSha2(c) => c.session_id.clone()
+                );
+
                 // TODO: Handle replacements if the replaced event is also UTD
                 self.add_item(TimelineItemContent::unable_to_decrypt(content, utd_cause), None);
 
@@ -449,7 +460,13 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 // timeline.
                 if let Some(hook) = self.meta.unable_to_decrypt_hook.as_ref() {
                     if let Some(event_id) = &self.ctx.flow.event_id() {
-                        hook.on_utd(event_id, utd_cause).await;
+                        let origin = UtdEventOrigin {
+                            room_id: self.meta.room_id.clone(),
+                            sender: self.ctx.sender.clone(),
+                            sender_device,
+                            session_id,
+                        };
+                        hook.on_utd(event_id, utd_cause, origin).await;
                     }
                 }
             }
@@ -55,7 +55,9 @@ use thiserror::Error;
 use tracing::{error, instrument, trace, warn};
 use util::rfind_event_by_item_id;
 
-use crate::timeline::pinned_events_loader::PinnedEventsRoom;
+use crate::{
+    room_list_service::RoomSubscriptionHandle, timeline::pinned_events_loader::PinnedEventsRoom,
+};
 
 mod builder;
 mod controller;
@@ -225,13 +227,14 @@ impl Timeline {
             .retry_event_decryption(
                 self.room(),
                 Some(session_ids.into_iter().map(Into::into).collect()),
+                None,
             )
             .await;
     }
 
     #[tracing::instrument(skip(self))]
     async fn retry_decryption_for_all_events(&self) {
-        self.controller.retry_event_decryption(self.room(), None).await;
+        self.controller.retry_event_decryption(self.room(), None, None).await;
     }
 
     /// Get the current timeline item for the given event ID, if any.
@@ -651,6 +654,10 @@ impl Timeline {
     /// first if the receipt points to an event in this timeline that is more
     /// recent than the current ones, to avoid unnecessary requests.
     ///
+    /// Threaded read receipts, for [`ReceiptThread::Thread`] or
+    /// [`ReceiptThread::Main`], are only compared to our previous receipts in
+    /// the same thread.
+    ///
     /// Returns a boolean indicating if it sent the request or not.
     #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
     pub async fn send_single_receipt(
@@ -820,6 +827,7 @@ struct TimelineDropHandle {
     local_echo_listener_handle: JoinHandle<()>,
     _event_cache_drop_handle: Arc<EventCacheDropHandles>,
     encryption_changes_handle: JoinHandle<()>,
+    _room_subscription: Option<RoomSubscriptionHandle>,
 }
 
 impl Drop for TimelineDropHandle {
@@ -178,6 +178,7 @@ async fn test_edit_updates_encryption_info() {
             sender_claimed_keys: BTreeMap::new(),
         },
         verification_state: VerificationState::Verified,
+        session_id: None,
     };
 
     let original_event: SyncTimelineEvent = DecryptedRoomEvent {
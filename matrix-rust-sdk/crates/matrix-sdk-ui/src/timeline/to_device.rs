@@ -22,6 +22,7 @@ use ruma::{
 use tracing::{debug_span, error, trace, Instrument};
 
 use super::controller::TimelineController;
+use crate::unable_to_decrypt_hook::UtdKeySource;
 
 pub(super) fn handle_room_key_event(
     timeline: TimelineController,
@@ -31,7 +32,15 @@ pub(super) fn handle_room_key_event(
         async move {
             let event_room_id = event.content.room_id;
             let session_id = event.content.session_id;
-            retry_decryption(client, timeline, room_id, event_room_id, session_id).await;
+            retry_decryption(
+                client,
+                timeline,
+                room_id,
+                event_room_id,
+                session_id,
+                UtdKeySource::RoomKey,
+            )
+            .await;
         }
         .instrument(debug_span!("handle_room_key_event"))
     }
@@ -45,7 +54,15 @@ pub(super) fn handle_forwarded_room_key_event(
         async move {
             let event_room_id = event.content.room_id;
             let session_id = event.content.session_id;
-            retry_decryption(client, timeline, room_id, event_room_id, session_id).await;
+            retry_decryption(
+                client,
+                timeline,
+                room_id,
+                event_room_id,
+                session_id,
+                UtdKeySource::ForwardedRoomKey,
+            )
+            .await;
         }
         .instrument(debug_span!("handle_forwarded_room_key_event"))
     }
@@ -57,6 +74,7 @@ async fn retry_decryption(
     room_id: OwnedRoomId,
     event_room_id: OwnedRoomId,
     session_id: String,
+    key_source: UtdKeySource,
 ) {
     if event_room_id != room_id {
         trace!(
@@ -71,5 +89,7 @@ async fn retry_decryption(
         return;
     };
 
-    timeline.retry_event_decryption(&room, Some(iter::once(session_id).collect())).await;
+    timeline
+        .retry_event_decryption(&room, Some(iter::once(session_id).collect()), Some(key_source))
+        .await;
 }
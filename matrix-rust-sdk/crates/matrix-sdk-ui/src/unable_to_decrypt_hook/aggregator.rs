@@ -0,0 +1,437 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An [`UnableToDecryptHook`] computing rolling statistics about the reported
+//! UTDs.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use matrix_sdk::crypto::types::events::UtdCause;
+use ruma::{OwnedDeviceId, OwnedRoomId, OwnedUserId};
+use serde::{Deserialize, Serialize};
+
+use super::{UnableToDecryptHook, UnableToDecryptInfo, UtdKeySource};
+
+/// The upper bounds of the buckets of the late decryption latency histogram,
+/// in milliseconds. The last bucket, without an upper bound, is implicit.
+const LATENCY_BUCKETS_MS: [u64; 7] = [500, 1_000, 2_000, 5_000, 10_000, 30_000, 60_000];
+
+/// A UTD reported to the aggregator.
+#[derive(Debug)]
+struct UtdRecord {
+    /// When the UTD was reported to the aggregator.
+    reported_at: Instant,
+
+    /// The reported UTD.
+    info: UnableToDecryptInfo,
+}
+
+/// An [`UnableToDecryptHook`] that computes rolling statistics about the UTDs
+/// reported to it: per cause, per room and per sender device, along with the
+/// latency of the late decryptions and how the room keys that fixed them were
+/// received.
+///
+/// It's meant to be used as the parent hook of a [`UtdHookManager`], which
+/// deduplicates the UTDs. Note that late decryptions are only reported by the
+/// [`UtdHookManager`] if it's configured with a max delay.
+///
+/// Only the UTDs reported during the last `window` are taken into account in
+/// the [`UtdReport`].
+///
+/// [`UtdHookManager`]: super::UtdHookManager
+#[derive(Debug)]
+pub struct UtdAggregator {
+    /// An optional hook to forward the UTDs to, once they've been recorded.
+    parent: Option<Arc<dyn UnableToDecryptHook>>,
+
+    /// How long the UTDs are taken into account in the statistics.
+    window: Duration,
+
+    /// The UTDs reported during the last `window`, from the oldest to the
+    /// newest.
+    records: Mutex<VecDeque<UtdRecord>>,
+}
+
+impl UtdAggregator {
+    /// Create a new [`UtdAggregator`], computing statistics over the UTDs
+    /// reported during the given `window`.
+    pub fn new(window: Duration) -> Self {
+        Self { parent: None, window, records: Default::default() }
+    }
+
+    /// Forward the UTDs to the given hook, once they've been recorded.
+    pub fn with_parent(mut self, parent: Arc<dyn UnableToDecryptHook>) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Compute a report of the UTDs reported during the last window.
+    pub fn report(&self) -> UtdReport {
+        let mut records = self.records.lock().unwrap();
+        self.prune(&mut records);
+
+        let mut report = UtdReport {
+            window_ms: duration_to_millis(self.window),
+            late_decryption_latency: LATENCY_BUCKETS_MS
+                .iter()
+                .map(|upper_bound| LatencyBucket { upper_bound_ms: Some(*upper_bound), count: 0 })
+                .chain([LatencyBucket { upper_bound_ms: None, count: 0 }])
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut by_sender_device: BTreeMap<_, UtdStats> = BTreeMap::new();
+
+        for UtdRecord { info, .. } in records.iter() {
+            report.overall.add(info);
+            report.by_cause.entry(cause_name(info.cause).to_owned()).or_default().add(info);
+            report.by_room.entry(info.room_id.clone()).or_default().add(info);
+            by_sender_device
+                .entry((info.sender.clone(), info.sender_device.clone()))
+                .or_default()
+                .add(info);
+
+            if let Some(time_to_decrypt) = info.time_to_decrypt {
+                let latency_ms = duration_to_millis(time_to_decrypt);
+                // The last bucket has no upper bound, so there's always a matching bucket.
+                if let Some(bucket) = report
+                    .late_decryption_latency
+                    .iter_mut()
+                    .find(|bucket| !bucket.upper_bound_ms.is_some_and(|bound| latency_ms > bound))
+                {
+                    bucket.count += 1;
+                }
+
+                match info.key_source {
+                    Some(UtdKeySource::RoomKey) => report.fixed_by.room_key += 1,
+                    Some(UtdKeySource::ForwardedRoomKey) => report.fixed_by.forwarded_room_key += 1,
+                    Some(UtdKeySource::Backup) => report.fixed_by.backup += 1,
+                    None => report.fixed_by.unknown += 1,
+                }
+            }
+        }
+
+        report.by_sender_device = by_sender_device
+            .into_iter()
+            .map(|((sender, device_id), stats)| SenderDeviceUtdStats { sender, device_id, stats })
+            .collect();
+
+        report
+    }
+
+    /// Forget about all the UTDs reported so far.
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+
+    /// Remove the records that are older than the window.
+    fn prune(&self, records: &mut VecDeque<UtdRecord>) {
+        while records.front().is_some_and(|record| record.reported_at.elapsed() > self.window) {
+            records.pop_front();
+        }
+    }
+}
+
+impl UnableToDecryptHook for UtdAggregator {
+    fn on_utd(&self, info: UnableToDecryptInfo) {
+        {
+            let mut records = self.records.lock().unwrap();
+            self.prune(&mut records);
+            records.push_back(UtdRecord { reported_at: Instant::now(), info: info.clone() });
+        }
+
+        if let Some(parent) = &self.parent {
+            parent.on_utd(info);
+        }
+    }
+}
+
+/// A serializable report of the UTDs reported to a [`UtdAggregator`] during
+/// its window.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UtdReport {
+    /// The duration of the window the statistics are computed over, in
+    /// milliseconds.
+    pub window_ms: u64,
+
+    /// The statistics over all the UTDs.
+    pub overall: UtdStats,
+
+    /// The statistics per [`UtdCause`], keyed by the name of the cause.
+    pub by_cause: BTreeMap<String, UtdStats>,
+
+    /// The statistics per room.
+    pub by_room: BTreeMap<OwnedRoomId, UtdStats>,
+
+    /// The statistics per sender device.
+    pub by_sender_device: Vec<SenderDeviceUtdStats>,
+
+    /// The histogram of the time it took to decrypt the events that could be
+    /// decrypted late, from the fastest to the slowest bucket.
+    pub late_decryption_latency: Vec<LatencyBucket>,
+
+    /// How the room keys that allowed to decrypt events late were received.
+    pub fixed_by: UtdFixStats,
+}
+
+/// The number of UTDs in a group.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtdStats {
+    /// The number of events that couldn't be decrypted at all.
+    pub definite: u64,
+
+    /// The number of events that could be decrypted late.
+    pub late_decrypted: u64,
+}
+
+impl UtdStats {
+    /// The total number of UTDs in the group.
+    pub fn total(&self) -> u64 {
+        self.definite + self.late_decrypted
+    }
+
+    fn add(&mut self, info: &UnableToDecryptInfo) {
+        if info.time_to_decrypt.is_some() {
+            self.late_decrypted += 1;
+        } else {
+            self.definite += 1;
+        }
+    }
+}
+
+/// The number of UTDs of events sent by a given device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderDeviceUtdStats {
+    /// The user who sent the events.
+    pub sender: OwnedUserId,
+
+    /// The device which sent the events, if known.
+    pub device_id: Option<OwnedDeviceId>,
+
+    /// The number of UTDs of events sent by this device.
+    pub stats: UtdStats,
+}
+
+/// A bucket of the late decryption latency histogram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    /// The inclusive upper bound of the bucket in milliseconds, or `None` for
+    /// the last bucket.
+    pub upper_bound_ms: Option<u64>,
+
+    /// The number of late decryptions that took at most `upper_bound_ms`, and
+    /// more than the upper bound of the previous bucket.
+    pub count: u64,
+}
+
+/// How the room keys that allowed to decrypt events late were received.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtdFixStats {
+    /// The number of events decrypted thanks to a room key sent by the sender.
+    pub room_key: u64,
+
+    /// The number of events decrypted thanks to a forwarded room key.
+    pub forwarded_room_key: u64,
+
+    /// The number of events decrypted thanks to the key backup.
+    pub backup: u64,
+
+    /// The number of events decrypted for an unknown reason, e.g. after an
+    /// explicit retry.
+    pub unknown: u64,
+}
+
+impl UtdFixStats {
+    /// The total number of events that could be decrypted late.
+    pub fn total(&self) -> u64 {
+        self.room_key + self.forwarded_room_key + self.backup + self.unknown
+    }
+
+    /// The share of the late decryptions that were fixed by the key backup,
+    /// between 0 and 1, or `None` if there weren't any late decryption.
+    pub fn backup_share(&self) -> Option<f64> {
+        self.share(self.backup)
+    }
+
+    /// The share of the late decryptions that were fixed by a forwarded room
+    /// key, between 0 and 1, or `None` if there weren't any late decryption.
+    pub fn forwarded_room_key_share(&self) -> Option<f64> {
+        self.share(self.forwarded_room_key)
+    }
+
+    fn share(&self, count: u64) -> Option<f64> {
+        let total = self.total();
+        (total > 0).then(|| count as f64 / total as f64)
+    }
+}
+
+fn duration_to_millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+fn cause_name(cause: UtdCause) -> &'static str {
+    match cause {
+        UtdCause::Unknown => "Unknown",
+        UtdCause::SentBeforeWeJoined => "SentBeforeWeJoined",
+        UtdCause::VerificationViolation => "VerificationViolation",
+        UtdCause::UnsignedDevice => "UnsignedDevice",
+        UtdCause::UnknownDevice => "UnknownDevice",
+        UtdCause::HistoricalMessage => "HistoricalMessage",
+        UtdCause::WithheldForUnverifiedOrInsecureDevice => "WithheldForUnverifiedOrInsecureDevice",
+        UtdCause::WithheldBySender => "WithheldBySender",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ruma::{event_id, owned_device_id, owned_room_id, owned_user_id, room_id, user_id};
+
+    use super::*;
+
+    fn utd(
+        event_id: &str,
+        cause: UtdCause,
+        room_id: OwnedRoomId,
+        time_to_decrypt: Option<Duration>,
+        key_source: Option<UtdKeySource>,
+    ) -> UnableToDecryptInfo {
+        UnableToDecryptInfo {
+            event_id: event_id.try_into().unwrap(),
+            time_to_decrypt,
+            cause,
+            room_id,
+            sender: owned_user_id!("@alice:example.org"),
+            sender_device: Some(owned_device_id!("ALICEDEVICE")),
+            session_id: None,
+            key_source,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let aggregator = UtdAggregator::new(Duration::from_secs(3600));
+        let room_a = owned_room_id!("!a:example.org");
+        let room_b = owned_room_id!("!b:example.org");
+
+        aggregator.on_utd(utd("$1", UtdCause::Unknown, room_a.clone(), None, None));
+        aggregator.on_utd(utd("$2", UtdCause::SentBeforeWeJoined, room_a.clone(), None, None));
+        aggregator.on_utd(utd(
+            "$3",
+            UtdCause::Unknown,
+            room_b.clone(),
+            Some(Duration::from_millis(300)),
+            Some(UtdKeySource::Backup),
+        ));
+        aggregator.on_utd(utd(
+            "$4",
+            UtdCause::Unknown,
+            room_b.clone(),
+            Some(Duration::from_secs(45)),
+            Some(UtdKeySource::ForwardedRoomKey),
+        ));
+        aggregator.on_utd(utd(
+            "$5",
+            UtdCause::Unknown,
+            room_b.clone(),
+            Some(Duration::from_secs(120)),
+            Some(UtdKeySource::Backup),
+        ));
+
+        let report = aggregator.report();
+
+        assert_eq!(report.window_ms, 3_600_000);
+        assert_eq!(report.overall, UtdStats { definite: 2, late_decrypted: 3 });
+        assert_eq!(report.overall.total(), 5);
+
+        assert_eq!(report.by_cause.len(), 2);
+        assert_eq!(report.by_cause["Unknown"], UtdStats { definite: 1, late_decrypted: 3 });
+        assert_eq!(
+            report.by_cause["SentBeforeWeJoined"],
+            UtdStats { definite: 1, late_decrypted: 0 }
+        );
+
+        assert_eq!(report.by_room[room_id!("!a:example.org")].definite, 2);
+        assert_eq!(report.by_room[room_id!("!b:example.org")].late_decrypted, 3);
+
+        assert_eq!(report.by_sender_device.len(), 1);
+        assert_eq!(report.by_sender_device[0].sender, user_id!("@alice:example.org"));
+        assert_eq!(report.by_sender_device[0].stats.total(), 5);
+
+        // 300ms is in the first bucket, 45s in the one up to 60s, and 120s in the
+        // last one.
+        let counts: Vec<_> = report.late_decryption_latency.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [1, 0, 0, 0, 0, 0, 1, 1]);
+        assert_eq!(report.late_decryption_latency.last().unwrap().upper_bound_ms, None);
+
+        assert_eq!(
+            report.fixed_by,
+            UtdFixStats { room_key: 0, forwarded_room_key: 1, backup: 2, unknown: 0 }
+        );
+        assert_eq!(report.fixed_by.forwarded_room_key_share(), Some(1.0 / 3.0));
+        assert_eq!(report.fixed_by.backup_share(), Some(2.0 / 3.0));
+
+        // The report can be serialized.
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["overall"]["definite"], 2);
+        assert_eq!(json["by_room"]["!b:example.org"]["late_decrypted"], 3);
+    }
+
+    #[test]
+    fn test_forwards_to_parent() {
+        #[derive(Debug, Default)]
+        struct Dummy {
+            utds: Mutex<Vec<UnableToDecryptInfo>>,
+        }
+
+        impl UnableToDecryptHook for Dummy {
+            fn on_utd(&self, info: UnableToDecryptInfo) {
+                self.utds.lock().unwrap().push(info);
+            }
+        }
+
+        let parent = Arc::new(Dummy::default());
+        let aggregator = UtdAggregator::new(Duration::from_secs(60)).with_parent(parent.clone());
+
+        aggregator.on_utd(utd("$1", UtdCause::Unknown, owned_room_id!("!a:b.c"), None, None));
+
+        let utds = parent.utds.lock().unwrap();
+        assert_eq!(utds.len(), 1);
+        assert_eq!(utds[0].event_id, event_id!("$1"));
+        assert_eq!(aggregator.report().overall.total(), 1);
+    }
+
+    #[test]
+    fn test_window() {
+        let aggregator = UtdAggregator::new(Duration::ZERO);
+
+        aggregator.on_utd(utd("$1", UtdCause::Unknown, owned_room_id!("!a:b.c"), None, None));
+        std::thread::sleep(Duration::from_millis(1));
+
+        // The UTD is out of the window by now.
+        let report = aggregator.report();
+        assert_eq!(report.overall.total(), 0);
+        assert!(report.by_room.is_empty());
+        assert_eq!(report.fixed_by.backup_share(), None);
+
+        aggregator.on_utd(utd("$2", UtdCause::Unknown, owned_room_id!("!a:b.c"), None, None));
+        aggregator.clear();
+        assert_eq!(aggregator.report().overall.total(), 0);
+    }
+}
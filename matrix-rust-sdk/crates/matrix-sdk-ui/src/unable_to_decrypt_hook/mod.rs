@@ -16,7 +16,8 @@
 //! events, and notable updates to such events.
 //!
 //! This provides a general trait that a consumer may implement, as well as
-//! utilities to simplify usage of this trait.
+//! utilities to simplify usage of this trait, like the [`UtdAggregator`] which
+//! computes statistics about the reported UTDs.
 
 use std::{
     collections::HashMap,
@@ -27,7 +28,8 @@ use std::{
 use growable_bloom_filter::{GrowableBloom, GrowableBloomBuilder};
 use matrix_sdk::{crypto::types::events::UtdCause, Client};
 use matrix_sdk_base::{StateStoreDataKey, StateStoreDataValue, StoreError};
-use ruma::{EventId, OwnedEventId};
+use ruma::{EventId, OwnedDeviceId, OwnedEventId, OwnedRoomId, OwnedUserId};
+use serde::{Deserialize, Serialize};
 use tokio::{
     spawn,
     sync::{Mutex as AsyncMutex, MutexGuard},
@@ -36,6 +38,12 @@ use tokio::{
 };
 use tracing::error;
 
+mod aggregator;
+
+pub use self::aggregator::{
+    LatencyBucket, SenderDeviceUtdStats, UtdAggregator, UtdFixStats, UtdReport, UtdStats,
+};
+
 /// A generic interface which methods get called whenever we observe a
 /// unable-to-decrypt (UTD) event.
 pub trait UnableToDecryptHook: std::fmt::Debug + Send + Sync {
@@ -63,6 +71,58 @@ pub struct UnableToDecryptInfo {
     /// What we know about what caused this UTD. E.g. was this event sent when
     /// we were not a member of this room?
     pub cause: UtdCause,
+
+    /// The room the event was sent in.
+    pub room_id: OwnedRoomId,
+
+    /// The user who sent the event.
+    pub sender: OwnedUserId,
+
+    /// The device which sent the event, as claimed in the encrypted content of
+    /// the event, if known.
+    pub sender_device: Option<OwnedDeviceId>,
+
+    /// The ID of the Megolm session the event was encrypted with, if known.
+    ///
+    /// It can be matched with the updates of
+    /// [`matrix_sdk::encryption::Encryption::key_request_updates_stream()`],
+    /// to follow the room key requests sent for this event.
+    pub session_id: Option<String>,
+
+    /// If the event could be decrypted late, how we received the room key that
+    /// allowed to decrypt it, if known.
+    pub key_source: Option<UtdKeySource>,
+}
+
+/// How we received the room key that allowed to decrypt an event late.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UtdKeySource {
+    /// The room key was sent by the sender of the event, in a `m.room_key`
+    /// to-device event.
+    RoomKey,
+
+    /// The room key was forwarded by another device, in a
+    /// `m.forwarded_room_key` to-device event.
+    ForwardedRoomKey,
+
+    /// The room key was downloaded from the server-side key backup.
+    Backup,
+}
+
+/// Where an event we were unable to decrypt comes from.
+#[derive(Clone, Debug)]
+pub(crate) struct UtdEventOrigin {
+    /// The room the event was sent in.
+    pub room_id: OwnedRoomId,
+
+    /// The user who sent the event.
+    pub sender: OwnedUserId,
+
+    /// The device which sent the event, if known.
+    pub sender_device: Option<OwnedDeviceId>,
+
+    /// The Megolm session the event was encrypted with, if known.
+    pub session_id: Option<String>,
 }
 
 /// Data about a UTD event which we are waiting to report to the parent hook.
@@ -71,6 +131,9 @@ struct PendingUtdReport {
     /// The time that we received the UTD report from the timeline code.
     marked_utd_at: Instant,
 
+    /// Where the event comes from.
+    origin: UtdEventOrigin,
+
     /// The task that will report this UTD to the parent hook.
     report_task: JoinHandle<()>,
 }
@@ -197,7 +260,7 @@ impl UtdHookManager {
     /// The function to call whenever a UTD is seen for the first time.
     ///
     /// Pipe in any information that needs to be included in the final report.
-    pub(crate) async fn on_utd(&self, event_id: &EventId, cause: UtdCause) {
+    pub(crate) async fn on_utd(&self, event_id: &EventId, cause: UtdCause, origin: UtdEventOrigin) {
         // Hold the lock on `reported_utds` throughout, to avoid races with other
         // threads.
         let mut reported_utds_lock = self.reported_utds.lock().await;
@@ -213,8 +276,16 @@ impl UtdHookManager {
             return;
         }
 
-        let info =
-            UnableToDecryptInfo { event_id: event_id.to_owned(), time_to_decrypt: None, cause };
+        let info = UnableToDecryptInfo {
+            event_id: event_id.to_owned(),
+            time_to_decrypt: None,
+            cause,
+            room_id: origin.room_id.clone(),
+            sender: origin.sender.clone(),
+            sender_device: origin.sender_device.clone(),
+            session_id: origin.session_id.clone(),
+            key_source: None,
+        };
 
         let Some(max_delay) = self.max_delay else {
             // No delay: immediately report the event to the parent hook.
@@ -251,7 +322,7 @@ impl UtdHookManager {
         // Add the task to the set of pending tasks.
         self.pending_delayed.lock().unwrap().insert(
             event_id.to_owned(),
-            PendingUtdReport { marked_utd_at: Instant::now(), report_task: handle },
+            PendingUtdReport { marked_utd_at: Instant::now(), origin, report_task: handle },
         );
     }
 
@@ -260,7 +331,12 @@ impl UtdHookManager {
     ///
     /// Note: if this is called for an event that was never marked as a UTD
     /// before, it has no effect.
-    pub(crate) async fn on_late_decrypt(&self, event_id: &EventId, cause: UtdCause) {
+    pub(crate) async fn on_late_decrypt(
+        &self,
+        event_id: &EventId,
+        cause: UtdCause,
+        key_source: Option<UtdKeySource>,
+    ) {
         // Hold the lock on `reported_utds` throughout, to avoid races with other
         // threads.
         let mut reported_utds_lock = self.reported_utds.lock().await;
@@ -276,10 +352,16 @@ impl UtdHookManager {
         pending_utd_report.report_task.abort();
 
         // Now we can report the late decryption.
+        let origin = pending_utd_report.origin;
         let info = UnableToDecryptInfo {
             event_id: event_id.to_owned(),
             time_to_decrypt: Some(pending_utd_report.marked_utd_at.elapsed()),
             cause,
+            room_id: origin.room_id,
+            sender: origin.sender,
+            sender_device: origin.sender_device,
+            session_id: origin.session_id,
+            key_source,
         };
         Self::report_utd(info, &self.parent, &self.client, &mut reported_utds_lock).await;
     }
@@ -335,10 +417,19 @@ impl Drop for UtdHookManager {
 mod tests {
     use matrix_sdk::test_utils::no_retry_test_client;
     use matrix_sdk_test::async_test;
-    use ruma::event_id;
+    use ruma::{event_id, owned_device_id, owned_room_id, owned_user_id};
 
     use super::*;
 
+    fn origin() -> UtdEventOrigin {
+        UtdEventOrigin {
+            room_id: owned_room_id!("!room:example.org"),
+            sender: owned_user_id!("@alice:example.org"),
+            sender_device: Some(owned_device_id!("ALICEDEVICE")),
+            session_id: Some("session".to_owned()),
+        }
+    }
+
     #[derive(Debug, Default)]
     struct Dummy {
         utds: Mutex<Vec<UnableToDecryptInfo>>,
@@ -359,12 +450,12 @@ mod tests {
         let wrapper = UtdHookManager::new(hook.clone(), no_retry_test_client(None).await);
 
         // And I call the `on_utd` method multiple times, sometimes on the same event,
-        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
-        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
-        wrapper.on_utd(event_id!("$2"), UtdCause::Unknown).await;
-        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
-        wrapper.on_utd(event_id!("$2"), UtdCause::Unknown).await;
-        wrapper.on_utd(event_id!("$3"), UtdCause::Unknown).await;
+        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
+        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
+        wrapper.on_utd(event_id!("$2"), UtdCause::Unknown, origin()).await;
+        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
+        wrapper.on_utd(event_id!("$2"), UtdCause::Unknown, origin()).await;
+        wrapper.on_utd(event_id!("$3"), UtdCause::Unknown, origin()).await;
 
         // Then the event ids have been deduplicated,
         {
@@ -393,8 +484,8 @@ mod tests {
             let wrapper = UtdHookManager::new(hook.clone(), client.clone());
 
             // I call it a couple of times with different events
-            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
-            wrapper.on_utd(event_id!("$2"), UtdCause::Unknown).await;
+            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
+            wrapper.on_utd(event_id!("$2"), UtdCause::Unknown, origin()).await;
 
             // Sanity-check the reported event IDs
             {
@@ -414,8 +505,8 @@ mod tests {
             wrapper.reload_from_store().await.unwrap();
 
             // Call it with more events, some of which match the previous instance
-            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
-            wrapper.on_utd(event_id!("$3"), UtdCause::Unknown).await;
+            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
+            wrapper.on_utd(event_id!("$3"), UtdCause::Unknown, origin()).await;
 
             // Only the *new* ones should be reported
             let utds = hook.utds.lock().unwrap();
@@ -439,7 +530,7 @@ mod tests {
                 .with_max_delay(Duration::from_secs(2));
 
             // a UTD event
-            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
+            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
 
             // The event ID should not yet have been reported.
             {
@@ -455,7 +546,7 @@ mod tests {
             wrapper.reload_from_store().await.unwrap();
 
             // Call the new hook with the same event
-            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
+            wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
 
             // And it should be reported.
             sleep(Duration::from_millis(2500)).await;
@@ -476,7 +567,9 @@ mod tests {
 
         // And I call the `on_late_decrypt` method before the event had been marked as
         // utd,
-        wrapper.on_late_decrypt(event_id!("$1"), UtdCause::Unknown).await;
+        wrapper
+            .on_late_decrypt(event_id!("$1"), UtdCause::Unknown, Some(UtdKeySource::Backup))
+            .await;
 
         // Then nothing is registered in the parent hook.
         assert!(hook.utds.lock().unwrap().is_empty());
@@ -491,7 +584,7 @@ mod tests {
         let wrapper = UtdHookManager::new(hook.clone(), no_retry_test_client(None).await);
 
         // And I call the `on_utd` method for an event,
-        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
+        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
 
         // Then the UTD has been notified, but not as late-decrypted event.
         {
@@ -502,7 +595,9 @@ mod tests {
         }
 
         // And when I call the `on_late_decrypt` method,
-        wrapper.on_late_decrypt(event_id!("$1"), UtdCause::Unknown).await;
+        wrapper
+            .on_late_decrypt(event_id!("$1"), UtdCause::Unknown, Some(UtdKeySource::Backup))
+            .await;
 
         // Then the event is not reported again as a late-decryption.
         {
@@ -527,7 +622,7 @@ mod tests {
             .with_max_delay(Duration::from_secs(2));
 
         // And I call the `on_utd` method for an event,
-        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
+        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
 
         // Then the UTD is not being reported immediately.
         assert!(hook.utds.lock().unwrap().is_empty());
@@ -564,7 +659,7 @@ mod tests {
             .with_max_delay(Duration::from_secs(2));
 
         // And I call the `on_utd` method for an event,
-        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown).await;
+        wrapper.on_utd(event_id!("$1"), UtdCause::Unknown, origin()).await;
 
         // Then the UTD has not been notified quite yet.
         assert!(hook.utds.lock().unwrap().is_empty());
@@ -573,7 +668,9 @@ mod tests {
         // If I wait for 1 second, and mark the event as late-decrypted,
         sleep(Duration::from_secs(1)).await;
 
-        wrapper.on_late_decrypt(event_id!("$1"), UtdCause::Unknown).await;
+        wrapper
+            .on_late_decrypt(event_id!("$1"), UtdCause::Unknown, Some(UtdKeySource::Backup))
+            .await;
 
         // Then it's being immediately reported as a late-decryption UTD.
         {
@@ -581,6 +678,13 @@ mod tests {
             assert_eq!(utds.len(), 1);
             assert_eq!(utds[0].event_id, event_id!("$1"));
             assert!(utds[0].time_to_decrypt.is_some());
+
+            // With where the event comes from, and how it was decrypted.
+            assert_eq!(utds[0].room_id, "!room:example.org");
+            assert_eq!(utds[0].sender, "@alice:example.org");
+            assert_eq!(utds[0].sender_device.as_deref().map(|d| d.as_str()), Some("ALICEDEVICE"));
+            assert_eq!(utds[0].session_id.as_deref(), Some("session"));
+            assert_eq!(utds[0].key_source, Some(UtdKeySource::Backup));
         }
 
         // And there aren't any pending delayed reports anymore.
@@ -8,22 +8,29 @@ use eyeball_im::VectorDiff;
 use futures_util::{pin_mut, FutureExt, StreamExt};
 use matrix_sdk::{
     config::RequestConfig,
+    sync_checkpoints::{SyncCheckpoint, SyncPosition},
     test_utils::{logged_in_client_with_server, set_client_session, test_client_builder},
     Client,
 };
-use matrix_sdk_base::sync::UnreadNotificationsCount;
+use matrix_sdk_base::{
+    sliding_sync::http::request::{ListFilters, RoomSubscription},
+    sync::UnreadNotificationsCount,
+};
 use matrix_sdk_test::{async_test, mocks::mock_encryption_state};
 use matrix_sdk_ui::{
     room_list_service::{
         filters::{new_filter_fuzzy_match_room_name, new_filter_non_left, new_filter_none},
-        Error, RoomListLoadingState, State, SyncIndicator, ALL_ROOMS_LIST_NAME as ALL_ROOMS,
+        Error, RoomListDefinition, RoomListLoadingState, State, SyncIndicator,
+        ALL_ROOMS_LIST_NAME as ALL_ROOMS,
     },
     timeline::{TimelineItemKind, VirtualTimelineItem},
     RoomListService,
 };
 use ruma::{
-    api::client::room::create_room::v3::Request as CreateRoomRequest, event_id,
-    events::room::message::RoomMessageEventContent, mxc_uri, room_id,
+    api::client::room::create_room::v3::Request as CreateRoomRequest,
+    assign, event_id,
+    events::{room::message::RoomMessageEventContent, StateEventType},
+    mxc_uri, room_id, uint, MilliSecondsSinceUnixEpoch,
 };
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
@@ -34,7 +41,10 @@ use wiremock::{
     Mock, MockServer, ResponseTemplate,
 };
 
-use crate::timeline::sliding_sync::{assert_timeline_stream, timeline_event};
+use crate::{
+    sliding_sync::last_room_subscriptions,
+    timeline::sliding_sync::{assert_timeline_stream, timeline_event},
+};
 
 async fn new_room_list_service() -> Result<(Client, MockServer, RoomListService), Error> {
     let (client, server) = logged_in_client_with_server().await;
@@ -498,6 +508,115 @@ async fn test_sync_all_states() -> Result<(), Error> {
     Ok(())
 }
 
+#[async_test]
+async fn test_sync_custom_lists() -> Result<(), Error> {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_list = RoomListService::builder(client)
+        .add_list(
+            RoomListDefinition::new("invites")
+                .required_state(vec![(StateEventType::RoomName, "".to_owned())])
+                .timeline_limit(0)
+                .include_heroes(false)
+                .filters(Some(assign!(ListFilters::default(), { is_invite: Some(true) })))
+                .selective_range(0..=9)
+                .growing_batch_size(50),
+        )
+        .typing_extension(false)
+        .build()
+        .await?;
+
+    assert_eq!(room_list.list_names().collect::<Vec<_>>(), [ALL_ROOMS, "invites"]);
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert request = {
+            "conn_id": "room-list",
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                    "timeline_limit": 1,
+                },
+                "invites": {
+                    "ranges": [[0, 9]],
+                    "required_state": [
+                        ["m.room.name", ""],
+                    ],
+                    "include_heroes": false,
+                    "filters": {
+                        "is_invite": true,
+                    },
+                    "timeline_limit": 0,
+                },
+            },
+            "extensions": {
+                "account_data": {
+                    "enabled": true
+                },
+                "receipts": {
+                    "enabled": true,
+                    "rooms": ["*"]
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 420,
+                },
+                "invites": {
+                    "count": 2,
+                },
+            },
+            "rooms": {},
+        },
+    };
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = SettingUp => Running,
+        assert request = {
+            "conn_id": "room-list",
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 99]],
+                },
+                "invites": {
+                    "ranges": [[0, 1]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "1",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 420,
+                },
+                "invites": {
+                    "count": 2,
+                },
+            },
+            "rooms": {},
+        },
+    };
+
+    let invites = room_list.list("invites").await?;
+    assert_matches!(
+        invites.loading_state().get(),
+        RoomListLoadingState::Loaded { maximum_number_of_rooms: Some(2) }
+    );
+
+    assert_matches!(room_list.list("unknown").await, Err(Error::UnknownList(name)) => {
+        assert_eq!(name, "unknown");
+    });
+
+    Ok(())
+}
+
 #[async_test]
 async fn test_sync_resumes_from_previous_state() -> Result<(), Error> {
     let (_, server, room_list) = new_room_list_service().await?;
@@ -688,6 +807,49 @@ async fn test_sync_resumes_from_previous_state_after_restart() -> Result<(), Err
     Ok(())
 }
 
+#[async_test]
+async fn test_sync_resumes_from_checkpoint_position() -> Result<(), Error> {
+    let (_, server, room_list) = new_room_list_service().await?;
+
+    let checkpoint = |positions: &[(&str, &str)]| SyncCheckpoint {
+        id: 0,
+        created_at: MilliSecondsSinceUnixEpoch::now(),
+        position: SyncPosition::SlidingSync {
+            positions: positions
+                .iter()
+                .map(|(conn_id, pos)| ((*conn_id).to_owned(), (*pos).to_owned()))
+                .collect(),
+        },
+    };
+
+    // A checkpoint without a position for the room list sliding sync is ignored.
+    assert!(room_list.restore_checkpoint_position(&checkpoint(&[("encryption", "7")])).await.not());
+
+    // Otherwise, the room list sliding sync resumes from its own position.
+    assert!(
+        room_list
+            .restore_checkpoint_position(&checkpoint(&[("encryption", "7"), ("room-list", "42")]))
+            .await
+    );
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Init => SettingUp,
+        assert pos Some("42"),
+        assert request >= {},
+        respond with = {
+            "pos": "43",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    Ok(())
+}
+
 #[async_test]
 async fn test_sync_resumes_from_error() -> Result<(), Error> {
     let (_, server, room_list) = new_room_list_service().await?;
@@ -2280,27 +2442,163 @@ async fn test_room_subscription() -> Result<(), Error> {
         },
     };
 
-    // Subscribe to an already subscribed room. Nothing happens.
+    // All the subscriptions are sent with every request.
+    assert_eq!(last_room_subscriptions(&server).await, [room_id_1.as_str(), room_id_2.as_str()]);
+
+    // Subscribe to an already subscribed room. Nothing changes.
 
     room_list.subscribe_to_rooms(&[room_id_1]);
 
     sync_then_assert_request_and_fake_response! {
         [server, room_list, sync]
-        // strict comparison (with `=`) because we want to ensure
-        // the absence of `room_subscriptions`.
-        assert request = {
-            "conn_id": "room-list",
+        assert request >= {
             "lists": {
                 ALL_ROOMS: {
                     "ranges": [[0, 2]],
-                    "timeline_limit": 1,
                 },
             },
-            // NO `room_subscriptions`!
-            "extensions": {
-                "account_data": { "enabled": true },
-                "receipts": { "enabled": true, "rooms": [ "*" ] },
-                "typing": { "enabled": true },
+        },
+        respond with = {
+            "pos": "3",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    assert_eq!(last_room_subscriptions(&server).await, [room_id_1.as_str(), room_id_2.as_str()]);
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_room_subscription_handles() -> Result<(), Error> {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_list = RoomListService::builder(client).max_room_subscriptions(2).build().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let room_id_0 = room_id!("!r0:bar.org");
+    let room_id_1 = room_id!("!r1:bar.org");
+    let room_id_2 = room_id!("!r2:bar.org");
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 3,
+                },
+            },
+            "rooms": {
+                room_id_0: {
+                    "initial": true,
+                },
+                room_id_1: {
+                    "initial": true,
+                },
+                room_id_2: {
+                    "initial": true,
+                }
+            },
+        },
+    };
+
+    // Subscribe twice to the same room, with different settings: they are merged.
+
+    let handle_0 = room_list.subscribe_to_room(
+        room_id_0,
+        Some(assign!(RoomSubscription::default(), {
+            timeline_limit: uint!(3),
+            required_state: vec![(StateEventType::RoomName, "".to_owned())],
+        })),
+    );
+    let handle_0_bis = room_list.subscribe_to_room(
+        room_id_0,
+        Some(assign!(RoomSubscription::default(), {
+            timeline_limit: uint!(5),
+            required_state: vec![(StateEventType::RoomTopic, "".to_owned())],
+        })),
+    );
+
+    assert_eq!(handle_0.room_id(), room_id_0);
+    assert_eq!(room_list.subscribed_rooms(), [room_id_0.to_owned()]);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "room_subscriptions": {
+                room_id_0: {
+                    "timeline_limit": 5,
+                    "required_state": [
+                        ["m.room.name", ""],
+                        ["m.room.topic", ""],
+                    ],
+                },
+            },
+        },
+        respond with = {
+            "pos": "1",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    // Dropping one handle keeps the subscription alive, with the settings of the
+    // remaining handle only.
+
+    drop(handle_0_bis);
+
+    assert_eq!(room_list.subscribed_rooms(), [room_id_0.to_owned()]);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "room_subscriptions": {
+                room_id_0: {
+                    "timeline_limit": 3,
+                    "required_state": [
+                        ["m.room.name", ""],
+                    ],
+                },
+            },
+        },
+        respond with = {
+            "pos": "2",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    // Subscribe to a room without a handle, then to another room with a handle:
+    // the subscription without a handle is evicted, even if it's not the least
+    // recently used one, because `room_id_0` has a live handle.
+
+    room_list.subscribe_to_rooms(&[room_id_1]);
+    let handle_2 = room_list.subscribe_to_room(room_id_2, None);
+
+    let mut subscribed_rooms = room_list.subscribed_rooms();
+    subscribed_rooms.sort();
+    assert_eq!(subscribed_rooms, [room_id_0.to_owned(), room_id_2.to_owned()]);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "room_subscriptions": {
+                room_id_0: {
+                    "timeline_limit": 3,
+                },
+                room_id_2: {
+                    "timeline_limit": 20,
+                },
             },
         },
         respond with = {
@@ -2310,6 +2608,217 @@ async fn test_room_subscription() -> Result<(), Error> {
         },
     };
 
+    assert_eq!(last_room_subscriptions(&server).await, [room_id_0.as_str(), room_id_2.as_str()]);
+
+    // When all the subscriptions have live handles, none of them is evicted, even
+    // if the maximum is exceeded.
+
+    let handle_1 = room_list.subscribe_to_room(room_id_1, None);
+
+    let mut subscribed_rooms = room_list.subscribed_rooms();
+    subscribed_rooms.sort();
+    assert_eq!(
+        subscribed_rooms,
+        [room_id_0.to_owned(), room_id_1.to_owned(), room_id_2.to_owned()]
+    );
+
+    // Dropping the last handle of a subscription unsubscribes the room: it's not
+    // sent anymore, so the server drops it.
+
+    drop(handle_2);
+
+    let mut subscribed_rooms = room_list.subscribed_rooms();
+    subscribed_rooms.sort();
+    assert_eq!(subscribed_rooms, [room_id_0.to_owned(), room_id_1.to_owned()]);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "room_subscriptions": {
+                room_id_0: {
+                    "timeline_limit": 3,
+                },
+                room_id_1: {
+                    "timeline_limit": 20,
+                },
+            },
+        },
+        respond with = {
+            "pos": "4",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    assert_eq!(last_room_subscriptions(&server).await, [room_id_0.as_str(), room_id_1.as_str()]);
+
+    drop(handle_0);
+    drop(handle_1);
+
+    assert!(room_list.subscribed_rooms().is_empty());
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_room_subscription_after_session_expiration() -> Result<(), Error> {
+    let (_, server, room_list) = new_room_list_service().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let room_id_0 = room_id!("!r0:bar.org");
+    let room_id_1 = room_id!("!r1:bar.org");
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "lists": {
+                ALL_ROOMS: {
+                    "ranges": [[0, 19]],
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 2,
+                },
+            },
+            "rooms": {
+                room_id_0: {
+                    "initial": true,
+                },
+                room_id_1: {
+                    "initial": true,
+                },
+            },
+        },
+    };
+
+    // Subscribe to a room without a handle, and to another one with a handle.
+
+    room_list.subscribe_to_rooms(&[room_id_0]);
+    let handle_1 = room_list.subscribe_to_room(
+        room_id_1,
+        Some(assign!(RoomSubscription::default(), { timeline_limit: uint!(5) })),
+    );
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "room_subscriptions": {
+                room_id_0: {
+                    "timeline_limit": 20,
+                },
+                room_id_1: {
+                    "timeline_limit": 5,
+                },
+            },
+        },
+        respond with = {
+            "pos": "1",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    // Another handle with a higher `timeline_limit` updates the subscription,
+    // which is sent again.
+
+    let handle_1_bis = room_list.subscribe_to_room(
+        room_id_1,
+        Some(assign!(RoomSubscription::default(), { timeline_limit: uint!(10) })),
+    );
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {
+            "room_subscriptions": {
+                room_id_1: {
+                    "timeline_limit": 10,
+                },
+            },
+        },
+        respond with = {
+            "pos": "2",
+            "lists": {},
+            "rooms": {},
+        },
+    };
+
+    // Another error than an expired session doesn't change the subscriptions.
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        sync matches Some(Err(_)),
+        states = Running => Error { .. },
+        assert request >= {},
+        respond with = (code 400) {
+            "error": "foo",
+            "errcode": "M_UNKNOWN",
+        },
+    };
+
+    // Ensure sync is terminated.
+    assert!(sync.next().await.is_none());
+
+    let mut subscribed_rooms = room_list.subscribed_rooms();
+    subscribed_rooms.sort();
+    assert_eq!(subscribed_rooms, [room_id_0.to_owned(), room_id_1.to_owned()]);
+
+    // The session expires.
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        sync matches Some(Err(_)),
+        states = Error { .. } => Error { .. },
+        assert request >= {},
+        respond with = (code 400) {
+            "error": "foo",
+            "errcode": "M_UNKNOWN_POS",
+        },
+    };
+
+    // Ensure sync is terminated.
+    assert!(sync.next().await.is_none());
+
+    // Only the subscription with live handles is kept.
+    assert_eq!(room_list.subscribed_rooms(), [room_id_1.to_owned()]);
+
+    // Start a new sync: only the subscription with live handles is sent again.
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        states = Error { .. } => Recovering,
+        assert pos None::<String>,
+        assert request >= {
+            "room_subscriptions": {
+                room_id_1: {
+                    "timeline_limit": 10,
+                },
+            },
+        },
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 2,
+                },
+            },
+            "rooms": {},
+        },
+    };
+
+    drop(handle_1);
+    drop(handle_1_bis);
+
     Ok(())
 }
 
@@ -2464,6 +2973,55 @@ async fn test_room_timeline() -> Result<(), Error> {
     Ok(())
 }
 
+#[async_test]
+async fn test_room_timeline_keeps_the_room_subscribed() -> Result<(), Error> {
+    let (_, server, room_list) = new_room_list_service().await?;
+
+    let sync = room_list.sync();
+    pin_mut!(sync);
+
+    let room_id = room_id!("!r0:bar.org");
+
+    sync_then_assert_request_and_fake_response! {
+        [server, room_list, sync]
+        assert request >= {},
+        respond with = {
+            "pos": "0",
+            "lists": {
+                ALL_ROOMS: {
+                    "count": 1,
+                },
+            },
+            "rooms": {
+                room_id: {
+                    "initial": true,
+                },
+            },
+        },
+    };
+
+    mock_encryption_state(&server, false).await;
+
+    let room = room_list.room(room_id)?;
+    room.init_timeline_with_builder(room.default_room_timeline_builder().await.unwrap()).await?;
+    let timeline = room.timeline().unwrap();
+    assert_eq!(room_list.subscribed_rooms(), [room_id.to_owned()]);
+
+    // Dropping the room keeps the subscription, as long as its timeline is alive.
+    drop(room);
+    assert_eq!(room_list.subscribed_rooms(), [room_id.to_owned()]);
+
+    // Including its streams.
+    let (_, timeline_stream) = timeline.subscribe().await;
+    drop(timeline);
+    assert_eq!(room_list.subscribed_rooms(), [room_id.to_owned()]);
+
+    drop(timeline_stream);
+    assert!(room_list.subscribed_rooms().is_empty());
+
+    Ok(())
+}
+
 #[async_test]
 async fn test_room_empty_timeline() {
     let (client, server, room_list) = new_room_list_service().await.unwrap();
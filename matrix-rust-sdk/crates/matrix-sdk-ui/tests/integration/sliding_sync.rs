@@ -36,6 +36,28 @@ pub(crate) async fn check_requests(server: MockServer, expected_requests: &[serd
     assert_eq!(num_requests, expected_requests.len(), "missing requests");
 }
 
+/// The IDs of the rooms subscribed by the last sliding sync request received
+/// by the server, sorted.
+pub(crate) async fn last_room_subscriptions(server: &MockServer) -> Vec<String> {
+    let requests = server.received_requests().await.expect("Request recording has been disabled");
+    let request = requests
+        .iter()
+        .rev()
+        .find(|request| SlidingSyncMatcher.matches(request))
+        .expect("no sliding sync request received by the server");
+
+    let json_value = serde_json::from_slice::<serde_json::Value>(&request.body).unwrap();
+
+    let mut room_ids = json_value
+        .get("room_subscriptions")
+        .and_then(|room_subscriptions| room_subscriptions.as_object())
+        .map(|room_subscriptions| room_subscriptions.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    room_ids.sort();
+
+    room_ids
+}
+
 pub(crate) struct SlidingSyncMatcher;
 
 #[derive(serde::Deserialize)]
@@ -13,16 +13,32 @@
 // limitations under the License.
 
 use std::{
+    ops::Not,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use matrix_sdk::test_utils::logged_in_client_with_server;
-use matrix_sdk_test::async_test;
-use matrix_sdk_ui::sync_service::{State, SyncService};
+use assert_matches::assert_matches;
+use eyeball_im::VectorDiff;
+use futures_util::pin_mut;
+use matrix_sdk::{
+    assert_next_matches_with_timeout, assert_next_with_timeout,
+    test_utils::{logged_in_client, logged_in_client_with_server},
+};
+use matrix_sdk_test::{async_test, sync_timeline_event, JoinedRoomBuilder, SyncResponseBuilder};
+use matrix_sdk_ui::{
+    room_list_service::{
+        filters::new_filter_non_left, RoomListLoadingState, State as RoomListState,
+    },
+    sync_service::{State, SyncService},
+};
+use ruma::room_id;
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
-use wiremock::{Match as _, Mock, MockGuard, MockServer, Request, ResponseTemplate};
+use wiremock::{
+    matchers::{method, path, path_regex},
+    Match as _, Mock, MockGuard, MockServer, Request, ResponseTemplate,
+};
 
 use crate::sliding_sync::{PartialSlidingSyncRequest, SlidingSyncMatcher};
 
@@ -202,3 +218,327 @@ async fn test_sync_service_state() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[async_test]
+async fn test_sync_service_goes_offline_after_network_errors() -> anyhow::Result<()> {
+    // Nothing listens on this port, so all the requests fail at the network layer.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let client = logged_in_client(Some(format!("http://127.0.0.1:{port}"))).await;
+
+    let sync_service = SyncService::builder(client.clone()).with_offline_mode().build().await?;
+    let mut state_stream = sync_service.state();
+
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+
+    // After a few network errors, the sync service goes offline instead of
+    // running into an error, and pauses the send queue.
+    assert_eq!(assert_next_with_timeout!(state_stream, 1000), State::Offline);
+    assert_eq!(sync_service.task_states(), (false, false));
+    assert!(!client.send_queue().is_enabled());
+
+    // Starting it again doesn't change anything, since the homeserver still
+    // can't be reached.
+    sync_service.start().await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_pending!(state_stream);
+
+    // Stopping it works, and resumes the send queue.
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+    assert!(client.send_queue().is_enabled());
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_sync_service_follows_connectivity_changes() -> anyhow::Result<()> {
+    let (client, server) = logged_in_client_with_server().await;
+
+    let encryption_pos = Arc::new(Mutex::new(0));
+    let room_pos = Arc::new(Mutex::new(0));
+    let _guard =
+        setup_mocking_sliding_sync_server(&server, encryption_pos.clone(), room_pos.clone()).await;
+
+    let sync_service = SyncService::builder(client.clone()).with_offline_mode().build().await?;
+    let mut state_stream = sync_service.state();
+
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+
+    // When the application signals that the network is gone, the sync service
+    // goes offline right away.
+    sync_service.set_network_reachable(false).await?;
+    assert_eq!(assert_next_with_timeout!(state_stream, 500), State::Offline);
+    assert_eq!(sync_service.task_states(), (false, false));
+    assert!(!client.send_queue().is_enabled());
+
+    // When the network is back, the homeserver is checked right away, and the
+    // syncs are restarted.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "versions": ["v1.0"] })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    sync_service.set_network_reachable(true).await?;
+    assert_eq!(assert_next_with_timeout!(state_stream, 500), State::Running);
+    assert_eq!(sync_service.task_states(), (true, true));
+    assert!(client.send_queue().is_enabled());
+
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_sync_service_falls_back_on_classic_sync() -> anyhow::Result<()> {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_id = room_id!("!r0:bar.org");
+
+    // The homeserver doesn't advertise the support of sliding sync.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["v1.0"],
+            "unstable_features": {},
+        })))
+        .mount(&server)
+        .await;
+
+    // Until it's known, the syncs use sliding sync, which never answers.
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "pos": "0" }))
+                .set_delay(Duration::from_secs(10)),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/filter"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "filter_id": "1" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut sync_response_builder = SyncResponseBuilder::new();
+    sync_response_builder
+        .add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(sync_timeline_event!({
+            "content": {
+                "body": "hello",
+                "msgtype": "m.text",
+            },
+            "event_id": "$1",
+            "origin_server_ts": 1432135524678u64,
+            "sender": "@example:localhost",
+            "type": "m.room.message",
+        })))
+        .add_joined_room(JoinedRoomBuilder::new(room_id!("!r1:bar.org")));
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(sync_response_builder.build_json_sync_response()),
+        )
+        .mount(&server)
+        .await;
+
+    // Building the service doesn't wait for the features of the homeserver.
+    let sync_service = SyncService::builder(client.clone()).build().await?;
+    assert!(sync_service.uses_classic_sync().not());
+
+    let room_list_service = sync_service.room_list_service();
+    let mut room_list_state = room_list_service.state();
+    let mut state_stream = sync_service.state();
+
+    let all_rooms = room_list_service.all_rooms().await?;
+    let mut loading_state = all_rooms.loading_state();
+    assert_next_matches!(loading_state, RoomListLoadingState::NotLoaded);
+
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+
+    // Once the syncs are restarted, the room list is driven by `/sync`.
+    assert_eq!(assert_next_with_timeout!(room_list_state, 1000), RoomListState::SettingUp);
+    assert!(sync_service.uses_classic_sync());
+
+    // There is no encryption sync, only the room list sync.
+    assert_eq!(sync_service.task_states(), (false, true));
+
+    // The number of rooms is known from the rooms received by `/sync`.
+    assert_eq!(
+        assert_next_with_timeout!(loading_state, 500),
+        RoomListLoadingState::Loaded { maximum_number_of_rooms: Some(2) }
+    );
+
+    // So the room list can be paginated.
+    let (dynamic_entries_stream, dynamic_entries) = all_rooms.entries_with_dynamic_adapters(1);
+    pin_mut!(dynamic_entries_stream);
+
+    dynamic_entries.set_filter(Box::new(new_filter_non_left()));
+    assert_next_matches_with_timeout!(dynamic_entries_stream, diffs => {
+        assert_matches!(&diffs[..], [VectorDiff::Reset { values }] => assert_eq!(values.len(), 1));
+    });
+
+    dynamic_entries.add_one_page();
+    assert_next_matches_with_timeout!(dynamic_entries_stream, diffs => {
+        assert_matches!(&diffs[..], [VectorDiff::Append { values }] => assert_eq!(values.len(), 1));
+    });
+    assert_pending!(dynamic_entries_stream);
+
+    let room = room_list_service.room(room_id)?;
+    assert!(room.latest_event().await.is_some());
+
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+    assert_eq!(sync_service.task_states(), (false, false));
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_sync_service_falls_back_on_classic_sync_after_sliding_sync_error(
+) -> anyhow::Result<()> {
+    let (client, server) = logged_in_client_with_server().await;
+
+    // The homeserver doesn't advertise the support of sliding sync, but it takes
+    // some time to know it.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "versions": ["v1.0"],
+                    "unstable_features": {},
+                }))
+                .set_delay(Duration::from_millis(300)),
+        )
+        .mount(&server)
+        .await;
+
+    // In the meantime, sliding sync fails right away.
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "errcode": "M_UNRECOGNIZED",
+            "error": "Unrecognized request",
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/filter"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "filter_id": "1" })))
+        .mount(&server)
+        .await;
+
+    let mut sync_response_builder = SyncResponseBuilder::new();
+    sync_response_builder.add_joined_room(JoinedRoomBuilder::new(room_id!("!r0:bar.org")));
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(sync_response_builder.build_json_sync_response()),
+        )
+        .mount(&server)
+        .await;
+
+    let sync_service = SyncService::builder(client.clone()).build().await?;
+    assert!(sync_service.uses_classic_sync().not());
+
+    let all_rooms = sync_service.room_list_service().all_rooms().await?;
+    let mut loading_state = all_rooms.loading_state();
+    assert_next_matches!(loading_state, RoomListLoadingState::NotLoaded);
+
+    let mut state_stream = sync_service.state();
+
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+
+    // The error of sliding sync isn't reported: once the discovery has finished,
+    // the syncs fall back on `/sync`.
+    assert_eq!(
+        assert_next_with_timeout!(loading_state, 1000),
+        RoomListLoadingState::Loaded { maximum_number_of_rooms: Some(1) }
+    );
+    assert!(sync_service.uses_classic_sync());
+    assert_pending!(state_stream);
+
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+
+    Ok(())
+}
+
+#[async_test]
+async fn test_sync_service_stops_classic_sync_right_after_start() -> anyhow::Result<()> {
+    let (client, server) = logged_in_client_with_server().await;
+
+    // The homeserver doesn't advertise the support of sliding sync.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "versions": ["v1.0"],
+            "unstable_features": {},
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "pos": "0" }))
+                .set_delay(Duration::from_secs(10)),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/filter"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "filter_id": "1" })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(SyncResponseBuilder::new().build_json_sync_response())
+                .set_delay(Duration::from_millis(50)),
+        )
+        .mount(&server)
+        .await;
+
+    let sync_service = SyncService::builder(client.clone()).build().await?;
+    let mut room_list_state = sync_service.room_list_service().state();
+    let mut state_stream = sync_service.state();
+
+    // Wait until the syncs have fallen back on `/sync`.
+    sync_service.start().await;
+    assert_next_matches!(state_stream, State::Running);
+    assert_eq!(assert_next_with_timeout!(room_list_state, 1000), RoomListState::SettingUp);
+    assert!(sync_service.uses_classic_sync());
+
+    sync_service.stop().await?;
+    assert_next_matches!(state_stream, State::Idle);
+
+    // Stopping the service before the room list task had a chance to run stops
+    // the `/sync` loop all the same.
+    for _ in 0..3 {
+        sync_service.start().await;
+        assert_next_matches!(state_stream, State::Running);
+
+        tokio::time::timeout(Duration::from_secs(2), sync_service.stop())
+            .await
+            .expect("stopping the sync service shouldn't hang")?;
+        assert_next_matches!(state_stream, State::Idle);
+        assert_eq!(sync_service.task_states(), (false, false));
+    }
+
+    Ok(())
+}
@@ -856,6 +856,103 @@ async fn test_send_single_receipt() {
         .unwrap();
 }
 
+#[async_test]
+async fn test_send_single_threaded_receipt() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client_with_server().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let own_user_id = client.user_id().unwrap();
+    let thread_root_id = event_id!("$thread_root");
+    let thread_event_id = event_id!("$thread_event");
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await.unwrap();
+
+    // Unknown threaded receipts are sent.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/receipt/m\.read/"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({ "thread_id": thread_root_id })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .named("Public threaded read receipt")
+        .mount(&server)
+        .await;
+
+    let sent = timeline
+        .send_single_receipt(
+            ReceiptType::Read,
+            ReceiptThread::Thread(thread_root_id.to_owned()),
+            thread_event_id.to_owned(),
+        )
+        .await
+        .unwrap();
+    assert!(sent);
+    server.reset().await;
+
+    // Unchanged threaded receipts are not sent.
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_ephemeral_event(
+        EphemeralTestEvent::Custom(json!({
+            "content": {
+                thread_event_id: {
+                    "m.read": {
+                        own_user_id: {
+                            "ts": 1436453550,
+                            "thread_id": thread_root_id,
+                        },
+                    },
+                },
+            },
+            "type": "m.receipt",
+        })),
+    ));
+
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let sent = timeline
+        .send_single_receipt(
+            ReceiptType::Read,
+            ReceiptThread::Thread(thread_root_id.to_owned()),
+            thread_event_id.to_owned(),
+        )
+        .await
+        .unwrap();
+    assert!(sent.not());
+
+    // The receipt in the thread doesn't prevent sending a receipt for the same
+    // event in another thread.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/receipt/m\.read/"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .named("Public unthreaded read receipt")
+        .mount(&server)
+        .await;
+
+    let sent = timeline
+        .send_single_receipt(
+            ReceiptType::Read,
+            ReceiptThread::Unthreaded,
+            thread_event_id.to_owned(),
+        )
+        .await
+        .unwrap();
+    assert!(sent);
+}
+
 #[async_test]
 async fn test_mark_as_read() {
     let room_id = room_id!("!a98sd12bjh:example.org");
@@ -32,16 +32,20 @@ use super::{Client, ClientInner};
 use crate::crypto::{CollectStrategy, TrustRequirement};
 #[cfg(feature = "e2e-encryption")]
 use crate::encryption::EncryptionSettings;
+#[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+use crate::http_client::CertificatePin;
 #[cfg(not(target_arch = "wasm32"))]
-use crate::http_client::HttpSettings;
+use crate::http_client::{DnsResolver, HttpSettings};
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::OidcCtx;
 #[cfg(feature = "experimental-sliding-sync")]
 use crate::sliding_sync::VersionBuilder as SlidingSyncVersionBuilder;
+#[cfg(feature = "sqlite")]
+use crate::wipe::SqliteStores;
 use crate::{
     authentication::AuthCtx, client::ClientServerCapabilities, config::RequestConfig,
-    error::RumaApiError, http_client::HttpClient, send_queue::SendQueueData, HttpError,
-    IdParseError,
+    error::RumaApiError, http_client::HttpClient, send_queue::SendQueueData,
+    sync_checkpoints::SyncCheckpointsConfig, HttpError, IdParseError,
 };
 
 /// Builder that allows creating and configuring various parts of a [`Client`].
@@ -102,6 +106,7 @@ pub struct ClientBuilder {
     #[cfg(feature = "e2e-encryption")]
     decryption_trust_requirement: TrustRequirement,
     cross_process_store_locks_holder_name: String,
+    sync_checkpoints: Option<SyncCheckpointsConfig>,
 }
 
 impl ClientBuilder {
@@ -129,6 +134,7 @@ impl ClientBuilder {
             decryption_trust_requirement: TrustRequirement::Untrusted,
             cross_process_store_locks_holder_name:
                 Self::DEFAULT_CROSS_PROCESS_STORE_LOCKS_HOLDER_NAME.to_owned(),
+            sync_checkpoints: None,
         }
     }
 
@@ -343,6 +349,66 @@ impl ClientBuilder {
         self
     }
 
+    /// Only trust the TLS certificates of the given host that match one of the
+    /// given pins.
+    ///
+    /// The certificates are still verified with the built-in root
+    /// certificates, unless
+    /// [`disable_built_in_root_certificates`][ClientBuilder::disable_built_in_root_certificates]
+    /// is used: they must be valid at the current time, and for the name of the
+    /// host. A pinned certificate is trusted even if it's not signed by a root
+    /// certificate, which allows to connect to a server with a self-signed
+    /// certificate without disabling the SSL verification. Such a certificate
+    /// must not be a CA certificate.
+    ///
+    /// The pins only apply to the given host, the certificates of the other
+    /// hosts aren't pinned. Can be called several times to pin the certificates
+    /// of several hosts.
+    ///
+    /// Pins can't be combined with
+    /// [`add_root_certificates`][ClientBuilder::add_root_certificates] or
+    /// [`disable_ssl_verification`][ClientBuilder::disable_ssl_verification]:
+    /// building the client fails with
+    /// [`ClientBuildError::IncompatibleCertificatePins`] in this case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{CertificatePin, Client};
+    ///
+    /// let pin: CertificatePin =
+    ///     "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".parse()?;
+    /// let client_config =
+    ///     Client::builder().pin_certificates("matrix.localhost", vec![pin]);
+    /// # anyhow::Ok(())
+    /// ```
+    #[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+    pub fn pin_certificates(mut self, host: impl AsRef<str>, pins: Vec<CertificatePin>) -> Self {
+        self.http_settings().certificate_pins.insert(host.as_ref().to_ascii_lowercase(), pins);
+        self
+    }
+
+    /// Resolve the given domain to the given addresses, instead of querying
+    /// the DNS, like with a hosts file.
+    ///
+    /// The port of the URL is used to connect to the addresses. Can be called
+    /// several times to override several domains.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn resolve(mut self, domain: impl AsRef<str>, addresses: Vec<std::net::IpAddr>) -> Self {
+        self.http_settings().dns_overrides.insert(domain.as_ref().to_ascii_lowercase(), addresses);
+        self
+    }
+
+    /// Use a custom DNS resolver for the HTTP requests.
+    ///
+    /// The domains overridden with [`resolve()`][ClientBuilder::resolve] don't
+    /// go through it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn dns_resolver<R: reqwest::dns::Resolve + 'static>(mut self, resolver: Arc<R>) -> Self {
+        self.http_settings().dns_resolver = Some(DnsResolver(resolver));
+        self
+    }
+
     /// Specify a [`reqwest::Client`] instance to handle sending requests and
     /// receiving responses.
     ///
@@ -351,6 +417,8 @@ impl ClientBuilder {
     /// [`disable_ssl_verification`][ClientBuilder::disable_ssl_verification],
     /// [`add_root_certificates`][ClientBuilder::add_root_certificates],
     /// [`disable_built_in_root_certificates`][ClientBuilder::disable_built_in_root_certificates],
+    /// [`resolve()`][ClientBuilder::resolve],
+    /// [`dns_resolver()`][ClientBuilder::dns_resolver],
     /// and [`user_agent()`][ClientBuilder::user_agent].
     pub fn http_client(mut self, client: reqwest::Client) -> Self {
         self.http_cfg = Some(HttpConfig::Custom(client));
@@ -444,6 +512,17 @@ impl ClientBuilder {
         self
     }
 
+    /// Take checkpoints of the sync state, to be able to roll the client back
+    /// to one of them with [`Client::restore_checkpoint`].
+    ///
+    /// This is meant for debugging: each checkpoint is a full copy of the state
+    /// store. See the [`sync_checkpoints`](crate::sync_checkpoints) module for
+    /// more details.
+    pub fn sync_checkpoints(mut self, config: SyncCheckpointsConfig) -> Self {
+        self.sync_checkpoints = Some(config);
+        self
+    }
+
     /// Create a [`Client`] with the options set on this builder.
     ///
     /// # Errors
@@ -467,20 +546,39 @@ impl ClientBuilder {
         let inner_http_client = match self.http_cfg.unwrap_or_default() {
             #[cfg(not(target_arch = "wasm32"))]
             HttpConfig::Settings(mut settings) => {
+                // The pinned TLS configuration replaces the one that would trust the
+                // additional root certificates, or that wouldn't verify anything.
+                #[cfg(feature = "rustls-tls")]
+                if !settings.certificate_pins.is_empty()
+                    && (settings.disable_ssl_verification
+                        || !settings.additional_root_certificates.is_empty())
+                {
+                    return Err(ClientBuildError::IncompatibleCertificatePins);
+                }
+
                 settings.timeout = self.request_config.timeout;
                 settings.make_client()?
             }
             HttpConfig::Custom(c) => c,
         };
 
+        #[cfg(feature = "sqlite")]
+        let mut sqlite_stores = None;
+
         let base_client = if let Some(base_client) = self.base_client {
             base_client
         } else {
-            #[allow(unused_mut)]
-            let mut client = BaseClient::with_store_config(
+            let stores =
                 build_store_config(self.store_config, &self.cross_process_store_locks_holder_name)
-                    .await?,
-            );
+                    .await?;
+
+            #[cfg(feature = "sqlite")]
+            {
+                sqlite_stores = stores.sqlite_stores;
+            }
+
+            #[allow(unused_mut)]
+            let mut client = BaseClient::with_store_config(stores.store_config);
 
             #[cfg(feature = "e2e-encryption")]
             {
@@ -554,6 +652,9 @@ impl ClientBuilder {
             #[cfg(feature = "e2e-encryption")]
             self.encryption_settings,
             self.cross_process_store_locks_holder_name,
+            self.sync_checkpoints,
+            #[cfg(feature = "sqlite")]
+            sqlite_stores,
         )
         .await;
 
@@ -572,48 +673,77 @@ pub fn sanitize_server_name(s: &str) -> crate::Result<OwnedServerName, IdParseEr
     )
 }
 
+/// The stores built from a [`BuilderStoreConfig`].
+struct BuiltStores {
+    store_config: StoreConfig,
+
+    /// The SQLite stores, if they were opened by the builder, to be able to
+    /// wipe them.
+    #[cfg(feature = "sqlite")]
+    sqlite_stores: Option<SqliteStores>,
+}
+
+impl From<StoreConfig> for BuiltStores {
+    fn from(store_config: StoreConfig) -> Self {
+        Self {
+            store_config,
+            #[cfg(feature = "sqlite")]
+            sqlite_stores: None,
+        }
+    }
+}
+
 #[allow(clippy::unused_async, unused)] // False positive when building with !sqlite & !indexeddb
 async fn build_store_config(
     builder_config: BuilderStoreConfig,
     cross_process_store_locks_holder_name: &str,
-) -> Result<StoreConfig, ClientBuildError> {
+) -> Result<BuiltStores, ClientBuildError> {
     #[allow(clippy::infallible_destructuring_match)]
-    let store_config = match builder_config {
+    let stores = match builder_config {
         #[cfg(feature = "sqlite")]
         BuilderStoreConfig::Sqlite { path, cache_path, passphrase } => {
+            let state_store =
+                matrix_sdk_sqlite::SqliteStateStore::open(&path, passphrase.as_deref()).await?;
+            let event_cache_store = matrix_sdk_sqlite::SqliteEventCacheStore::open(
+                cache_path.as_ref().unwrap_or(&path),
+                passphrase.as_deref(),
+            )
+            .await?;
+
             let store_config = StoreConfig::new(cross_process_store_locks_holder_name.to_owned())
-                .state_store(
-                    matrix_sdk_sqlite::SqliteStateStore::open(&path, passphrase.as_deref()).await?,
-                )
-                .event_cache_store(
-                    matrix_sdk_sqlite::SqliteEventCacheStore::open(
-                        cache_path.as_ref().unwrap_or(&path),
-                        passphrase.as_deref(),
-                    )
-                    .await?,
-                );
+                .state_store(state_store.clone())
+                .event_cache_store(event_cache_store.clone());
 
             #[cfg(feature = "e2e-encryption")]
-            let store_config = store_config.crypto_store(
-                matrix_sdk_sqlite::SqliteCryptoStore::open(&path, passphrase.as_deref()).await?,
-            );
+            let crypto_store =
+                matrix_sdk_sqlite::SqliteCryptoStore::open(&path, passphrase.as_deref()).await?;
 
-            store_config
+            #[cfg(feature = "e2e-encryption")]
+            let store_config = store_config.crypto_store(crypto_store.clone());
+
+            let sqlite_stores = SqliteStores {
+                paths: std::iter::once(path).chain(cache_path).collect(),
+                state_store,
+                event_cache_store,
+                #[cfg(feature = "e2e-encryption")]
+                crypto_store,
+            };
+
+            BuiltStores { store_config, sqlite_stores: Some(sqlite_stores) }
         }
 
         #[cfg(feature = "indexeddb")]
-        BuilderStoreConfig::IndexedDb { name, passphrase } => {
-            build_indexeddb_store_config(
-                &name,
-                passphrase.as_deref(),
-                cross_process_store_locks_holder_name,
-            )
-            .await?
-        }
+        BuilderStoreConfig::IndexedDb { name, passphrase } => build_indexeddb_store_config(
+            &name,
+            passphrase.as_deref(),
+            cross_process_store_locks_holder_name,
+        )
+        .await?
+        .into(),
 
-        BuilderStoreConfig::Custom(config) => config,
+        BuilderStoreConfig::Custom(config) => config.into(),
     };
-    Ok(store_config)
+    Ok(stores)
 }
 
 // The indexeddb stores only implement `IntoStateStore` and `IntoCryptoStore` on
@@ -754,6 +884,15 @@ pub enum ClientBuildError {
     #[error(transparent)]
     Http(#[from] HttpError),
 
+    /// Certificates were pinned along with additional root certificates or
+    /// with the SSL verification disabled, which can't be combined.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+    #[error(
+        "certificate pins can't be combined with additional root certificates \
+         or with the SSL verification disabled"
+    )]
+    IncompatibleCertificatePins,
+
     /// Error opening the indexeddb store.
     #[cfg(feature = "indexeddb")]
     #[error(transparent)]
@@ -1075,6 +1214,55 @@ pub(crate) mod tests {
         assert_matches!(client.sliding_sync_version(), SlidingSyncVersion::Native);
     }
 
+    #[async_test]
+    async fn test_dns_override() {
+        // Given a homeserver that is only reachable through a domain that doesn't
+        // exist.
+        let homeserver = make_mock_homeserver().await;
+        let address = homeserver.address();
+        let url = format!("http://matrix.invalid:{}", address.port());
+        let mut builder = ClientBuilder::new();
+
+        // When building a client with the domain resolved to the server's address.
+        builder = builder
+            .server_name_or_homeserver_url(&url)
+            .resolve("matrix.invalid", vec![address.ip()]);
+        let client = builder.build().await.unwrap();
+
+        // Then the discovery should succeed, and keep the domain.
+        assert_eq!(client.homeserver().as_str(), format!("{url}/"));
+    }
+
+    #[async_test]
+    #[cfg(feature = "rustls-tls")]
+    async fn test_certificate_pins_with_incompatible_settings() {
+        let certificate = reqwest::Certificate::from_der(include_bytes!(
+            "../../../../../testing/data/tls/localhost.crt.der"
+        ))
+        .unwrap();
+        let pin: CertificatePin =
+            "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".parse().unwrap();
+
+        // Pinning the certificates of a host while trusting additional root
+        // certificates for the others is rejected.
+        let result = ClientBuilder::new()
+            .homeserver_url("https://localhost")
+            .pin_certificates("localhost", vec![pin])
+            .add_root_certificates(vec![certificate])
+            .build()
+            .await;
+        assert_matches!(result, Err(ClientBuildError::IncompatibleCertificatePins));
+
+        // And so is disabling the SSL verification.
+        let result = ClientBuilder::new()
+            .homeserver_url("https://localhost")
+            .pin_certificates("localhost", vec![pin])
+            .disable_ssl_verification()
+            .build()
+            .await;
+        assert_matches!(result, Err(ClientBuildError::IncompatibleCertificatePins));
+    }
+
     #[async_test]
     #[cfg(feature = "e2e-encryption")]
     async fn test_set_up_decryption_trust_requirement_cross_signed() {
@@ -81,6 +81,8 @@ use self::futures::SendRequest;
 use crate::oidc::Oidc;
 #[cfg(feature = "experimental-sliding-sync")]
 use crate::sliding_sync::Version as SlidingSyncVersion;
+#[cfg(feature = "sqlite")]
+use crate::wipe::SqliteStores;
 use crate::{
     authentication::{AuthCtx, AuthData, ReloadSessionCallback, SaveSessionCallback},
     config::RequestConfig,
@@ -91,13 +93,15 @@ use crate::{
         EventHandler, EventHandlerContext, EventHandlerDropGuard, EventHandlerHandle,
         EventHandlerStore, ObservableEventHandler, SyncEvent,
     },
-    http_client::HttpClient,
+    http_client::{ClientMetrics, HttpClient, RateLimitMetrics},
     matrix_auth::MatrixAuth,
     notification_settings::NotificationSettings,
+    presence::PresenceData,
     room_preview::RoomPreview,
     send_queue::SendQueueData,
     sync::{RoomUpdate, SyncResponse},
-    Account, AuthApi, AuthSession, Error, Media, Pusher, RefreshTokenError, Result, Room,
+    sync_checkpoints::{SyncCheckpointsConfig, SyncCheckpointsData, SyncPosition},
+    Account, AuthApi, AuthSession, Error, Media, Presence, Pusher, RefreshTokenError, Result, Room,
     TransmissionProgress,
 };
 #[cfg(feature = "e2e-encryption")]
@@ -332,6 +336,18 @@ pub(crate) struct ClientInner {
     ///
     /// [`SendQueue`]: crate::send_queue::SendQueue
     pub(crate) send_queue_data: Arc<SendQueueData>,
+
+    /// Data related to the [`Presence`] of users.
+    pub(crate) presence_data: PresenceData,
+
+    /// Data related to the sync checkpoints, see
+    /// [`Client::restore_checkpoint`].
+    pub(crate) sync_checkpoints: SyncCheckpointsData,
+
+    /// The SQLite stores opened by the [`ClientBuilder`], see
+    /// [`Client::logout_and_wipe`].
+    #[cfg(feature = "sqlite")]
+    pub(crate) sqlite_stores: Option<SqliteStores>,
 }
 
 impl ClientInner {
@@ -354,6 +370,8 @@ impl ClientInner {
         send_queue: Arc<SendQueueData>,
         #[cfg(feature = "e2e-encryption")] encryption_settings: EncryptionSettings,
         cross_process_store_locks_holder_name: String,
+        sync_checkpoints: Option<SyncCheckpointsConfig>,
+        #[cfg(feature = "sqlite")] sqlite_stores: Option<SqliteStores>,
     ) -> Arc<Self> {
         let client = Self {
             server,
@@ -377,6 +395,10 @@ impl ClientInner {
             sync_beat: event_listener::Event::new(),
             event_cache,
             send_queue_data: send_queue,
+            presence_data: Default::default(),
+            sync_checkpoints: SyncCheckpointsData::new(sync_checkpoints),
+            #[cfg(feature = "sqlite")]
+            sqlite_stores,
             #[cfg(feature = "e2e-encryption")]
             e2ee: EncryptionData::new(encryption_settings),
             #[cfg(feature = "e2e-encryption")]
@@ -435,6 +457,30 @@ impl Client {
         &self.inner.http_client.inner
     }
 
+    /// Get a snapshot of the rate limiting metrics of this client.
+    ///
+    /// When the homeserver rate-limits a request, the requests of the same
+    /// [`EndpointClass`](crate::EndpointClass), and of the classes with a lower
+    /// priority, are held back until the delay the homeserver asked for has
+    /// passed. These metrics tell how often that happened, and for how
+    /// long.
+    pub fn rate_limit_metrics(&self) -> RateLimitMetrics {
+        self.inner.http_client.rate_limiter.metrics()
+    }
+
+    /// Get a snapshot of the metrics of the requests sent by this client.
+    ///
+    /// The metrics are recorded per endpoint: latencies, bytes sent and
+    /// received, retries and error categories. The rate limiting metrics, as
+    /// returned by [`Client::rate_limit_metrics()`], are included too.
+    pub fn metrics(&self) -> ClientMetrics {
+        let http_client = &self.inner.http_client;
+        ClientMetrics {
+            endpoints: http_client.metrics.endpoints(),
+            rate_limits: http_client.rate_limiter.metrics(),
+        }
+    }
+
     pub(crate) fn locks(&self) -> &ClientLocks {
         &self.inner.locks
     }
@@ -640,6 +686,11 @@ impl Client {
         Media::new(self.clone())
     }
 
+    /// Get the presence manager of the client.
+    pub fn presence(&self) -> Presence {
+        Presence::new(self.clone())
+    }
+
     /// Get the pusher manager of the client.
     pub fn pusher(&self) -> Pusher {
         Pusher::new(self.clone())
@@ -1265,6 +1316,9 @@ impl Client {
             )
             .await?;
 
+        #[cfg(feature = "e2e-encryption")]
+        self.encryption().apply_olm_machine_settings().await;
+
         Ok(())
     }
 
@@ -2027,6 +2081,9 @@ impl Client {
         let next_batch = response.next_batch.clone();
         let response = self.process_sync(response).await?;
 
+        self.maybe_take_sync_checkpoint(SyncPosition::SyncToken { token: next_batch.clone() })
+            .await;
+
         #[cfg(feature = "e2e-encryption")]
         if let Err(e) = self.send_outgoing_requests().await {
             error!(error = ?e, "Error while sending outgoing E2EE requests");
@@ -2408,6 +2465,11 @@ impl Client {
                 #[cfg(feature = "e2e-encryption")]
                 self.inner.e2ee.encryption_settings,
                 cross_process_store_locks_holder_name,
+                // The in-memory state store of this client can't be rolled back.
+                None,
+                // The stores on disk are owned by the parent client.
+                #[cfg(feature = "sqlite")]
+                None,
             )
             .await,
         };
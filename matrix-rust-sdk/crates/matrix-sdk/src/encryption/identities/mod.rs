@@ -114,4 +114,8 @@ pub enum RequestVerificationError {
     /// signals that we didn't have a DM and that we failed to create one.
     #[error("Couldn't create a DM with user {0} where the verification should take place")]
     RoomCreation(ruma::OwnedUserId),
+    /// The user to verify didn't set up cross-signing, so there is no identity
+    /// to verify.
+    #[error("User {0} doesn't have a cross-signing identity")]
+    MissingIdentity(ruma::OwnedUserId),
 }
@@ -0,0 +1,565 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inspection of the local crypto state, to help debugging encryption issues.
+//!
+//! The [`Inspector`] gives a read-only view over what the crypto store knows
+//! about the devices of a user, the outbound session of a room, or the room key
+//! that decrypted an event.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use matrix_sdk_base::{
+    crypto::{olm::SenderData, store::RoomSettings, LocalTrust},
+    deserialized_responses::TimelineEventKind,
+};
+use ruma::{
+    api::client::room::get_room_event,
+    events::{
+        room::encrypted::{EncryptedEventScheme, OriginalSyncRoomEncryptedEvent},
+        AnySyncTimelineEvent,
+    },
+    serde::Raw,
+    EventId, OwnedDeviceId, OwnedUserId, RoomId, SecondsSinceUnixEpoch, UserId,
+};
+use vodozemac::{Curve25519PublicKey, Ed25519PublicKey};
+
+use crate::{Client, Error, Result};
+
+/// What the crypto store knows about a user.
+#[derive(Clone, Debug)]
+pub struct UserInspection {
+    /// The cross-signing identity of the user, if we know it.
+    pub identity: Option<IdentityInspection>,
+
+    /// The devices of the user.
+    pub devices: Vec<DeviceInspection>,
+}
+
+/// What the crypto store knows about the cross-signing identity of a user.
+#[derive(Clone, Debug)]
+pub struct IdentityInspection {
+    /// The public part of the master key of the identity.
+    pub master_key: Option<Ed25519PublicKey>,
+
+    /// Whether we verified this identity.
+    pub is_verified: bool,
+}
+
+/// What the crypto store knows about a device.
+#[derive(Clone, Debug)]
+pub struct DeviceInspection {
+    /// The ID of the device.
+    pub device_id: OwnedDeviceId,
+
+    /// The display name of the device, if it has one.
+    pub display_name: Option<String>,
+
+    /// The trust state we manually set for the device.
+    pub local_trust: LocalTrust,
+
+    /// Whether the device is signed by the cross-signing identity of its
+    /// owner.
+    pub is_cross_signed_by_owner: bool,
+
+    /// Whether the device is verified, either manually or with
+    /// cross-signing.
+    pub is_verified: bool,
+
+    /// Whether the device was deleted by its owner.
+    pub is_deleted: bool,
+
+    /// The Curve25519 identity key of the device.
+    pub curve25519_key: Option<Curve25519PublicKey>,
+
+    /// The Ed25519 fingerprint key of the device.
+    pub ed25519_key: Option<Ed25519PublicKey>,
+}
+
+/// What the crypto store knows about an encrypted room.
+#[derive(Clone, Debug)]
+pub struct RoomInspection {
+    /// The outbound session we use to encrypt our messages in the room, if
+    /// there is one.
+    pub outbound_session: Option<OutboundSessionInspection>,
+
+    /// The encryption settings of the room, if they were saved.
+    pub settings: Option<RoomSettings>,
+}
+
+/// What the crypto store knows about an outbound group session.
+#[derive(Clone, Debug)]
+pub struct OutboundSessionInspection {
+    /// The ID of the session.
+    pub session_id: String,
+
+    /// The time at which the session was created.
+    pub creation_time: SecondsSinceUnixEpoch,
+
+    /// The number of messages encrypted with the session.
+    pub message_count: u64,
+
+    /// Whether the session was shared with the members of the room.
+    pub is_shared: bool,
+
+    /// Whether the session should be rotated, because it's too old or it was
+    /// used for too many messages, according to the encryption settings of the
+    /// room and its local rotation policy.
+    pub is_expired: bool,
+
+    /// Whether the session was invalidated, e.g. because a member left the
+    /// room.
+    pub is_invalidated: bool,
+
+    /// The devices the session was shared with, grouped by user.
+    pub shared_with: BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>>,
+
+    /// How long the session is used before rotating it, taking the local
+    /// rotation policy of the room into account.
+    pub rotation_period: Duration,
+
+    /// How many messages are encrypted with the session before rotating it,
+    /// taking the local rotation policy of the room into account.
+    pub rotation_period_msgs: u64,
+}
+
+impl OutboundSessionInspection {
+    /// How long ago the session was created.
+    pub fn age(&self) -> Duration {
+        let now = SecondsSinceUnixEpoch::now().get();
+        Duration::from_secs(now.saturating_sub(self.creation_time.get()).into())
+    }
+}
+
+/// What the crypto store knows about the room key of an encrypted event.
+#[derive(Clone, Debug)]
+pub struct EventInspection {
+    /// The ID of the Megolm session the event was encrypted with.
+    pub session_id: String,
+
+    /// The inbound group session matching the session ID, if we have it.
+    pub inbound_session: Option<InboundSessionInspection>,
+}
+
+/// What the crypto store knows about an inbound group session.
+#[derive(Clone, Debug)]
+pub struct InboundSessionInspection {
+    /// The Curve25519 key of the device that created the session.
+    pub sender_key: Curve25519PublicKey,
+
+    /// What we know about the device that created the session.
+    pub sender_data: SenderData,
+
+    /// The first message index we can decrypt with the session.
+    pub first_known_index: u32,
+
+    /// Whether the session was backed up to the current backup.
+    pub is_backed_up: bool,
+
+    /// Whether the session was imported, from a key export, a backup or a
+    /// forward, rather than received directly from its creator.
+    pub is_imported: bool,
+
+    /// Whether the session can be shared with users invited to the room, as
+    /// per MSC3061.
+    pub shared_history: bool,
+}
+
+/// A read-only view over the local crypto state, to help debugging encryption
+/// issues.
+///
+/// Get one with [`Encryption::inspector()`].
+///
+/// [`Encryption::inspector()`]: super::Encryption::inspector
+#[derive(Debug, Clone)]
+pub struct Inspector {
+    pub(super) client: Client,
+}
+
+impl Inspector {
+    /// Inspect the cross-signing identity and the devices of the given user.
+    ///
+    /// Only the data from the crypto store is used, so the user must be
+    /// tracked for it to be up to date.
+    pub async fn user(&self, user_id: &UserId) -> Result<UserInspection> {
+        let encryption = self.client.encryption();
+
+        let identity =
+            encryption.get_user_identity(user_id).await?.map(|identity| IdentityInspection {
+                master_key: identity.master_key().get_first_key(),
+                is_verified: identity.is_verified(),
+            });
+
+        let devices = encryption
+            .get_user_devices(user_id)
+            .await?
+            .devices()
+            .map(|device| DeviceInspection {
+                device_id: device.device_id().to_owned(),
+                display_name: device.display_name().map(ToOwned::to_owned),
+                local_trust: device.local_trust_state(),
+                is_cross_signed_by_owner: device.is_cross_signed_by_owner(),
+                is_verified: device.is_verified(),
+                is_deleted: device.is_deleted(),
+                curve25519_key: device.curve25519_key(),
+                ed25519_key: device.ed25519_key(),
+            })
+            .collect();
+
+        Ok(UserInspection { identity, devices })
+    }
+
+    /// Inspect the outbound session and the encryption settings of the given
+    /// room.
+    ///
+    /// The outbound session is the one last saved in the crypto store.
+    pub async fn room(&self, room_id: &RoomId) -> Result<RoomInspection> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+        let store = olm.store();
+
+        let settings = store.get_room_settings(room_id).await?;
+        let rotation_policy = store.get_room_key_rotation_policy(room_id).await?;
+
+        let outbound_session = store.get_outbound_group_session(room_id).await?.map(|session| {
+            let mut encryption_settings = session.settings().clone();
+            rotation_policy.apply(&mut encryption_settings);
+
+            OutboundSessionInspection {
+                session_id: session.session_id().to_owned(),
+                creation_time: session.creation_time(),
+                message_count: session.message_count(),
+                is_shared: session.shared(),
+                is_expired: session.expired_with(&encryption_settings),
+                is_invalidated: session.invalidated(),
+                shared_with: session.shared_with(),
+                rotation_period: encryption_settings.rotation_period,
+                rotation_period_msgs: encryption_settings.rotation_period_msgs,
+            }
+        });
+
+        Ok(RoomInspection { outbound_session, settings })
+    }
+
+    /// Inspect the room key of the given event.
+    ///
+    /// The event is looked up in the event cache first, and fetched from the
+    /// homeserver if it isn't cached or if the cache doesn't know which room
+    /// key decrypted it. Returns `None` if the event isn't encrypted with
+    /// Megolm.
+    pub async fn event(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<Option<EventInspection>> {
+        let session_id = match self.cached_session_id(room_id, event_id).await {
+            Some(session_id) => session_id,
+            None => {
+                let request =
+                    get_room_event::v3::Request::new(room_id.to_owned(), event_id.to_owned());
+                let response = self.client.send(request, None).await?;
+
+                let Some(session_id) = megolm_session_id(response.event.cast_ref()) else {
+                    return Ok(None);
+                };
+                session_id
+            }
+        };
+
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        let session = olm.store().get_inbound_group_session(room_id, &session_id).await?;
+        let inbound_session = session.map(|session| InboundSessionInspection {
+            sender_key: session.sender_key(),
+            sender_data: session.sender_data.clone(),
+            first_known_index: session.first_known_index(),
+            is_backed_up: session.backed_up(),
+            is_imported: session.has_been_imported(),
+            shared_history: session.shared_history(),
+        });
+
+        Ok(Some(EventInspection { session_id, inbound_session }))
+    }
+
+    /// Get the ID of the Megolm session of the given event from the event
+    /// cache, if the event is cached and the session is known.
+    async fn cached_session_id(&self, room_id: &RoomId, event_id: &EventId) -> Option<String> {
+        let room = self.client.get_room(room_id)?;
+        let (cache, _handles) = room.event_cache().await.ok()?;
+
+        match cache.event(event_id).await?.kind {
+            TimelineEventKind::Decrypted(event) => event.encryption_info.session_id,
+            TimelineEventKind::UnableToDecrypt { event, .. }
+            | TimelineEventKind::PlainText { event } => megolm_session_id(&event),
+        }
+    }
+}
+
+/// Get the ID of the Megolm session the given event was encrypted with, if it's
+/// an encrypted event.
+fn megolm_session_id(event: &Raw<AnySyncTimelineEvent>) -> Option<String> {
+    let event = event.deserialize_as::<OriginalSyncRoomEncryptedEvent>().ok()?;
+
+    match event.content.scheme {
+        EncryptedEventScheme::MegolmV1AesSha2(content) => Some(content.session_id),
+        _ => None,
+    }
+}
+
+// The http mocking library is not supported for wasm32
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::iter;
+
+    use assert_matches2::assert_let;
+    use matrix_sdk_base::{
+        crypto::{olm::SenderData, EncryptionSettings},
+        deserialized_responses::{
+            AlgorithmInfo, DecryptedRoomEvent, EncryptionInfo, VerificationState,
+        },
+        RoomState,
+    };
+    use matrix_sdk_test::{async_test, event_factory::EventFactory};
+    use ruma::{event_id, room_id, EventId, RoomId};
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{test_utils::logged_in_client, Client};
+
+    /// Create an outbound session in the given room and return its ID.
+    async fn create_room_key(client: &Client, room_id: &RoomId) -> String {
+        let olm = client.olm_machine().await;
+        let olm = olm.as_ref().unwrap();
+
+        let requests = olm
+            .share_room_key(room_id, iter::empty(), EncryptionSettings::default())
+            .await
+            .unwrap();
+        assert!(requests.is_empty());
+
+        olm.store()
+            .get_outbound_group_session(room_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .session_id()
+            .to_owned()
+    }
+
+    /// Let the homeserver return an event encrypted with the given session.
+    async fn mock_encrypted_event(
+        server: &MockServer,
+        room_id: &RoomId,
+        event_id: &EventId,
+        session_id: &str,
+    ) {
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "type": "m.room.encrypted",
+                "event_id": event_id,
+                "room_id": room_id,
+                "sender": "@bob:localhost",
+                "origin_server_ts": 1_700_000_000_000_u64,
+                "content": {
+                    "algorithm": "m.megolm.v1.aes-sha2",
+                    "ciphertext": "AwgAEnAC",
+                    "device_id": "BOBDEVICE",
+                    "sender_key": "Nn0L2hkcCMFKqynTjyGsJbth7QrVmX3lbrksMkrGOAw",
+                    "session_id": session_id,
+                },
+            })))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[async_test]
+    async fn test_inspect_own_devices() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let inspection =
+            client.encryption().inspector().user(client.user_id().unwrap()).await.unwrap();
+
+        let own_device = inspection
+            .devices
+            .iter()
+            .find(|d| d.device_id == client.device_id().unwrap())
+            .expect("Our own device should be known");
+        assert!(own_device.curve25519_key.is_some());
+        assert!(own_device.ed25519_key.is_some());
+        assert!(!own_device.is_deleted);
+    }
+
+    #[async_test]
+    async fn test_inspect_unknown_room() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let inspection =
+            client.encryption().inspector().room(room_id!("!test:localhost")).await.unwrap();
+
+        assert!(inspection.outbound_session.is_none());
+        assert!(inspection.settings.is_none());
+    }
+
+    #[async_test]
+    async fn test_inspect_event_with_missing_room_key() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = room_id!("!test:localhost");
+        let event_id = event_id!("$encrypted:localhost");
+
+        mock_encrypted_event(&server, room_id, event_id, "mysession").await;
+
+        let inspection =
+            client.encryption().inspector().event(room_id, event_id).await.unwrap().unwrap();
+
+        assert_eq!(inspection.session_id, "mysession");
+        assert!(inspection.inbound_session.is_none());
+    }
+
+    #[async_test]
+    async fn test_inspect_room_with_outbound_session() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = room_id!("!test:localhost");
+
+        let session_id = create_room_key(&client, room_id).await;
+
+        let inspection = client.encryption().inspector().room(room_id).await.unwrap();
+
+        let outbound_session = inspection.outbound_session.expect("The session should be found");
+        assert_eq!(outbound_session.session_id, session_id);
+        assert_eq!(outbound_session.message_count, 0);
+        assert!(outbound_session.is_shared);
+        assert!(!outbound_session.is_expired);
+        assert!(!outbound_session.is_invalidated);
+        assert!(outbound_session.shared_with.is_empty());
+
+        let settings = EncryptionSettings::default();
+        assert_eq!(outbound_session.rotation_period, settings.rotation_period);
+        assert_eq!(outbound_session.rotation_period_msgs, settings.rotation_period_msgs);
+    }
+
+    #[async_test]
+    async fn test_inspect_event_with_room_key() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = room_id!("!test:localhost");
+        let event_id = event_id!("$encrypted:localhost");
+
+        let session_id = create_room_key(&client, room_id).await;
+        mock_encrypted_event(&server, room_id, event_id, &session_id).await;
+
+        let inspection =
+            client.encryption().inspector().event(room_id, event_id).await.unwrap().unwrap();
+
+        assert_eq!(inspection.session_id, session_id);
+        let inbound_session = inspection.inbound_session.expect("The room key should be found");
+
+        let own_device = client.encryption().get_own_device().await.unwrap().unwrap();
+        assert_eq!(Some(inbound_session.sender_key), own_device.curve25519_key());
+        assert_let!(
+            SenderData::DeviceInfo { device_keys, legacy_session: false } =
+                inbound_session.sender_data
+        );
+        assert_eq!(&*device_keys.device_id, own_device.device_id());
+        assert_eq!(inbound_session.first_known_index, 0);
+        assert!(!inbound_session.is_backed_up);
+        assert!(!inbound_session.is_imported);
+    }
+
+    #[async_test]
+    async fn test_inspect_event_with_backed_up_room_key() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = room_id!("!test:localhost");
+        let event_id = event_id!("$encrypted:localhost");
+
+        let session_id = create_room_key(&client, room_id).await;
+        mock_encrypted_event(&server, room_id, event_id, &session_id).await;
+
+        {
+            let olm = client.olm_machine().await;
+            let olm = olm.as_ref().unwrap();
+            olm.store()
+                .mark_inbound_group_sessions_as_backed_up("1", &[(room_id, &session_id)])
+                .await
+                .unwrap();
+        }
+
+        let inspection =
+            client.encryption().inspector().event(room_id, event_id).await.unwrap().unwrap();
+
+        assert!(inspection.inbound_session.unwrap().is_backed_up);
+    }
+
+    #[async_test]
+    async fn test_inspect_cached_event() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_id = room_id!("!test:localhost");
+        let event_id = event_id!("$encrypted:localhost");
+
+        let session_id = create_room_key(&client, room_id).await;
+
+        client.event_cache().subscribe().unwrap();
+        client.base_client().get_or_create_room(room_id, RoomState::Joined);
+        let room = client.get_room(room_id).unwrap();
+        let (room_event_cache, _drop_handles) = room.event_cache().await.unwrap();
+
+        let event = EventFactory::new()
+            .room(room_id)
+            .sender(client.user_id().unwrap())
+            .text_msg("secret")
+            .event_id(event_id)
+            .into_raw_timeline();
+        let encryption_info = EncryptionInfo {
+            sender: client.user_id().unwrap().to_owned(),
+            sender_device: client.device_id().map(ToOwned::to_owned),
+            algorithm_info: AlgorithmInfo::MegolmV1AesSha2 {
+                curve25519_key: "xxx".to_owned(),
+                sender_claimed_keys: Default::default(),
+            },
+            verification_state: VerificationState::Verified,
+            session_id: Some(session_id.clone()),
+        };
+        room_event_cache
+            .save_event(
+                DecryptedRoomEvent {
+                    event: event.cast(),
+                    encryption_info,
+                    unsigned_encryption_info: None,
+                }
+                .into(),
+            )
+            .await;
+
+        // The homeserver isn't queried since the event is in the cache.
+        let inspection =
+            client.encryption().inspector().event(room_id, event_id).await.unwrap().unwrap();
+
+        assert_eq!(inspection.session_id, session_id);
+        assert!(inspection.inbound_session.is_some());
+    }
+}
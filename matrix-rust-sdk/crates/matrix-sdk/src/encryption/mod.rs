@@ -63,6 +63,7 @@ use self::{
     backups::{types::BackupClientState, Backups},
     futures::UploadEncryptedFile,
     identities::{Device, DeviceUpdates, IdentityUpdates, UserDevices, UserIdentity},
+    inspector::Inspector,
     recovery::{Recovery, RecoveryState},
     secret_storage::SecretStorage,
     tasks::{BackupDownloadTask, BackupUploadingTask, ClientTasks},
@@ -79,6 +80,7 @@ use crate::{
 pub mod backups;
 pub mod futures;
 pub mod identities;
+pub mod inspector;
 pub mod recovery;
 pub mod secret_storage;
 pub(crate) mod tasks;
@@ -89,9 +91,11 @@ pub use matrix_sdk_base::crypto::{
         SessionCreationError as MegolmSessionCreationError,
         SessionExportError as OlmSessionExportError,
     },
+    store::RoomKeyRotationPolicy,
     vodozemac, CrossSigningStatus, CryptoStoreError, DecryptorError, EventError, KeyExportError,
-    LocalTrust, MediaEncryptionInfo, MegolmError, OlmError, RoomKeyImportResult, SecretImportError,
-    SessionCreationError, SignatureError, VERSION,
+    KeyRequestPolicy, KeyRequestState, KeyRequestUpdate, LocalTrust, MediaEncryptionInfo,
+    MegolmError, OlmError, RoomKeyImportResult, SecretImportError, SessionCreationError,
+    SignatureError, VERSION,
 };
 
 pub use crate::error::RoomKeyImportError;
@@ -152,7 +156,11 @@ impl EncryptionData {
 }
 
 /// Settings for end-to-end encryption features.
+///
+/// New settings may be added in the future: start from
+/// [`EncryptionSettings::default()`] and change the fields you need.
 #[derive(Clone, Copy, Debug, Default)]
+#[non_exhaustive]
 pub struct EncryptionSettings {
     /// Automatically bootstrap cross-signing for a user once they're logged, in
     /// case it's not already done yet.
@@ -169,6 +177,24 @@ pub struct EncryptionSettings {
 
     /// Automatically create a backup version if no backup exists.
     pub auto_enable_backups: bool,
+
+    /// Share the room keys of the history of an encrypted room with the users
+    /// we invite to it, as described in [MSC3061].
+    ///
+    /// Only the room keys that were created while the history of the room was
+    /// visible to new members are shared. See [`Room::share_room_key_history`].
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    /// [`Room::share_room_key_history`]: crate::Room::share_room_key_history
+    pub share_room_key_history_on_invite: bool,
+
+    /// The policy used to retry the room key requests we send to our other
+    /// devices when we fail to decrypt an event.
+    ///
+    /// [`KeyRequestPolicy::prefer_backup`] only has an effect with the
+    /// [`BackupDownloadStrategy::AfterDecryptionFailure`] strategy. See
+    /// [`KeyRequestPolicy`] for the defaults.
+    pub key_request_policy: KeyRequestPolicy,
 }
 
 /// Settings for end-to-end encryption features.
@@ -1074,6 +1100,106 @@ impl Encryption {
             .map(move |updates| IdentityUpdates::new(client.to_owned(), updates)))
     }
 
+    /// Get a stream of updates about the lifecycle of the room key requests we
+    /// send to our other devices when we fail to decrypt an event.
+    ///
+    /// The session ID of an update can be matched with the one of an event we
+    /// couldn't decrypt, e.g. in an unable-to-decrypt hook.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{encryption::KeyRequestState, Client};
+    /// # use futures_util::{pin_mut, StreamExt};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// let updates = client.encryption().key_request_updates_stream().await?;
+    /// pin_mut!(updates);
+    ///
+    /// while let Some(update) = updates.next().await {
+    ///     if let KeyRequestState::Withheld(code) = update.state {
+    ///         println!(
+    ///             "The room key of {} was withheld: {code}",
+    ///             update.session_id
+    ///         );
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn key_request_updates_stream(&self) -> Result<impl Stream<Item = KeyRequestUpdate>> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(olm.key_request_updates_stream())
+    }
+
+    /// Subscribe to changes in the identity status of the users we share a
+    /// room with, across all our joined and invited rooms.
+    ///
+    /// This is the client-wide counterpart of
+    /// [`Room::subscribe_to_identity_status_changes`]. The first item in the
+    /// stream lists the identity changes that haven't been acknowledged yet,
+    /// and the following items report the changes as they happen.
+    ///
+    /// A change is acknowledged with [`Encryption::pin_identity`] or
+    /// [`Encryption::withdraw_identity_verification`], after which the stream
+    /// reports the user as `Pinned` again.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{crypto::IdentityState, Client};
+    /// # use futures_util::{pin_mut, StreamExt};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// let changes =
+    ///     client.encryption().subscribe_to_identity_status_changes().await?;
+    /// pin_mut!(changes);
+    ///
+    /// while let Some(changes) = changes.next().await {
+    ///     for change in changes {
+    ///         if change.changed_to == IdentityState::PinViolation {
+    ///             println!("The identity of {} changed", change.user_id);
+    ///         }
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn subscribe_to_identity_status_changes(
+        &self,
+    ) -> Result<impl Stream<Item = Vec<matrix_sdk_base::crypto::IdentityStatusChange>>> {
+        crate::room::ClientIdentityStatusChanges::create_stream(self.client.clone()).await
+    }
+
+    /// Acknowledge the identity change of a user by pinning their new
+    /// identity.
+    ///
+    /// This is persisted in the crypto store, see [`UserIdentity::pin`].
+    ///
+    /// Returns `false` if we don't know the identity of the user.
+    pub async fn pin_identity(&self, user_id: &UserId) -> Result<bool, CryptoStoreError> {
+        let Some(identity) = self.get_user_identity(user_id).await? else { return Ok(false) };
+        identity.pin().await?;
+        Ok(true)
+    }
+
+    /// Acknowledge that a previously verified user changed their identity, by
+    /// withdrawing the requirement for their identity to be verified.
+    ///
+    /// This is persisted in the crypto store, see
+    /// [`UserIdentity::withdraw_verification`].
+    ///
+    /// Returns `false` if we don't know the identity of the user.
+    pub async fn withdraw_identity_verification(
+        &self,
+        user_id: &UserId,
+    ) -> Result<bool, CryptoStoreError> {
+        let Some(identity) = self.get_user_identity(user_id).await? else { return Ok(false) };
+        identity.withdraw_verification().await?;
+        Ok(true)
+    }
+
     /// Create and upload a new cross signing identity.
     ///
     /// # Arguments
@@ -1447,6 +1573,12 @@ impl Encryption {
         Recovery { client: self.client.to_owned() }
     }
 
+    /// Get the inspector of the local crypto state, to help debugging
+    /// encryption issues.
+    pub fn inspector(&self) -> Inspector {
+        Inspector { client: self.client.to_owned() }
+    }
+
     /// Enables the crypto-store cross-process lock.
     ///
     /// This may be required if there are multiple processes that may do writes
@@ -1514,6 +1646,7 @@ impl Encryption {
                 drop(olm_machine_guard);
                 // Recreate the OlmMachine.
                 self.client.base_client().regenerate_olm(None).await?;
+                self.apply_olm_machine_settings().await;
             }
             Ok(generation_number)
         } else {
@@ -1576,6 +1709,23 @@ impl Encryption {
         Ok(olm_machine.uploaded_key_count().await?)
     }
 
+    /// Apply the [`EncryptionSettings`] that are handled by the
+    /// [`OlmMachine`] itself, after it was created or regenerated.
+    pub(crate) async fn apply_olm_machine_settings(&self) {
+        if let Some(olm) = self.client.olm_machine().await.as_ref() {
+            let settings = self.settings();
+            let mut policy = settings.key_request_policy;
+
+            // The key backup is only queried for the room keys of the events we
+            // fail to decrypt with this strategy, there is nothing to wait for
+            // otherwise.
+            policy.prefer_backup &=
+                settings.backup_download_strategy == BackupDownloadStrategy::AfterDecryptionFailure;
+
+            olm.set_key_request_policy(policy);
+        }
+    }
+
     /// Bootstrap encryption and enables event listeners for the E2EE support.
     ///
     /// Based on the `EncryptionSettings`, this call might:
@@ -36,13 +36,13 @@
 //!
 //! # async {
 //! # let homeserver = "http://example.org";
+//! let mut encryption_settings = EncryptionSettings::default();
+//! encryption_settings.auto_enable_cross_signing = true;
+//! encryption_settings.auto_enable_backups = true;
+//!
 //! let client = Client::builder()
 //!     .homeserver_url(homeserver)
-//!     .with_encryption_settings(EncryptionSettings {
-//!         auto_enable_cross_signing: true,
-//!         auto_enable_backups: true,
-//!         ..Default::default()
-//!     })
+//!     .with_encryption_settings(encryption_settings)
 //!     .build()
 //!     .await?;
 //! # anyhow::Ok(()) };
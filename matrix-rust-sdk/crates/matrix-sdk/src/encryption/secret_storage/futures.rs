@@ -16,7 +16,6 @@ use std::{future::IntoFuture, pin::Pin};
 
 use futures_core::Future;
 use matrix_sdk_base::crypto::secret_storage::SecretStorageKey;
-use ruma::events::secret_storage::default_key::SecretStorageDefaultKeyEventContent;
 
 use super::{Result, SecretStorage, SecretStore};
 
@@ -70,10 +69,7 @@ impl<'a> IntoFuture for CreateStore<'a> {
             let store = SecretStore { client: secret_storage.client.to_owned(), key: new_key };
             store.export_secrets().await?;
 
-            let default_key_content =
-                SecretStorageDefaultKeyEventContent::new(store.key.key_id().to_owned());
-
-            store.client.account().set_account_data(default_key_content).await?;
+            store.set_as_default().await?;
 
             Ok(store)
         })
@@ -60,20 +60,26 @@
 //! [spec]: https://spec.matrix.org/v1.8/client-server-api/#secret-storage
 //! [account data]: https://spec.matrix.org/v1.8/client-server-api/#client-config
 
-use std::string::FromUtf8Error;
+use std::{collections::BTreeSet, string::FromUtf8Error};
 
 use matrix_sdk_base::crypto::{
     secret_storage::{DecodeError, MacError, SecretStorageKey},
     CryptoStoreError, SecretImportError,
 };
-use ruma::events::{
-    secret_storage::{
-        default_key::SecretStorageDefaultKeyEventContent, key::SecretStorageKeyEventContent,
+use ruma::{
+    events::{
+        secret::request::SecretName,
+        secret_storage::{
+            default_key::SecretStorageDefaultKeyEventContent, key::SecretStorageKeyEventContent,
+            secret::SecretEventContent,
+        },
+        EventContentFromType, GlobalAccountDataEventType,
     },
-    EventContentFromType, GlobalAccountDataEventType,
+    serde::Raw,
 };
-use serde_json::value::to_raw_value;
+use serde_json::{json, value::to_raw_value};
 use thiserror::Error;
+use tracing::info;
 
 use super::identities::ManualVerifyError;
 use crate::Client;
@@ -84,6 +90,15 @@ mod secret_store;
 pub use futures::CreateStore;
 pub use secret_store::SecretStore;
 
+/// The secrets the SDK knows about, which are moved to the new key when secrets
+/// are migrated from one secret storage key to another.
+const WELL_KNOWN_SECRETS: [SecretName; 4] = [
+    SecretName::CrossSigningMasterKey,
+    SecretName::CrossSigningSelfSigningKey,
+    SecretName::CrossSigningUserSigningKey,
+    SecretName::RecoveryKey,
+];
+
 /// Convenicence type alias for the secret-storage specific results.
 pub type Result<T, E = SecretStorageError> = std::result::Result<T, E>;
 
@@ -134,6 +149,27 @@ pub enum SecretStorageError {
     /// Error describing a decryption failure of a secret.
     #[error(transparent)]
     Decryption(#[from] DecryptionError),
+
+    /// The secret storage key can't be deleted because it is the default key.
+    /// Another key needs to be set as the default key first, using
+    /// [`SecretStore::set_as_default()`].
+    #[error("The default secret storage key {key_id} can't be deleted")]
+    DeletingDefaultKey {
+        /// The key ID of the default key.
+        key_id: String,
+    },
+
+    /// The secret storage key can't be deleted because a secret is only
+    /// encrypted with this key, and would be lost. The secret needs to be
+    /// encrypted with another key first, using
+    /// [`SecretStore::migrate_secrets_to()`].
+    #[error("The secret {secret_name} is only encrypted with the secret storage key {key_id}")]
+    DeletingLastKeyOfSecret {
+        /// The key ID of the key that was about to be deleted.
+        key_id: String,
+        /// The name of the secret only encrypted with this key.
+        secret_name: SecretName,
+    },
 }
 
 /// Error type describing decryption failures of the secret-storage system.
@@ -186,6 +222,41 @@ impl SecretStorage {
     /// # anyhow::Ok(()) };
     /// ```
     pub async fn open_secret_store(&self, secret_storage_key: &str) -> Result<SecretStore> {
+        let Some(default_key_id) = self.fetch_default_key_id().await? else {
+            return Err(SecretStorageError::MissingKeyInfo { key_id: None });
+        };
+
+        self.open_secret_store_with_key_id(&default_key_id, secret_storage_key).await
+    }
+
+    /// Open the [`SecretStore`] with the given `key`, using the secret storage
+    /// key with the given ID instead of the default one.
+    ///
+    /// This is useful to read the secrets that were encrypted using an older
+    /// key, for example to migrate them to the default key with
+    /// [`SecretStore::migrate_secrets_to()`].
+    ///
+    /// The `secret_storage_key` can be a passphrase or a Base58 encoded secret
+    /// storage key.
+    pub async fn open_secret_store_with_key_id(
+        &self,
+        key_id: &str,
+        secret_storage_key: &str,
+    ) -> Result<SecretStore> {
+        let Some(secret_key_content) = self.fetch_key_info(key_id).await? else {
+            return Err(SecretStorageError::MissingKeyInfo { key_id: Some(key_id.to_owned()) });
+        };
+
+        let key = SecretStorageKey::from_account_data(secret_storage_key, secret_key_content)?;
+
+        Ok(SecretStore { client: self.client.to_owned(), key })
+    }
+
+    /// Fetch the ID of the default secret storage key, from the
+    /// `m.secret_storage.default_key` event in the account data of the user.
+    ///
+    /// Returns `None` if secret storage isn't set up.
+    pub async fn fetch_default_key_id(&self) -> Result<Option<String>> {
         let maybe_default_key_id = self
             .client
             .account()
@@ -196,28 +267,158 @@ impl SecretStorage {
             let default_key_id =
                 default_key_id.deserialize_as::<SecretStorageDefaultKeyEventContent>()?;
 
-            let event_type =
-                GlobalAccountDataEventType::SecretStorageKey(default_key_id.key_id.to_owned());
-            let secret_key =
-                self.client.account().fetch_account_data(event_type.to_owned()).await?;
+            Ok(Some(default_key_id.key_id))
+        } else {
+            Ok(None)
+        }
+    }
 
-            if let Some(secret_key_content) = secret_key {
-                let event_type = event_type.to_string();
-                let secret_key_content = to_raw_value(&secret_key_content)?;
+    /// Fetch the info about all the secret storage keys of the user, sorted by
+    /// key ID.
+    ///
+    /// The account data of a user can't be listed, so the keys are found from
+    /// the default key and from the keys the well-known secrets are encrypted
+    /// with:
+    ///
+    /// - `m.cross_signing.master`: The master cross-signing key.
+    /// - `m.cross_signing.self_signing`: The self-signing cross-signing key.
+    /// - `m.cross_signing.user_signing`: The user-signing cross-signing key.
+    /// - `m.megolm_backup.v1`: The backup recovery key.
+    ///
+    /// The keys that were deleted with [`SecretStorage::delete_key()`] aren't
+    /// returned.
+    pub async fn fetch_keys(&self) -> Result<Vec<SecretStorageKeyEventContent>> {
+        let mut key_ids = BTreeSet::new();
+        key_ids.extend(self.fetch_default_key_id().await?);
 
-                let secret_key_content =
-                    SecretStorageKeyEventContent::from_parts(&event_type, &secret_key_content)?;
+        for secret_name in WELL_KNOWN_SECRETS {
+            let event_type = GlobalAccountDataEventType::from(secret_name);
 
-                let key =
-                    SecretStorageKey::from_account_data(secret_storage_key, secret_key_content)?;
+            if let Some(content) = self.client.account().fetch_account_data(event_type).await? {
+                if let Ok(content) = content.deserialize_as::<SecretEventContent>() {
+                    key_ids.extend(content.encrypted.into_keys());
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
 
-                Ok(SecretStore { client: self.client.to_owned(), key })
-            } else {
-                Err(SecretStorageError::MissingKeyInfo { key_id: Some(default_key_id.key_id) })
+        for key_id in key_ids {
+            match self.fetch_key_info(&key_id).await {
+                Ok(Some(key)) => keys.push(key),
+                Ok(None) => {}
+                // Since we can't delete account data events, deleted keys are replaced by an
+                // empty event, which fails to deserialize.
+                Err(SecretStorageError::Json(_)) => {}
+                Err(e) => return Err(e),
             }
-        } else {
-            Err(SecretStorageError::MissingKeyInfo { key_id: None })
         }
+
+        Ok(keys)
+    }
+
+    /// Delete the secret storage key with the given ID.
+    ///
+    /// The secrets encrypted with this key are removed from the well-known
+    /// secrets, see [`SecretStorage::fetch_keys()`], and the info about the
+    /// key is replaced by an empty account data event, since account data
+    /// can't be deleted.
+    ///
+    /// The default key can't be deleted: set another key as the default key
+    /// first, using [`SecretStore::set_as_default()`], after migrating the
+    /// secrets to it with [`SecretStore::migrate_secrets_to()`]. Likewise, the
+    /// key isn't deleted if one of the well-known secrets is only encrypted
+    /// with it, since the secret would be lost.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let secret_storage = client.encryption().secret_storage();
+    ///
+    /// // Replace the passphrase protecting our secrets with a recovery key.
+    /// let old_store =
+    ///     secret_storage.open_secret_store("It's a secret to everybody").await?;
+    /// let new_store = old_store.add_key(None).await?;
+    /// new_store.set_as_default().await?;
+    ///
+    /// secret_storage.delete_key(old_store.key_id()).await?;
+    ///
+    /// println!("Your new recovery key is {}", new_store.secret_storage_key());
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn delete_key(&self, key_id: &str) -> Result<()> {
+        if self.fetch_default_key_id().await?.as_deref() == Some(key_id) {
+            return Err(SecretStorageError::DeletingDefaultKey { key_id: key_id.to_owned() });
+        }
+
+        {
+            // See the documentation for the lock in the `SecretStore::put_secret` method
+            // for more info.
+            let _guard = self.client.locks().store_secret_lock.lock().await;
+
+            let mut secrets = Vec::new();
+
+            for secret_name in WELL_KNOWN_SECRETS {
+                let event_type = GlobalAccountDataEventType::from(secret_name.clone());
+
+                let Some(content) =
+                    self.client.account().fetch_account_data(event_type.to_owned()).await?
+                else {
+                    continue;
+                };
+                let Ok(mut content) = content.deserialize_as::<SecretEventContent>() else {
+                    continue;
+                };
+
+                if content.encrypted.remove(key_id).is_some() {
+                    // Check all the secrets before touching any of them, so we don't delete
+                    // the key halfway.
+                    if content.encrypted.is_empty() {
+                        return Err(SecretStorageError::DeletingLastKeyOfSecret {
+                            key_id: key_id.to_owned(),
+                            secret_name,
+                        });
+                    }
+
+                    secrets.push((event_type, content));
+                }
+            }
+
+            for (event_type, content) in secrets {
+                let content = Raw::from_json(to_raw_value(&content)?);
+                self.client.account().set_account_data_raw(event_type, content).await?;
+            }
+        }
+
+        let event_type = GlobalAccountDataEventType::SecretStorageKey(key_id.to_owned());
+        let content = Raw::from_json(to_raw_value(&json!({}))?);
+        self.client.account().set_account_data_raw(event_type, content).await?;
+
+        info!(key_id, "Deleted the secret storage key");
+
+        Ok(())
+    }
+
+    /// Fetch the info about the secret storage key with the given ID, from the
+    /// `m.secret_storage.key.{key_id}` event in the account data of the user.
+    async fn fetch_key_info(&self, key_id: &str) -> Result<Option<SecretStorageKeyEventContent>> {
+        let event_type = GlobalAccountDataEventType::SecretStorageKey(key_id.to_owned());
+
+        let Some(secret_key_content) =
+            self.client.account().fetch_account_data(event_type.to_owned()).await?
+        else {
+            return Ok(None);
+        };
+
+        let event_type = event_type.to_string();
+        let secret_key_content = to_raw_value(&secret_key_content)?;
+
+        Ok(Some(SecretStorageKeyEventContent::from_parts(&event_type, &secret_key_content)?))
     }
 
     /// Create a new [`SecretStore`].
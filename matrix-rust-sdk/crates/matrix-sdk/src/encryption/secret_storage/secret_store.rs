@@ -17,7 +17,10 @@ use std::fmt;
 use matrix_sdk_base::crypto::{secret_storage::SecretStorageKey, CrossSigningKeyExport};
 use ruma::{
     events::{
-        secret::request::SecretName, secret_storage::secret::SecretEventContent,
+        secret::request::SecretName,
+        secret_storage::{
+            default_key::SecretStorageDefaultKeyEventContent, secret::SecretEventContent,
+        },
         GlobalAccountDataEventType,
     },
     serde::Raw,
@@ -30,7 +33,7 @@ use tracing::{
 };
 use zeroize::Zeroize;
 
-use super::{DecryptionError, Result};
+use super::{DecryptionError, Result, WELL_KNOWN_SECRETS};
 use crate::Client;
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -110,6 +113,81 @@ impl SecretStore {
         self.key.to_base58()
     }
 
+    /// The ID of the [`SecretStorageKey`] of this [`SecretStore`].
+    pub fn key_id(&self) -> &str {
+        self.key.key_id()
+    }
+
+    /// Set the [`SecretStorageKey`] of this [`SecretStore`] as the default key,
+    /// in the `m.secret_storage.default_key` event.
+    ///
+    /// The default key is the one used by
+    /// [`SecretStorage::open_secret_store()`].
+    ///
+    /// [`SecretStorage::open_secret_store()`]: super::SecretStorage::open_secret_store
+    pub async fn set_as_default(&self) -> Result<()> {
+        let content = SecretStorageDefaultKeyEventContent::new(self.key_id().to_owned());
+        self.client.account().set_account_data(content).await?;
+
+        Ok(())
+    }
+
+    /// Create a new secret storage key alongside the key of this
+    /// [`SecretStore`], and migrate the secrets to it.
+    ///
+    /// The new key is protected by a randomly generated key, or by the given
+    /// passphrase. It isn't set as the default key: use
+    /// [`SecretStore::set_as_default()`] for this.
+    ///
+    /// See [`SecretStore::migrate_secrets_to()`] for the secrets that are
+    /// migrated.
+    pub async fn add_key(&self, passphrase: Option<&str>) -> Result<SecretStore> {
+        let new_key = if let Some(passphrase) = passphrase {
+            SecretStorageKey::new_from_passphrase(passphrase)
+        } else {
+            SecretStorageKey::new()
+        };
+
+        let content = new_key.event_content().to_owned();
+        self.client.account().set_account_data(content).await?;
+
+        let store = SecretStore { client: self.client.to_owned(), key: new_key };
+        self.migrate_secrets_to(&store).await?;
+
+        info!(key_id = store.key_id(), "Added a new secret storage key");
+
+        Ok(store)
+    }
+
+    /// Encrypt the secrets of this [`SecretStore`] with the key of another
+    /// [`SecretStore`] as well.
+    ///
+    /// The secrets stay encrypted with the key of this [`SecretStore`], until
+    /// it is deleted with [`SecretStorage::delete_key()`].
+    ///
+    /// The following secrets are migrated by this method:
+    ///
+    /// - `m.cross_signing.master`: The master cross-signing key.
+    /// - `m.cross_signing.self_signing`: The self-signing cross-signing key.
+    /// - `m.cross_signing.user_signing`: The user-signing cross-signing key.
+    /// - `m.megolm_backup.v1`: The backup recovery key.
+    ///
+    /// Other secrets need to be migrated with [`SecretStore::get_secret()`]
+    /// and [`SecretStore::put_secret()`].
+    ///
+    /// [`SecretStorage::delete_key()`]: super::SecretStorage::delete_key
+    pub async fn migrate_secrets_to(&self, other: &SecretStore) -> Result<()> {
+        for secret_name in WELL_KNOWN_SECRETS {
+            if let Some(mut secret) = self.get_secret(secret_name.to_owned()).await? {
+                let ret = other.put_secret(secret_name, &secret).await;
+                secret.zeroize();
+                ret?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Retrieve a secret from the homeserver's account data
     ///
     /// This method allows you to retrieve a secret from the account data stored
@@ -234,6 +234,9 @@ impl BackupDownloadTask {
                 // We decided against doing a download. Mark the job done for this event before
                 // dropping the lock.
                 state.active_tasks.remove(&download_request.event_id);
+                drop(state);
+
+                Self::release_key_request(&client, &download_request).await;
                 return;
             }
 
@@ -278,6 +281,30 @@ impl BackupDownloadTask {
 
             state.active_tasks.remove(&download_request.event_id);
         }
+
+        Self::release_key_request(&client, &download_request).await;
+    }
+
+    /// Tell the [`OlmMachine`] that we're done with the backup download for
+    /// the given request, so the room key request waiting for it can be
+    /// released.
+    ///
+    /// [`OlmMachine`]: matrix_sdk_base::crypto::OlmMachine
+    async fn release_key_request(client: &Client, download_request: &RoomKeyDownloadRequest) {
+        let olm_machine = client.olm_machine().await;
+        let Some(olm_machine) = olm_machine.as_ref() else {
+            return;
+        };
+
+        if let Err(e) = olm_machine
+            .backup_download_finished(
+                &download_request.room_id,
+                &download_request.megolm_session_id,
+            )
+            .await
+        {
+            warn!("Couldn't release the room key request waiting for the key backup: {e}");
+        }
     }
 }
 
@@ -22,7 +22,7 @@ use http::StatusCode;
 use matrix_sdk_base::crypto::ScanError;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::{
-    CryptoStoreError, DecryptorError, KeyExportError, MegolmError, OlmError,
+    CryptoStoreError, DecryptorError, KeyExportError, MegolmError, OlmError, SetRoomSettingsError,
 };
 use matrix_sdk_base::{
     event_cache::store::EventCacheStoreError, Error as SdkBaseError, QueueWedgeError, RoomState,
@@ -108,6 +108,11 @@ pub enum HttpError {
     /// Error while refreshing the access token.
     #[error(transparent)]
     RefreshToken(RefreshTokenError),
+
+    /// The TLS configuration of the HTTP client is invalid.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
 }
 
 #[rustfmt::skip] // stop rustfmt breaking the `<code>` in docs across multiple lines
@@ -312,6 +317,11 @@ pub enum Error {
     #[error(transparent)]
     DecryptorError(#[from] DecryptorError),
 
+    /// The encryption settings of a room couldn't be changed.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    SetRoomSettings(#[from] SetRoomSettingsError),
+
     /// An error occurred in the state store.
     #[error(transparent)]
     StateStore(#[from] StoreError),
@@ -380,6 +390,15 @@ pub enum Error {
     #[error("backups are not enabled")]
     BackupNotEnabled,
 
+    /// There is no sync checkpoint with the given identifier.
+    #[error("unknown sync checkpoint: {0}")]
+    UnknownSyncCheckpoint(u64),
+
+    /// The stores of the client can't be wiped, because they weren't opened
+    /// by the client.
+    #[error("the stores of the client can't be wiped")]
+    StoresNotWipeable,
+
     /// An error happened during handling of a media subrequest.
     #[error(transparent)]
     Media(#[from] MediaError),
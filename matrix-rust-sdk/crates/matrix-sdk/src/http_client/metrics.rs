@@ -0,0 +1,431 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-endpoint metrics of the requests sent by a client.
+
+use std::{collections::BTreeMap, sync::Mutex as StdMutex, time::Duration};
+
+use ruma::api::{client::error::ErrorKind, error::FromHttpResponseError, OutgoingRequest};
+
+use super::RateLimitMetrics;
+use crate::{error::HttpError, RumaApiError};
+
+/// The upper bounds of the buckets of a [`LatencyHistogram`], the last bucket
+/// holding everything above the last bound.
+const LATENCY_BUCKETS: [Duration; 9] = [
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// The name of the endpoint of the given request type, as used in the
+/// [`ClientMetrics`] and the tracing spans.
+///
+/// It's made of the HTTP method and the path template of the endpoint, taken
+/// from its metadata, e.g. `GET /_matrix/client/v3/rooms/:room_id/messages`.
+/// The latest stable path is used, or the latest unstable one if the endpoint
+/// isn't stable yet, so the name doesn't depend on the versions supported by
+/// the homeserver.
+pub(crate) fn endpoint_name<R: OutgoingRequest>() -> String {
+    let history = &R::METADATA.history;
+    let path = history
+        .stable_paths()
+        .last()
+        .map(|(_, path)| path)
+        .or_else(|| history.unstable_paths().last())
+        .unwrap_or_default();
+
+    format!("{} {path}", R::METADATA.method)
+}
+
+/// The category of an error that happened while sending a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorCategory {
+    /// The request couldn't reach the homeserver, or the connection failed.
+    Network,
+    /// The request timed out.
+    Timeout,
+    /// The homeserver rate-limited the request.
+    RateLimited,
+    /// The homeserver rejected the request with a 4xx status code.
+    ClientError,
+    /// The homeserver failed to handle the request, with a 5xx status code.
+    ServerError,
+    /// The response of the homeserver couldn't be deserialized.
+    Deserialization,
+    /// Any other error, like a failure to serialize the request.
+    Other,
+}
+
+impl ErrorCategory {
+    fn from_error(error: &HttpError) -> Self {
+        match error {
+            HttpError::Reqwest(error) if error.is_timeout() => Self::Timeout,
+            HttpError::Reqwest(_) => Self::Network,
+
+            HttpError::Api(FromHttpResponseError::Server(api_error)) => {
+                if matches!(error.client_api_error_kind(), Some(ErrorKind::LimitExceeded { .. })) {
+                    return Self::RateLimited;
+                }
+
+                let status_code = match api_error {
+                    RumaApiError::ClientApi(error) => error.status_code,
+                    RumaApiError::Other(error) => error.status_code,
+                    RumaApiError::Uiaa(_) => return Self::ClientError,
+                };
+
+                if status_code.is_server_error() {
+                    Self::ServerError
+                } else if status_code.is_client_error() {
+                    Self::ClientError
+                } else {
+                    Self::Other
+                }
+            }
+
+            HttpError::Api(FromHttpResponseError::Deserialization(_)) => Self::Deserialization,
+
+            _ => Self::Other,
+        }
+    }
+}
+
+/// A histogram of the latencies of the requests to an endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct LatencyHistogram {
+    /// The number of requests in each bucket, the last one holding the
+    /// requests slower than the last bound.
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    /// The sum of all the latencies.
+    total: Duration,
+    /// The highest latency.
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// The buckets of the histogram, as pairs of upper bound and number of
+    /// requests whose latency is within this bound and the previous one.
+    ///
+    /// The upper bound of the last bucket is `None`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        LATENCY_BUCKETS.iter().copied().map(Some).chain([None]).zip(self.counts.iter().copied())
+    }
+
+    /// The number of recorded latencies.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The mean latency, if at least one was recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count()).unwrap_or(u32::MAX);
+        (count > 0).then(|| self.total / count)
+    }
+
+    /// The highest recorded latency.
+    ///
+    /// For a histogram computed with [`ClientMetrics::since`], this is the
+    /// upper bound of the bucket of the highest latency, unless the highest
+    /// latency since the creation of the client was recorded in the window.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The latencies recorded since the given earlier snapshot of this
+    /// histogram.
+    fn since(&self, earlier: &Self) -> Self {
+        let mut counts = self.counts;
+        for (count, earlier) in counts.iter_mut().zip(earlier.counts) {
+            *count = count.saturating_sub(earlier);
+        }
+
+        let max = if self.max > earlier.max {
+            self.max
+        } else {
+            // The highest latency of the window isn't known, use the upper bound of its
+            // bucket.
+            counts
+                .iter()
+                .rposition(|count| *count > 0)
+                .map(|bucket| LATENCY_BUCKETS.get(bucket).map_or(self.max, |b| (*b).min(self.max)))
+                .unwrap_or_default()
+        };
+
+        Self { counts, total: self.total.saturating_sub(earlier.total), max }
+    }
+}
+
+/// The metrics of the requests to one endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct EndpointMetrics {
+    /// How many requests were sent, not counting the retries.
+    pub requests: u64,
+
+    /// How many times a request was retried.
+    pub retries: u64,
+
+    /// How many bytes were sent in the request bodies, retries included.
+    pub bytes_sent: u64,
+
+    /// How many bytes were received in the response bodies, retries included.
+    pub bytes_received: u64,
+
+    /// The latencies of the requests, from the first attempt to the final
+    /// response, retries included.
+    pub latency: LatencyHistogram,
+
+    /// How many requests failed, by category of their final error.
+    pub errors: BTreeMap<ErrorCategory, u64>,
+}
+
+impl EndpointMetrics {
+    /// The metrics recorded since the given earlier snapshot of the metrics of
+    /// this endpoint.
+    fn since(&self, earlier: &Self) -> Self {
+        let errors = self
+            .errors
+            .iter()
+            .map(|(category, count)| {
+                let earlier = earlier.errors.get(category).copied().unwrap_or(0);
+                (*category, count.saturating_sub(earlier))
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        Self {
+            requests: self.requests.saturating_sub(earlier.requests),
+            retries: self.retries.saturating_sub(earlier.retries),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+            latency: self.latency.since(&earlier.latency),
+            errors,
+        }
+    }
+
+    /// Whether nothing was recorded for this endpoint.
+    fn is_empty(&self) -> bool {
+        self.requests == 0 && self.retries == 0 && self.bytes_sent == 0 && self.bytes_received == 0
+    }
+}
+
+/// A snapshot of the metrics of the requests sent by a client.
+///
+/// The metrics are cumulative since the creation of the client. To get the
+/// metrics over a time window, e.g. the last hour, keep a snapshot taken at
+/// the start of the window, and use [`ClientMetrics::since`].
+///
+/// Get one with [`Client::metrics()`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # async {
+/// # let client: matrix_sdk::Client = unimplemented!();
+/// let start = client.metrics();
+/// tokio::time::sleep(std::time::Duration::from_secs(60 * 60)).await;
+///
+/// let last_hour = client.metrics().since(&start);
+/// for (endpoint, metrics) in &last_hour.endpoints {
+///     println!(
+///         "{endpoint}: {} requests, mean {:?}",
+///         metrics.requests,
+///         metrics.latency.mean()
+///     );
+/// }
+/// # anyhow::Ok(()) };
+/// ```
+///
+/// [`Client::metrics()`]: crate::Client::metrics
+#[derive(Clone, Debug, Default)]
+pub struct ClientMetrics {
+    /// The metrics of each endpoint, by endpoint name.
+    ///
+    /// The name is made of the HTTP method and the path template of the
+    /// endpoint, e.g. `GET /_matrix/client/v3/sync` or
+    /// `POST /_matrix/client/v3/keys/query`.
+    pub endpoints: BTreeMap<String, EndpointMetrics>,
+
+    /// The rate limiting metrics.
+    pub rate_limits: RateLimitMetrics,
+}
+
+impl ClientMetrics {
+    /// The metrics recorded since the given earlier snapshot.
+    ///
+    /// The endpoints without any request since the earlier snapshot are left
+    /// out.
+    pub fn since(&self, earlier: &ClientMetrics) -> ClientMetrics {
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|(name, metrics)| {
+                let metrics = match earlier.endpoints.get(name) {
+                    Some(earlier) => metrics.since(earlier),
+                    None => metrics.clone(),
+                };
+                (name.clone(), metrics)
+            })
+            .filter(|(_, metrics)| !metrics.is_empty())
+            .collect();
+
+        ClientMetrics { endpoints, rate_limits: self.rate_limits.since(&earlier.rate_limits) }
+    }
+}
+
+/// Records the metrics of the requests sent by a client.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsRecorder {
+    endpoints: StdMutex<BTreeMap<String, EndpointMetrics>>,
+}
+
+impl MetricsRecorder {
+    fn update(&self, endpoint: &str, f: impl FnOnce(&mut EndpointMetrics)) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+
+        if let Some(metrics) = endpoints.get_mut(endpoint) {
+            f(metrics);
+        } else {
+            f(endpoints.entry(endpoint.to_owned()).or_default());
+        }
+    }
+
+    /// Record an attempt to send a request to the given endpoint.
+    pub(crate) fn on_attempt(&self, endpoint: &str, attempt: u64, bytes_sent: usize) {
+        self.update(endpoint, |metrics| {
+            if attempt > 1 {
+                metrics.retries += 1;
+            }
+            metrics.bytes_sent += u64::try_from(bytes_sent).unwrap_or(u64::MAX);
+        });
+    }
+
+    /// Record a response received from the given endpoint, whatever its
+    /// status.
+    pub(crate) fn on_response(&self, endpoint: &str, bytes_received: usize) {
+        self.update(endpoint, |metrics| {
+            metrics.bytes_received += u64::try_from(bytes_received).unwrap_or(u64::MAX);
+        });
+    }
+
+    /// Record the outcome of a request to the given endpoint, once it won't be
+    /// retried anymore.
+    pub(crate) fn on_finish(&self, endpoint: &str, latency: Duration, error: Option<&HttpError>) {
+        self.update(endpoint, |metrics| {
+            metrics.requests += 1;
+            metrics.latency.record(latency);
+
+            if let Some(error) = error {
+                *metrics.errors.entry(ErrorCategory::from_error(error)).or_default() += 1;
+            }
+        });
+    }
+
+    /// Get a snapshot of the metrics of the endpoints.
+    pub(crate) fn endpoints(&self) -> BTreeMap<String, EndpointMetrics> {
+        self.endpoints.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{endpoint_name, EndpointMetrics, ErrorCategory, LatencyHistogram};
+
+    #[test]
+    fn test_endpoint_name() {
+        assert_eq!(
+            endpoint_name::<ruma::api::client::sync::sync_events::v3::Request>(),
+            "GET /_matrix/client/v3/sync"
+        );
+        assert_eq!(
+            endpoint_name::<ruma::api::client::message::get_message_events::v3::Request>(),
+            "GET /_matrix/client/v3/rooms/:room_id/messages"
+        );
+        // An endpoint which only has an unstable path.
+        assert_eq!(
+            endpoint_name::<ruma::api::client::sync::sync_events::v5::Request>(),
+            "POST /_matrix/client/unstable/org.matrix.simplified_msc3575/sync"
+        );
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.mean(), None);
+
+        histogram.record(Duration::from_millis(10));
+        histogram.record(Duration::from_millis(50));
+        histogram.record(Duration::from_millis(300));
+        histogram.record(Duration::from_secs(60));
+
+        let buckets = histogram.buckets().collect::<Vec<_>>();
+        assert_eq!(buckets.len(), 10);
+        assert_eq!(buckets[0], (Some(Duration::from_millis(50)), 2));
+        assert_eq!(buckets[3], (Some(Duration::from_millis(500)), 1));
+        assert_eq!(buckets[9], (None, 1));
+
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.max(), Duration::from_secs(60));
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(15090)));
+    }
+
+    #[test]
+    fn test_metrics_since() {
+        let mut earlier = EndpointMetrics { requests: 2, bytes_sent: 100, ..Default::default() };
+        earlier.latency.record(Duration::from_millis(10));
+        earlier.latency.record(Duration::from_secs(60));
+        earlier.errors.insert(ErrorCategory::Timeout, 1);
+
+        let mut later = earlier.clone();
+        later.requests += 2;
+        later.bytes_sent += 50;
+        later.latency.record(Duration::from_millis(300));
+        later.latency.record(Duration::from_millis(400));
+        later.errors.insert(ErrorCategory::ServerError, 1);
+
+        let window = later.since(&earlier);
+        assert_eq!(window.requests, 2);
+        assert_eq!(window.bytes_sent, 50);
+        assert_eq!(window.latency.count(), 2);
+        assert_eq!(window.latency.mean(), Some(Duration::from_millis(350)));
+        // The highest latency of the window is only known by its bucket.
+        assert_eq!(window.latency.max(), Duration::from_millis(500));
+        assert_eq!(
+            window.errors.into_iter().collect::<Vec<_>>(),
+            [(ErrorCategory::ServerError, 1)]
+        );
+
+        // Nothing happened since the later snapshot.
+        assert!(later.since(&later).is_empty());
+    }
+}
@@ -27,22 +27,35 @@ use bytes::{Bytes, BytesMut};
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
 use http::Method;
-use ruma::api::{
-    error::{FromHttpResponseError, IntoHttpError},
-    AuthScheme, MatrixVersion, OutgoingRequest, SendAccessToken,
+use ruma::{
+    api::{
+        error::{FromHttpResponseError, IntoHttpError},
+        AuthScheme, MatrixVersion, OutgoingRequest, SendAccessToken,
+    },
+    time::Instant,
 };
 use tokio::sync::{Semaphore, SemaphorePermit};
 use tracing::{debug, field::debug, instrument, trace};
 
 use crate::{config::RequestConfig, error::HttpError};
 
+mod metrics;
 #[cfg(not(target_arch = "wasm32"))]
 mod native;
+#[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+mod pinning;
+mod rate_limiter;
 #[cfg(target_arch = "wasm32")]
 mod wasm;
 
+pub(crate) use metrics::MetricsRecorder;
+pub use metrics::{ClientMetrics, EndpointMetrics, ErrorCategory, LatencyHistogram};
 #[cfg(not(target_arch = "wasm32"))]
-pub(crate) use native::HttpSettings;
+pub(crate) use native::{DnsResolver, HttpSettings};
+#[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+pub use pinning::{CertificatePin, InvalidCertificatePin};
+pub(crate) use rate_limiter::RateLimiter;
+pub use rate_limiter::{EndpointClass, EndpointRateLimitMetrics, RateLimitMetrics};
 
 pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -76,6 +89,8 @@ pub(crate) struct HttpClient {
     pub(crate) request_config: RequestConfig,
     concurrent_request_semaphore: MaybeSemaphore,
     next_request_id: Arc<AtomicU64>,
+    pub(crate) rate_limiter: Arc<RateLimiter>,
+    pub(crate) metrics: Arc<MetricsRecorder>,
 }
 
 impl HttpClient {
@@ -87,6 +102,8 @@ impl HttpClient {
                 request_config.max_concurrent_requests,
             ),
             next_request_id: AtomicU64::new(0).into(),
+            rate_limiter: Default::default(),
+            metrics: Default::default(),
         }
     }
 
@@ -142,6 +159,8 @@ impl HttpClient {
             request_size,
             request_body,
             request_id,
+            endpoint,
+            attempt,
             status,
             response_size,
             sentry_event_id,
@@ -173,7 +192,9 @@ impl HttpClient {
 
             // At this point in the code, the config isn't behind an Option anymore, that's
             // why we record it here, instead of in the #[instrument] macro.
-            span.record("config", debug(config)).record("request_id", request_id);
+            span.record("config", debug(config))
+                .record("request_id", request_id)
+                .record("endpoint", metrics::endpoint_name::<R>().as_str());
 
             let auth_scheme = R::METADATA.authentication;
             match auth_scheme {
@@ -220,12 +241,16 @@ impl HttpClient {
             request
         };
 
-        // will be automatically dropped at the end of this function
-        let _handle = self.concurrent_request_semaphore.acquire().await;
+        let start = Instant::now();
 
         // There's a bunch of state in send_request, factor out a pinned inner
         // future to reduce this size of futures that await this function.
-        match Box::pin(self.send_request::<R>(request, config, send_progress)).await {
+        let result = Box::pin(self.send_request::<R>(request, config, send_progress)).await;
+
+        let endpoint = metrics::endpoint_name::<R>();
+        self.metrics.on_finish(&endpoint, start.elapsed(), result.as_ref().err());
+
+        match result {
             Ok(response) => {
                 debug!("Got response");
                 Ok(response)
@@ -13,9 +13,14 @@
 // limitations under the License.
 
 use std::{
-    fmt::Debug,
+    collections::BTreeMap,
+    fmt::{self, Debug},
     mem,
-    sync::atomic::{AtomicU64, Ordering},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
@@ -24,11 +29,17 @@ use bytes::Bytes;
 use bytesize::ByteSize;
 use eyeball::SharedObservable;
 use http::header::CONTENT_LENGTH;
-use reqwest::Certificate;
+use reqwest::{
+    dns::{Name, Resolve, Resolving},
+    Certificate,
+};
 use ruma::api::{error::FromHttpResponseError, IncomingResponse, OutgoingRequest};
 use tracing::{debug, info, warn};
 
-use super::{response_to_http_response, HttpClient, TransmissionProgress, DEFAULT_REQUEST_TIMEOUT};
+use super::{
+    metrics::endpoint_name, response_to_http_response, EndpointClass, HttpClient,
+    TransmissionProgress, DEFAULT_REQUEST_TIMEOUT,
+};
 use crate::{
     config::RequestConfig,
     error::{HttpError, RetryKind},
@@ -48,20 +59,32 @@ impl HttpClient {
         let backoff =
             ExponentialBackoff { max_elapsed_time: config.retry_timeout, ..Default::default() };
         let retry_count = AtomicU64::new(1);
+        let endpoint_class = EndpointClass::from_path(request.uri().path());
+        let endpoint = endpoint_name::<R>();
 
         let send_request = || {
             let send_progress = send_progress.clone();
             async {
-                debug!(num_attempt = retry_count.load(Ordering::SeqCst), "Sending request");
+                self.rate_limiter.wait(endpoint_class).await;
 
-                let stop = if let Some(retry_limit) = config.retry_limit {
-                    retry_count.fetch_add(1, Ordering::Relaxed) >= retry_limit
-                } else {
-                    false
-                };
+                // The slot is only taken once the request isn't held back anymore, so an
+                // attempt that's held back because of rate limiting, or waiting to be
+                // retried, doesn't take a slot from the requests that can be sent. It's
+                // automatically released at the end of the attempt.
+                let _permit = self.concurrent_request_semaphore.acquire().await;
+
+                let attempt = retry_count.fetch_add(1, Ordering::SeqCst);
+                debug!(num_attempt = attempt, "Sending request");
+
+                tracing::Span::current().record("attempt", attempt);
+                self.metrics.on_attempt(&endpoint, attempt, request.body().len());
+
+                let stop = config.retry_limit.is_some_and(|retry_limit| attempt >= retry_limit);
 
                 // Turn errors into permanent errors when the retry limit is reached.
                 let error_type = |err: HttpError| {
+                    self.rate_limiter.on_error(endpoint_class, &err);
+
                     if stop {
                         RetryError::Permanent(err)
                     } else {
@@ -90,6 +113,8 @@ impl HttpClient {
                     .await
                     .map_err(error_type)?;
 
+                self.metrics.on_response(&endpoint, response.body().len());
+
                 let status_code = response.status();
                 let response_size = ByteSize(response.body().len().try_into().unwrap_or(u64::MAX));
                 tracing::Span::current()
@@ -127,6 +152,10 @@ pub(crate) struct HttpSettings {
     pub(crate) timeout: Duration,
     pub(crate) additional_root_certificates: Vec<Certificate>,
     pub(crate) disable_built_in_root_certificates: bool,
+    #[cfg(feature = "rustls-tls")]
+    pub(crate) certificate_pins: BTreeMap<String, Vec<super::CertificatePin>>,
+    pub(crate) dns_overrides: BTreeMap<String, Vec<IpAddr>>,
+    pub(crate) dns_resolver: Option<DnsResolver>,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -139,6 +168,10 @@ impl Default for HttpSettings {
             timeout: DEFAULT_REQUEST_TIMEOUT,
             additional_root_certificates: Default::default(),
             disable_built_in_root_certificates: false,
+            #[cfg(feature = "rustls-tls")]
+            certificate_pins: BTreeMap::new(),
+            dns_overrides: BTreeMap::new(),
+            dns_resolver: None,
         }
     }
 }
@@ -172,15 +205,59 @@ impl HttpSettings {
             http_client = http_client.tls_built_in_root_certs(false);
         }
 
+        #[cfg(feature = "rustls-tls")]
+        if !self.certificate_pins.is_empty() {
+            for (host, pins) in &self.certificate_pins {
+                info!(host, ?pins, "Pinning the TLS certificates in the HTTP client");
+            }
+
+            http_client = http_client.use_preconfigured_tls(super::pinning::pinned_tls_config(
+                self.certificate_pins.clone(),
+                !self.disable_built_in_root_certificates,
+            )?);
+        }
+
         if let Some(p) = &self.proxy {
             info!(proxy_url = p, "Setting the proxy for the HTTP client");
             http_client = http_client.proxy(reqwest::Proxy::all(p.as_str())?);
         }
 
+        if let Some(resolver) = &self.dns_resolver {
+            info!("Setting a custom DNS resolver for the HTTP client");
+            http_client = http_client.dns_resolver(Arc::new(resolver.clone()));
+        }
+
+        for (domain, addresses) in &self.dns_overrides {
+            info!(domain, ?addresses, "Overriding the DNS resolution in the HTTP client");
+            // The port is ignored by reqwest, the one of the URL is used.
+            let addresses: Vec<_> =
+                addresses.iter().map(|address| SocketAddr::new(*address, 0)).collect();
+            http_client = http_client.resolve_to_addrs(domain, &addresses);
+        }
+
         Ok(http_client.build()?)
     }
 }
 
+/// A custom DNS resolver for the HTTP client.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub(crate) struct DnsResolver(pub(crate) Arc<dyn Resolve>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Debug for DnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsResolver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Resolve for DnsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.0.resolve(name)
+    }
+}
+
 pub(super) async fn send_request(
     client: &reqwest::Client,
     request: &http::Request<Bytes>,
@@ -0,0 +1,464 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pinning of the TLS certificates of the servers.
+
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::Arc};
+
+use ruma::serde::{base64::Standard, Base64};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, SubjectPublicKeyInfoDer, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use webpki::EndEntityCert;
+
+/// A pin of the TLS certificate of a server.
+///
+/// The pins are parsed from strings in one of these formats:
+///
+/// * `sha256/<base64>`: the SHA-256 digest of the public key of the
+///   certificate, encoded in base64, like in HPKP,
+/// * the SHA-256 fingerprint of the certificate, as hexadecimal digits that can
+///   be separated by colons, like in the output of `openssl x509 -fingerprint
+///   -sha256`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CertificatePin {
+    /// The SHA-256 digest of the DER-encoded certificate.
+    ///
+    /// It changes every time the certificate is renewed.
+    Certificate([u8; 32]),
+
+    /// The SHA-256 digest of the DER-encoded `SubjectPublicKeyInfo` of the
+    /// certificate.
+    ///
+    /// It stays the same when the certificate is renewed with the same key.
+    PublicKey([u8; 32]),
+}
+
+impl CertificatePin {
+    /// Whether the given DER-encoded certificate matches this pin.
+    fn matches(&self, certificate: &CertificateDer<'_>) -> bool {
+        match self {
+            Self::Certificate(digest) => Sha256::digest(certificate).as_slice() == digest,
+            Self::PublicKey(digest) => subject_public_key_info(certificate)
+                .is_some_and(|spki| Sha256::digest(&spki).as_slice() == digest),
+        }
+    }
+}
+
+/// Get the DER-encoded `SubjectPublicKeyInfo` of a DER-encoded X.509
+/// certificate.
+fn subject_public_key_info(
+    certificate: &CertificateDer<'_>,
+) -> Option<SubjectPublicKeyInfoDer<'static>> {
+    EndEntityCert::try_from(certificate).ok().map(|cert| cert.subject_public_key_info())
+}
+
+impl fmt::Debug for CertificatePin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Certificate(digest) => {
+                let hex: Vec<_> = digest.iter().map(|byte| format!("{byte:02X}")).collect();
+                write!(f, "{}", hex.join(":"))
+            }
+            Self::PublicKey(digest) => write!(f, "sha256/{}", Base64::<Standard, _>::new(digest)),
+        }
+    }
+}
+
+/// An error when parsing a [`CertificatePin`].
+#[derive(Debug, thiserror::Error)]
+#[error("invalid certificate pin `{0}`")]
+pub struct InvalidCertificatePin(String);
+
+impl FromStr for CertificatePin {
+    type Err = InvalidCertificatePin;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCertificatePin(s.to_owned());
+
+        if let Some(encoded) = s.strip_prefix("sha256/") {
+            let decoded = Base64::<Standard>::parse(encoded).map_err(|_| invalid())?;
+            return decoded.as_bytes().try_into().map(Self::PublicKey).map_err(|_| invalid());
+        }
+
+        let hex: String = s.chars().filter(|c| *c != ':').collect();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut digest = [0; 32];
+        for (byte, chunk) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let chunk = std::str::from_utf8(chunk).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(chunk, 16).map_err(|_| invalid())?;
+        }
+
+        Ok(Self::Certificate(digest))
+    }
+}
+
+/// A verifier that checks that the certificates of the hosts with pins match
+/// one of their pins.
+///
+/// The certificates are still verified like usual, with the built-in root
+/// certificates: they must be valid at the current time and for the name of
+/// the host. A pinned certificate is trusted even if it's not signed by a root
+/// certificate, so a self-signed certificate can be pinned, as long as it's not
+/// a CA certificate.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    /// The pins of the certificates of each host.
+    pins: BTreeMap<String, Vec<CertificatePin>>,
+
+    /// The built-in root certificates, if they are trusted.
+    roots: RootCertStore,
+
+    /// The verifier of the certificates of the hosts without pins, if any root
+    /// certificate is trusted.
+    default_verifier: Option<Arc<WebPkiServerVerifier>>,
+
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    fn new(
+        pins: BTreeMap<String, Vec<CertificatePin>>,
+        built_in_root_certificates: bool,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, rustls::Error> {
+        let mut roots = RootCertStore::empty();
+        if built_in_root_certificates {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        let default_verifier =
+            if roots.is_empty() { None } else { Some(verifier(roots.clone(), provider.clone())?) };
+
+        Ok(Self { pins, roots, default_verifier, provider })
+    }
+}
+
+/// Build a verifier of the certificates signed by one of the given roots.
+fn verifier(
+    roots: RootCertStore,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<WebPkiServerVerifier>, rustls::Error> {
+    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(|error| rustls::Error::General(error.to_string()))
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let Some(pins) = self.pins.get(&server_name.to_str().to_ascii_lowercase()) else {
+            return match &self.default_verifier {
+                Some(verifier) => verifier.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    ocsp_response,
+                    now,
+                ),
+                None => Err(CertificateError::UnknownIssuer.into()),
+            };
+        };
+
+        if !pins.iter().any(|pin| pin.matches(end_entity)) {
+            return Err(rustls::Error::General("the certificate doesn't match any pin".to_owned()));
+        }
+
+        // The pinned certificate is its own root, so a self-signed certificate is
+        // trusted too.
+        let mut roots = self.roots.clone();
+        roots.add(end_entity.clone().into_owned())?;
+
+        verifier(roots, self.provider.clone())?.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Create the TLS configuration of an HTTP client that checks that the
+/// certificates of the given hosts match one of their pins.
+///
+/// The hosts must be lowercase.
+pub(crate) fn pinned_tls_config(
+    pins: BTreeMap<String, Vec<CertificatePin>>,
+    built_in_root_certificates: bool,
+) -> Result<ClientConfig, rustls::Error> {
+    let provider = Arc::new(ring::default_provider());
+    let verifier = PinnedCertVerifier::new(pins, built_in_root_certificates, provider.clone())?;
+
+    Ok(ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+    use assert_matches2::{assert_let, assert_matches};
+    use rustls::{
+        client::danger::ServerCertVerifier,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+        CertificateError, ClientConnection, Connection, ServerConfig, ServerConnection,
+    };
+    use sha2::{Digest, Sha256};
+
+    use super::{pinned_tls_config, subject_public_key_info, CertificatePin, PinnedCertVerifier};
+
+    /// A self-signed certificate for `localhost`, with a P-256 key.
+    const CERTIFICATE: &[u8] = include_bytes!("../../../../testing/data/tls/localhost.crt.der");
+
+    /// The PKCS#8 private key of [`CERTIFICATE`].
+    const PRIVATE_KEY: &[u8] = include_bytes!("../../../../testing/data/tls/localhost.key.der");
+
+    /// The SHA-256 fingerprint of [`CERTIFICATE`].
+    const CERTIFICATE_FINGERPRINT: &str = "39:C6:72:1E:05:5A:E6:D4:20:06:84:18:56:17:B8:1E:\
+                                           6F:BE:53:41:C3:B9:41:8B:E1:E6:79:47:86:6E:70:27";
+
+    /// The pin of the public key of [`CERTIFICATE`].
+    const PUBLIC_KEY_PIN: &str = "sha256/z6zf1x+7dALc24w1KzNkrgS+AvTSejLPELBam1m3QyY=";
+
+    /// Send the pending TLS messages of `from` to `to`.
+    fn transfer(from: &mut Connection, to: &mut Connection) -> Result<(), rustls::Error> {
+        let mut buffer = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut buffer).unwrap();
+        }
+
+        let mut reader = buffer.as_slice();
+        while !reader.is_empty() {
+            to.read_tls(&mut reader).unwrap();
+        }
+
+        to.process_new_packets()?;
+        Ok(())
+    }
+
+    /// Run a TLS handshake with the given host, between a client using the
+    /// given pins, and a server using [`CERTIFICATE`].
+    fn handshake(
+        host: &'static str,
+        pins: BTreeMap<String, Vec<CertificatePin>>,
+    ) -> Result<(), rustls::Error> {
+        let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(CERTIFICATE)],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(PRIVATE_KEY)),
+            )
+            .unwrap();
+
+        let mut server = Connection::from(ServerConnection::new(Arc::new(server_config)).unwrap());
+        let mut client = Connection::from(
+            ClientConnection::new(
+                Arc::new(pinned_tls_config(pins, true).unwrap()),
+                ServerName::try_from(host).unwrap(),
+            )
+            .unwrap(),
+        );
+
+        // A few round trips are enough to complete the handshake.
+        for _ in 0..5 {
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return Ok(());
+            }
+
+            transfer(&mut client, &mut server)?;
+            transfer(&mut server, &mut client)?;
+        }
+
+        panic!("the handshake didn't complete");
+    }
+
+    #[test]
+    fn test_parse_certificate_pin() {
+        let pin: CertificatePin =
+            "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".parse().unwrap();
+        assert_let!(CertificatePin::PublicKey(digest) = pin);
+        assert_eq!(digest[0], 0xe3);
+        assert_eq!(format!("{pin:?}"), "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU");
+
+        let fingerprint = "E3:B0:C4:42:98:FC:1C:14:9A:FB:F4:C8:99:6F:B9:24:\
+                           27:AE:41:E4:64:9B:93:4C:A4:95:99:1B:78:52:B8:55";
+        let pin: CertificatePin = fingerprint.parse().unwrap();
+        assert_let!(CertificatePin::Certificate(digest) = pin);
+        assert_eq!(digest[31], 0x55);
+        assert_eq!(format!("{pin:?}"), fingerprint);
+
+        // Without the colons.
+        assert_eq!(fingerprint.replace(':', "").parse::<CertificatePin>().unwrap(), pin);
+
+        // Wrong lengths.
+        "sha256/47DEQpj8HBSa".parse::<CertificatePin>().unwrap_err();
+        "E3:B0:C4".parse::<CertificatePin>().unwrap_err();
+        // Not hexadecimal.
+        fingerprint.replace('E', "G").parse::<CertificatePin>().unwrap_err();
+    }
+
+    #[test]
+    fn test_certificate_pins_match() {
+        let certificate = CertificateDer::from(CERTIFICATE);
+
+        let pin: CertificatePin = CERTIFICATE_FINGERPRINT.parse().unwrap();
+        assert!(pin.matches(&certificate));
+
+        let pin: CertificatePin = PUBLIC_KEY_PIN.parse().unwrap();
+        assert!(pin.matches(&certificate));
+
+        let spki = subject_public_key_info(&certificate).unwrap();
+        assert_let!(CertificatePin::PublicKey(digest) = pin);
+        assert_eq!(Sha256::digest(&spki).as_slice(), digest);
+
+        // Not a certificate.
+        assert!(subject_public_key_info(&CertificateDer::from(&CERTIFICATE[1..])).is_none());
+    }
+
+    /// The given pins of `localhost`.
+    fn localhost_pins(pins: &[&str]) -> BTreeMap<String, Vec<CertificatePin>> {
+        let pins = pins.iter().map(|pin| pin.parse().unwrap()).collect();
+        BTreeMap::from([("localhost".to_owned(), pins)])
+    }
+
+    #[test]
+    fn test_handshake_with_matching_pins() {
+        handshake("localhost", localhost_pins(&[CERTIFICATE_FINGERPRINT])).unwrap();
+        handshake("localhost", localhost_pins(&[PUBLIC_KEY_PIN])).unwrap();
+
+        // One matching pin is enough.
+        handshake(
+            "localhost",
+            localhost_pins(&[
+                "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+                PUBLIC_KEY_PIN,
+            ]),
+        )
+        .unwrap();
+
+        // The host names are case-insensitive.
+        handshake("LocalHost", localhost_pins(&[PUBLIC_KEY_PIN])).unwrap();
+    }
+
+    #[test]
+    fn test_handshake_without_matching_pins() {
+        let result = handshake(
+            "localhost",
+            localhost_pins(&["sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="]),
+        );
+        assert_matches!(result, Err(rustls::Error::General(_)));
+
+        let result = handshake("localhost", localhost_pins(&[]));
+        assert_matches!(result, Err(rustls::Error::General(_)));
+    }
+
+    #[test]
+    fn test_handshake_with_unpinned_host() {
+        // The certificates of the other hosts are verified like usual, so the
+        // self-signed certificate isn't trusted.
+        let pins =
+            BTreeMap::from([("example.org".to_owned(), vec![PUBLIC_KEY_PIN.parse().unwrap()])]);
+        let result = handshake("localhost", pins);
+        assert_matches!(
+            result,
+            Err(rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer))
+        );
+    }
+
+    #[test]
+    fn test_pinned_certificates_are_verified() {
+        let certificate = CertificateDer::from(CERTIFICATE);
+        let pins = BTreeMap::from([
+            ("localhost".to_owned(), vec![PUBLIC_KEY_PIN.parse().unwrap()]),
+            ("example.org".to_owned(), vec![PUBLIC_KEY_PIN.parse().unwrap()]),
+        ]);
+        let verifier =
+            PinnedCertVerifier::new(pins, false, Arc::new(ring::default_provider())).unwrap();
+        let verify = |host: &'static str, now| {
+            verifier.verify_server_cert(
+                &certificate,
+                &[],
+                &ServerName::try_from(host).unwrap(),
+                &[],
+                now,
+            )
+        };
+
+        verify("localhost", UnixTime::now()).unwrap();
+
+        // The certificate isn't valid for this host, even if it matches its pins.
+        let result = verify("example.org", UnixTime::now());
+        assert_matches!(
+            result,
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+        );
+
+        // The certificate expired.
+        let result = verify("localhost", UnixTime::since_unix_epoch(Duration::from_secs(1 << 33)));
+        assert_matches!(result, Err(rustls::Error::InvalidCertificate(CertificateError::Expired)));
+
+        // Without the built-in root certificates, the certificates of the hosts
+        // without pins aren't trusted.
+        let result = verify("matrix.org", UnixTime::now());
+        assert_matches!(
+            result,
+            Err(rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer))
+        );
+    }
+}
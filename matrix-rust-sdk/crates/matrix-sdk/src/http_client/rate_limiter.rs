@@ -0,0 +1,463 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A rate limiter shared by all the requests of a client.
+//!
+//! When the homeserver rate-limits a request, the other requests of the same
+//! [`EndpointClass`] would most likely be rate-limited too, so they are queued
+//! until the delay the homeserver asked for has passed. The queued requests are
+//! then released by priority.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    pin::pin,
+    sync::Mutex as StdMutex,
+    time::Duration,
+};
+
+#[cfg(target_arch = "wasm32")]
+use ruma::time::Instant;
+use ruma::{
+    api::client::error::{ErrorKind, RetryAfter},
+    time::SystemTime,
+};
+use tokio::sync::Notify;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::error::HttpError;
+
+/// The class of an endpoint, as seen by the rate limiter.
+///
+/// The classes are ordered by priority: when a request gets rate-limited, the
+/// requests of its class and of the classes with a lower priority are held
+/// back, but the requests of the classes with a higher priority aren't. This
+/// way, a rate-limited back-pagination never delays the sync. Once the
+/// homeserver accepts requests again, the queued requests with a higher
+/// priority are sent first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EndpointClass {
+    /// The `/sync` endpoint, in all its flavours.
+    Sync,
+    /// The `/sendToDevice` endpoint.
+    ToDevice,
+    /// Any endpoint that doesn't fit in another class.
+    Other,
+    /// The media repository endpoints.
+    Media,
+    /// The endpoints used to back-paginate: `/messages` and `/context`.
+    Backfill,
+}
+
+impl EndpointClass {
+    /// Get the class of the endpoint with the given path.
+    pub(crate) fn from_path(path: &str) -> Self {
+        if path.ends_with("/sync") {
+            Self::Sync
+        } else if path.contains("/sendToDevice/") {
+            Self::ToDevice
+        } else if path.contains("/media/") {
+            Self::Media
+        } else if path.ends_with("/messages") || path.contains("/context/") {
+            Self::Backfill
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Rate limiting metrics for one [`EndpointClass`].
+#[derive(Clone, Debug, Default)]
+pub struct EndpointRateLimitMetrics {
+    /// How many responses were rate-limited by the homeserver.
+    pub rate_limited_responses: u64,
+
+    /// How many requests were held back before being sent, because of a
+    /// previous rate-limited response.
+    pub delayed_requests: u64,
+
+    /// How long the requests were held back for, in total.
+    pub total_delay: Duration,
+
+    /// How long the requests are still going to be held back for, if they
+    /// are.
+    pub remaining_delay: Option<Duration>,
+
+    /// How many requests are queued, waiting to be sent.
+    pub queued_requests: usize,
+}
+
+/// A snapshot of the rate limiting metrics of a client.
+///
+/// Get one with [`Client::rate_limit_metrics()`].
+///
+/// [`Client::rate_limit_metrics()`]: crate::Client::rate_limit_metrics
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitMetrics {
+    /// The metrics of every class of endpoints that was rate-limited or held
+    /// back at least once.
+    pub classes: BTreeMap<EndpointClass, EndpointRateLimitMetrics>,
+}
+
+impl RateLimitMetrics {
+    /// The metrics recorded since the given earlier snapshot.
+    ///
+    /// The remaining delays and the queued requests are the ones of this
+    /// snapshot.
+    pub fn since(&self, earlier: &RateLimitMetrics) -> RateLimitMetrics {
+        let classes = self
+            .classes
+            .iter()
+            .map(|(class, metrics)| {
+                let Some(earlier) = earlier.classes.get(class) else {
+                    return (*class, metrics.clone());
+                };
+
+                let metrics = EndpointRateLimitMetrics {
+                    rate_limited_responses: metrics
+                        .rate_limited_responses
+                        .saturating_sub(earlier.rate_limited_responses),
+                    delayed_requests: metrics
+                        .delayed_requests
+                        .saturating_sub(earlier.delayed_requests),
+                    total_delay: metrics.total_delay.saturating_sub(earlier.total_delay),
+                    remaining_delay: metrics.remaining_delay,
+                    queued_requests: metrics.queued_requests,
+                };
+                (*class, metrics)
+            })
+            .collect();
+
+        RateLimitMetrics { classes }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClassState {
+    /// Requests of this class must not be sent before this instant.
+    blocked_until: Option<Instant>,
+    metrics: EndpointRateLimitMetrics,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    classes: BTreeMap<EndpointClass, ClassState>,
+
+    /// The requests waiting to be sent, in the order they are going to be
+    /// released: by priority, then in the order they were queued in.
+    queue: BTreeSet<(EndpointClass, u64)>,
+
+    /// The ticket of the next queued request.
+    next_ticket: u64,
+}
+
+impl SchedulerState {
+    /// How long a request of the given class must still be held back for.
+    fn remaining_delay(&self, class: EndpointClass, now: Instant) -> Option<Duration> {
+        let blocked_until = self.classes.get(&class)?.blocked_until?;
+        blocked_until.checked_duration_since(now).filter(|delay| !delay.is_zero())
+    }
+}
+
+/// Queues the requests of the endpoint classes that were rate-limited by the
+/// homeserver, and releases them by priority once the homeserver accepts
+/// requests again.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    state: StdMutex<SchedulerState>,
+
+    /// Notified every time a request leaves the queue.
+    released: Notify,
+}
+
+/// Removes a request from the queue when it's released, or when the request is
+/// dropped while it's still queued.
+struct QueuedRequest<'a> {
+    limiter: &'a RateLimiter,
+    ticket: (EndpointClass, u64),
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().queue.remove(&self.ticket);
+        self.limiter.released.notify_waiters();
+    }
+}
+
+impl RateLimiter {
+    /// Wait until a request of the given class can be sent.
+    ///
+    /// The request is queued until its class isn't held back anymore, and
+    /// until all the requests queued before it with the same priority, or with
+    /// a higher priority, were released.
+    pub(crate) async fn wait(&self, class: EndpointClass) {
+        let ticket = {
+            let mut state = self.state.lock().unwrap();
+            let ticket = (class, state.next_ticket);
+            state.next_ticket += 1;
+            state.queue.insert(ticket);
+            ticket
+        };
+        let queued = QueuedRequest { limiter: self, ticket };
+
+        let start = Instant::now();
+        let mut was_held_back = false;
+
+        loop {
+            // Register for the notification before looking at the queue, to not miss a
+            // request being released in between.
+            let mut released = pin!(self.released.notified());
+            released.as_mut().enable();
+
+            let delay = {
+                let state = self.state.lock().unwrap();
+
+                if let Some(delay) = state.remaining_delay(class, Instant::now()) {
+                    Some(delay)
+                } else if state.queue.first() == Some(&ticket) {
+                    break;
+                } else {
+                    None
+                }
+            };
+
+            was_held_back = true;
+
+            match delay {
+                Some(delay) => {
+                    // Another request may be rate-limited while we're waiting, so the delay is
+                    // checked again afterwards.
+                    debug!(?class, ?delay, "Holding the request back, because of rate limiting");
+                    sleep(delay).await;
+                }
+                // A request with a higher priority, or queued before this one, is about to be
+                // released.
+                None => released.await,
+            }
+        }
+
+        drop(queued);
+
+        if was_held_back {
+            let mut state = self.state.lock().unwrap();
+            let metrics = &mut state.classes.entry(class).or_default().metrics;
+            metrics.delayed_requests += 1;
+            metrics.total_delay += start.elapsed();
+        }
+    }
+
+    /// Take the given error of a request of the given class into account.
+    ///
+    /// If the request was rate-limited, the requests of its class, and of the
+    /// classes with a lower priority, are held back for the delay the
+    /// homeserver asked for.
+    pub(crate) fn on_error(&self, class: EndpointClass, error: &HttpError) {
+        let Some(ErrorKind::LimitExceeded { retry_after }) = error.client_api_error_kind() else {
+            return;
+        };
+
+        let delay = retry_after.as_ref().and_then(|retry_after| match retry_after {
+            RetryAfter::Delay(delay) => Some(*delay),
+            RetryAfter::DateTime(time) => time.duration_since(SystemTime::now()).ok(),
+        });
+
+        self.on_rate_limited(class, delay);
+    }
+
+    /// Hold back the requests of the given class, and of the classes with a
+    /// lower priority, for the given delay.
+    fn on_rate_limited(&self, class: EndpointClass, delay: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        state.classes.entry(class).or_default().metrics.rate_limited_responses += 1;
+
+        let Some(delay) = delay else {
+            return;
+        };
+
+        debug!(?class, ?delay, "The homeserver rate-limited a request");
+
+        let blocked_until = Instant::now() + delay;
+        let affected = [
+            EndpointClass::Sync,
+            EndpointClass::ToDevice,
+            EndpointClass::Other,
+            EndpointClass::Media,
+            EndpointClass::Backfill,
+        ];
+
+        for affected_class in affected.into_iter().filter(|c| *c >= class) {
+            let class_state = state.classes.entry(affected_class).or_default();
+            if class_state.blocked_until.map_or(true, |until| until < blocked_until) {
+                class_state.blocked_until = Some(blocked_until);
+            }
+        }
+    }
+
+    /// Get a snapshot of the rate limiting metrics.
+    pub(crate) fn metrics(&self) -> RateLimitMetrics {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+
+        let classes = state
+            .classes
+            .iter()
+            .map(|(class, class_state)| {
+                let metrics = EndpointRateLimitMetrics {
+                    remaining_delay: state.remaining_delay(*class, now),
+                    queued_requests: state.queue.iter().filter(|(c, _)| c == class).count(),
+                    ..class_state.metrics.clone()
+                };
+                (*class, metrics)
+            })
+            .collect();
+
+        RateLimitMetrics { classes }
+    }
+}
+
+async fn sleep(delay: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::TimeoutFuture::new(delay.as_millis().try_into().unwrap_or(u32::MAX)).await;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(delay).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use futures_util::join;
+    use matrix_sdk_test::async_test;
+    use ruma::time::Instant;
+
+    use super::{EndpointClass, RateLimiter};
+
+    #[test]
+    fn test_endpoint_class_from_path() {
+        assert_eq!(EndpointClass::from_path("/_matrix/client/v3/sync"), EndpointClass::Sync);
+        assert_eq!(
+            EndpointClass::from_path("/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"),
+            EndpointClass::Sync
+        );
+        assert_eq!(
+            EndpointClass::from_path("/_matrix/client/v3/sendToDevice/m.room.encrypted/1234"),
+            EndpointClass::ToDevice
+        );
+        assert_eq!(
+            EndpointClass::from_path("/_matrix/client/v1/media/download/localhost/abcd"),
+            EndpointClass::Media
+        );
+        assert_eq!(EndpointClass::from_path("/_matrix/media/v3/upload"), EndpointClass::Media);
+        assert_eq!(
+            EndpointClass::from_path("/_matrix/client/v3/rooms/!a:localhost/messages"),
+            EndpointClass::Backfill
+        );
+        assert_eq!(
+            EndpointClass::from_path("/_matrix/client/v3/rooms/!a:localhost/context/$b"),
+            EndpointClass::Backfill
+        );
+        assert_eq!(EndpointClass::from_path("/_matrix/client/v3/keys/query"), EndpointClass::Other);
+    }
+
+    #[async_test]
+    async fn test_only_lower_priority_classes_are_held_back() {
+        let delay = Duration::from_millis(200);
+        let start = Instant::now();
+
+        let limiter = RateLimiter::default();
+        limiter.on_rate_limited(EndpointClass::Media, Some(delay));
+
+        // The classes with a higher priority aren't held back.
+        limiter.wait(EndpointClass::Sync).await;
+        limiter.wait(EndpointClass::Other).await;
+        assert!(start.elapsed() < delay);
+
+        let metrics = limiter.metrics();
+        assert!(!metrics.classes.contains_key(&EndpointClass::Sync));
+        assert!(!metrics.classes.contains_key(&EndpointClass::Other));
+        assert_eq!(metrics.classes[&EndpointClass::Media].rate_limited_responses, 1);
+        let remaining_delay = metrics.classes[&EndpointClass::Backfill].remaining_delay.unwrap();
+        assert!(remaining_delay <= delay);
+
+        // The rate-limited class, and the classes with a lower priority, are.
+        limiter.wait(EndpointClass::Backfill).await;
+        assert!(start.elapsed() >= delay);
+
+        let metrics = limiter.metrics();
+        let backfill = &metrics.classes[&EndpointClass::Backfill];
+        assert_eq!(backfill.rate_limited_responses, 0);
+        assert_eq!(backfill.delayed_requests, 1);
+        assert!(backfill.total_delay > Duration::ZERO);
+        assert!(backfill.total_delay <= start.elapsed());
+        assert_eq!(backfill.remaining_delay, None);
+
+        // Once the delay has passed, the requests aren't held back anymore.
+        let start = Instant::now();
+        limiter.wait(EndpointClass::Media).await;
+        assert!(start.elapsed() < delay);
+        assert_eq!(limiter.metrics().classes[&EndpointClass::Media].delayed_requests, 0);
+    }
+
+    #[async_test]
+    async fn test_queued_requests_are_released_by_priority() {
+        let delay = Duration::from_millis(100);
+        let start = Instant::now();
+
+        let limiter = RateLimiter::default();
+        limiter.on_rate_limited(EndpointClass::Sync, Some(delay));
+
+        let released = Mutex::new(Vec::new());
+        let send = |class| {
+            let limiter = &limiter;
+            let released = &released;
+            async move {
+                limiter.wait(class).await;
+                released.lock().unwrap().push(class);
+            }
+        };
+
+        let check_queue = async {
+            let metrics = limiter.metrics();
+            assert_eq!(metrics.classes[&EndpointClass::Sync].queued_requests, 1);
+            assert_eq!(metrics.classes[&EndpointClass::Media].queued_requests, 1);
+            assert_eq!(metrics.classes[&EndpointClass::Backfill].queued_requests, 2);
+        };
+
+        // The requests are queued in the reverse order of their priority.
+        join!(
+            send(EndpointClass::Backfill),
+            send(EndpointClass::Media),
+            send(EndpointClass::Backfill),
+            send(EndpointClass::Sync),
+            check_queue,
+        );
+
+        assert!(start.elapsed() >= delay);
+        assert_eq!(
+            *released.lock().unwrap(),
+            [
+                EndpointClass::Sync,
+                EndpointClass::Media,
+                EndpointClass::Backfill,
+                EndpointClass::Backfill
+            ]
+        );
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.classes[&EndpointClass::Backfill].delayed_requests, 2);
+        assert_eq!(metrics.classes[&EndpointClass::Backfill].queued_requests, 0);
+    }
+}
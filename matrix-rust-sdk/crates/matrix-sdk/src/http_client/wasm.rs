@@ -19,7 +19,10 @@ use bytesize::ByteSize;
 use eyeball::SharedObservable;
 use ruma::api::{error::FromHttpResponseError, IncomingResponse, OutgoingRequest};
 
-use super::{response_to_http_response, HttpClient, TransmissionProgress};
+use super::{
+    metrics::endpoint_name, response_to_http_response, EndpointClass, HttpClient,
+    TransmissionProgress,
+};
 use crate::{config::RequestConfig, error::HttpError};
 
 impl HttpClient {
@@ -33,17 +36,40 @@ impl HttpClient {
         R: OutgoingRequest + Debug,
         HttpError: From<FromHttpResponseError<R::EndpointError>>,
     {
+        let endpoint_class = EndpointClass::from_path(request.uri().path());
+        let endpoint = endpoint_name::<R>();
+        self.rate_limiter.wait(endpoint_class).await;
+
+        // The slot is only taken once the request isn't held back anymore, so a request
+        // that's held back because of rate limiting doesn't take a slot from the
+        // requests that can be sent.
+        let _permit = self.concurrent_request_semaphore.acquire().await;
+
         tracing::debug!("Sending request");
 
-        let request = reqwest::Request::try_from(request)?;
-        let response = response_to_http_response(self.inner.execute(request).await?).await?;
+        tracing::Span::current().record("attempt", 1);
+        self.metrics.on_attempt(&endpoint, 1, request.body().len());
+
+        let result: Result<_, HttpError> = async {
+            let request = reqwest::Request::try_from(request)?;
+            let response = response_to_http_response(self.inner.execute(request).await?).await?;
+
+            self.metrics.on_response(&endpoint, response.body().len());
+
+            let status_code = response.status();
+            let response_size = ByteSize(response.body().len().try_into().unwrap_or(u64::MAX));
+            tracing::Span::current()
+                .record("status", status_code.as_u16())
+                .record("response_size", response_size.to_string_as(true));
+
+            Ok(R::IncomingResponse::try_from_http_response(response)?)
+        }
+        .await;
 
-        let status_code = response.status();
-        let response_size = ByteSize(response.body().len().try_into().unwrap_or(u64::MAX));
-        tracing::Span::current()
-            .record("status", status_code.as_u16())
-            .record("response_size", response_size.to_string_as(true));
+        if let Err(error) = &result {
+            self.rate_limiter.on_error(endpoint_class, error);
+        }
 
-        Ok(R::IncomingResponse::try_from_http_response(response)?)
+        result
     }
 }
@@ -50,6 +50,7 @@ pub mod media;
 pub mod notification_settings;
 #[cfg(feature = "experimental-oidc")]
 pub mod oidc;
+pub mod presence;
 pub mod pusher;
 pub mod room;
 pub mod room_directory_search;
@@ -64,8 +65,11 @@ pub mod futures {
 #[cfg(feature = "experimental-sliding-sync")]
 pub mod sliding_sync;
 pub mod sync;
+pub mod sync_checkpoints;
 #[cfg(feature = "experimental-widgets")]
 pub mod widget;
+#[cfg(feature = "sqlite")]
+mod wipe;
 
 pub use account::Account;
 pub use authentication::{AuthApi, AuthSession, SessionTokens};
@@ -76,12 +80,18 @@ pub use error::{
     Error, HttpError, HttpResult, NotificationSettingsError, RefreshTokenError, Result,
     RumaApiError,
 };
-pub use http_client::TransmissionProgress;
+#[cfg(all(not(target_arch = "wasm32"), feature = "rustls-tls"))]
+pub use http_client::{CertificatePin, InvalidCertificatePin};
+pub use http_client::{
+    ClientMetrics, EndpointClass, EndpointMetrics, EndpointRateLimitMetrics, ErrorCategory,
+    LatencyHistogram, RateLimitMetrics, TransmissionProgress,
+};
 #[cfg(all(feature = "e2e-encryption", feature = "sqlite"))]
 pub use matrix_sdk_sqlite::SqliteCryptoStore;
 #[cfg(feature = "sqlite")]
 pub use matrix_sdk_sqlite::{SqliteEventCacheStore, SqliteStateStore};
 pub use media::Media;
+pub use presence::Presence;
 pub use pusher::Pusher;
 pub use room::Room;
 pub use ruma::{IdParseError, OwnedServerName, ServerName};
@@ -90,6 +100,8 @@ pub use sliding_sync::{
     SlidingSync, SlidingSyncBuilder, SlidingSyncList, SlidingSyncListBuilder,
     SlidingSyncListLoadingState, SlidingSyncMode, SlidingSyncRoom, UpdateSummary,
 };
+#[cfg(feature = "sqlite")]
+pub use wipe::WipeSummary;
 
 #[cfg(feature = "uniffi")]
 uniffi::setup_scaffolding!();
@@ -0,0 +1,565 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! High-level presence API.
+//!
+//! The presence of other users is received through the `/sync` responses, and
+//! can be requested explicitly with [`Presence::fetch`]. The latest known
+//! presence of each user is cached in the state store.
+//!
+//! Sliding sync doesn't include presence by default. It can be received
+//! through the presence extension, returned by
+//! [`Presence::sliding_sync_extension`], if the server supports it. Otherwise,
+//! clients using sliding sync need to call [`Presence::fetch`] to get the
+//! presence of other users.
+
+use std::{collections::BTreeMap, sync::Mutex as StdMutex, time::Duration};
+
+use eyeball::{SharedObservable, Subscriber};
+use matrix_sdk_base::{store::PRESENCE_RECEIVED_AT_FIELD, StateChanges};
+use matrix_sdk_common::executor::spawn;
+use ruma::{
+    api::client::presence::{get_presence, set_presence},
+    events::presence::{PresenceEvent, PresenceEventContent},
+    presence::PresenceState,
+    serde::{JsonObject, Raw},
+    MilliSecondsSinceUnixEpoch, OwnedUserId, UInt, UserId,
+};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use tokio::sync::mpsc;
+use tracing::{trace, warn};
+
+use crate::{sliding_sync::SlidingSyncExtension, Client, Error, Result};
+
+/// The presence of a user, as last known by the client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserPresence {
+    /// The presence state of the user.
+    pub state: PresenceState,
+
+    /// The status message of the user, if they set one.
+    pub status_msg: Option<String>,
+
+    /// Whether the user is currently active.
+    pub currently_active: Option<bool>,
+
+    /// How long ago the user was last active when the presence was received,
+    /// if known.
+    last_active_ago: Option<Duration>,
+
+    /// When the presence was received, if known.
+    received_at: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl UserPresence {
+    /// Create a [`UserPresence`] from the content of a presence event received
+    /// at the given time.
+    fn from_content(
+        content: &PresenceEventContent,
+        received_at: Option<MilliSecondsSinceUnixEpoch>,
+    ) -> Self {
+        Self {
+            state: content.presence.clone(),
+            status_msg: content.status_msg.clone(),
+            currently_active: content.currently_active,
+            last_active_ago: content.last_active_ago.map(|ago| Duration::from_millis(ago.into())),
+            received_at,
+        }
+    }
+
+    /// The time at which the user was last active, if known.
+    ///
+    /// It's unknown for a presence saved in the state store by an older version
+    /// of the SDK, which didn't save the time at which it was received.
+    pub fn last_active(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        let received_at = self.received_at?;
+        let last_active_ago = u64::try_from(self.last_active_ago?.as_millis()).ok()?;
+        Some(MilliSecondsSinceUnixEpoch(
+            received_at.0.saturating_sub(UInt::new_saturating(last_active_ago)),
+        ))
+    }
+
+    /// How long ago the user was last active, if known.
+    ///
+    /// The time elapsed since the presence was received is added to the value
+    /// reported by the server, so it grows over time until a new presence is
+    /// received for the user. Like [`UserPresence::last_active`], it's unknown
+    /// if the time at which the presence was received is unknown.
+    pub fn last_active_ago(&self) -> Option<Duration> {
+        let last_active_ago = self.last_active_ago?;
+        let received_at = self.received_at?;
+
+        let elapsed = MilliSecondsSinceUnixEpoch::now().0.saturating_sub(received_at.0);
+        Some(last_active_ago + Duration::from_millis(elapsed.into()))
+    }
+}
+
+/// Data related to the presence of users, shared by all the [`Presence`]
+/// handles of a client.
+#[derive(Default)]
+pub(crate) struct PresenceData {
+    /// The observable presence of the users someone subscribed to.
+    ///
+    /// The observables without subscribers are removed when subscribing to the
+    /// presence of a user.
+    observables: StdMutex<BTreeMap<OwnedUserId, SharedObservable<Option<UserPresence>>>>,
+}
+
+/// A high-level API to manage the presence of the current user, and observe
+/// the presence of other users.
+#[derive(Debug, Clone)]
+pub struct Presence {
+    /// The underlying client.
+    client: Client,
+}
+
+impl Presence {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Set the presence state and status message of the current user.
+    ///
+    /// Note that a sync loop also sets the presence of the current user, as
+    /// configured with [`SyncSettings::set_presence`].
+    ///
+    /// [`SyncSettings::set_presence`]: crate::config::SyncSettings::set_presence
+    pub async fn set(&self, state: PresenceState, status_msg: Option<String>) -> Result<()> {
+        let user_id = self.client.user_id().ok_or(Error::AuthenticationRequired)?;
+
+        let mut request = set_presence::v3::Request::new(user_id.to_owned(), state.clone());
+        request.status_msg = status_msg.clone();
+        self.client.send(request, None).await?;
+
+        let mut content = PresenceEventContent::new(state);
+        content.status_msg = status_msg;
+        content.currently_active = Some(true);
+        content.last_active_ago = Some(UInt::MIN);
+        self.update(user_id, &content);
+
+        Ok(())
+    }
+
+    /// Get the latest known presence of the given user, from the state store.
+    ///
+    /// Use [`Presence::fetch`] to get an up-to-date presence.
+    pub async fn get(&self, user_id: &UserId) -> Result<Option<UserPresence>> {
+        let Some(raw_event) = self.client.store().get_presence_event(user_id).await? else {
+            return Ok(None);
+        };
+        let Ok(event) = raw_event.deserialize() else {
+            return Ok(None);
+        };
+
+        let received_at = raw_event
+            .get_field::<MilliSecondsSinceUnixEpoch>(PRESENCE_RECEIVED_AT_FIELD)
+            .ok()
+            .flatten();
+        Ok(Some(UserPresence::from_content(&event.content, received_at)))
+    }
+
+    /// Fetch the presence of the given user from the server.
+    ///
+    /// The presence is saved in the state store, and the subscribers to the
+    /// presence of this user are notified.
+    pub async fn fetch(&self, user_id: &UserId) -> Result<UserPresence> {
+        let request = get_presence::v3::Request::new(user_id.to_owned());
+        let response = self.client.send(request, None).await?;
+
+        let mut content = PresenceEventContent::new(response.presence);
+        content.status_msg = response.status_msg;
+        content.currently_active = response.currently_active;
+        content.last_active_ago = response
+            .last_active_ago
+            .and_then(|last_active_ago| u64::try_from(last_active_ago.as_millis()).ok())
+            .and_then(UInt::new);
+
+        let event = PresenceEvent { content, sender: user_id.to_owned() };
+        let mut changes = StateChanges::default();
+        changes.add_received_presence_event(Raw::new(&event)?);
+        self.client.store().save_changes(&changes).await?;
+
+        Ok(self.update(user_id, &event.content))
+    }
+
+    /// Subscribe to the presence of the given user.
+    ///
+    /// The initial value is the latest known presence of the user, if any. The
+    /// subscriber is then updated with the presence received through the sync,
+    /// or fetched with [`Presence::fetch`].
+    ///
+    /// With sliding sync, the presence is only received if the
+    /// [presence extension](Self::sliding_sync_extension) is enabled.
+    pub async fn subscribe(&self, user_id: &UserId) -> Result<Subscriber<Option<UserPresence>>> {
+        if let Some(observable) = self.observable(user_id) {
+            return Ok(observable.subscribe());
+        }
+
+        let presence = self.get(user_id).await?;
+
+        let mut observables = self.client.inner.presence_data.observables.lock().unwrap();
+        observables.retain(|_, observable| observable.subscriber_count() > 0);
+
+        let observable = observables
+            .entry(user_id.to_owned())
+            .or_insert_with(|| SharedObservable::new(presence));
+
+        Ok(observable.subscribe())
+    }
+
+    /// Get the sliding sync extension receiving the presence of other users.
+    ///
+    /// The presence extension isn't part of MSC4186 yet, so the servers that
+    /// don't support it ignore it. The received presence is cached in the
+    /// state store, and then the subscribers are notified, like with `/sync`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async {
+    /// # let client: matrix_sdk::Client = unimplemented!();
+    /// let sliding_sync = client
+    ///     .sliding_sync("main")?
+    ///     .with_custom_extension(client.presence().sliding_sync_extension())
+    ///     .build()
+    ///     .await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn sliding_sync_extension(&self) -> PresenceExtension {
+        let (events_sender, events_receiver) = mpsc::unbounded_channel();
+
+        PresenceExtension {
+            presence: self.clone(),
+            events_sender,
+            events_receiver: StdMutex::new(Some(events_receiver)),
+        }
+    }
+
+    /// Handle the presence events received in a sync response, notifying the
+    /// subscribers to the presence of their senders.
+    pub(crate) fn handle_sync_events(&self, events: &[Raw<PresenceEvent>]) {
+        for raw_event in events {
+            match raw_event.deserialize() {
+                Ok(event) => {
+                    if self.observable(&event.sender).is_some() {
+                        self.update(&event.sender, &event.content);
+                    }
+                }
+                Err(error) => {
+                    warn!(?error, "Couldn't deserialize a presence event");
+                }
+            }
+        }
+    }
+
+    fn observable(&self, user_id: &UserId) -> Option<SharedObservable<Option<UserPresence>>> {
+        self.client.inner.presence_data.observables.lock().unwrap().get(user_id).cloned()
+    }
+
+    /// Update the observed presence of the given user, if there's a
+    /// subscriber for it.
+    fn update(&self, user_id: &UserId, content: &PresenceEventContent) -> UserPresence {
+        let presence = UserPresence::from_content(content, Some(MilliSecondsSinceUnixEpoch::now()));
+
+        if let Some(observable) = self.observable(user_id) {
+            trace!(%user_id, state = ?presence.state, "Updating the presence of a user");
+            observable.set(Some(presence.clone()));
+        }
+
+        presence
+    }
+
+    /// Save the presence events received through sliding sync in the state
+    /// store.
+    ///
+    /// The presence events received through `/sync` are saved by the base
+    /// client.
+    async fn save_events(&self, events: Vec<Raw<PresenceEvent>>) -> Result<()> {
+        let mut changes = StateChanges::default();
+
+        for raw_event in events {
+            changes.add_received_presence_event(raw_event);
+        }
+
+        if !changes.presence.is_empty() {
+            self.client.store().save_changes(&changes).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The sliding sync extension receiving the presence of other users.
+///
+/// It's created with [`Presence::sliding_sync_extension`], and must be added
+/// with [`SlidingSyncBuilder::with_custom_extension`].
+///
+/// [`SlidingSyncBuilder::with_custom_extension`]: crate::SlidingSyncBuilder::with_custom_extension
+#[derive(Debug)]
+pub struct PresenceExtension {
+    presence: Presence,
+
+    /// Sends the received presence events to the task saving them in the
+    /// state store.
+    events_sender: mpsc::UnboundedSender<Vec<Raw<PresenceEvent>>>,
+
+    /// The receiver of the presence events, until the task saving them is
+    /// spawned from the sync loop, when the first response is handled.
+    events_receiver: StdMutex<Option<mpsc::UnboundedReceiver<Vec<Raw<PresenceEvent>>>>>,
+}
+
+impl PresenceExtension {
+    /// Spawn the task saving the received presence events, if it isn't
+    /// running yet.
+    fn spawn_save_task(&self) {
+        let Some(mut events_receiver) = self.events_receiver.lock().unwrap().take() else {
+            return;
+        };
+
+        // The events are saved by a single task, so they're saved in the order
+        // they were received, and an older presence can't overwrite a newer one.
+        let presence = self.presence.clone();
+        spawn(async move {
+            while let Some(events) = events_receiver.recv().await {
+                if let Err(error) = presence.save_events(events.clone()).await {
+                    warn!("Couldn't save the presence received through sliding sync: {error}");
+                }
+
+                presence.handle_sync_events(&events);
+            }
+        });
+    }
+}
+
+/// The response of the sliding sync presence extension.
+#[derive(Debug, Default, Deserialize)]
+#[non_exhaustive]
+pub struct PresenceExtensionResponse {
+    /// The presence events of the users that the current user shares a room
+    /// with.
+    #[serde(default)]
+    pub events: Vec<Raw<PresenceEvent>>,
+}
+
+impl SlidingSyncExtension for PresenceExtension {
+    type Config = JsonValue;
+    type Response = PresenceExtensionResponse;
+    type State = JsonObject;
+
+    fn name(&self) -> &str {
+        "presence"
+    }
+
+    fn config(&self) -> Self::Config {
+        json!({ "enabled": true })
+    }
+
+    fn handle_response(&self, response: Self::Response, _state: &mut Self::State) {
+        if response.events.is_empty() {
+            return;
+        }
+
+        self.spawn_save_task();
+        let _ = self.events_sender.send(response.events);
+    }
+}
+
+// The http mocking library is not supported for wasm32
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::time::Duration;
+
+    use assert_matches2::assert_let;
+    use futures_util::{pin_mut, FutureExt, StreamExt};
+    use matrix_sdk_test::{async_test, test_json, PresenceTestEvent, SyncResponseBuilder};
+    use ruma::{presence::PresenceState, user_id};
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{config::SyncSettings, test_utils::logged_in_client};
+
+    #[async_test]
+    async fn test_set_presence() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        Mock::given(method("PUT"))
+            .and(path("/_matrix/client/r0/presence/@example:localhost/status"))
+            .and(body_json(json!({ "presence": "unavailable", "status_msg": "Lunch" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let presence = client.presence();
+        let mut subscriber = presence.subscribe(client.user_id().unwrap()).await.unwrap();
+        assert!(subscriber.get().is_none());
+
+        presence.set(PresenceState::Unavailable, Some("Lunch".to_owned())).await.unwrap();
+
+        // Our own presence has been updated.
+        assert_let!(Some(Some(Some(own_presence))) = subscriber.next().now_or_never());
+        assert_eq!(own_presence.state, PresenceState::Unavailable);
+        assert_eq!(own_presence.status_msg.as_deref(), Some("Lunch"));
+    }
+
+    #[async_test]
+    async fn test_fetch_presence() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let user_id = user_id!("@bob:localhost");
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/presence/@bob:localhost/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "presence": "online",
+                "last_active_ago": 60_000,
+                "currently_active": true,
+                "status_msg": "Making cupcakes",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let presence = client.presence();
+        assert!(presence.get(user_id).await.unwrap().is_none());
+
+        let mut subscriber = presence.subscribe(user_id).await.unwrap();
+        let fetched = presence.fetch(user_id).await.unwrap();
+
+        assert_eq!(fetched.state, PresenceState::Online);
+        assert_eq!(fetched.status_msg.as_deref(), Some("Making cupcakes"));
+        assert_eq!(fetched.currently_active, Some(true));
+        assert!(fetched.last_active_ago().unwrap() >= Duration::from_secs(60));
+
+        // The subscriber has been notified.
+        assert_let!(Some(Some(Some(observed))) = subscriber.next().now_or_never());
+        assert_eq!(observed, fetched);
+
+        // And the presence has been cached.
+        let cached = presence.get(user_id).await.unwrap().unwrap();
+        assert_eq!(cached.state, PresenceState::Online);
+
+        // With the time at which it was received.
+        assert!(cached.last_active().is_some());
+        assert!(cached.last_active_ago().unwrap() >= Duration::from_secs(60));
+    }
+
+    #[async_test]
+    async fn test_unobserved_presence_is_removed() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let presence = client.presence();
+
+        let subscriber = presence.subscribe(user_id!("@bob:localhost")).await.unwrap();
+        drop(subscriber);
+
+        let _subscriber = presence.subscribe(user_id!("@alice:localhost")).await.unwrap();
+
+        // The presence of Bob isn't observed anymore.
+        let observables = client.inner.presence_data.observables.lock().unwrap();
+        assert_eq!(observables.keys().collect::<Vec<_>>(), [user_id!("@alice:localhost")]);
+    }
+
+    #[async_test]
+    async fn test_presence_from_sync() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let user_id = user_id!("@example:localhost");
+
+        let mut subscriber = client.presence().subscribe(user_id).await.unwrap();
+        assert!(subscriber.get().is_none());
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        sync_builder.add_presence_event(PresenceTestEvent::Presence);
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(sync_builder.build_json_sync_response()),
+            )
+            .mount(&server)
+            .await;
+
+        client.sync_once(SyncSettings::default()).await.unwrap();
+
+        assert_let!(Some(Some(Some(observed))) = subscriber.next().now_or_never());
+        assert_eq!(observed.state, PresenceState::Online);
+        assert_eq!(observed.status_msg.as_deref(), Some("Making cupcakes"));
+        assert_eq!(observed.currently_active, Some(false));
+        assert!(observed.last_active().is_some());
+    }
+
+    #[async_test]
+    async fn test_presence_from_sliding_sync() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let user_id = user_id!("@example:localhost");
+
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "pos": "0",
+                "extensions": {
+                    "presence": {
+                        "events": [test_json::PRESENCE.to_owned()],
+                    },
+                },
+            })))
+            .mount(&server)
+            .await;
+
+        let presence = client.presence();
+        let mut subscriber = presence.subscribe(user_id).await.unwrap();
+
+        let sliding_sync = client
+            .sliding_sync("presence")
+            .unwrap()
+            .with_custom_extension(presence.sliding_sync_extension())
+            .build()
+            .await
+            .unwrap();
+
+        let sync = sliding_sync.sync();
+        pin_mut!(sync);
+        sync.next().await.unwrap().unwrap();
+
+        // The subscribers are notified once the presence is cached in the background.
+        assert_let!(Some(Some(observed)) = subscriber.next().await);
+        assert_eq!(observed.state, PresenceState::Online);
+        assert_eq!(observed.status_msg.as_deref(), Some("Making cupcakes"));
+
+        let cached = presence.get(user_id).await.unwrap().unwrap();
+        assert_eq!(cached.state, PresenceState::Online);
+        assert!(cached.last_active().is_some());
+    }
+
+    #[test]
+    fn test_presence_extension_without_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let client = runtime.block_on(logged_in_client(None));
+
+        // Creating the extension doesn't need a runtime.
+        let extension = client.presence().sliding_sync_extension();
+
+        runtime.block_on(async move {
+            drop(extension);
+            drop(client);
+        });
+    }
+}
@@ -16,6 +16,8 @@
 
 #![deny(unreachable_pub)]
 
+#[cfg(feature = "e2e-encryption")]
+use std::collections::BTreeMap;
 use std::future::IntoFuture;
 
 use eyeball::SharedObservable;
@@ -30,6 +32,8 @@ use ruma::{
     serde::Raw,
     OwnedTransactionId, TransactionId,
 };
+#[cfg(feature = "e2e-encryption")]
+use ruma::{OwnedDeviceId, OwnedUserId};
 use tracing::{info, trace, Instrument, Span};
 
 use super::Room;
@@ -107,6 +111,8 @@ pub struct SendRawMessageLikeEvent<'a> {
     tracing_span: Span,
     transaction_id: Option<OwnedTransactionId>,
     request_config: Option<RequestConfig>,
+    #[cfg(feature = "e2e-encryption")]
+    ignored_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
 }
 
 impl<'a> SendRawMessageLikeEvent<'a> {
@@ -123,6 +129,8 @@ impl<'a> SendRawMessageLikeEvent<'a> {
             tracing_span: Span::current(),
             transaction_id: None,
             request_config: None,
+            #[cfg(feature = "e2e-encryption")]
+            ignored_devices: BTreeMap::new(),
         }
     }
 
@@ -151,6 +159,19 @@ impl<'a> SendRawMessageLikeEvent<'a> {
         self.request_config = Some(request_config);
         self
     }
+
+    /// Share the room key with the given devices as if their local trust was
+    /// [`LocalTrust::Ignored`], for this event only.
+    ///
+    /// [`LocalTrust::Ignored`]: crate::crypto::LocalTrust::Ignored
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) fn with_ignored_devices(
+        mut self,
+        ignored_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
+    ) -> Self {
+        self.ignored_devices = ignored_devices;
+        self
+    }
 }
 
 impl<'a> IntoFuture for SendRawMessageLikeEvent<'a> {
@@ -166,6 +187,8 @@ impl<'a> IntoFuture for SendRawMessageLikeEvent<'a> {
             tracing_span,
             transaction_id,
             request_config,
+            #[cfg(feature = "e2e-encryption")]
+            ignored_devices,
         } = self;
 
         let fut = async move {
@@ -201,7 +224,7 @@ impl<'a> IntoFuture for SendRawMessageLikeEvent<'a> {
                     // could have not query their keys ever.
                     room.query_keys_for_untracked_users().await?;
 
-                    room.preshare_room_key().await?;
+                    room.preshare_room_key(ignored_devices).await?;
 
                     let olm = room.client.olm_machine().await;
                     let olm = olm.as_ref().expect("Olm machine wasn't started");
@@ -15,15 +15,23 @@
 //! Facility to track changes to the identity of members of rooms.
 #![cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use async_stream::stream;
+use async_trait::async_trait;
 use futures_core::Stream;
 use futures_util::{stream_select, StreamExt};
-use matrix_sdk_base::crypto::{
-    IdentityState, IdentityStatusChange, RoomIdentityChange, RoomIdentityState,
+use matrix_sdk_base::{
+    crypto::{
+        IdentityState, IdentityStatusChange, RoomIdentityChange, RoomIdentityProvider,
+        RoomIdentityState, UserIdentity as CryptoUserIdentity,
+    },
+    RoomMemberships, RoomStateFilter,
+};
+use ruma::{
+    events::room::member::{MembershipState, SyncRoomMemberEvent},
+    OwnedUserId, UserId,
 };
-use ruma::{events::room::member::SyncRoomMemberEvent, OwnedUserId, UserId};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
@@ -122,6 +130,141 @@ impl IdentityStatusChanges {
     }
 }
 
+/// Support for creating a stream of batches of [`IdentityStatusChange`] for
+/// all the users we share a room with.
+///
+/// This is the client-wide counterpart of [`IdentityStatusChanges`]: instead
+/// of the members of a single room, it follows the members of all the rooms
+/// we are joined to or invited to.
+///
+/// It does provide a method to create the stream:
+/// [`ClientIdentityStatusChanges::create_stream`].
+#[derive(Debug)]
+pub struct ClientIdentityStatusChanges {
+    /// Who we share a room with and who is in identity violation at this
+    /// moment
+    room_identity_state: RoomIdentityState<SharedRooms>,
+
+    /// Dropped when we are dropped, and unregisters the event handler we
+    /// registered to listen for membership events
+    _drop_guard: EventHandlerDropGuard,
+}
+
+impl ClientIdentityStatusChanges {
+    /// Create a new stream of significant changes to the identity status of
+    /// the users we share a room with.
+    ///
+    /// See [`IdentityStatusChanges::create_stream`] for what a significant
+    /// change is.
+    ///
+    /// The first item in the stream lists the users we share a room with who
+    /// are not in "pinned" or "verified" state, i.e. the identity changes that
+    /// haven't been acknowledged yet.
+    ///
+    /// A change is acknowledged by pinning the new identity of the user, or by
+    /// withdrawing the verification of their previous identity. Both are
+    /// persisted in the crypto store, so acknowledged changes aren't reported
+    /// again after a restart.
+    ///
+    /// Note: when a user in identity violation leaves the last room we share
+    /// with them, an update is generated stating that they have become pinned.
+    /// Leaving a room that we still share with them in another room is
+    /// ignored.
+    pub async fn create_stream(
+        client: Client,
+    ) -> Result<impl Stream<Item = Vec<IdentityStatusChange>>> {
+        let identity_updates = wrap_identity_updates(&client).await?;
+        let (drop_guard, room_member_events) = wrap_shared_room_member_events(&client);
+        let mut unprocessed_stream = combine_streams(identity_updates, room_member_events);
+        let own_user_id = client.user_id().ok_or(Error::InsufficientData)?.to_owned();
+
+        let mut state = ClientIdentityStatusChanges {
+            room_identity_state: RoomIdentityState::new(SharedRooms { client }).await,
+            _drop_guard: drop_guard,
+        };
+
+        Ok(stream!({
+            let mut current_state =
+                filter_for_initial_update(state.room_identity_state.current_state(), &own_user_id);
+
+            if !current_state.is_empty() {
+                current_state.sort();
+                yield current_state;
+            }
+
+            while let Some(item) = unprocessed_stream.next().await {
+                let mut update = filter_non_self(
+                    state.room_identity_state.process_change(item).await,
+                    &own_user_id,
+                );
+                if !update.is_empty() {
+                    update.sort();
+                    yield update;
+                }
+            }
+        }))
+    }
+}
+
+/// The users we share a joined or invited room with, seen as the members of a
+/// single room.
+///
+/// The members are read from the local store only: in rooms whose member list
+/// hasn't been loaded yet, members are only known once they send a membership
+/// event.
+#[derive(Debug)]
+struct SharedRooms {
+    client: Client,
+}
+
+impl SharedRooms {
+    fn rooms(&self) -> Vec<Room> {
+        self.client.rooms_filtered(RoomStateFilter::JOINED | RoomStateFilter::INVITED)
+    }
+}
+
+#[async_trait]
+impl RoomIdentityProvider for SharedRooms {
+    async fn is_member(&self, user_id: &UserId) -> bool {
+        for room in self.rooms() {
+            if let Ok(Some(member)) = room.get_member_no_sync(user_id).await {
+                if matches!(member.membership(), MembershipState::Join | MembershipState::Invite) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    async fn member_identities(&self) -> Vec<CryptoUserIdentity> {
+        let mut user_ids = BTreeSet::new();
+        for room in self.rooms() {
+            let members = room
+                .members_no_sync(RoomMemberships::JOIN | RoomMemberships::INVITE)
+                .await
+                .unwrap_or_default();
+            user_ids.extend(members.into_iter().map(|member| member.user_id().to_owned()));
+        }
+
+        let mut ret = Vec::new();
+        for user_id in user_ids {
+            if let Some(i) = self.user_identity(&user_id).await {
+                ret.push(i);
+            }
+        }
+        ret
+    }
+
+    async fn user_identity(&self, user_id: &UserId) -> Option<CryptoUserIdentity> {
+        self.client
+            .encryption()
+            .get_user_identity(user_id)
+            .await
+            .unwrap_or(None)
+            .map(|u| u.underlying_identity())
+    }
+}
+
 fn filter_for_initial_update(
     mut input: Vec<IdentityStatusChange>,
     own_user_id: &UserId,
@@ -192,6 +335,33 @@ fn wrap_room_member_events(
     (drop_guard, ReceiverStream::new(receiver))
 }
 
+fn wrap_shared_room_member_events(
+    client: &Client,
+) -> (EventHandlerDropGuard, impl Stream<Item = RoomIdentityChange>) {
+    let (sender, receiver) = mpsc::channel(16);
+    let handle = client.add_event_handler(move |event: SyncRoomMemberEvent, client: Client| {
+        let sender = sender.clone();
+        async move {
+            if client.user_id().is_some_and(|own_user_id| event.state_key() == own_user_id) {
+                return;
+            }
+
+            // Someone leaving a room only matters if it was the last room we
+            // shared with them.
+            let shared_rooms = SharedRooms { client };
+            if matches!(event.membership(), MembershipState::Leave | MembershipState::Ban)
+                && shared_rooms.is_member(event.state_key()).await
+            {
+                return;
+            }
+
+            let _: Result<_, _> = sender.send(RoomIdentityChange::SyncRoomMemberEvent(event)).await;
+        }
+    });
+    let drop_guard = client.event_handler_drop_guard(handle);
+    (drop_guard, ReceiverStream::new(receiver))
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -570,6 +740,106 @@ mod tests {
         assert_eq!(change.len(), 1);
     }
 
+    #[async_test]
+    async fn test_client_wide_changes_report_unacknowledged_changes_immediately() {
+        // Given a room containing Bob, who is unpinned
+        let t = TestSetup::new_room_with_other_bob().await;
+        t.unpin_bob().await;
+
+        // When we start listening for identity changes of the whole client
+        let changes = t.subscribe_to_client_identity_status_changes().await;
+
+        // Then we were immediately notified about Bob being unpinned
+        let change = next_change(&mut pin!(changes)).await;
+        assert_eq!(change[0].user_id, t.bob_user_id());
+        assert_eq!(change[0].changed_to, IdentityState::PinViolation);
+        assert_eq!(change.len(), 1);
+    }
+
+    #[async_test]
+    async fn test_client_wide_changes_report_pinning_the_identity() {
+        // Given a room containing Bob, who is unpinned
+        let t = TestSetup::new_room_with_other_bob().await;
+        t.unpin_bob().await;
+
+        // And we are listening for identity changes of the whole client
+        let changes = t.subscribe_to_client_identity_status_changes().await;
+        let mut changes = pin!(changes);
+        let change1 = next_change(&mut changes).await;
+
+        // When we acknowledge the change by pinning Bob's new identity
+        t.acknowledge_bob_by_pinning().await;
+
+        // Then the warning is removed
+        let change2 = next_change(&mut changes).await;
+        assert_eq!(change1[0].changed_to, IdentityState::PinViolation);
+        assert_eq!(change2[0].user_id, t.bob_user_id());
+        assert_eq!(change2[0].changed_to, IdentityState::Pinned);
+        assert_eq!(change2.len(), 1);
+
+        // And it is not reported again to a new subscriber
+        let changes = t.subscribe_to_client_identity_status_changes().await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(pin!(changes).next().now_or_never().is_none());
+    }
+
+    #[async_test]
+    async fn test_client_wide_changes_report_withdrawing_the_verification() {
+        // Given a room containing Bob, who is verified
+        let t = TestSetup::new_room_with_other_bob().await;
+        t.verify_bob().await;
+
+        // And we are listening for identity changes of the whole client
+        let changes = t.subscribe_to_client_identity_status_changes().await;
+        let mut changes = pin!(changes);
+
+        // When Bob's identity changes
+        t.unpin_bob().await;
+        let change1 = next_change(&mut changes).await;
+
+        // And we acknowledge it by withdrawing the verification
+        t.acknowledge_bob_by_withdrawing_verification().await;
+        let change2 = next_change(&mut changes).await;
+
+        // Then we were notified about the violation, and about its removal
+        assert_eq!(change1[0].user_id, t.bob_user_id());
+        assert_eq!(change1[0].changed_to, IdentityState::VerificationViolation);
+        assert_eq!(change2[0].user_id, t.bob_user_id());
+        assert_eq!(change2[0].changed_to, IdentityState::Pinned);
+    }
+
+    #[async_test]
+    async fn test_client_wide_changes_ignore_leaving_a_room_we_still_share() {
+        // Given two rooms containing us and Bob
+        let mut t = TestSetup::new_room_with_other_bob().await;
+        t.bob_joins_other_room().await;
+
+        // And Bob's identity is unpinned
+        t.unpin_bob().await;
+
+        // And we are listening for identity changes of the whole client
+        let changes = t.subscribe_to_client_identity_status_changes().await;
+        let mut changes = pin!(changes);
+        let change1 = next_change(&mut changes).await;
+
+        // When Bob leaves one of the rooms
+        t.bob_leaves_other_room().await;
+
+        // Then there is no notification, since we still share a room
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(changes.next().now_or_never().is_none());
+
+        // And when Bob leaves the last room we share
+        t.bob_leaves().await;
+
+        // Then the warning is removed
+        let change2 = next_change(&mut changes).await;
+        assert_eq!(change1[0].changed_to, IdentityState::PinViolation);
+        assert_eq!(change2[0].user_id, t.bob_user_id());
+        assert_eq!(change2[0].changed_to, IdentityState::Pinned);
+        assert_eq!(change2.len(), 1);
+    }
+
     // TODO: I (andyb) haven't figured out how to test room membership changes that
     // affect our own user (they should not be shown). Specifically, I haven't
     // figure out how to get out own user into a non-pinned state.
@@ -602,7 +872,7 @@ mod tests {
         use ruma::{
             api::client::keys::{get_keys, get_keys::v3::Response as KeyQueryResponse},
             events::room::member::MembershipState,
-            owned_user_id, OwnedUserId, TransactionId, UserId,
+            owned_user_id, room_id, OwnedUserId, RoomId, TransactionId, UserId,
         };
         use serde_json::json;
         use tokio_stream::{StreamExt as _, Timeout};
@@ -784,6 +1054,34 @@ mod tests {
                 self.bob_membership_change(MembershipState::Leave).await;
             }
 
+            pub(super) async fn bob_joins_other_room(&mut self) {
+                self.bob_membership_change_in(other_room_id(), MembershipState::Join).await;
+            }
+
+            pub(super) async fn bob_leaves_other_room(&mut self) {
+                self.bob_membership_change_in(other_room_id(), MembershipState::Leave).await;
+            }
+
+            pub(super) async fn acknowledge_bob_by_pinning(&self) {
+                let pinned = self
+                    .client
+                    .encryption()
+                    .pin_identity(&self.bob_user_id)
+                    .await
+                    .expect("Should not fail to pin");
+                assert!(pinned);
+            }
+
+            pub(super) async fn acknowledge_bob_by_withdrawing_verification(&self) {
+                let withdrawn = self
+                    .client
+                    .encryption()
+                    .withdraw_identity_verification(&self.bob_user_id)
+                    .await
+                    .expect("Should not fail to withdraw the verification");
+                assert!(withdrawn);
+            }
+
             pub(super) async fn subscribe_to_identity_status_changes(
                 &self,
             ) -> Timeout<impl Stream<Item = Vec<IdentityStatusChange>>> {
@@ -794,6 +1092,17 @@ mod tests {
                     .timeout(Duration::from_secs(5))
             }
 
+            pub(super) async fn subscribe_to_client_identity_status_changes(
+                &self,
+            ) -> Timeout<impl Stream<Item = Vec<IdentityStatusChange>>> {
+                self.client
+                    .encryption()
+                    .subscribe_to_identity_status_changes()
+                    .await
+                    .expect("Should be able to subscribe")
+                    .timeout(Duration::from_secs(5))
+            }
+
             async fn init() -> (Client, OwnedUserId, SyncResponseBuilder) {
                 let (client, _server) = create_client_and_server().await;
 
@@ -829,20 +1138,30 @@ mod tests {
             }
 
             async fn bob_membership_change(&mut self, new_state: MembershipState) {
+                self.bob_membership_change_in(&DEFAULT_TEST_ROOM_ID, new_state).await;
+            }
+
+            async fn bob_membership_change_in(
+                &mut self,
+                room_id: &RoomId,
+                new_state: MembershipState,
+            ) {
                 let sync_response = self
                     .sync_response_builder
-                    .add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_event(
+                    .add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
                         StateTestEvent::Custom(sync_response_member(
                             &self.bob_user_id,
                             new_state.clone(),
                         )),
                     ))
                     .build_sync_response();
-                self.room.client.process_sync(sync_response).await.unwrap();
+                self.client.process_sync(sync_response).await.unwrap();
 
                 // Make sure the membership stuck as expected
                 let m = self
-                    .room
+                    .client
+                    .get_room(room_id)
+                    .expect("Room should exist")
                     .get_member_no_sync(&self.bob_user_id)
                     .await
                     .expect("Should not fail to get member");
@@ -884,6 +1203,10 @@ mod tests {
             }
         }
 
+        fn other_room_id() -> &'static RoomId {
+            room_id!("!other_room:localhost")
+        }
+
         async fn create_just_me_room(
             client: &Client,
             sync_response_builder: &mut SyncResponseBuilder,
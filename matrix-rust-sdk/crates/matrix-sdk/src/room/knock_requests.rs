@@ -0,0 +1,74 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Requests from users to join a room, made by knocking on it.
+
+use ruma::{EventId, MilliSecondsSinceUnixEpoch, MxcUri, UserId};
+
+use crate::{room::RoomMember, Result, Room};
+
+/// A request from a user to join a room, made by knocking on it.
+///
+/// Users with the appropriate permissions can accept or deny it.
+#[derive(Debug, Clone)]
+pub struct KnockRequest {
+    room: Room,
+    member: RoomMember,
+}
+
+impl KnockRequest {
+    pub(crate) fn new(room: Room, member: RoomMember) -> Self {
+        Self { room, member }
+    }
+
+    /// The user who knocked on the room.
+    pub fn user_id(&self) -> &UserId {
+        self.member.user_id()
+    }
+
+    /// The display name of the user who knocked, if they set one.
+    pub fn display_name(&self) -> Option<&str> {
+        self.member.display_name()
+    }
+
+    /// The avatar of the user who knocked, if they set one.
+    pub fn avatar_url(&self) -> Option<&MxcUri> {
+        self.member.avatar_url()
+    }
+
+    /// The reason given by the user for joining the room, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.member.event().original_content().and_then(|content| content.reason.as_deref())
+    }
+
+    /// The ID of the membership event of the knock, if known.
+    pub fn event_id(&self) -> Option<&EventId> {
+        self.member.event().event_id()
+    }
+
+    /// The time at which the user knocked, if known.
+    pub fn timestamp(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.member.event().origin_server_ts()
+    }
+
+    /// Accept the request, by inviting the user to the room.
+    pub async fn accept(&self) -> Result<()> {
+        self.room.accept_knock(self.user_id()).await
+    }
+
+    /// Deny the request, with an optional reason.
+    pub async fn deny(&self, reason: Option<&str>) -> Result<()> {
+        self.room.deny_knock(self.user_id(), reason).await
+    }
+}
@@ -2,6 +2,8 @@ use std::ops::Deref;
 
 use ruma::events::room::MediaSource;
 
+#[cfg(feature = "e2e-encryption")]
+use crate::encryption::{identities::RequestVerificationError, verification::VerificationRequest};
 use crate::{
     media::{MediaFormat, MediaRequestParameters},
     BaseRoomMember, Client, Result,
@@ -89,6 +91,52 @@ impl RoomMember {
     pub fn suggested_role_for_power_level(&self) -> RoomMemberRole {
         RoomMemberRole::suggested_role_for_power_level(self.power_level())
     }
+
+    /// Request an interactive verification with this member.
+    ///
+    /// The identity of the member is fetched from the homeserver if we don't
+    /// know it yet. The verification request is then sent in the DM we share
+    /// with the member, which is created if there is none, like
+    /// [`UserIdentity::request_verification()`] does.
+    ///
+    /// Returns a [`VerificationRequest`] object that can be used to control
+    /// the verification flow.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{ruma::{room_id, user_id}, Client};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// let room = client.get_room(room_id!("!roomid:example.com")).unwrap();
+    ///
+    /// if let Some(member) =
+    ///     room.get_member(user_id!("@alice:example.org")).await?
+    /// {
+    ///     let request = member.request_verification().await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [`UserIdentity::request_verification()`]: crate::encryption::identities::UserIdentity::request_verification
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn request_verification(
+        &self,
+    ) -> Result<VerificationRequest, RequestVerificationError> {
+        let encryption = self.client.encryption();
+        let user_id = self.inner.user_id();
+
+        let mut identity =
+            encryption.get_user_identity(user_id).await.map_err(crate::Error::from)?;
+        if identity.is_none() {
+            identity = encryption.request_user_identity(user_id).await?;
+        }
+        let Some(identity) = identity else {
+            return Err(RequestVerificationError::MissingIdentity(user_id.to_owned()));
+        };
+
+        identity.request_verification().await
+    }
 }
 
 /// The role of a member in a room.
@@ -24,7 +24,7 @@ use std::{
 
 #[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
 use async_trait::async_trait;
-use eyeball::SharedObservable;
+use eyeball::{SharedObservable, Subscriber};
 use futures_core::Stream;
 use futures_util::{
     future::{try_join, try_join_all},
@@ -32,9 +32,9 @@ use futures_util::{
 };
 use http::StatusCode;
 #[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
-pub use identity_status_changes::IdentityStatusChanges;
+pub use identity_status_changes::{ClientIdentityStatusChanges, IdentityStatusChanges};
 #[cfg(feature = "e2e-encryption")]
-use matrix_sdk_base::crypto::{DecryptionSettings, RoomEventDecryptionResult};
+use matrix_sdk_base::crypto::{CollectStrategy, DecryptionSettings, RoomEventDecryptionResult};
 #[cfg(all(feature = "e2e-encryption", not(target_arch = "wasm32")))]
 use matrix_sdk_base::crypto::{IdentityStatusChange, RoomIdentityProvider, UserIdentity};
 use matrix_sdk_base::{
@@ -80,6 +80,8 @@ use ruma::{
             avatar::{self, RoomAvatarEventContent},
             encryption::RoomEncryptionEventContent,
             history_visibility::HistoryVisibility,
+            join_rules::{JoinRule, RoomJoinRulesEventContent},
+            member::SyncRoomMemberEvent,
             message::{
                 AudioInfo, AudioMessageEventContent, FileInfo, FileMessageEventContent,
                 FormattedBody, ImageMessageEventContent, MessageType, RoomMessageEventContent,
@@ -114,7 +116,7 @@ use ruma::{
         room::encrypted::OriginalSyncRoomEncryptedEvent, AnySyncMessageLikeEvent,
         AnySyncTimelineEvent, SyncMessageLikeEvent,
     },
-    MilliSecondsSinceUnixEpoch,
+    MilliSecondsSinceUnixEpoch, OwnedDeviceId,
 };
 use serde::de::DeserializeOwned;
 use thiserror::Error;
@@ -123,6 +125,7 @@ use tracing::{debug, info, instrument, warn};
 
 use self::futures::{SendAttachment, SendMessageLikeEvent, SendRawMessageLikeEvent};
 pub use self::{
+    knock_requests::KnockRequest,
     member::{RoomMember, RoomMemberRole},
     messages::{EventWithContextResponse, Messages, MessagesOptions},
 };
@@ -143,11 +146,17 @@ use crate::{
     BaseRoom, Client, Error, HttpResult, Result, RoomState, TransmissionProgress,
 };
 #[cfg(feature = "e2e-encryption")]
-use crate::{crypto::types::events::CryptoContextInfo, encryption::backups::BackupState};
+use crate::{
+    crypto::types::events::CryptoContextInfo,
+    encryption::{
+        backups::BackupState, inspector::OutboundSessionInspection, RoomKeyRotationPolicy,
+    },
+};
 
 pub mod edit;
 pub mod futures;
 pub mod identity_status_changes;
+mod knock_requests;
 mod member;
 mod messages;
 pub mod power_levels;
@@ -216,8 +225,35 @@ impl Room {
                 false
             });
 
+        // Room keys shared with us with the invite are only accepted once we've
+        // joined, and only if they come from the user that invited us.
+        #[cfg(feature = "e2e-encryption")]
+        let inviter = if prev_room_state == RoomState::Invited {
+            match self.get_member_no_sync(self.own_user_id()).await {
+                Ok(member) => member.map(|member| member.event().sender().to_owned()),
+                Err(e) => {
+                    warn!(room_id = ?self.room_id(), "Couldn't load our invite: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
         self.client.join_room_by_id(self.room_id()).await?;
 
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(inviter) = inviter {
+            if let Some(olm) = self.client.olm_machine().await.as_ref() {
+                if let Err(e) = olm.accept_room_key_history(self.room_id(), &inviter).await {
+                    warn!(
+                        room_id = ?self.room_id(),
+                        "Couldn't accept the shared room key history: {e}"
+                    );
+                }
+            }
+        }
+
         if mark_as_direct {
             self.set_is_direct(true).await?;
         }
@@ -1303,6 +1339,77 @@ impl Room {
         }
     }
 
+    /// Get the local policy controlling when the room key of this room is
+    /// rotated.
+    ///
+    /// Returns the default policy, which doesn't add anything to the room's
+    /// encryption settings, if none was set with
+    /// [`Self::set_room_key_rotation_policy`].
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn room_key_rotation_policy(&self) -> Result<RoomKeyRotationPolicy> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(machine.room_key_rotation_policy(self.room_id()).await?)
+    }
+
+    /// Set the local policy controlling when the room key of this room is
+    /// rotated.
+    ///
+    /// The policy is persisted in the crypto store and can only make the
+    /// rotation stricter than what the room's encryption settings ask for. It's
+    /// taken into account the next time a message is sent in the room.
+    ///
+    /// Returns [`Error::SetRoomSettings`] if one of the rotation periods of the
+    /// policy is zero.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn set_room_key_rotation_policy(&self, policy: RoomKeyRotationPolicy) -> Result<()> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        machine.set_room_key_rotation_policy(self.room_id(), policy).await?;
+        Ok(())
+    }
+
+    /// Get information about the room key currently used to encrypt the
+    /// messages sent in this room: its age, how many messages it encrypted and
+    /// who received it.
+    ///
+    /// Returns `None` if no room key was created yet.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn outbound_session_info(&self) -> Result<Option<OutboundSessionInspection>> {
+        Ok(self.client.encryption().inspector().room(self.room_id()).await?.outbound_session)
+    }
+
+    /// Get the strategy used to pick the devices that receive the room keys,
+    /// when sending encrypted messages in this room.
+    ///
+    /// This is the strategy set with [`Self::set_room_key_recipient_strategy`]
+    /// if any, or the one set for the whole client with
+    /// [`ClientBuilder::with_room_key_recipient_strategy`] otherwise.
+    ///
+    /// [`ClientBuilder::with_room_key_recipient_strategy`]: crate::ClientBuilder::with_room_key_recipient_strategy
+    #[cfg(feature = "e2e-encryption")]
+    pub fn room_key_recipient_strategy(&self) -> CollectStrategy {
+        self.client.base_client().room_key_recipient_strategy_for_room(self.room_id())
+    }
+
+    /// Set the strategy used to pick the devices that receive the room keys,
+    /// when sending encrypted messages in this room, overriding the one set
+    /// for the whole client.
+    ///
+    /// Passing `None` goes back to using the client-wide strategy. The
+    /// override is only kept in memory.
+    ///
+    /// If the new strategy excludes devices that already received the current
+    /// room key, the room key will be rotated before sending the next message.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn set_room_key_recipient_strategy(&self, strategy: Option<CollectStrategy>) {
+        self.client
+            .base_client()
+            .set_room_key_recipient_strategy_for_room(self.room_id(), strategy);
+    }
+
     /// Ban the user with `UserId` from this room.
     ///
     /// # Arguments
@@ -1371,6 +1478,17 @@ impl Room {
         // but before the /sync request could fetch the membership change event.
         self.mark_members_missing();
 
+        #[cfg(feature = "e2e-encryption")]
+        if self.client.encryption().settings().share_room_key_history_on_invite
+            && self.is_encrypted().await?
+        {
+            // The invite went through, failing to share the history shouldn't
+            // be reported as a failure to invite.
+            if let Err(error) = self.share_room_key_history(user_id).await {
+                warn!(%user_id, "Couldn't share the room key history with an invited user: {error}");
+            }
+        }
+
         Ok(())
     }
 
@@ -1393,6 +1511,74 @@ impl Room {
         Ok(())
     }
 
+    /// Get the pending requests to join this room, made by users knocking on
+    /// it.
+    ///
+    /// *Note*: This method will fetch the members from the homeserver if the
+    /// member list isn't synchronized due to member lazy loading.
+    pub async fn knock_requests(&self) -> Result<Vec<KnockRequest>> {
+        Ok(self
+            .members(RoomMemberships::KNOCK)
+            .await?
+            .into_iter()
+            .map(|member| KnockRequest::new(self.clone(), member))
+            .collect())
+    }
+
+    /// Subscribe to the pending requests to join this room.
+    ///
+    /// The initial value of the returned subscriber is the current list of
+    /// requests, as returned by [`Room::knock_requests`]. It's then updated
+    /// every time a sync response contains a membership change for this room,
+    /// for as long as the returned [`EventHandlerDropGuard`] is alive.
+    pub async fn subscribe_to_knock_requests(
+        &self,
+    ) -> Result<(EventHandlerDropGuard, Subscriber<Vec<KnockRequest>>)> {
+        let knock_requests = SharedObservable::new(self.knock_requests().await?);
+        let subscriber = knock_requests.subscribe();
+
+        let handle = self.client.add_room_event_handler(
+            self.room_id(),
+            move |_: SyncRoomMemberEvent, room: Room| {
+                let knock_requests = knock_requests.clone();
+                async move {
+                    match room.members_no_sync(RoomMemberships::KNOCK).await {
+                        Ok(members) => {
+                            knock_requests.set(
+                                members
+                                    .into_iter()
+                                    .map(|member| KnockRequest::new(room.clone(), member))
+                                    .collect(),
+                            );
+                        }
+                        Err(error) => warn!("Couldn't load the knock requests: {error}"),
+                    }
+                }
+            },
+        );
+
+        Ok((self.client.event_handler_drop_guard(handle), subscriber))
+    }
+
+    /// Accept the request of the given user to join this room, by inviting
+    /// them.
+    #[instrument(skip_all)]
+    pub async fn accept_knock(&self, user_id: &UserId) -> Result<()> {
+        self.invite_user_by_id(user_id).await
+    }
+
+    /// Deny the request of the given user to join this room.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The `UserId` of the user who knocked on the room.
+    ///
+    /// * `reason` - Optional reason why the request is denied.
+    #[instrument(skip_all)]
+    pub async fn deny_knock(&self, user_id: &UserId, reason: Option<&str>) -> Result<()> {
+        self.kick_user(user_id, reason).await
+    }
+
     /// Activate typing notice for this room.
     ///
     /// The typing notice remains active for 4s. It can be deactivate at any
@@ -1630,7 +1816,10 @@ impl Room {
     // e.g. a user starts to type a message for a room.
     #[cfg(feature = "e2e-encryption")]
     #[instrument(skip_all, fields(room_id = ?self.room_id(), store_generation))]
-    async fn preshare_room_key(&self) -> Result<()> {
+    async fn preshare_room_key(
+        &self,
+        ignored_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
+    ) -> Result<()> {
         self.ensure_room_joined()?;
 
         // Take and release the lock on the store, if needs be.
@@ -1650,7 +1839,7 @@ impl Room {
                     self.client.claim_one_time_keys(members.iter().map(Deref::deref)).await?;
                 };
 
-                let response = self.share_room_key().await;
+                let response = self.share_room_key(ignored_devices).await;
 
                 // If one of the responses failed invalidate the group
                 // session as using it would end up in undecryptable
@@ -1675,10 +1864,60 @@ impl Room {
     /// Panics if the client isn't logged in.
     #[cfg(feature = "e2e-encryption")]
     #[instrument(skip_all)]
-    async fn share_room_key(&self) -> Result<()> {
+    async fn share_room_key(
+        &self,
+        ignored_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
+    ) -> Result<()> {
         self.ensure_room_joined()?;
 
-        let requests = self.client.base_client().share_room_key(self.room_id()).await?;
+        let requests = self
+            .client
+            .base_client()
+            .share_room_key_ignoring_devices(self.room_id(), ignored_devices)
+            .await?;
+
+        for request in requests {
+            let response = self.client.send_to_device(&request).await?;
+            self.client.mark_request_as_sent(&request.txn_id, &response).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Share the room keys of the history of this room with the given user, as
+    /// described in [MSC3061].
+    ///
+    /// Only the room keys that were created while the history of the room was
+    /// visible to new members are shared, with all the devices of the user.
+    /// This is done automatically when inviting a user if
+    /// [`EncryptionSettings::share_room_key_history_on_invite`] is enabled.
+    ///
+    /// The user only accepts the room keys if they verified the device of the
+    /// current user.
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    /// [`EncryptionSettings::share_room_key_history_on_invite`]: crate::encryption::EncryptionSettings::share_room_key_history_on_invite
+    #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip_all, fields(room_id = ?self.room_id(), %user_id))]
+    pub async fn share_room_key_history(&self, user_id: &UserId) -> Result<()> {
+        self.ensure_room_joined()?;
+
+        // Make sure we know about all the devices of the user, and have Olm
+        // sessions with them.
+        let (request_id, request) = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+            olm.query_keys_for_users(std::iter::once(user_id))
+        };
+        self.client.keys_query(&request_id, request.device_keys).await?;
+        self.client.claim_one_time_keys(std::iter::once(user_id)).await?;
+
+        let requests = {
+            let olm = self.client.olm_machine().await;
+            let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+            olm.share_room_key_history(self.room_id(), user_id, &self.room_key_recipient_strategy())
+                .await?
+        };
 
         for request in requests {
             let response = self.client.send_to_device(&request).await?;
@@ -2238,6 +2477,33 @@ impl Room {
         self.send_state_event(RoomNameEventContent::new(name)).await
     }
 
+    /// Sets the rule for joining this room.
+    ///
+    /// # Examples
+    ///
+    /// Only allow the members of a space to join the room:
+    ///
+    /// ```no_run
+    /// use matrix_sdk::ruma::{
+    ///     events::room::join_rules::{AllowRule, JoinRule, Restricted},
+    ///     owned_room_id,
+    /// };
+    /// # async {
+    /// # let room: matrix_sdk::Room = todo!();
+    /// let space_id = owned_room_id!("!space:example.org");
+    /// let join_rule = JoinRule::Restricted(Restricted::new(vec![
+    ///     AllowRule::room_membership(space_id),
+    /// ]));
+    /// room.set_join_rule(join_rule).await?;
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn set_join_rule(
+        &self,
+        join_rule: JoinRule,
+    ) -> Result<send_state_event::v3::Response> {
+        self.send_state_event(RoomJoinRulesEventContent::new(join_rule)).await
+    }
+
     /// Sets a new topic for this room.
     pub async fn set_room_topic(&self, topic: &str) -> Result<send_state_event::v3::Response> {
         self.send_state_event(RoomTopicEventContent::new(topic.into())).await
@@ -3545,7 +3811,7 @@ mod tests {
         let room = client.get_room(&DEFAULT_TEST_ROOM_ID).expect("Room should exist");
 
         // Step 1, preshare the room keys.
-        room.preshare_room_key().await.unwrap();
+        room.preshare_room_key(Default::default()).await.unwrap();
 
         // Step 2, force lock invalidation by pretending another client obtained the
         // lock.
@@ -37,6 +37,9 @@
 //!
 //! - enable/disable them all at once with [`SendQueue::set_enabled()`].
 //! - get notifications about send errors with [`SendQueue::subscribe_errors`].
+//! - list every request that hasn't been sent yet, across all the rooms, with
+//!   [`SendQueue::outbox()`], and retry all the wedged ones at once with
+//!   [`SendQueue::unwedge_all()`].
 //! - reload all unsent events that had been persisted in storage using
 //!   [`SendQueue::respawn_tasks_for_rooms_with_unsent_requests()`]. It is
 //!   recommended to call this method during initialization of a client,
@@ -164,11 +167,13 @@ use ruma::{
     serde::Raw,
     OwnedEventId, OwnedRoomId, OwnedTransactionId, TransactionId,
 };
+#[cfg(feature = "e2e-encryption")]
+use ruma::{OwnedDeviceId, OwnedUserId};
 use tokio::sync::{broadcast, oneshot, Mutex, Notify, OwnedMutexGuard};
 use tracing::{debug, error, info, instrument, trace, warn};
 
 #[cfg(feature = "e2e-encryption")]
-use crate::crypto::{OlmError, SessionRecipientCollectionError};
+use crate::crypto::{CryptoStoreError, OlmError, SessionRecipientCollectionError};
 use crate::{
     client::WeakClient,
     config::RequestConfig,
@@ -239,6 +244,7 @@ impl SendQueue {
         let room_q = RoomSendQueue::new(
             self.is_enabled(),
             data.error_reporter.clone(),
+            data.global_updates.clone(),
             data.is_dropping.clone(),
             &self.client,
             owned_room_id.clone(),
@@ -284,6 +290,112 @@ impl SendQueue {
     pub fn subscribe_errors(&self) -> broadcast::Receiver<SendQueueRoomError> {
         self.data().error_reporter.subscribe()
     }
+
+    /// Returns every request that hasn't been sent yet, across all the rooms,
+    /// as well as a receiver to listen to the updates of all the room send
+    /// queues.
+    ///
+    /// This includes requests waiting to be sent, requests that ran into an
+    /// unrecoverable error (wedged), and requests that are scheduled to be
+    /// sent after another one (e.g. a media event waiting for its upload).
+    ///
+    /// This will spawn the sending tasks for rooms with unsent requests from
+    /// previous sessions, if they weren't spawned yet.
+    pub async fn outbox(
+        &self,
+    ) -> Result<(Vec<OutboxEntry>, broadcast::Receiver<SendQueueUpdate>), RoomSendQueueError> {
+        let mut entries = Vec::new();
+
+        for room_q in self.rooms_with_requests().await? {
+            entries.extend(room_q.inner.queue.outbox_entries(&room_q).await?);
+        }
+
+        Ok((entries, self.data().global_updates.subscribe()))
+    }
+
+    /// Unwedge all the requests that ran into an unrecoverable error, in all
+    /// the rooms, and try to resend them.
+    ///
+    /// Returns the number of requests that have been unwedged.
+    pub async fn unwedge_all(&self) -> Result<usize, RoomSendQueueError> {
+        let mut num_unwedged = 0;
+
+        for room_q in self.rooms_with_requests().await? {
+            num_unwedged += room_q.unwedge_all().await?;
+        }
+
+        Ok(num_unwedged)
+    }
+
+    /// Returns the send queues of all the rooms which may have requests to be
+    /// sent, i.e. the ones we know about already, and the ones having unsent
+    /// requests in the store.
+    async fn rooms_with_requests(&self) -> Result<Vec<RoomSendQueue>, RoomSendQueueError> {
+        let mut room_ids = self
+            .client
+            .store()
+            .load_rooms_with_unsent_requests()
+            .await
+            .map_err(RoomSendQueueStorageError::StateStoreError)?;
+
+        for room_id in self.data().rooms.read().unwrap().keys() {
+            if !room_ids.contains(room_id) {
+                room_ids.push(room_id.clone());
+            }
+        }
+
+        Ok(room_ids
+            .into_iter()
+            .filter_map(|room_id| self.client.get_room(&room_id))
+            .map(|room| self.for_room(room))
+            .collect())
+    }
+}
+
+/// An update to a room send queue, observable with [`SendQueue::outbox()`].
+#[derive(Clone, Debug)]
+pub struct SendQueueUpdate {
+    /// The room in which the update happened.
+    pub room_id: OwnedRoomId,
+
+    /// The update itself, as it has been sent to the room's subscribers.
+    pub update: RoomSendQueueUpdate,
+}
+
+/// The state of a request listed in the [`SendQueue::outbox()`].
+#[derive(Clone, Debug)]
+pub enum OutboxRequestState {
+    /// The request is in the queue, and will be sent as soon as the room's send
+    /// queue gets to it (or it is being sent right now).
+    Pending,
+
+    /// The request couldn't be sent because of an unrecoverable error, and it
+    /// won't be sent until it's unwedged.
+    Wedged(QueueWedgeError),
+
+    /// The request will be sent after another request it depends on has been
+    /// sent (e.g. a media event waiting for the media to be uploaded).
+    Scheduled,
+}
+
+/// A request that hasn't been sent yet, as returned by
+/// [`SendQueue::outbox()`].
+#[derive(Clone, Debug)]
+pub struct OutboxEntry {
+    /// The room in which the request is going to be sent.
+    pub room_id: OwnedRoomId,
+
+    /// The local echo for this request, with handles to act upon it.
+    pub local_echo: LocalEcho,
+
+    /// Whether the request is pending, wedged or scheduled.
+    pub state: OutboxRequestState,
+
+    /// The priority at which the request will be sent, if it's in the queue.
+    ///
+    /// `None` for [`OutboxRequestState::Scheduled`] requests, which will be
+    /// put in the queue only after the request they depend on has been sent.
+    pub priority: Option<usize>,
 }
 
 /// A specific room's send queue ran into an error, and it has disabled itself.
@@ -324,6 +436,9 @@ pub(super) struct SendQueueData {
     /// Global error updates for the send queue.
     error_reporter: broadcast::Sender<SendQueueRoomError>,
 
+    /// Updates of all the room send queues, tagged with their room.
+    global_updates: broadcast::Sender<SendQueueUpdate>,
+
     /// Are we currently dropping the Client?
     is_dropping: Arc<AtomicBool>,
 }
@@ -332,11 +447,13 @@ impl SendQueueData {
     /// Create the data for a send queue, in the given enabled state.
     pub fn new(globally_enabled: bool) -> Self {
         let (sender, _) = broadcast::channel(32);
+        let (global_updates, _) = broadcast::channel(32);
 
         Self {
             rooms: Default::default(),
             globally_enabled: AtomicBool::new(globally_enabled),
             error_reporter: sender,
+            global_updates,
             is_dropping: Arc::new(false.into()),
         }
     }
@@ -382,12 +499,19 @@ impl RoomSendQueue {
     fn new(
         globally_enabled: bool,
         global_error_reporter: broadcast::Sender<SendQueueRoomError>,
+        global_updates: broadcast::Sender<SendQueueUpdate>,
         is_dropping: Arc<AtomicBool>,
         client: &Client,
         room_id: OwnedRoomId,
     ) -> Self {
         let (updates_sender, _) = broadcast::channel(32);
 
+        let forward_task = spawn(Self::forward_updates(
+            room_id.clone(),
+            updates_sender.subscribe(),
+            global_updates,
+        ));
+
         let queue = QueueStorage::new(WeakClient::from_client(client), room_id.clone());
         let notifier = Arc::new(Notify::new());
 
@@ -409,6 +533,7 @@ impl RoomSendQueue {
                 room: weak_room,
                 updates: updates_sender,
                 _task: task,
+                _forward_task: forward_task,
                 queue,
                 notifier,
                 locally_enabled,
@@ -503,6 +628,80 @@ impl RoomSendQueue {
         Ok((local_echoes, self.inner.updates.subscribe()))
     }
 
+    /// Returns the current outbox entries for this room, that is, all the
+    /// requests that haven't been sent yet, along with their state.
+    pub async fn outbox(&self) -> Result<Vec<OutboxEntry>, RoomSendQueueError> {
+        Ok(self.inner.queue.outbox_entries(self).await?)
+    }
+
+    /// Unwedge all the requests of this room that ran into an unrecoverable
+    /// error, and try to resend them.
+    ///
+    /// Returns the number of requests that have been unwedged.
+    pub async fn unwedge_all(&self) -> Result<usize, RoomSendQueueError> {
+        let unwedged = self.inner.queue.unwedge_all().await?;
+
+        if !unwedged.is_empty() {
+            // Wake up the queue, in case the room was asleep before unwedging the requests.
+            self.inner.notifier.notify_one();
+        }
+
+        for transaction_id in &unwedged {
+            let _ = self
+                .inner
+                .updates
+                .send(RoomSendQueueUpdate::RetryEvent { transaction_id: transaction_id.clone() });
+        }
+
+        Ok(unwedged.len())
+    }
+
+    /// Cancel all the requests of this room that haven't been sent yet.
+    ///
+    /// Requests that are being sent at the moment will be redacted once
+    /// they've been sent, as with [`SendHandle::abort`].
+    ///
+    /// Returns the number of requests that have been cancelled.
+    pub async fn cancel_all(&self) -> Result<usize, RoomSendQueueError> {
+        let mut num_cancelled = 0;
+
+        for local_echo in self.inner.queue.local_echoes(self).await? {
+            let cancelled = match local_echo.content {
+                LocalEchoContent::Event { send_handle, .. } => send_handle.abort().await?,
+                LocalEchoContent::React { send_handle, .. } => send_handle.abort().await?,
+            };
+
+            if cancelled {
+                num_cancelled += 1;
+            }
+        }
+
+        Ok(num_cancelled)
+    }
+
+    /// A task forwarding all the updates of a room send queue to the global
+    /// send queue, so they can be observed with [`SendQueue::outbox()`].
+    async fn forward_updates(
+        room_id: OwnedRoomId,
+        mut updates: broadcast::Receiver<RoomSendQueueUpdate>,
+        global_updates: broadcast::Sender<SendQueueUpdate>,
+    ) {
+        loop {
+            match updates.recv().await {
+                Ok(update) => {
+                    let _ =
+                        global_updates.send(SendQueueUpdate { room_id: room_id.clone(), update });
+                }
+
+                Err(broadcast::error::RecvError::Lagged(num_skipped)) => {
+                    warn!(%room_id, num_skipped, "lagged when forwarding send queue updates");
+                }
+
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
     /// A task that must be spawned in the async runtime, running in the
     /// background for each room that has a send queue.
     ///
@@ -574,7 +773,7 @@ impl RoomSendQueue {
                 continue;
             };
 
-            match Self::handle_request(&room, queued_request, cancel_upload_rx).await {
+            match Self::handle_request(&room, &queue, queued_request, cancel_upload_rx).await {
                 Ok(Some(parent_key)) => match queue.mark_as_sent(&txn_id, parent_key.clone()).await
                 {
                     Ok(()) => match parent_key {
@@ -674,6 +873,7 @@ impl RoomSendQueue {
     /// `None`).
     async fn handle_request(
         room: &Room,
+        #[cfg_attr(not(feature = "e2e-encryption"), allow(unused_variables))] queue: &QueueStorage,
         request: QueuedRequest,
         cancel_upload_rx: Option<oneshot::Receiver<()>>,
     ) -> Result<Option<SentRequestKey>, crate::Error> {
@@ -681,11 +881,34 @@ impl RoomSendQueue {
             QueuedRequestKind::Event { content } => {
                 let (event, event_type) = content.raw();
 
-                let res = room
+                let send = room
                     .send_raw(event_type, event)
                     .with_transaction_id(&request.transaction_id)
-                    .with_request_config(RequestConfig::short_retry())
-                    .await?;
+                    .with_request_config(RequestConfig::short_retry());
+
+                #[cfg(feature = "e2e-encryption")]
+                let ignored_devices = queue.ignored_devices(&request.transaction_id);
+                #[cfg(feature = "e2e-encryption")]
+                let has_ignored_devices = !ignored_devices.is_empty();
+                #[cfg(feature = "e2e-encryption")]
+                let send = send.with_ignored_devices(ignored_devices);
+
+                let res = send.await;
+
+                // The room key may have been shared with the ignored devices, whether the
+                // event could be sent or not: rotate it, so that these devices can't decrypt
+                // the next events.
+                #[cfg(feature = "e2e-encryption")]
+                if has_ignored_devices {
+                    if let Err(err) = room.discard_room_key().await {
+                        error!(
+                            txn_id = %request.transaction_id,
+                            "couldn't discard the room key shared with the ignored devices: {err}"
+                        );
+                    }
+                }
+
+                let res = res?;
 
                 trace!(txn_id = %request.transaction_id, event_id = %res.event_id, "event successfully sent");
                 Ok(Some(SentRequestKey::Event(res.event_id)))
@@ -853,6 +1076,10 @@ struct RoomSendQueueInner {
     /// Handle to the actual sending task. Unused, but kept alive along this
     /// data structure.
     _task: JoinHandle<()>,
+
+    /// Handle to the task forwarding updates to the global send queue. Unused,
+    /// but kept alive along this data structure.
+    _forward_task: JoinHandle<()>,
 }
 
 /// Information about a request being sent right this moment.
@@ -930,6 +1157,16 @@ struct QueueStorage {
 
     /// To which room is this storage related.
     room_id: OwnedRoomId,
+
+    /// The devices that receive the room key as if they were ignored, when
+    /// sending the event with the given transaction id.
+    ///
+    /// Set by [`SendHandle::ignore_insecure_devices_and_resend`], it only
+    /// applies to a single event and is only kept in memory. It's removed once
+    /// the event has been sent or aborted.
+    #[cfg(feature = "e2e-encryption")]
+    ignored_devices:
+        Arc<RwLock<HashMap<OwnedTransactionId, BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>>>>,
 }
 
 impl QueueStorage {
@@ -941,7 +1178,50 @@ impl QueueStorage {
 
     /// Create a new queue for queuing requests to be sent later.
     fn new(client: WeakClient, room: OwnedRoomId) -> Self {
-        Self { room_id: room, store: StoreLock { client, being_sent: Default::default() } }
+        Self {
+            room_id: room,
+            store: StoreLock { client, being_sent: Default::default() },
+            #[cfg(feature = "e2e-encryption")]
+            ignored_devices: Default::default(),
+        }
+    }
+
+    /// Share the room key with the given devices as if they were ignored, when
+    /// sending the event with the given transaction id.
+    #[cfg(feature = "e2e-encryption")]
+    fn ignore_devices(
+        &self,
+        transaction_id: &TransactionId,
+        devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
+    ) {
+        let mut ignored_devices = self.ignored_devices.write().unwrap();
+        let ignored_devices = ignored_devices.entry(transaction_id.to_owned()).or_default();
+
+        for (user_id, device_ids) in devices {
+            let ignored = ignored_devices.entry(user_id).or_default();
+            for device_id in device_ids {
+                if !ignored.contains(&device_id) {
+                    ignored.push(device_id);
+                }
+            }
+        }
+    }
+
+    /// Get the devices that receive the room key as if they were ignored, when
+    /// sending the event with the given transaction id.
+    #[cfg(feature = "e2e-encryption")]
+    fn ignored_devices(
+        &self,
+        transaction_id: &TransactionId,
+    ) -> BTreeMap<OwnedUserId, Vec<OwnedDeviceId>> {
+        self.ignored_devices.read().unwrap().get(transaction_id).cloned().unwrap_or_default()
+    }
+
+    /// Forget the devices that receive the room key as if they were ignored,
+    /// when sending the event with the given transaction id.
+    #[cfg(feature = "e2e-encryption")]
+    fn forget_ignored_devices(&self, transaction_id: &TransactionId) {
+        self.ignored_devices.write().unwrap().remove(transaction_id);
     }
 
     /// Push a new event to be sent in the queue, with a default priority of 0.
@@ -1060,6 +1340,110 @@ impl QueueStorage {
             .await?)
     }
 
+    /// Marks all the wedged requests as being now unwedged, and adds them back
+    /// to the queue.
+    ///
+    /// Returns the transaction ids that observers know about for the unwedged
+    /// requests, i.e. the one of the related event for a media upload.
+    async fn unwedge_all(&self) -> Result<Vec<OwnedTransactionId>, RoomSendQueueStorageError> {
+        let guard = self.store.lock().await;
+        let client = guard.client()?;
+        let store = client.store();
+
+        let mut unwedged = Vec::new();
+
+        for request in store.load_send_queue_requests(&self.room_id).await? {
+            if !request.is_wedged() {
+                continue;
+            }
+
+            store
+                .update_send_queue_request_status(&self.room_id, &request.transaction_id, None)
+                .await?;
+
+            unwedged.push(match request.kind {
+                QueuedRequestKind::MediaUpload { related_to, .. } => related_to,
+                QueuedRequestKind::Event { .. } => request.transaction_id,
+            });
+        }
+
+        Ok(unwedged)
+    }
+
+    /// Changes the priority of a request that's in the queue.
+    ///
+    /// The request is moved after all the other requests with the same
+    /// priority, and keeps its wedged status.
+    ///
+    /// Returns whether the priority has been changed. If false, this either
+    /// means that the transaction id was unrelated to this queue, or that the
+    /// request is being sent or has been sent already.
+    async fn set_priority(
+        &self,
+        transaction_id: &TransactionId,
+        priority: usize,
+    ) -> Result<bool, RoomSendQueueStorageError> {
+        let guard = self.store.lock().await;
+
+        if guard.being_sent.as_ref().map(|info| info.transaction_id.as_ref())
+            == Some(transaction_id)
+        {
+            // Too late, the request is being sent.
+            return Ok(false);
+        }
+
+        let client = guard.client()?;
+        let store = client.store();
+
+        let Some(request) = store
+            .load_send_queue_requests(&self.room_id)
+            .await?
+            .into_iter()
+            .find(|request| request.transaction_id == transaction_id)
+        else {
+            return Ok(false);
+        };
+
+        // The store doesn't allow updating the priority in place, so remove and insert
+        // the request back, while holding the lock.
+        store.remove_send_queue_request(&self.room_id, transaction_id).await?;
+        store
+            .save_send_queue_request(
+                &self.room_id,
+                request.transaction_id.clone(),
+                request.kind,
+                priority,
+            )
+            .await?;
+
+        if let Some(error) = request.error {
+            store
+                .update_send_queue_request_status(&self.room_id, transaction_id, Some(error))
+                .await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the reason why the request identified with the given
+    /// transaction id is wedged, if it's in the queue and wedged.
+    async fn wedge_reason(
+        &self,
+        transaction_id: &TransactionId,
+    ) -> Result<Option<QueueWedgeError>, RoomSendQueueStorageError> {
+        Ok(self
+            .store
+            .lock()
+            .await
+            .client()?
+            .store()
+            .load_send_queue_requests(&self.room_id)
+            .await?
+            .into_iter()
+            .find(|request| request.transaction_id == transaction_id)
+            .and_then(|request| request.error))
+    }
+
     /// Marks a request pushed with [`Self::push`] and identified with the given
     /// transaction id as sent, by removing it from the local queue.
     async fn mark_as_sent(
@@ -1086,6 +1470,9 @@ impl QueueStorage {
 
         let removed = store.remove_send_queue_request(&self.room_id, transaction_id).await?;
 
+        #[cfg(feature = "e2e-encryption")]
+        self.forget_ignored_devices(transaction_id);
+
         if !removed {
             warn!(txn_id = %transaction_id, "request marked as sent was missing from storage");
         }
@@ -1308,33 +1695,64 @@ impl QueueStorage {
         &self,
         room: &RoomSendQueue,
     ) -> Result<Vec<LocalEcho>, RoomSendQueueStorageError> {
+        Ok(self.outbox_entries(room).await?.into_iter().map(|entry| entry.local_echo).collect())
+    }
+
+    /// Returns a list of the outbox entries, that is, the local echoes along
+    /// with the state and priority of their associated requests.
+    async fn outbox_entries(
+        &self,
+        room: &RoomSendQueue,
+    ) -> Result<Vec<OutboxEntry>, RoomSendQueueStorageError> {
         let guard = self.store.lock().await;
         let client = guard.client()?;
         let store = client.store();
 
-        let local_requests =
-            store.load_send_queue_requests(&self.room_id).await?.into_iter().filter_map(|queued| {
-                Some(LocalEcho {
-                    transaction_id: queued.transaction_id.clone(),
-                    content: match queued.kind {
-                        QueuedRequestKind::Event { content } => LocalEchoContent::Event {
-                            serialized_event: content,
-                            send_handle: SendHandle {
-                                room: room.clone(),
-                                transaction_id: queued.transaction_id,
-                                media_handles: None,
+        // Remember which media uploads are wedged, so as to reflect it on the
+        // associated media events.
+        let mut wedged_uploads = HashMap::new();
+
+        let local_requests = store
+            .load_send_queue_requests(&self.room_id)
+            .await?
+            .into_iter()
+            .filter_map(|queued| {
+                let state = match &queued.error {
+                    Some(error) => OutboxRequestState::Wedged(error.clone()),
+                    None => OutboxRequestState::Pending,
+                };
+
+                Some(OutboxEntry {
+                    room_id: self.room_id.clone(),
+                    local_echo: LocalEcho {
+                        transaction_id: queued.transaction_id.clone(),
+                        content: match queued.kind {
+                            QueuedRequestKind::Event { content } => LocalEchoContent::Event {
+                                serialized_event: content,
+                                send_handle: SendHandle {
+                                    room: room.clone(),
+                                    transaction_id: queued.transaction_id,
+                                    media_handles: None,
+                                },
+                                send_error: queued.error,
                             },
-                            send_error: queued.error,
-                        },
 
-                        QueuedRequestKind::MediaUpload { .. } => {
-                            // Don't return uploaded medias as their own things; the accompanying
-                            // event represented as a dependent request should be sufficient.
-                            return None;
-                        }
+                            QueuedRequestKind::MediaUpload { related_to, .. } => {
+                                // Don't return uploaded medias as their own things; the
+                                // accompanying event represented as a dependent request should be
+                                // sufficient.
+                                if let Some(error) = queued.error {
+                                    wedged_uploads.insert(related_to, error);
+                                }
+                                return None;
+                            }
+                        },
                     },
+                    state,
+                    priority: Some(queued.priority),
                 })
-            });
+            })
+            .collect::<Vec<_>>();
 
         let reactions_and_medias = store
             .load_dependent_queued_requests(&self.room_id)
@@ -1347,16 +1765,21 @@ impl QueueStorage {
                     None
                 }
 
-                DependentQueuedRequestKind::ReactEvent { key } => Some(LocalEcho {
-                    transaction_id: dep.own_transaction_id.clone().into(),
-                    content: LocalEchoContent::React {
-                        key,
-                        send_handle: SendReactionHandle {
-                            room: room.clone(),
-                            transaction_id: dep.own_transaction_id,
+                DependentQueuedRequestKind::ReactEvent { key } => Some(OutboxEntry {
+                    room_id: self.room_id.clone(),
+                    local_echo: LocalEcho {
+                        transaction_id: dep.own_transaction_id.clone().into(),
+                        content: LocalEchoContent::React {
+                            key,
+                            send_handle: SendReactionHandle {
+                                room: room.clone(),
+                                transaction_id: dep.own_transaction_id,
+                            },
+                            applies_to: dep.parent_transaction_id,
                         },
-                        applies_to: dep.parent_transaction_id,
                     },
+                    state: OutboxRequestState::Scheduled,
+                    priority: None,
                 }),
 
                 DependentQueuedRequestKind::UploadFileWithThumbnail { .. } => {
@@ -1369,27 +1792,37 @@ impl QueueStorage {
                     file_upload,
                     thumbnail_info,
                 } => {
+                    let state = match wedged_uploads.get(&*dep.own_transaction_id) {
+                        Some(error) => OutboxRequestState::Wedged(error.clone()),
+                        None => OutboxRequestState::Scheduled,
+                    };
+
                     // Materialize as an event local echo.
-                    Some(LocalEcho {
-                        transaction_id: dep.own_transaction_id.clone().into(),
-                        content: LocalEchoContent::Event {
-                            serialized_event: SerializableEventContent::new(&local_echo.into())
-                                .ok()?,
-                            send_handle: SendHandle {
-                                room: room.clone(),
-                                transaction_id: dep.own_transaction_id.into(),
-                                media_handles: Some(MediaHandles {
-                                    upload_thumbnail_txn: thumbnail_info.map(|info| info.txn),
-                                    upload_file_txn: file_upload,
-                                }),
+                    Some(OutboxEntry {
+                        room_id: self.room_id.clone(),
+                        local_echo: LocalEcho {
+                            transaction_id: dep.own_transaction_id.clone().into(),
+                            content: LocalEchoContent::Event {
+                                serialized_event: SerializableEventContent::new(&local_echo.into())
+                                    .ok()?,
+                                send_handle: SendHandle {
+                                    room: room.clone(),
+                                    transaction_id: dep.own_transaction_id.into(),
+                                    media_handles: Some(MediaHandles {
+                                        upload_thumbnail_txn: thumbnail_info.map(|info| info.txn),
+                                        upload_file_txn: file_upload,
+                                    }),
+                                },
+                                send_error: None,
                             },
-                            send_error: None,
                         },
+                        state,
+                        priority: None,
                     })
                 }
             });
 
-        Ok(local_requests.chain(reactions_and_medias).collect())
+        Ok(local_requests.into_iter().chain(reactions_and_medias).collect())
     }
 
     /// Try to apply a single dependent request, whether it's local or remote.
@@ -1823,6 +2256,12 @@ pub enum RoomSendQueueError {
     /// Error coming from storage.
     #[error(transparent)]
     StorageError(#[from] RoomSendQueueStorageError),
+
+    /// Error coming from the crypto store, when trying to resolve an identity
+    /// problem that prevented sending.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    CryptoStoreError(#[from] CryptoStoreError),
 }
 
 /// An error triggered by the send queue storage.
@@ -1911,6 +2350,9 @@ impl SendHandle {
 
         if let Some(handles) = &self.media_handles {
             if queue.abort_upload(&self.transaction_id, handles).await? {
+                #[cfg(feature = "e2e-encryption")]
+                queue.forget_ignored_devices(&self.transaction_id);
+
                 // Propagate a cancelled update.
                 let _ = self.room.inner.updates.send(RoomSendQueueUpdate::CancelledLocalEvent {
                     transaction_id: self.transaction_id.clone(),
@@ -1927,6 +2369,9 @@ impl SendHandle {
         if queue.cancel_event(&self.transaction_id).await? {
             trace!("successful abort");
 
+            #[cfg(feature = "e2e-encryption")]
+            queue.forget_ignored_devices(&self.transaction_id);
+
             // Propagate a cancelled update too.
             let _ = self.room.inner.updates.send(RoomSendQueueUpdate::CancelledLocalEvent {
                 transaction_id: self.transaction_id.clone(),
@@ -2062,6 +2507,114 @@ impl SendHandle {
         Ok(())
     }
 
+    /// If sending the event failed because some previously verified users
+    /// changed their identity (see [`QueueWedgeError::IdentityViolations`]),
+    /// withdraw the verification of these users, and try to resend the event.
+    ///
+    /// Withdrawing the verification isn't limited to this event: these users
+    /// won't be considered as previously verified anymore, in every room, so
+    /// their identity changes won't block sending anymore. This persists until
+    /// they are verified again.
+    ///
+    /// Returns the users whose verification was withdrawn, or `None` if the
+    /// event wasn't wedged because of identity violations, in which case
+    /// nothing has been done.
+    #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip(self), fields(room_id = %self.room.inner.room.room_id(), txn_id = %self.transaction_id))]
+    pub async fn withdraw_verification_and_resend(
+        &self,
+    ) -> Result<Option<Vec<OwnedUserId>>, RoomSendQueueError> {
+        let Some(QueueWedgeError::IdentityViolations { users }) =
+            self.room.inner.queue.wedge_reason(&self.transaction_id).await?
+        else {
+            debug!("event isn't wedged because of identity violations");
+            return Ok(None);
+        };
+
+        let room = self.room.inner.room.get().ok_or(RoomSendQueueError::RoomDisappeared)?;
+        let encryption = room.client().encryption();
+
+        let mut withdrawn = Vec::new();
+        for user_id in users {
+            if let Some(identity) = encryption.get_user_identity(&user_id).await? {
+                warn!(%user_id, "withdrawing verification, for all the rooms");
+                identity.withdraw_verification().await?;
+                withdrawn.push(user_id);
+            }
+        }
+
+        self.unwedge().await?;
+
+        Ok(Some(withdrawn))
+    }
+
+    /// If sending the event failed because some verified users have unsigned
+    /// devices (see [`QueueWedgeError::InsecureDevices`]), try to resend the
+    /// event, sharing its room key with these devices as if they were
+    /// [`LocalTrust::Ignored`].
+    ///
+    /// This only applies to this event: the local trust of the devices isn't
+    /// changed, so they still block sending the next events. Use
+    /// [`Device::set_local_trust`] to ignore them in every room. Since the room
+    /// key is shared with these devices, it's discarded after the event has
+    /// been sent, so that they can't decrypt the next events of the room.
+    ///
+    /// Returns the devices that are ignored for this event, or `None` if the
+    /// event wasn't wedged because of insecure devices, in which case nothing
+    /// has been done.
+    ///
+    /// [`LocalTrust::Ignored`]: crate::crypto::LocalTrust::Ignored
+    /// [`Device::set_local_trust`]: crate::encryption::identities::Device::set_local_trust
+    #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip(self), fields(room_id = %self.room.inner.room.room_id(), txn_id = %self.transaction_id))]
+    pub async fn ignore_insecure_devices_and_resend(
+        &self,
+    ) -> Result<Option<BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>>, RoomSendQueueError> {
+        let Some(QueueWedgeError::InsecureDevices { user_device_map }) =
+            self.room.inner.queue.wedge_reason(&self.transaction_id).await?
+        else {
+            debug!("event isn't wedged because of insecure devices");
+            return Ok(None);
+        };
+
+        warn!(?user_device_map, "ignoring insecure devices, for this event only");
+        self.room.inner.queue.ignore_devices(&self.transaction_id, user_device_map.clone());
+
+        if let Err(err) = self.unwedge().await {
+            self.room.inner.queue.forget_ignored_devices(&self.transaction_id);
+            return Err(err);
+        }
+
+        Ok(Some(user_device_map))
+    }
+
+    /// Changes the priority at which the event will be sent, relative to the
+    /// other requests of the same room.
+    ///
+    /// Requests with a higher priority are sent first; requests queued with
+    /// [`RoomSendQueue::send()`] have a priority of 0. The event is moved
+    /// after all the other requests with the same priority.
+    ///
+    /// Returns true if the priority was changed, false if not (i.e. the event
+    /// is being sent, or has been sent already).
+    #[instrument(skip(self), fields(room_id = %self.room.inner.room.room_id(), txn_id = %self.transaction_id))]
+    pub async fn set_priority(&self, priority: usize) -> Result<bool, RoomSendQueueStorageError> {
+        trace!("received a request to change the priority");
+        self.nyi_for_uploads()?;
+
+        if self.room.inner.queue.set_priority(&self.transaction_id, priority).await? {
+            trace!("successful priority change");
+
+            // Wake up the queue, in case the room was asleep before.
+            self.room.inner.notifier.notify_one();
+
+            Ok(true)
+        } else {
+            debug!("local echo doesn't exist anymore, can't change its priority");
+            Ok(false)
+        }
+    }
+
     /// Send a reaction to the event as soon as it's sent.
     ///
     /// If returning `Ok(None)`; this means the reaction couldn't be sent
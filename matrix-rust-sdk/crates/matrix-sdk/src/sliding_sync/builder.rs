@@ -9,12 +9,14 @@ use matrix_sdk_base::sliding_sync::http;
 use matrix_sdk_common::timer;
 use ruma::OwnedRoomId;
 use tokio::sync::{broadcast::channel, Mutex as AsyncMutex, RwLock as AsyncRwLock};
+use tracing::warn;
 
 use super::{
     cache::{format_storage_key_prefix, restore_sliding_sync_state},
+    extensions::{erase_extension, merge_into_custom_extension, SharedExtension, KNOWN_EXTENSIONS},
     sticky_parameters::SlidingSyncStickyManager,
-    Error, SlidingSync, SlidingSyncInner, SlidingSyncListBuilder, SlidingSyncPositionMarkers,
-    Version,
+    Error, SlidingSync, SlidingSyncExtension, SlidingSyncInner, SlidingSyncListBuilder,
+    SlidingSyncPositionMarkers, Version,
 };
 use crate::{sliding_sync::SlidingSyncStickyParameters, Client, Result};
 
@@ -30,6 +32,7 @@ pub struct SlidingSyncBuilder {
     client: Client,
     lists: Vec<SlidingSyncListBuilder>,
     extensions: Option<http::request::Extensions>,
+    custom_extensions: BTreeMap<String, SharedExtension>,
     subscriptions: BTreeMap<OwnedRoomId, http::request::RoomSubscription>,
     poll_timeout: Duration,
     network_timeout: Duration,
@@ -52,6 +55,7 @@ impl SlidingSyncBuilder {
                 client,
                 lists: Vec::new(),
                 extensions: None,
+                custom_extensions: BTreeMap::new(),
                 subscriptions: BTreeMap::new(),
                 poll_timeout: Duration::from_secs(30),
                 network_timeout: Duration::from_secs(30),
@@ -185,6 +189,20 @@ impl SlidingSyncBuilder {
         self
     }
 
+    /// Register a custom extension, that the SDK doesn't know about.
+    ///
+    /// Its configuration is a sticky parameter, like the one of the known
+    /// extensions, and its state is cached and reloaded with the sliding sync.
+    ///
+    /// Replace any custom extension with the same name. If it has the name of
+    /// an extension known by the SDK, [`Self::build`] fails with
+    /// [`Error::KnownCustomExtension`].
+    pub fn with_custom_extension(mut self, extension: impl SlidingSyncExtension) -> Self {
+        let extension = erase_extension(extension);
+        self.custom_extensions.insert(extension.name().to_owned(), extension);
+        self
+    }
+
     /// Sets a custom timeout duration for the sliding sync polling endpoint.
     ///
     /// This is the maximum time to wait before the sliding sync server returns
@@ -236,6 +254,14 @@ impl SlidingSyncBuilder {
             return Err(crate::error::Error::SlidingSync(Error::VersionIsMissing));
         }
 
+        if let Some(name) =
+            self.custom_extensions.keys().find(|name| KNOWN_EXTENSIONS.contains(&name.as_str()))
+        {
+            return Err(crate::error::Error::SlidingSync(Error::KnownCustomExtension(
+                name.clone(),
+            )));
+        }
+
         let (internal_channel_sender, _internal_channel_receiver) = channel(8);
 
         let mut lists = BTreeMap::new();
@@ -256,11 +282,29 @@ impl SlidingSyncBuilder {
             #[cfg(not(feature = "e2e-encryption"))]
             let pos = None;
 
+            for (name, state) in fields.custom_extensions {
+                let Some(extension) = self.custom_extensions.get(&name) else {
+                    continue;
+                };
+
+                if let Err(error) = extension.restore_state(state) {
+                    warn!(name, "Couldn't restore the state of a custom extension: {error}");
+                }
+            }
+
             (pos, fields.rooms)
         } else {
             (None, BTreeMap::new())
         };
 
+        // The configurations of the custom extensions are sticky, like the ones of the
+        // known extensions.
+        let mut extensions = self.extensions.unwrap_or_default();
+
+        for (name, extension) in &self.custom_extensions {
+            merge_into_custom_extension(&mut extensions, name, extension.config()?)?;
+        }
+
         #[cfg(feature = "e2e-encryption")]
         let share_pos = self.share_pos;
         #[cfg(not(feature = "e2e-encryption"))]
@@ -283,11 +327,10 @@ impl SlidingSyncBuilder {
             position: Arc::new(AsyncMutex::new(SlidingSyncPositionMarkers { pos })),
 
             sticky: StdRwLock::new(SlidingSyncStickyManager::new(
-                SlidingSyncStickyParameters::new(
-                    self.subscriptions,
-                    self.extensions.unwrap_or_default(),
-                ),
+                SlidingSyncStickyParameters::new(extensions),
             )),
+            room_subscriptions: StdRwLock::new(self.subscriptions),
+            custom_extensions: self.custom_extensions,
 
             internal_channel: internal_channel_sender,
 
@@ -8,7 +8,7 @@ use std::collections::BTreeMap;
 
 use matrix_sdk_base::{StateStore, StoreError};
 use matrix_sdk_common::timer;
-use ruma::{OwnedRoomId, UserId};
+use ruma::{serde::JsonObject, OwnedRoomId, UserId};
 use tracing::{trace, warn};
 
 use super::{
@@ -83,12 +83,22 @@ pub(super) async fn store_sliding_sync_state(
     trace!(storage_key, "Saving a `SlidingSync` to the state store");
     let storage = sliding_sync.inner.client.store();
 
+    let custom_extensions = sliding_sync
+        .inner
+        .custom_extensions
+        .iter()
+        .map(|(name, extension)| Ok((name.clone(), extension.state()?)))
+        .collect::<Result<BTreeMap<_, _>, serde_json::Error>>()?;
+
     // Write this `SlidingSync` instance, as a `FrozenSlidingSync` instance, inside
     // the store.
     storage
         .set_custom_value(
             instance_storage_key.as_bytes(),
-            serde_json::to_vec(&FrozenSlidingSync::new(&*sliding_sync.inner.rooms.read().await))?,
+            serde_json::to_vec(&FrozenSlidingSync::new(
+                &*sliding_sync.inner.rooms.read().await,
+                custom_extensions,
+            ))?,
         )
         .await?;
 
@@ -184,6 +194,7 @@ pub(super) struct RestoredFields {
     pub to_device_token: Option<String>,
     pub pos: Option<String>,
     pub rooms: BTreeMap<OwnedRoomId, SlidingSyncRoom>,
+    pub custom_extensions: BTreeMap<String, JsonObject>,
 }
 
 /// Restore the `SlidingSync`'s state from what is stored in the storage.
@@ -219,7 +230,7 @@ pub(super) async fn restore_sliding_sync_state(
         .map(|custom_value| serde_json::from_slice::<FrozenSlidingSync>(&custom_value))
     {
         // `SlidingSync` has been found and successfully deserialized.
-        Some(Ok(FrozenSlidingSync { to_device_since, rooms: frozen_rooms })) => {
+        Some(Ok(FrozenSlidingSync { to_device_since, rooms: frozen_rooms, custom_extensions })) => {
             trace!("Successfully read the `SlidingSync` from the cache");
             // Only update the to-device token if we failed to read it from the crypto store
             // above.
@@ -252,6 +263,8 @@ pub(super) async fn restore_sliding_sync_state(
                     )
                 })
                 .collect();
+
+            restored_fields.custom_extensions = custom_extensions;
         }
 
         // `SlidingSync` has been found, but it wasn't possible to deserialize it. It's
@@ -473,6 +486,8 @@ mod tests {
     #[cfg(feature = "e2e-encryption")]
     #[async_test]
     async fn test_sliding_sync_high_level_cache_and_restore() -> Result<()> {
+        use std::collections::BTreeMap;
+
         use imbl::Vector;
         use ruma::owned_room_id;
 
@@ -549,6 +564,7 @@ mod tests {
                         prev_batch: Some("t0ken".to_owned()),
                         timeline_queue: Vector::new(),
                     }],
+                    custom_extensions: BTreeMap::new(),
                 })?,
             )
             .await?;
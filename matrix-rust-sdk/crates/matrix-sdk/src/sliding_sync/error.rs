@@ -45,6 +45,10 @@ pub enum Error {
     #[error("The Sliding Sync instance's identifier must be less than 16 chars long")]
     InvalidSlidingSyncIdentifier,
 
+    /// A custom extension has the name of an extension known by the SDK.
+    #[error("The custom extension `{0}` has the name of an extension known by the SDK")]
+    KnownCustomExtension(String),
+
     /// A task failed to execute to completion.
     #[error("A task failed to execute to completion; task description: {task_description}, error: {error}")]
     JoinError {
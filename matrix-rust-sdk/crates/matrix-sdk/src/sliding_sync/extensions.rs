@@ -0,0 +1,304 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Custom sliding sync extensions.
+//!
+//! The SDK knows about a few extensions (e2ee, to-device, account data, typing
+//! and receipts). Others, like experimental MSCs or server-specific data, can
+//! be registered with [`SlidingSyncBuilder::with_custom_extension`] by
+//! implementing [`SlidingSyncExtension`].
+//!
+//! [`SlidingSyncBuilder::with_custom_extension`]: super::SlidingSyncBuilder::with_custom_extension
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use bytes::BufMut;
+use ruma::{
+    api::{
+        error::{FromHttpResponseError, IntoHttpError},
+        IncomingResponse, MatrixVersion, Metadata, OutgoingRequest, SendAccessToken,
+    },
+    serde::{JsonObject, Raw},
+};
+use serde::{de::DeserializeOwned, ser::Error as _, Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// A sliding sync extension that the SDK doesn't know about.
+///
+/// The request part of the extension is made of its [configuration], which is
+/// a sticky parameter, and its [state], which is sent with every request. The
+/// response part is deserialized and given to [`Self::handle_response`].
+///
+/// [configuration]: Self::Config
+/// [state]: Self::State
+pub trait SlidingSyncExtension: Send + Sync + 'static {
+    /// The configuration of the extension, e.g. whether it's enabled.
+    ///
+    /// It's a sticky parameter: it's only sent with the first request of a
+    /// session, and after the sticky parameters have been invalidated. It must
+    /// serialize to a JSON object.
+    type Config: Serialize;
+
+    /// The response of the extension.
+    type Response: DeserializeOwned;
+
+    /// The state of the extension, e.g. a `since` token.
+    ///
+    /// It's cached with the sliding sync, and sent with every request, merged
+    /// into the configuration. It must serialize to a JSON object.
+    type State: Default + Serialize + DeserializeOwned + Send;
+
+    /// The name of the extension, i.e. its key in the `extensions` of the
+    /// requests and the responses.
+    ///
+    /// It must not be the name of an extension known by the SDK, otherwise
+    /// [`SlidingSyncBuilder::build`] fails.
+    ///
+    /// [`SlidingSyncBuilder::build`]: super::SlidingSyncBuilder::build
+    fn name(&self) -> &str;
+
+    /// The configuration of the extension.
+    fn config(&self) -> Self::Config;
+
+    /// Handle the response of the extension, and update its state accordingly.
+    ///
+    /// It's called once the rest of the sliding sync response has been
+    /// handled.
+    fn handle_response(&self, response: Self::Response, state: &mut Self::State);
+}
+
+/// A [`SlidingSyncExtension`] whose types have been erased, with its state.
+pub(super) trait AnySlidingSyncExtension: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn config(&self) -> serde_json::Result<JsonObject>;
+
+    fn state(&self) -> serde_json::Result<JsonObject>;
+
+    fn restore_state(&self, state: JsonObject) -> serde_json::Result<()>;
+
+    fn handle_response(&self, response: &Raw<JsonValue>) -> serde_json::Result<()>;
+}
+
+impl fmt::Debug for dyn AnySlidingSyncExtension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlidingSyncExtension").field("name", &self.name()).finish_non_exhaustive()
+    }
+}
+
+/// A custom extension, shared by the builder and the sliding sync.
+pub(super) type SharedExtension = Arc<dyn AnySlidingSyncExtension>;
+
+struct ExtensionWithState<E: SlidingSyncExtension> {
+    extension: E,
+    state: StdMutex<E::State>,
+}
+
+pub(super) fn erase_extension<E: SlidingSyncExtension>(extension: E) -> SharedExtension {
+    Arc::new(ExtensionWithState { extension, state: Default::default() })
+}
+
+impl<E: SlidingSyncExtension> AnySlidingSyncExtension for ExtensionWithState<E> {
+    fn name(&self) -> &str {
+        self.extension.name()
+    }
+
+    fn config(&self) -> serde_json::Result<JsonObject> {
+        to_json_object(&self.extension.config())
+    }
+
+    fn state(&self) -> serde_json::Result<JsonObject> {
+        to_json_object(&*self.state.lock().unwrap())
+    }
+
+    fn restore_state(&self, state: JsonObject) -> serde_json::Result<()> {
+        *self.state.lock().unwrap() = serde_json::from_value(JsonValue::Object(state))?;
+        Ok(())
+    }
+
+    fn handle_response(&self, response: &Raw<JsonValue>) -> serde_json::Result<()> {
+        let response = response.deserialize_as()?;
+        self.extension.handle_response(response, &mut self.state.lock().unwrap());
+        Ok(())
+    }
+}
+
+/// Serialize a value that must be a JSON object, `null` being an empty one.
+fn to_json_object(value: &impl Serialize) -> serde_json::Result<JsonObject> {
+    match serde_json::to_value(value)? {
+        JsonValue::Object(object) => Ok(object),
+        JsonValue::Null => Ok(JsonObject::new()),
+        _ => Err(serde_json::Error::custom("a sliding sync extension must be a JSON object")),
+    }
+}
+
+/// Get the fields of the extension with the given name, in the `extensions` of
+/// an MSC3575 or MSC4186 request.
+pub(super) fn get_custom_extension<T: Serialize>(
+    extensions: &T,
+    name: &str,
+) -> serde_json::Result<Option<JsonObject>> {
+    Ok(match serde_json::to_value(extensions)? {
+        JsonValue::Object(mut object) => match object.remove(name) {
+            Some(JsonValue::Object(fields)) => Some(fields),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// The names of the extensions known by the SDK, that can't be used by a
+/// custom extension.
+pub(super) const KNOWN_EXTENSIONS: &[&str] =
+    &["to_device", "e2ee", "account_data", "receipts", "typing"];
+
+/// Merge fields into the extension with the given name, in the `extensions` of
+/// an MSC3575 or MSC4186 request.
+///
+/// The extensions unknown to Ruma are kept in a flattened map, that can only
+/// be reached with a round-trip through JSON.
+pub(super) fn merge_into_custom_extension<T: Serialize + DeserializeOwned>(
+    extensions: &mut T,
+    name: &str,
+    fields: JsonObject,
+) -> serde_json::Result<()> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+
+    let JsonValue::Object(mut object) = serde_json::to_value(&*extensions)? else {
+        return Err(serde_json::Error::custom("the extensions must be a JSON object"));
+    };
+
+    match object.entry(name).or_insert_with(|| JsonValue::Object(JsonObject::new())) {
+        JsonValue::Object(extension) => extension.extend(fields),
+        extension => *extension = JsonValue::Object(fields),
+    }
+
+    *extensions = serde_json::from_value(JsonValue::Object(object))?;
+
+    Ok(())
+}
+
+/// A sliding sync request whose response keeps the raw extensions, so that
+/// the custom ones can be handled.
+#[derive(Clone, Debug)]
+pub(super) struct WithCustomExtensions<R>(pub R);
+
+impl<R: OutgoingRequest> OutgoingRequest for WithCustomExtensions<R> {
+    type EndpointError = R::EndpointError;
+    type IncomingResponse = CustomExtensionsResponse<R::IncomingResponse>;
+
+    const METADATA: Metadata = R::METADATA;
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &'_ [MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        self.0.try_into_http_request(base_url, access_token, considering_versions)
+    }
+}
+
+/// The response of a [`WithCustomExtensions`] request.
+#[derive(Debug)]
+pub(super) struct CustomExtensionsResponse<R> {
+    /// The response parsed by Ruma.
+    pub response: R,
+
+    /// All the extensions of the response, by name.
+    pub extensions: BTreeMap<String, Raw<JsonValue>>,
+}
+
+impl<R: IncomingResponse> IncomingResponse for CustomExtensionsResponse<R> {
+    type EndpointError = R::EndpointError;
+
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<Self::EndpointError>> {
+        #[derive(Deserialize)]
+        struct Body {
+            #[serde(default)]
+            extensions: BTreeMap<String, Raw<JsonValue>>,
+        }
+
+        // Errors are reported by the inner response.
+        let extensions = if response.status().is_success() {
+            serde_json::from_slice::<Body>(response.body().as_ref())
+                .map(|body| body.extensions)
+                .unwrap_or_default()
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self { response: R::try_from_http_response(response)?, extensions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::serde::JsonObject;
+    use serde_json::json;
+
+    use super::{get_custom_extension, merge_into_custom_extension};
+    use crate::sliding_sync::http;
+
+    fn object(value: serde_json::Value) -> JsonObject {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_custom_extensions_round_trip() {
+        let mut extensions = http::request::Extensions::default();
+        extensions.typing.enabled = Some(true);
+
+        assert!(get_custom_extension(&extensions, "org.example.foo").unwrap().is_none());
+
+        merge_into_custom_extension(
+            &mut extensions,
+            "org.example.foo",
+            object(json!({ "enabled": true })),
+        )
+        .unwrap();
+        merge_into_custom_extension(
+            &mut extensions,
+            "org.example.foo",
+            object(json!({ "since": "s1" })),
+        )
+        .unwrap();
+
+        // Known extensions are kept.
+        assert_eq!(extensions.typing.enabled, Some(true));
+
+        assert_eq!(
+            get_custom_extension(&extensions, "org.example.foo").unwrap().unwrap(),
+            object(json!({ "enabled": true, "since": "s1" }))
+        );
+
+        // It survives the conversion to MSC3575, once copied.
+        let fields = get_custom_extension(&extensions, "org.example.foo").unwrap().unwrap();
+        let mut msc3575_extensions = http::msc3575::request::Extensions::from(extensions);
+        merge_into_custom_extension(&mut msc3575_extensions, "org.example.foo", fields).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&msc3575_extensions).unwrap()["org.example.foo"],
+            json!({ "enabled": true, "since": "s1" })
+        );
+    }
+}
@@ -147,6 +147,12 @@ impl SlidingSyncList {
         self.inner.maximum_number_of_rooms.get()
     }
 
+    /// Set the maximum number of rooms, when it isn't known from the sliding
+    /// sync responses, e.g. because `/sync` is used instead.
+    pub fn set_maximum_number_of_rooms(&self, maximum_number_of_rooms: Option<u32>) {
+        self.inner.maximum_number_of_rooms.set_if_not_eq(maximum_number_of_rooms);
+    }
+
     /// Get a stream of rooms count.
     ///
     /// If this list has been reloaded from a cache, the initial value is
@@ -209,11 +215,6 @@ impl SlidingSyncList {
 #[cfg(any(test, feature = "testing"))]
 #[allow(dead_code)]
 impl SlidingSyncList {
-    /// Set the maximum number of rooms.
-    pub(super) fn set_maximum_number_of_rooms(&self, maximum_number_of_rooms: Option<u32>) {
-        self.inner.maximum_number_of_rooms.set(maximum_number_of_rooms);
-    }
-
     /// Get the sync-mode.
     pub fn sync_mode(&self) -> SlidingSyncMode {
         self.inner.sync_mode.read().unwrap().clone()
@@ -19,6 +19,7 @@ mod builder;
 mod cache;
 mod client;
 mod error;
+mod extensions;
 mod list;
 mod room;
 mod sticky_parameters;
@@ -39,9 +40,12 @@ pub use matrix_sdk_base::sliding_sync::http;
 use matrix_sdk_common::{deserialized_responses::SyncTimelineEvent, timer};
 use ruma::{
     api::{client::error::ErrorKind, OutgoingRequest},
-    assign, OwnedEventId, OwnedRoomId, RoomId,
+    assign,
+    serde::{JsonObject, Raw},
+    OwnedEventId, OwnedRoomId, RoomId,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use tokio::{
     select, spawn,
     sync::{broadcast::Sender, Mutex as AsyncMutex, OwnedMutexGuard, RwLock as AsyncRwLock},
@@ -50,13 +54,24 @@ use tracing::{debug, error, info, instrument, trace, warn, Instrument, Span};
 
 #[cfg(feature = "e2e-encryption")]
 use self::utils::JoinHandleExt as _;
-pub use self::{builder::*, client::VersionBuilderError, error::*, list::*, room::*};
+pub use self::{
+    builder::*, client::VersionBuilderError, error::*, extensions::SlidingSyncExtension, list::*,
+    room::*,
+};
 use self::{
     cache::restore_sliding_sync_state,
     client::SlidingSyncResponseProcessor,
+    extensions::{
+        get_custom_extension, merge_into_custom_extension, CustomExtensionsResponse,
+        SharedExtension, WithCustomExtensions,
+    },
     sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager, StickyData},
 };
-use crate::{config::RequestConfig, Client, HttpError, Result};
+use crate::{
+    config::RequestConfig,
+    sync_checkpoints::{SyncCheckpoint, SyncPosition},
+    Client, HttpError, Result,
+};
 
 /// The Sliding Sync instance.
 ///
@@ -122,6 +137,16 @@ pub(super) struct SlidingSyncInner {
     /// Request parameters that are sticky.
     sticky: StdRwLock<SlidingSyncStickyManager<SlidingSyncStickyParameters>>,
 
+    /// Room subscriptions, i.e. rooms that may be out-of-scope of all lists
+    /// but one wants to receive updates.
+    ///
+    /// They aren't sticky: the server only keeps the ones of the last request,
+    /// so they are all sent with every request.
+    room_subscriptions: StdRwLock<BTreeMap<OwnedRoomId, http::request::RoomSubscription>>,
+
+    /// The custom extensions, by name, with their state.
+    custom_extensions: BTreeMap<String, SharedExtension>,
+
     /// Internal channel used to pass messages between Sliding Sync and other
     /// types.
     internal_channel: Sender<SlidingSyncInternalMessage>,
@@ -154,24 +179,20 @@ impl SlidingSync {
         cancel_in_flight_request: bool,
     ) {
         let settings = settings.unwrap_or_default();
-        let mut sticky = self.inner.sticky.write().unwrap();
-        let room_subscriptions = &mut sticky.data_mut().room_subscriptions;
+        let mut room_subscriptions = self.inner.room_subscriptions.write().unwrap();
 
         let mut skip_over_current_sync_loop_iteration = false;
 
         for room_id in room_ids {
-            // If the room subscription already exists, let's not
-            // override it with a new one. First, it would reset its
-            // state (`RoomSubscriptionState`), and second it would try to
-            // re-subscribe with the next request. We don't want that. A room
-            // subscription should happen once, and next subscriptions should
-            // be ignored.
+            // If the room subscription already exists, let's not override it with a new
+            // one, and let's not mark the members as missing again: use
+            // `update_room_subscription` to change its settings.
             if let Entry::Vacant(entry) = room_subscriptions.entry((*room_id).to_owned()) {
                 if let Some(room) = self.inner.client.get_room(room_id) {
                     room.mark_members_missing();
                 }
 
-                entry.insert((RoomSubscriptionState::default(), settings.clone()));
+                entry.insert(settings.clone());
 
                 skip_over_current_sync_loop_iteration = true;
             }
@@ -184,6 +205,54 @@ impl SlidingSync {
         }
     }
 
+    /// Unsubscribe from many rooms.
+    ///
+    /// The room subscriptions are sent with every request, so the next request
+    /// doesn't contain these rooms anymore, and the server stops sending
+    /// updates for them, unless they are in the range of a list.
+    ///
+    /// An unsubscription from a room that isn't subscribed is ignored.
+    pub fn unsubscribe_from_rooms(&self, room_ids: &[&RoomId], cancel_in_flight_request: bool) {
+        let mut room_subscriptions = self.inner.room_subscriptions.write().unwrap();
+
+        let mut has_unsubscribed = false;
+
+        for room_id in room_ids {
+            has_unsubscribed |= room_subscriptions.remove(*room_id).is_some();
+        }
+
+        if has_unsubscribed && cancel_in_flight_request {
+            self.inner.internal_channel_send_if_possible(
+                SlidingSyncInternalMessage::SyncLoopSkipOverCurrentIteration,
+            );
+        }
+    }
+
+    /// Replace the settings of a room subscription.
+    ///
+    /// The next request contains the new settings. An update of a room that
+    /// isn't subscribed is ignored.
+    pub fn update_room_subscription(
+        &self,
+        room_id: &RoomId,
+        settings: http::request::RoomSubscription,
+        cancel_in_flight_request: bool,
+    ) {
+        let mut room_subscriptions = self.inner.room_subscriptions.write().unwrap();
+
+        let Some(room_subscription) = room_subscriptions.get_mut(room_id) else {
+            return;
+        };
+
+        *room_subscription = settings;
+
+        if cancel_in_flight_request {
+            self.inner.internal_channel_send_if_possible(
+                SlidingSyncInternalMessage::SyncLoopSkipOverCurrentIteration,
+            );
+        }
+    }
+
     /// Lookup a specific room
     pub async fn get_room(&self, room_id: &RoomId) -> Option<SlidingSyncRoom> {
         self.inner.rooms.read().await.get(room_id).cloned()
@@ -532,6 +601,10 @@ impl SlidingSync {
         // Apply sticky parameters, if needs be.
         self.inner.sticky.write().unwrap().maybe_apply(&mut request, txn_id);
 
+        // The server forgets the room subscriptions that aren't in the request, so send
+        // all of them.
+        request.room_subscriptions = self.inner.room_subscriptions.read().unwrap().clone();
+
         // Extensions are now applied (via sticky parameters).
         //
         // Override the to-device token if the extension is enabled.
@@ -540,6 +613,11 @@ impl SlidingSync {
                 restored_fields.and_then(|fields| fields.to_device_token);
         }
 
+        // Add the state of the custom extensions, which isn't sticky.
+        for (name, extension) in &self.inner.custom_extensions {
+            merge_into_custom_extension(&mut request.extensions, name, extension.state()?)?;
+        }
+
         // Apply the transaction id if one was generated.
         if let Some(txn_id) = txn_id.get() {
             request.txn_id = Some(txn_id.to_string());
@@ -577,11 +655,32 @@ impl SlidingSync {
     {
         debug!("Sending request");
 
-        // Prepare the request.
-        let request =
-            self.inner.client.send(request, Some(request_config)).with_homeserver_override(
-                self.inner.version.overriding_url().map(ToString::to_string),
-            );
+        // Prepare the request. When there are custom extensions, it's wrapped to keep
+        // the custom extensions of the response, that Ruma doesn't know about.
+        // Otherwise, there's no need to parse the response twice.
+        let request = {
+            let client = self.inner.client.clone();
+            let homeserver_override = self.inner.version.overriding_url().map(ToString::to_string);
+            let has_custom_extensions = !self.inner.custom_extensions.is_empty();
+
+            async move {
+                if has_custom_extensions {
+                    let CustomExtensionsResponse { response, extensions } = client
+                        .send(WithCustomExtensions(request), Some(request_config))
+                        .with_homeserver_override(homeserver_override)
+                        .await?;
+
+                    Ok::<_, HttpError>((response, extensions))
+                } else {
+                    let response = client
+                        .send(request, Some(request_config))
+                        .with_homeserver_override(homeserver_override)
+                        .await?;
+
+                    Ok((response, BTreeMap::new()))
+                }
+            }
+        };
 
         // Send the request and get a response with end-to-end encryption support.
         //
@@ -643,6 +742,7 @@ impl SlidingSync {
         // The code manipulates `Request` and `Response` from MSC4186 because it's the
         // future standard. But this function may have received a `Request` from MSC4186
         // or MSC3575. We need to get back an MSC4186 `Response`.
+        let (response, custom_extensions) = response;
         let response = Into::<http::msc4186::Response>::into(response);
 
         debug!("Received response");
@@ -670,6 +770,8 @@ impl SlidingSync {
             // Handle the response.
             let updates = this.handle_response(response, &mut position_guard).await?;
 
+            this.handle_custom_extensions(&custom_extensions);
+
             this.cache_to_storage(&position_guard).await?;
 
             // Release the position guard lock.
@@ -684,6 +786,20 @@ impl SlidingSync {
         spawn(future.instrument(Span::current())).await.unwrap()
     }
 
+    /// Handle the custom extensions of a response.
+    ///
+    /// Errors are only logged: an extension unknown to the SDK must not break
+    /// the sync.
+    fn handle_custom_extensions(&self, extensions: &BTreeMap<String, Raw<JsonValue>>) {
+        for (name, extension) in &self.inner.custom_extensions {
+            if let Some(response) = extensions.get(name) {
+                if let Err(error) = extension.handle_response(response) {
+                    warn!(name, "Couldn't handle the response of a custom extension: {error}");
+                }
+            }
+        }
+    }
+
     /// Is the e2ee extension enabled for this sliding sync instance?
     #[cfg(feature = "e2e-encryption")]
     fn is_e2ee_enabled(&self) -> bool {
@@ -699,7 +815,7 @@ impl SlidingSync {
     async fn must_process_rooms_response(&self) -> bool {
         // We consider that we must, if there's any room subscription or there's any
         // list.
-        !self.inner.sticky.read().unwrap().data().room_subscriptions.is_empty()
+        !self.inner.room_subscriptions.read().unwrap().is_empty()
             || !self.inner.lists.read().await.is_empty()
     }
 
@@ -712,16 +828,30 @@ impl SlidingSync {
         // the future standard (at the time of writing: 2024-09-09). Let's check if
         // the generated request must be transformed into an MSC3575 `Request`.
         let summaries = if !self.inner.version.is_native() {
-            self.send_sync_request(
-                Into::<http::msc3575::Request>::into(request),
-                request_config,
-                position_guard,
-            )
-            .await?
+            // The custom extensions are lost in the conversion, copy them.
+            let mut custom_extensions = Vec::new();
+            for name in self.inner.custom_extensions.keys() {
+                if let Some(fields) = get_custom_extension(&request.extensions, name)? {
+                    custom_extensions.push((name, fields));
+                }
+            }
+
+            let mut request = Into::<http::msc3575::Request>::into(request);
+
+            for (name, fields) in custom_extensions {
+                merge_into_custom_extension(&mut request.extensions, name, fields)?;
+            }
+
+            self.send_sync_request(request, request_config, position_guard).await?
         } else {
             self.send_sync_request(request, request_config, position_guard).await?
         };
 
+        let pos = self.inner.position.lock().await.pos.clone();
+        if let Some(pos) = pos {
+            self.inner.client.maybe_take_sliding_sync_checkpoint(&self.inner.id, pos).await;
+        }
+
         // Notify a new sync was received
         self.inner.client.inner.sync_beat.notify(usize::MAX);
 
@@ -831,16 +961,44 @@ impl SlidingSync {
             }
         }
 
-        {
-            let mut sticky = self.inner.sticky.write().unwrap();
+        // Invalidate the sticky parameters, so that they are sent again with the next
+        // request.
+        let _ = self.inner.sticky.write().unwrap().data_mut();
 
-            // Clear all room subscriptions: we don't want to resend all room subscriptions
-            // when the session will restart.
-            sticky.data_mut().room_subscriptions.clear();
-        }
+        // Clear all room subscriptions: we don't want to resend all room subscriptions
+        // when the session will restart.
+        self.inner.room_subscriptions.write().unwrap().clear();
 
         self.inner.lists.read().await.values().for_each(|list| list.invalidate_sticky_data());
     }
+
+    /// Resume from the position of the given sync checkpoint, once it has been
+    /// restored with [`Client::restore_checkpoint`].
+    ///
+    /// Returns false if the checkpoint has no position for the connection of
+    /// this sliding sync, in which case nothing changes.
+    ///
+    /// This method **MUST** be called when the sync loop is stopped.
+    pub async fn restore_checkpoint_position(&self, checkpoint: &SyncCheckpoint) -> bool {
+        let SyncPosition::SlidingSync { positions } = &checkpoint.position else {
+            return false;
+        };
+
+        let Some(pos) = positions.get(&self.inner.id) else {
+            return false;
+        };
+
+        info!(checkpoint = checkpoint.id, "Resuming from a sync checkpoint");
+
+        let mut position = self.inner.position.lock().await;
+        position.pos = Some(pos.clone());
+
+        if let Err(err) = self.cache_to_storage(&position).await {
+            error!("couldn't save the sliding sync position of the checkpoint: {err}");
+        }
+
+        true
+    }
 }
 
 impl SlidingSyncInner {
@@ -910,10 +1068,16 @@ struct FrozenSlidingSync {
     to_device_since: Option<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     rooms: Vec<FrozenSlidingSyncRoom>,
+    /// The state of the custom extensions, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    custom_extensions: BTreeMap<String, JsonObject>,
 }
 
 impl FrozenSlidingSync {
-    fn new(rooms: &BTreeMap<OwnedRoomId, SlidingSyncRoom>) -> Self {
+    fn new(
+        rooms: &BTreeMap<OwnedRoomId, SlidingSyncRoom>,
+        custom_extensions: BTreeMap<String, JsonObject>,
+    ) -> Self {
         // The to-device token must be saved in the `FrozenCryptoSlidingSync` now.
         Self {
             to_device_since: None,
@@ -921,6 +1085,7 @@ impl FrozenSlidingSync {
                 .iter()
                 .map(|(_room_id, sliding_sync_room)| FrozenSlidingSyncRoom::from(sliding_sync_room))
                 .collect::<Vec<_>>(),
+            custom_extensions,
         }
     }
 }
@@ -941,31 +1106,10 @@ pub struct UpdateSummary {
     pub rooms: Vec<OwnedRoomId>,
 }
 
-/// A very basic bool-ish enum to represent the state of a
-/// [`http::request::RoomSubscription`]. A `RoomSubscription` that has been sent
-/// once should ideally not being sent again, to mostly save bandwidth.
-#[derive(Debug, Default)]
-enum RoomSubscriptionState {
-    /// The `RoomSubscription` has not been sent or received correctly from the
-    /// server, i.e. the `RoomSubscription` —which is part of the sticky
-    /// parameters— has not been committed.
-    #[default]
-    Pending,
-
-    /// The `RoomSubscription` has been sent and received correctly by the
-    /// server.
-    Applied,
-}
-
 /// The set of sticky parameters owned by the `SlidingSyncInner` instance, and
 /// sent in the request.
 #[derive(Debug)]
 pub(super) struct SlidingSyncStickyParameters {
-    /// Room subscriptions, i.e. rooms that may be out-of-scope of all lists
-    /// but one wants to receive updates.
-    room_subscriptions:
-        BTreeMap<OwnedRoomId, (RoomSubscriptionState, http::request::RoomSubscription)>,
-
     /// The intended state of the extensions being supplied to sliding /sync
     /// calls.
     extensions: http::request::Extensions,
@@ -973,19 +1117,8 @@ pub(super) struct SlidingSyncStickyParameters {
 
 impl SlidingSyncStickyParameters {
     /// Create a new set of sticky parameters.
-    pub fn new(
-        room_subscriptions: BTreeMap<OwnedRoomId, http::request::RoomSubscription>,
-        extensions: http::request::Extensions,
-    ) -> Self {
-        Self {
-            room_subscriptions: room_subscriptions
-                .into_iter()
-                .map(|(room_id, room_subscription)| {
-                    (room_id, (RoomSubscriptionState::Pending, room_subscription))
-                })
-                .collect(),
-            extensions,
-        }
+    pub fn new(extensions: http::request::Extensions) -> Self {
+        Self { extensions }
     }
 }
 
@@ -993,23 +1126,8 @@ impl StickyData for SlidingSyncStickyParameters {
     type Request = http::Request;
 
     fn apply(&self, request: &mut Self::Request) {
-        request.room_subscriptions = self
-            .room_subscriptions
-            .iter()
-            .filter(|(_, (state, _))| matches!(state, RoomSubscriptionState::Pending))
-            .map(|(room_id, (_, room_subscription))| (room_id.clone(), room_subscription.clone()))
-            .collect();
         request.extensions = self.extensions.clone();
     }
-
-    fn on_commit(&mut self) {
-        // All room subscriptions are marked as `Applied`.
-        for (state, _room_subscription) in self.room_subscriptions.values_mut() {
-            if matches!(state, RoomSubscriptionState::Pending) {
-                *state = RoomSubscriptionState::Applied;
-            }
-        }
-    }
 }
 
 /// As of 2023-07-13, the sliding sync proxy doesn't provide us with `limited`
@@ -1108,7 +1226,7 @@ mod tests {
         api::client::error::ErrorKind, assign, owned_room_id, room_id, serde::Raw, uint,
         OwnedRoomId, TransactionId,
     };
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use serde_json::json;
     use url::Url;
     use wiremock::{http::Method, Match, Mock, MockServer, Request, ResponseTemplate};
@@ -1116,11 +1234,16 @@ mod tests {
     use super::{
         compute_limited, http,
         sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager},
-        FrozenSlidingSync, SlidingSync, SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
-        SlidingSyncRoom, SlidingSyncStickyParameters, Version,
+        Error, FrozenSlidingSync, SlidingSync, SlidingSyncExtension, SlidingSyncList,
+        SlidingSyncListBuilder, SlidingSyncMode, SlidingSyncRoom, SlidingSyncStickyParameters,
+        Version,
     };
     use crate::{
-        sliding_sync::cache::restore_sliding_sync_state, test_utils::logged_in_client, Result,
+        config::RequestConfig,
+        sliding_sync::cache::restore_sliding_sync_state,
+        sync_checkpoints::{SyncCheckpoint, SyncCheckpointsConfig, SyncPosition},
+        test_utils::{logged_in_client, set_client_session, test_client_builder},
+        Result,
     };
 
     #[derive(Copy, Clone)]
@@ -1227,8 +1350,7 @@ mod tests {
         assert!(room0.are_members_synced().not());
 
         {
-            let sticky = sliding_sync.inner.sticky.read().unwrap();
-            let room_subscriptions = &sticky.data().room_subscriptions;
+            let room_subscriptions = sliding_sync.inner.room_subscriptions.read().unwrap();
 
             assert!(room_subscriptions.contains_key(room_id_0));
             assert!(room_subscriptions.contains_key(room_id_1));
@@ -1270,6 +1392,42 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_unsubscribe_from_rooms() -> Result<()> {
+        let (_server, sliding_sync) = new_sliding_sync(vec![SlidingSyncList::builder("foo")
+            .sync_mode(SlidingSyncMode::new_selective().add_range(0..=10))])
+        .await?;
+
+        let room_id_0 = room_id!("!r0:bar.org");
+        let room_id_1 = room_id!("!r1:bar.org");
+
+        sliding_sync.subscribe_to_rooms(&[room_id_0, room_id_1], None, false);
+
+        // Both subscriptions are sent.
+        {
+            let (request, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(request.room_subscriptions.len(), 2);
+        }
+
+        sliding_sync.unsubscribe_from_rooms(&[room_id_0], false);
+
+        // Only the remaining subscription is sent, so that the server drops the other
+        // one.
+        {
+            let (request, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(request.room_subscriptions.len(), 1);
+            assert!(request.room_subscriptions.contains_key(room_id_1));
+        }
+
+        // Unsubscribing from an unknown room is a no-op.
+        sliding_sync.unsubscribe_from_rooms(&[room_id!("!r2:bar.org")], false);
+        assert_eq!(sliding_sync.inner.room_subscriptions.read().unwrap().len(), 1);
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_room_subscriptions_are_reset_when_session_expires() -> Result<()> {
         let (_server, sliding_sync) = new_sliding_sync(vec![SlidingSyncList::builder("foo")
@@ -1284,8 +1442,7 @@ mod tests {
         sliding_sync.subscribe_to_rooms(&[room_id_0, room_id_1], None, false);
 
         {
-            let sticky = sliding_sync.inner.sticky.read().unwrap();
-            let room_subscriptions = &sticky.data().room_subscriptions;
+            let room_subscriptions = sliding_sync.inner.room_subscriptions.read().unwrap();
 
             assert!(room_subscriptions.contains_key(room_id_0));
             assert!(room_subscriptions.contains_key(room_id_1));
@@ -1296,8 +1453,7 @@ mod tests {
         sliding_sync.subscribe_to_rooms(&[room_id_2], None, false);
 
         {
-            let sticky = sliding_sync.inner.sticky.read().unwrap();
-            let room_subscriptions = &sticky.data().room_subscriptions;
+            let room_subscriptions = sliding_sync.inner.room_subscriptions.read().unwrap();
 
             assert!(room_subscriptions.contains_key(room_id_0));
             assert!(room_subscriptions.contains_key(room_id_1));
@@ -1308,8 +1464,7 @@ mod tests {
         sliding_sync.expire_session().await;
 
         {
-            let sticky = sliding_sync.inner.sticky.read().unwrap();
-            let room_subscriptions = &sticky.data().room_subscriptions;
+            let room_subscriptions = sliding_sync.inner.room_subscriptions.read().unwrap();
 
             assert!(room_subscriptions.is_empty());
         }
@@ -1318,8 +1473,7 @@ mod tests {
         sliding_sync.subscribe_to_rooms(&[room_id_2], None, false);
 
         {
-            let sticky = sliding_sync.inner.sticky.read().unwrap();
-            let room_subscriptions = &sticky.data().room_subscriptions;
+            let room_subscriptions = sliding_sync.inner.room_subscriptions.read().unwrap();
 
             assert!(room_subscriptions.contains_key(room_id_0).not());
             assert!(room_subscriptions.contains_key(room_id_1).not());
@@ -1337,7 +1491,8 @@ mod tests {
 
         // FrozenSlidingSync doesn't contain the to_device_token anymore, as it's saved
         // in the crypto store since PR #2323.
-        let frozen = FrozenSlidingSync::new(&*sliding_sync.inner.rooms.read().await);
+        let frozen =
+            FrozenSlidingSync::new(&*sliding_sync.inner.rooms.read().await, BTreeMap::new());
         assert!(frozen.to_device_since.is_none());
 
         Ok(())
@@ -1371,17 +1526,12 @@ mod tests {
 
     #[test]
     fn test_sticky_parameters_api_invalidated_flow() {
-        let r0 = room_id!("!r0.matrix.org");
-        let r1 = room_id!("!r1:matrix.org");
-
-        let mut room_subscriptions = BTreeMap::new();
-        room_subscriptions.insert(r0.to_owned(), Default::default());
+        let mut extensions = http::request::Extensions::default();
+        extensions.account_data.enabled = Some(true);
 
         // At first it's invalidated.
-        let mut sticky = SlidingSyncStickyManager::new(SlidingSyncStickyParameters::new(
-            room_subscriptions,
-            Default::default(),
-        ));
+        let mut sticky =
+            SlidingSyncStickyManager::new(SlidingSyncStickyParameters::new(extensions));
         assert!(sticky.is_invalidated());
 
         // Then when we create a request, the sticky parameters are applied.
@@ -1393,19 +1543,20 @@ mod tests {
         sticky.maybe_apply(&mut request, &mut LazyTransactionId::from_owned(txn_id.to_owned()));
 
         assert!(request.txn_id.is_some());
-        assert_eq!(request.room_subscriptions.len(), 1);
-        assert!(request.room_subscriptions.contains_key(r0));
+        assert_eq!(request.extensions.account_data.enabled, Some(true));
 
         let tid = request.txn_id.unwrap();
 
         sticky.maybe_commit(tid.as_str().into());
         assert!(!sticky.is_invalidated());
 
+        // Once committed, the sticky parameters aren't applied anymore.
+        let mut request = http::Request::default();
+        sticky.maybe_apply(&mut request, &mut LazyTransactionId::new());
+        assert_eq!(request.extensions.account_data.enabled, None);
+
         // Applying new parameters will invalidate again.
-        sticky
-            .data_mut()
-            .room_subscriptions
-            .insert(r1.to_owned(), (Default::default(), Default::default()));
+        sticky.data_mut().extensions.receipts.enabled = Some(true);
         assert!(sticky.is_invalidated());
 
         // Committing with the wrong transaction id will keep it invalidated.
@@ -1419,11 +1570,7 @@ mod tests {
         sticky.maybe_apply(&mut request1, &mut LazyTransactionId::from_owned(txn_id1.to_owned()));
 
         assert!(sticky.is_invalidated());
-        // The first room subscription has been applied to `request`, so it's not
-        // reapplied here. It's a particular logic of `room_subscriptions`, it's not
-        // related to the sticky design.
-        assert_eq!(request1.room_subscriptions.len(), 1);
-        assert!(request1.room_subscriptions.contains_key(r1));
+        assert_eq!(request1.extensions.receipts.enabled, Some(true));
 
         let txn_id2: &TransactionId = "tid789".into();
         let mut request2 = http::Request::default();
@@ -1431,10 +1578,9 @@ mod tests {
 
         sticky.maybe_apply(&mut request2, &mut LazyTransactionId::from_owned(txn_id2.to_owned()));
         assert!(sticky.is_invalidated());
-        // `request2` contains `r1` because the sticky parameters have not been
-        // committed, so it's still marked as pending.
-        assert_eq!(request2.room_subscriptions.len(), 1);
-        assert!(request2.room_subscriptions.contains_key(r1));
+        // `request2` contains the extensions because the sticky parameters have not
+        // been committed.
+        assert_eq!(request2.extensions.receipts.enabled, Some(true));
 
         // Here we commit with the not most-recent TID, so it keeps the invalidated
         // status.
@@ -1446,99 +1592,39 @@ mod tests {
         assert!(!sticky.is_invalidated());
     }
 
-    #[test]
-    fn test_room_subscriptions_are_sticky() {
-        let r0 = room_id!("!r0.matrix.org");
+    #[async_test]
+    async fn test_room_subscriptions_are_sent_with_every_request() -> Result<()> {
+        let (_server, sliding_sync) = new_sliding_sync(vec![SlidingSyncList::builder("foo")
+            .sync_mode(SlidingSyncMode::new_selective().add_range(0..=10))])
+        .await?;
+
+        let r0 = room_id!("!r0:matrix.org");
         let r1 = room_id!("!r1:matrix.org");
 
-        let mut sticky = SlidingSyncStickyManager::new(SlidingSyncStickyParameters::new(
-            BTreeMap::new(),
-            Default::default(),
-        ));
+        sliding_sync.subscribe_to_rooms(&[r0], None, false);
 
-        // A room subscription is added, applied, and committed.
+        // The room subscription is sent, and the sticky parameters are committed.
         {
-            // Insert `r0`.
-            sticky
-                .data_mut()
-                .room_subscriptions
-                .insert(r0.to_owned(), (Default::default(), Default::default()));
-
-            // Then the sticky parameters are applied.
-            let txn_id: &TransactionId = "tid0".into();
-            let mut request = http::Request::default();
-            request.txn_id = Some(txn_id.to_string());
-
-            sticky.maybe_apply(&mut request, &mut LazyTransactionId::from_owned(txn_id.to_owned()));
-
-            assert!(request.txn_id.is_some());
+            let mut txn_id = LazyTransactionId::new();
+            let (request, _, _) = sliding_sync.generate_sync_request(&mut txn_id).await?;
             assert_eq!(request.room_subscriptions.len(), 1);
             assert!(request.room_subscriptions.contains_key(r0));
 
-            // Then the sticky parameters are committed.
-            let tid = request.txn_id.unwrap();
-
-            sticky.maybe_commit(tid.as_str().into());
+            sliding_sync.inner.sticky.write().unwrap().maybe_commit(txn_id.get().unwrap());
         }
 
-        // A room subscription is added, applied, but NOT committed.
-        {
-            // Insert `r1`.
-            sticky
-                .data_mut()
-                .room_subscriptions
-                .insert(r1.to_owned(), (Default::default(), Default::default()));
-
-            // Then the sticky parameters are applied.
-            let txn_id: &TransactionId = "tid1".into();
-            let mut request = http::Request::default();
-            request.txn_id = Some(txn_id.to_string());
-
-            sticky.maybe_apply(&mut request, &mut LazyTransactionId::from_owned(txn_id.to_owned()));
-
-            assert!(request.txn_id.is_some());
-            assert_eq!(request.room_subscriptions.len(), 1);
-            // `r0` is not present, it's only `r1`.
-            assert!(request.room_subscriptions.contains_key(r1));
-
-            // Then the sticky parameters are NOT committed.
-            // It can happen if the request has failed to be sent for example,
-            // or if the response didn't match.
-        }
+        sliding_sync.subscribe_to_rooms(&[r1], None, false);
 
-        // A previously added room subscription is re-added, applied, and committed.
-        {
-            // Then the sticky parameters are applied.
-            let txn_id: &TransactionId = "tid2".into();
-            let mut request = http::Request::default();
-            request.txn_id = Some(txn_id.to_string());
-
-            sticky.maybe_apply(&mut request, &mut LazyTransactionId::from_owned(txn_id.to_owned()));
-
-            assert!(request.txn_id.is_some());
-            assert_eq!(request.room_subscriptions.len(), 1);
-            // `r0` is not present, it's only `r1`.
+        // Both room subscriptions are sent, even the one that has already been sent.
+        for _ in 0..2 {
+            let (request, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(request.room_subscriptions.len(), 2);
+            assert!(request.room_subscriptions.contains_key(r0));
             assert!(request.room_subscriptions.contains_key(r1));
-
-            // Then the sticky parameters are committed.
-            let tid = request.txn_id.unwrap();
-
-            sticky.maybe_commit(tid.as_str().into());
         }
 
-        // All room subscriptions have been committed.
-        {
-            // Then the sticky parameters are applied.
-            let txn_id: &TransactionId = "tid3".into();
-            let mut request = http::Request::default();
-            request.txn_id = Some(txn_id.to_string());
-
-            sticky.maybe_apply(&mut request, &mut LazyTransactionId::from_owned(txn_id.to_owned()));
-
-            assert!(request.txn_id.is_some());
-            // All room subscriptions have been sent.
-            assert!(request.room_subscriptions.is_empty());
-        }
+        Ok(())
     }
 
     #[test]
@@ -1547,10 +1633,8 @@ mod tests {
         extensions.account_data.enabled = Some(true);
 
         // At first it's invalidated.
-        let mut sticky = SlidingSyncStickyManager::new(SlidingSyncStickyParameters::new(
-            Default::default(),
-            extensions,
-        ));
+        let mut sticky =
+            SlidingSyncStickyManager::new(SlidingSyncStickyParameters::new(extensions));
 
         assert!(sticky.is_invalidated(), "invalidated because of non default parameters");
 
@@ -2180,6 +2264,161 @@ mod tests {
         Ok(())
     }
 
+    #[derive(Default, Serialize, Deserialize)]
+    struct CounterState {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        since: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct CounterResponse {
+        next_batch: String,
+        count: u32,
+    }
+
+    struct CounterExtension {
+        received: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl SlidingSyncExtension for CounterExtension {
+        type Config = serde_json::Value;
+        type Response = CounterResponse;
+        type State = CounterState;
+
+        fn name(&self) -> &str {
+            "org.example.counter"
+        }
+
+        fn config(&self) -> Self::Config {
+            json!({ "enabled": true })
+        }
+
+        fn handle_response(&self, response: Self::Response, state: &mut Self::State) {
+            self.received.lock().unwrap().push(response.count);
+            state.since = Some(response.next_batch);
+        }
+    }
+
+    #[async_test]
+    async fn test_custom_extension() -> Result<()> {
+        let server = MockServer::start().await;
+
+        #[derive(Deserialize)]
+        struct PartialRequest {
+            txn_id: Option<String>,
+        }
+
+        let _mock_guard = Mock::given(SlidingSyncMatcher)
+            .respond_with(|request: &Request| {
+                // Repeat the txn_id in the response, if set.
+                let request: PartialRequest = request.body_json().unwrap();
+
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "txn_id": request.txn_id,
+                    "pos": "0",
+                    "extensions": {
+                        "org.example.counter": {
+                            "next_batch": "b1",
+                            "count": 42,
+                        },
+                    },
+                }))
+            })
+            .mount_as_scoped(&server)
+            .await;
+
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sliding_sync = client
+            .sliding_sync("custom-ext")?
+            .with_custom_extension(CounterExtension { received: received.clone() })
+            .build()
+            .await?;
+
+        // The configuration is sent with the first request.
+        {
+            let (request, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(
+                serde_json::to_value(&request.extensions)?["org.example.counter"],
+                json!({ "enabled": true })
+            );
+        }
+
+        let sync = sliding_sync.sync();
+        pin_mut!(sync);
+
+        assert_matches!(sync.next().await, Some(Ok(_update_summary)));
+
+        // The response has been handled.
+        assert_eq!(*received.lock().unwrap(), [42]);
+
+        // The configuration is sticky, only the state is sent now.
+        {
+            let (request, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(
+                serde_json::to_value(&request.extensions)?["org.example.counter"],
+                json!({ "since": "b1" })
+            );
+        }
+
+        // The state is reloaded from the cache.
+        {
+            let sliding_sync = client
+                .sliding_sync("custom-ext")?
+                .with_custom_extension(CounterExtension { received: Default::default() })
+                .build()
+                .await?;
+
+            let (request, _, _) =
+                sliding_sync.generate_sync_request(&mut LazyTransactionId::new()).await?;
+            assert_eq!(
+                serde_json::to_value(&request.extensions)?["org.example.counter"],
+                json!({ "enabled": true, "since": "b1" })
+            );
+        }
+
+        Ok(())
+    }
+
+    struct TypingExtension;
+
+    impl SlidingSyncExtension for TypingExtension {
+        type Config = serde_json::Value;
+        type Response = serde_json::Value;
+        type State = CounterState;
+
+        fn name(&self) -> &str {
+            "typing"
+        }
+
+        fn config(&self) -> Self::Config {
+            json!({ "enabled": true })
+        }
+
+        fn handle_response(&self, _response: Self::Response, _state: &mut Self::State) {}
+    }
+
+    #[async_test]
+    async fn test_custom_extension_with_known_name() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let result = client
+            .sliding_sync("custom-ext")
+            .unwrap()
+            .with_custom_extension(TypingExtension)
+            .build()
+            .await;
+
+        assert_matches!(
+            result,
+            Err(crate::Error::SlidingSync(Error::KnownCustomExtension(name))) if name == "typing"
+        );
+    }
+
     #[async_test]
     async fn test_stop_sync_loop() -> Result<()> {
         let (_server, sliding_sync) = new_sliding_sync(vec![SlidingSyncList::builder("foo")
@@ -3083,6 +3322,79 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_sync_checkpoints_keep_the_position_of_each_connection() -> Result<()> {
+        let server = MockServer::start().await;
+
+        #[derive(Deserialize)]
+        struct PartialRequest {
+            conn_id: Option<String>,
+        }
+
+        // Each connection has its own positions.
+        let responses = Arc::new(Mutex::new(BTreeMap::<String, u32>::new()));
+        let _mock_guard = Mock::given(SlidingSyncMatcher)
+            .respond_with(move |request: &Request| {
+                let request: PartialRequest = request.body_json().unwrap();
+                let conn_id = request.conn_id.unwrap();
+
+                let mut responses = responses.lock().unwrap();
+                let count = responses.entry(conn_id.clone()).or_default();
+                let pos = format!("{conn_id}-{count}");
+                *count += 1;
+
+                ResponseTemplate::new(200).set_body_json(json!({ "pos": pos }))
+            })
+            .mount_as_scoped(&server)
+            .await;
+
+        let client = test_client_builder(Some(server.uri()))
+            .request_config(RequestConfig::new().disable_retry())
+            .sync_checkpoints(SyncCheckpointsConfig::new().every(1))
+            .build()
+            .await
+            .unwrap();
+        set_client_session(&client).await;
+
+        let room_list = client.sliding_sync("room-list")?.build().await?;
+        let encryption = client.sliding_sync("encryption")?.build().await?;
+
+        room_list.sync_once().await?;
+        encryption.sync_once().await?;
+        room_list.sync_once().await?;
+
+        // Every checkpoint has the latest position of each connection.
+        fn positions(checkpoint: &SyncCheckpoint) -> Vec<(&str, &str)> {
+            assert_matches!(&checkpoint.position, SyncPosition::SlidingSync { positions } => {
+                positions.iter().map(|(conn_id, pos)| (conn_id.as_str(), pos.as_str())).collect()
+            })
+        }
+
+        let checkpoints = client.sync_checkpoints().await?;
+        assert_eq!(
+            checkpoints.iter().map(positions).collect::<Vec<_>>(),
+            [
+                vec![("room-list", "room-list-0")],
+                vec![("encryption", "encryption-0"), ("room-list", "room-list-0")],
+                vec![("encryption", "encryption-0"), ("room-list", "room-list-1")],
+            ]
+        );
+
+        // Both connections resume from their position of the restored checkpoint.
+        let checkpoint = client.restore_checkpoint(checkpoints[1].id).await?;
+        assert!(room_list.restore_checkpoint_position(&checkpoint).await);
+        assert!(encryption.restore_checkpoint_position(&checkpoint).await);
+
+        assert_eq!(room_list.inner.position.lock().await.pos.as_deref(), Some("room-list-0"));
+        assert_eq!(encryption.inner.position.lock().await.pos.as_deref(), Some("encryption-0"));
+
+        // A connection without a position in the checkpoint starts a new session.
+        let other = client.sliding_sync("other")?.build().await?;
+        assert!(other.restore_checkpoint_position(&checkpoint).await.not());
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_sync_beat_is_notified_on_sync_response() -> Result<()> {
         let server = MockServer::start().await;
@@ -172,6 +172,7 @@ impl Client {
         let now = Instant::now();
         self.handle_sync_events(HandlerKind::GlobalAccountData, None, account_data).await?;
         self.handle_sync_events(HandlerKind::Presence, None, presence).await?;
+        self.presence().handle_sync_events(presence);
         self.handle_sync_events(HandlerKind::ToDevice, None, to_device).await?;
 
         // Ignore errors when there are no receivers.
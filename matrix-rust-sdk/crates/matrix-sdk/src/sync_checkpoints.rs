@@ -0,0 +1,314 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checkpoints of the sync state, for debugging.
+//!
+//! When enabled with [`ClientBuilder::sync_checkpoints`], a snapshot of the
+//! state store is saved every few sync responses, along with the position of
+//! the sync at that time: the sync token of `/sync` v2, or the `pos` of each
+//! sliding sync connection. Only the latest checkpoints are kept.
+//!
+//! [`Client::restore_checkpoint`] rolls the client back to one of them, so that
+//! the sync can be replayed from a known-good point, e.g. to find which
+//! response put the client in a bad state.
+//!
+//! Note that only the state store is part of the checkpoints: the crypto store
+//! and the event cache store aren't rolled back.
+//!
+//! [`ClientBuilder::sync_checkpoints`]: crate::ClientBuilder::sync_checkpoints
+
+use std::{collections::BTreeMap, sync::Mutex as StdMutex};
+
+use matrix_sdk_base::store::DynStateStore;
+use ruma::MilliSecondsSinceUnixEpoch;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::{Client, Error, Result};
+
+/// The key of the custom value of the state store holding the
+/// [`SavedCheckpoints`].
+const CHECKPOINTS_KEY: &[u8] = b"sync_checkpoints";
+
+/// Configuration of the sync checkpoints.
+#[derive(Clone, Copy, Debug)]
+pub struct SyncCheckpointsConfig {
+    every: usize,
+    keep: usize,
+}
+
+impl Default for SyncCheckpointsConfig {
+    fn default() -> Self {
+        Self { every: 10, keep: 5 }
+    }
+}
+
+impl SyncCheckpointsConfig {
+    /// Create a new configuration, taking a checkpoint every 10 sync responses
+    /// and keeping the 5 latest ones.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a checkpoint every `count` sync responses.
+    ///
+    /// Zero is treated as one.
+    pub fn every(mut self, count: usize) -> Self {
+        self.every = count.max(1);
+        self
+    }
+
+    /// Keep the `count` latest checkpoints, older ones are removed.
+    ///
+    /// Zero is treated as one.
+    pub fn keep(mut self, count: usize) -> Self {
+        self.keep = count.max(1);
+        self
+    }
+}
+
+/// A checkpoint of the sync state, see the [module documentation][self].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncCheckpoint {
+    /// The identifier of the checkpoint.
+    pub id: u64,
+
+    /// When the checkpoint was taken.
+    pub created_at: MilliSecondsSinceUnixEpoch,
+
+    /// The position of the sync when the checkpoint was taken.
+    pub position: SyncPosition,
+}
+
+/// The position of a sync loop, from which it can resume.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncPosition {
+    /// The `next_batch` token of a `/sync` v2 response.
+    ///
+    /// It's part of the state store, so the next `/sync` request resumes from
+    /// it as soon as the checkpoint is restored.
+    SyncToken {
+        /// The sync token.
+        token: String,
+    },
+
+    /// The `pos` of the sliding sync connections.
+    ///
+    /// Each sliding sync must be told to resume from its own position after
+    /// the checkpoint is restored, with
+    /// `SlidingSync::restore_checkpoint_position`.
+    SlidingSync {
+        /// The position in each sliding sync connection, by `conn_id`.
+        ///
+        /// A connection is missing if it hadn't received any response yet.
+        positions: BTreeMap<String, String>,
+    },
+}
+
+/// The checkpoints kept in the state store.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedCheckpoints {
+    /// The identifier of the next checkpoint.
+    next_id: u64,
+
+    /// The checkpoints, from the oldest to the newest.
+    checkpoints: Vec<SyncCheckpoint>,
+}
+
+impl SavedCheckpoints {
+    async fn load(store: &DynStateStore) -> Result<Self> {
+        Ok(match store.get_custom_value(CHECKPOINTS_KEY).await? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Self::default(),
+        })
+    }
+
+    async fn save(&self, store: &DynStateStore) -> Result<()> {
+        store.set_custom_value_no_read(CHECKPOINTS_KEY, serde_json::to_vec(self)?).await?;
+        Ok(())
+    }
+}
+
+/// Data related to the sync checkpoints of a client.
+#[derive(Debug, Default)]
+pub(crate) struct SyncCheckpointsData {
+    /// The configuration of the checkpoints, if they're enabled.
+    config: Option<SyncCheckpointsConfig>,
+
+    /// How many sync responses have been received since the last checkpoint.
+    responses: StdMutex<usize>,
+
+    /// The latest `pos` of each sliding sync connection, by `conn_id`.
+    ///
+    /// They are updated once the responses have been processed, so they're
+    /// never ahead of the state store.
+    sliding_sync_positions: StdMutex<BTreeMap<String, String>>,
+
+    /// A lock to not take and restore checkpoints at the same time.
+    lock: Mutex<()>,
+}
+
+impl SyncCheckpointsData {
+    pub(crate) fn new(config: Option<SyncCheckpointsConfig>) -> Self {
+        Self { config, ..Default::default() }
+    }
+}
+
+impl Client {
+    /// Take a checkpoint of the sync state, if checkpoints are enabled and
+    /// enough sync responses were received since the last one.
+    ///
+    /// Must be called after a sync response has been processed. Errors are
+    /// only logged, since they must not stop the sync.
+    pub(crate) async fn maybe_take_sync_checkpoint(&self, position: SyncPosition) {
+        self.maybe_take_sync_checkpoint_with(|| position).await;
+    }
+
+    /// Record the `pos` of a sliding sync connection, then take a checkpoint
+    /// with the positions of all the sliding sync connections, like
+    /// [`Client::maybe_take_sync_checkpoint`].
+    pub(crate) async fn maybe_take_sliding_sync_checkpoint(&self, conn_id: &str, pos: String) {
+        let data = &self.inner.sync_checkpoints;
+
+        if data.config.is_none() {
+            return;
+        }
+
+        data.sliding_sync_positions.lock().unwrap().insert(conn_id.to_owned(), pos);
+
+        self.maybe_take_sync_checkpoint_with(|| SyncPosition::SlidingSync {
+            positions: data.sliding_sync_positions.lock().unwrap().clone(),
+        })
+        .await;
+    }
+
+    async fn maybe_take_sync_checkpoint_with(&self, position: impl FnOnce() -> SyncPosition) {
+        let data = &self.inner.sync_checkpoints;
+
+        let Some(config) = data.config else {
+            return;
+        };
+
+        {
+            let mut responses = data.responses.lock().unwrap();
+            *responses += 1;
+
+            if *responses < config.every {
+                return;
+            }
+
+            *responses = 0;
+        }
+
+        if let Err(error) = self.take_sync_checkpoint(config, position).await {
+            warn!("Couldn't take a sync checkpoint: {error}");
+        }
+    }
+
+    async fn take_sync_checkpoint(
+        &self,
+        config: SyncCheckpointsConfig,
+        position: impl FnOnce() -> SyncPosition,
+    ) -> Result<()> {
+        let _lock = self.inner.sync_checkpoints.lock.lock().await;
+
+        let store = self.store();
+        let mut saved = SavedCheckpoints::load(store).await?;
+
+        let id = saved.next_id;
+        saved.next_id += 1;
+
+        let position = {
+            // Don't let a sync response be processed during the snapshot. The position is
+            // read before, so that it can't be ahead of the snapshot.
+            let _sync_lock = self.base_client().sync_lock().lock().await;
+            let position = position();
+            store.save_snapshot(id).await?;
+            position
+        };
+
+        saved.checkpoints.push(SyncCheckpoint {
+            id,
+            created_at: MilliSecondsSinceUnixEpoch::now(),
+            position,
+        });
+
+        let outdated = saved.checkpoints.len().saturating_sub(config.keep);
+        for checkpoint in saved.checkpoints.drain(..outdated) {
+            store.remove_snapshot(checkpoint.id).await?;
+        }
+
+        saved.save(store).await?;
+
+        debug!(id, "Took a sync checkpoint");
+
+        Ok(())
+    }
+
+    /// Get the sync checkpoints that can be restored, from the oldest to the
+    /// newest.
+    ///
+    /// See [`ClientBuilder::sync_checkpoints`] to enable them.
+    ///
+    /// [`ClientBuilder::sync_checkpoints`]: crate::ClientBuilder::sync_checkpoints
+    pub async fn sync_checkpoints(&self) -> Result<Vec<SyncCheckpoint>> {
+        Ok(SavedCheckpoints::load(self.store()).await?.checkpoints)
+    }
+
+    /// Roll the client back to the sync checkpoint with the given identifier.
+    ///
+    /// The state store is restored to its content at the time of the
+    /// checkpoint, and the rooms and the sync token are reloaded from it. The
+    /// checkpoint itself is kept, as well as the ones taken after it, so it's
+    /// possible to move back and forth between them.
+    ///
+    /// This must not be called while a sync loop is running. If the checkpoint
+    /// was taken by sliding sync, the position of each connection must be
+    /// restored too, with `SlidingSync::restore_checkpoint_position`.
+    pub async fn restore_checkpoint(&self, id: u64) -> Result<SyncCheckpoint> {
+        let _lock = self.inner.sync_checkpoints.lock.lock().await;
+
+        let store = self.store();
+        let saved = SavedCheckpoints::load(store).await?;
+
+        let checkpoint = saved
+            .checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.id == id)
+            .cloned()
+            .ok_or(Error::UnknownSyncCheckpoint(id))?;
+
+        if !self.base_client().restore_state_store_snapshot(id).await? {
+            return Err(Error::UnknownSyncCheckpoint(id));
+        }
+
+        // The snapshot contains the checkpoints known at the time it was saved, keep
+        // the current ones instead.
+        saved.save(store).await?;
+        *self.inner.sync_checkpoints.responses.lock().unwrap() = 0;
+
+        // The next checkpoints must not contain positions from after this one.
+        *self.inner.sync_checkpoints.sliding_sync_positions.lock().unwrap() =
+            match &checkpoint.position {
+                SyncPosition::SlidingSync { positions } => positions.clone(),
+                SyncPosition::SyncToken { .. } => BTreeMap::new(),
+            };
+
+        debug!(id, "Restored a sync checkpoint");
+
+        Ok(checkpoint)
+    }
+}
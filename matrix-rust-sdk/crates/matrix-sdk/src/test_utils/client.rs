@@ -20,6 +20,7 @@ use ruma::{api::MatrixVersion, device_id, user_id};
 use crate::{
     config::RequestConfig,
     matrix_auth::{MatrixSession, MatrixSessionTokens},
+    sync_checkpoints::SyncCheckpointsConfig,
     Client, ClientBuilder,
 };
 
@@ -57,6 +58,20 @@ impl MockClientBuilder {
         self
     }
 
+    /// Uses SQLite stores at the given path for the underlying
+    /// [`ClientBuilder`].
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite_store(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.builder = self.builder.sqlite_store(path, None);
+        self
+    }
+
+    /// Enables the sync checkpoints of the underlying [`ClientBuilder`].
+    pub fn sync_checkpoints(mut self, config: SyncCheckpointsConfig) -> Self {
+        self.builder = self.builder.sync_checkpoints(config);
+        self
+    }
+
     /// Finish building the client into the final [`Client`] instance.
     pub async fn build(self) -> Client {
         let client = self.builder.build().await.expect("building client failed");
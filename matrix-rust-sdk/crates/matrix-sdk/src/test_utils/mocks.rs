@@ -591,6 +591,14 @@ impl MatrixMockServer {
             .and(header("authorization", "Bearer 1234"));
         MockEndpoint { mock, server: &self.server, endpoint: RoomKeysVersionEndpoint }
     }
+
+    /// Create a prebuilt mock for logging out the current device.
+    pub fn mock_logout(&self) -> MockEndpoint<'_, LogoutEndpoint> {
+        let mock = Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/v3/logout$"))
+            .and(header("authorization", "Bearer 1234"));
+        MockEndpoint { mock, server: &self.server, endpoint: LogoutEndpoint }
+    }
 }
 
 /// Parameter to [`MatrixMockServer::sync_room`].
@@ -800,6 +808,23 @@ impl<'a, T> MockEndpoint<'a, T> {
         MatrixMock { mock: self.mock.respond_with(ResponseTemplate::new(500)), server: self.server }
     }
 
+    /// Returns an endpoint that rate-limits the request, asking to retry after
+    /// 2 seconds.
+    pub fn error429(self) -> MatrixMock<'a> {
+        self.error429_with_retry_after(Duration::from_secs(2))
+    }
+
+    /// Returns an endpoint that rate-limits the request, asking to retry after
+    /// the given delay.
+    pub fn error429_with_retry_after(self, retry_after: Duration) -> MatrixMock<'a> {
+        let mock = self.mock.respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": retry_after.as_millis(),
+        })));
+        MatrixMock { server: self.server, mock }
+    }
+
     /// Internal helper to return an `{ event_id }` JSON struct along with a 200
     /// ok response.
     fn ok_with_event_id(self, event_id: OwnedEventId) -> MatrixMock<'a> {
@@ -1697,19 +1722,20 @@ impl<'a> MockEndpoint<'a, RoomKeysVersionEndpoint> {
         MatrixMock { server: self.server, mock }
     }
 
-    /// Returns an endpoint that 429 errors when we get it
-    pub fn error429(self) -> MatrixMock<'a> {
-        let mock = self.mock.respond_with(ResponseTemplate::new(429).set_body_json(json!({
-            "errcode": "M_LIMIT_EXCEEDED",
-            "error": "Too many requests",
-            "retry_after_ms": 2000
-        })));
-        MatrixMock { server: self.server, mock }
-    }
-
     /// Returns an endpoint that 404 errors when we get it
     pub fn error404(self) -> MatrixMock<'a> {
         let mock = self.mock.respond_with(ResponseTemplate::new(404));
         MatrixMock { server: self.server, mock }
     }
 }
+
+/// A prebuilt mock for logging out the current device.
+pub struct LogoutEndpoint;
+
+impl<'a> MockEndpoint<'a, LogoutEndpoint> {
+    /// Returns an endpoint that logs the device out successfully.
+    pub fn ok(self) -> MatrixMock<'a> {
+        let mock = self.mock.respond_with(ResponseTemplate::new(200).set_body_json(json!({})));
+        MatrixMock { server: self.server, mock }
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wiping the data of a client from the disk, when logging out.
+
+use std::{fmt, io, path::PathBuf};
+
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_sqlite::SqliteCryptoStore;
+use matrix_sdk_sqlite::{SqliteEventCacheStore, SqliteStateStore};
+use tracing::{info, warn};
+
+use crate::{AuthApi, Client, Error, Result};
+
+/// The SQLite stores opened by a [`ClientBuilder`], kept to be able to wipe
+/// them.
+///
+/// [`ClientBuilder`]: crate::ClientBuilder
+#[derive(Clone, Debug)]
+pub(crate) struct SqliteStores {
+    /// The directories of the stores.
+    pub paths: Vec<PathBuf>,
+
+    pub state_store: SqliteStateStore,
+
+    pub event_cache_store: SqliteEventCacheStore,
+
+    #[cfg(feature = "e2e-encryption")]
+    pub crypto_store: SqliteCryptoStore,
+}
+
+impl SqliteStores {
+    /// Close the stores, wait until their connections are closed, and delete
+    /// their databases.
+    ///
+    /// The databases are deleted even if some connections are still in use
+    /// after the close timeout: they can't be used anymore since the stores
+    /// are closed, and an error is returned if they create a file again during
+    /// the deletion.
+    ///
+    /// Returns the paths of the deleted files.
+    async fn close_and_delete(&self) -> io::Result<Vec<PathBuf>> {
+        let closed = [
+            self.state_store.close().await,
+            self.event_cache_store.close().await,
+            #[cfg(feature = "e2e-encryption")]
+            self.crypto_store.close().await,
+        ];
+        for error in closed.into_iter().filter_map(Result::err) {
+            warn!("Couldn't close a store, deleting it anyway: {error}");
+        }
+
+        let mut deleted_files = Vec::new();
+        for path in &self.paths {
+            deleted_files.extend(matrix_sdk_sqlite::delete_stores(path).await?);
+        }
+
+        Ok(deleted_files)
+    }
+}
+
+/// What was done by [`Client::logout_and_wipe`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WipeSummary {
+    /// Whether the device was logged out on the homeserver.
+    ///
+    /// The data is wiped even if logging out failed, e.g. because the
+    /// homeserver couldn't be reached.
+    pub logged_out: bool,
+
+    /// The files that were deleted.
+    ///
+    /// They are the databases of the state store, of the crypto store, which
+    /// holds the keys of the device, and of the event cache store, which holds
+    /// the media cache.
+    pub deleted_files: Vec<PathBuf>,
+}
+
+impl Client {
+    /// Log out the device, and delete all the data of the client from the
+    /// disk.
+    ///
+    /// This is meant for shared devices, where nothing must remain after
+    /// logging out: the state store, the crypto store with the keys of the
+    /// device, and the event cache store with the media cache are deleted.
+    ///
+    /// The client must have been built with [`ClientBuilder::sqlite_store`] or
+    /// [`ClientBuilder::sqlite_store_with_cache_path`], otherwise
+    /// [`Error::StoresNotWipeable`] is returned and nothing is done.
+    ///
+    /// The data is wiped even if logging out fails, see
+    /// [`WipeSummary::logged_out`]. The sync must be stopped before calling
+    /// this, and the client must not be used afterwards: all the operations
+    /// using the stores fail.
+    ///
+    /// The session saved by the application, if any, isn't known by the SDK,
+    /// and must be deleted by the application.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async {
+    /// # let client: matrix_sdk::Client = unimplemented!();
+    /// let summary = client.logout_and_wipe().await?;
+    ///
+    /// if !summary.logged_out {
+    ///     println!("The device couldn't be logged out, but its data is gone");
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [`ClientBuilder::sqlite_store`]: crate::ClientBuilder::sqlite_store
+    /// [`ClientBuilder::sqlite_store_with_cache_path`]: crate::ClientBuilder::sqlite_store_with_cache_path
+    pub async fn logout_and_wipe(&self) -> Result<WipeSummary> {
+        let Some(stores) = &self.inner.sqlite_stores else {
+            return Err(Error::StoresNotWipeable);
+        };
+
+        let logged_out = match self.auth_api() {
+            Some(AuthApi::Matrix(auth)) => check_logout(auth.logout().await),
+            #[cfg(feature = "experimental-oidc")]
+            Some(AuthApi::Oidc(oidc)) => check_logout(oidc.logout().await),
+            None => false,
+        };
+
+        let deleted_files = stores.close_and_delete().await?;
+        info!(logged_out, deleted_files = deleted_files.len(), "Wiped the data of the client");
+
+        Ok(WipeSummary { logged_out, deleted_files })
+    }
+}
+
+/// Whether logging out succeeded, logging the error otherwise.
+fn check_logout<T, E: fmt::Display>(result: Result<T, E>) -> bool {
+    match result {
+        Ok(_) => true,
+        Err(error) => {
+            warn!("Couldn't log out, wiping the data anyway: {error}");
+            false
+        }
+    }
+}
@@ -6,11 +6,14 @@ use futures_util::FutureExt;
 use matrix_sdk::{
     config::{RequestConfig, StoreConfig, SyncSettings},
     matrix_auth::{MatrixSession, MatrixSessionTokens},
+    room::MessagesOptions,
     sync::RoomUpdate,
-    test_utils::no_retry_test_client_with_server,
-    Client, MemoryStore, SessionMeta, StateChanges, StateStore,
+    sync_checkpoints::{SyncCheckpointsConfig, SyncPosition},
+    test_utils::{mocks::MatrixMockServer, no_retry_test_client_with_server},
+    Client, EndpointClass, Error, ErrorCategory, MemoryStore, SessionMeta, StateChanges,
+    StateStore,
 };
-use matrix_sdk_base::{sync::RoomUpdates, RoomState};
+use matrix_sdk_base::{sync::RoomUpdates, RoomState, StateStoreDataKey, StateStoreDataValue};
 use matrix_sdk_test::{
     async_test, sync_state_event,
     test_json::{
@@ -35,7 +38,7 @@ use ruma::{
     assign, device_id,
     directory::Filter,
     event_id,
-    events::{direct::DirectEventContent, AnyInitialStateEvent},
+    events::{direct::DirectEventContent, AnyInitialStateEvent, AnyTimelineEvent},
     room_id,
     serde::Raw,
     user_id, OwnedUserId,
@@ -1391,3 +1394,202 @@ async fn test_restore_room() {
     assert!(room.is_favourite());
     assert!(!room.pinned_event_ids().unwrap().is_empty());
 }
+
+#[async_test]
+async fn test_rate_limited_requests_are_held_back_per_endpoint_class() {
+    let server = MatrixMockServer::new().await;
+    let client = server.client_builder().build().await;
+
+    let room = server.sync_joined_room(&client, room_id!("!a:localhost")).await;
+
+    let retry_after = Duration::from_millis(500);
+    server.mock_room_messages().error429_with_retry_after(retry_after).mock_once().mount().await;
+
+    room.messages(MessagesOptions::backward()).await.unwrap_err();
+
+    // The back-pagination was rate-limited, so the other back-paginations are held
+    // back, but not the requests with a higher priority.
+    let metrics = client.rate_limit_metrics();
+    let backfill = &metrics.classes[&EndpointClass::Backfill];
+    assert_eq!(backfill.rate_limited_responses, 1);
+    assert!(backfill.remaining_delay.is_some());
+    assert!(!metrics.classes.contains_key(&EndpointClass::Media));
+    assert!(!metrics.classes.contains_key(&EndpointClass::Sync));
+
+    // A sync isn't held back. The timing of the scheduler itself is tested with a
+    // paused clock in the `rate_limiter` module.
+    server.sync_joined_room(&client, room_id!("!b:localhost")).await;
+    assert!(!client.rate_limit_metrics().classes.contains_key(&EndpointClass::Sync));
+
+    // The next back-pagination is held back until the homeserver accepts requests
+    // again.
+    server
+        .mock_room_messages()
+        .ok("start".to_owned(), None, Vec::<Raw<AnyTimelineEvent>>::new(), Vec::new())
+        .mock_once()
+        .mount()
+        .await;
+    room.messages(MessagesOptions::backward()).await.unwrap();
+
+    let metrics = client.rate_limit_metrics();
+    let backfill = &metrics.classes[&EndpointClass::Backfill];
+    assert_eq!(backfill.rate_limited_responses, 1);
+    assert_eq!(backfill.delayed_requests, 1);
+    assert!(backfill.total_delay > Duration::ZERO);
+    assert!(backfill.remaining_delay.is_none());
+    assert_eq!(backfill.queued_requests, 0);
+}
+
+#[async_test]
+async fn test_client_metrics_are_recorded_per_endpoint() {
+    let server = MatrixMockServer::new().await;
+    let client = server.client_builder().build().await;
+
+    let room = server.sync_joined_room(&client, room_id!("!a:localhost")).await;
+    let after_sync = client.metrics();
+
+    server.mock_room_messages().error500().mock_once().mount().await;
+    room.messages(MessagesOptions::backward()).await.unwrap_err();
+
+    let metrics = client.metrics();
+
+    let sync = &metrics.endpoints["GET /_matrix/client/v3/sync"];
+    assert_eq!(sync.requests, 1);
+    assert_eq!(sync.retries, 0);
+    assert_eq!(sync.bytes_sent, 0);
+    assert!(sync.bytes_received > 0);
+    assert_eq!(sync.latency.count(), 1);
+    assert!(sync.errors.is_empty());
+
+    let messages = &metrics.endpoints["GET /_matrix/client/v3/rooms/:room_id/messages"];
+    assert_eq!(messages.requests, 1);
+    assert_eq!(messages.latency.count(), 1);
+    assert_eq!(messages.errors.len(), 1);
+    assert_eq!(messages.errors[&ErrorCategory::ServerError], 1);
+
+    // Only the messages were requested since the sync.
+    let since_sync = metrics.since(&after_sync);
+    assert_eq!(
+        since_sync.endpoints.keys().collect::<Vec<_>>(),
+        ["GET /_matrix/client/v3/rooms/:room_id/messages"]
+    );
+
+    assert!(metrics.rate_limits.classes.is_empty());
+}
+
+#[async_test]
+async fn test_sync_checkpoints() {
+    let server = MatrixMockServer::new().await;
+    let client = server
+        .client_builder()
+        .sync_checkpoints(SyncCheckpointsConfig::new().every(2).keep(2))
+        .build()
+        .await;
+
+    // Six sync responses, each with a new room.
+    let room_ids = [
+        room_id!("!a:localhost"),
+        room_id!("!b:localhost"),
+        room_id!("!c:localhost"),
+        room_id!("!d:localhost"),
+        room_id!("!e:localhost"),
+        room_id!("!f:localhost"),
+    ];
+
+    for room_id in room_ids {
+        server.sync_joined_room(&client, room_id).await;
+    }
+
+    // A checkpoint was taken every two responses, and only the two latest ones
+    // were kept.
+    let checkpoints = client.sync_checkpoints().await.unwrap();
+    assert_eq!(checkpoints.iter().map(|checkpoint| checkpoint.id).collect::<Vec<_>>(), [1, 2]);
+
+    // Go back to the checkpoint taken after the fourth response.
+    let checkpoint = client.restore_checkpoint(1).await.unwrap();
+    assert_eq!(checkpoint, checkpoints[0]);
+
+    for room_id in &room_ids[..4] {
+        assert!(client.get_room(room_id).is_some());
+    }
+    for room_id in &room_ids[4..] {
+        assert!(client.get_room(room_id).is_none());
+    }
+
+    assert_let!(SyncPosition::SyncToken { token } = checkpoint.position);
+    assert_let!(
+        Some(StateStoreDataValue::SyncToken(sync_token)) =
+            client.store().get_kv_data(StateStoreDataKey::SyncToken).await.unwrap()
+    );
+    assert_eq!(sync_token, token);
+
+    // The checkpoints are kept, but the outdated ones can't be restored.
+    assert_eq!(client.sync_checkpoints().await.unwrap(), checkpoints);
+    assert_matches!(client.restore_checkpoint(0).await, Err(Error::UnknownSyncCheckpoint(0)));
+
+    // It's possible to move forward again.
+    client.restore_checkpoint(2).await.unwrap();
+    assert!(client.get_room(room_ids[5]).is_some());
+}
+
+#[cfg(feature = "sqlite")]
+#[async_test]
+async fn test_logout_and_wipe() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let store_path = tmp_dir.path().join("store");
+
+    let server = MatrixMockServer::new().await;
+    let client = server.client_builder().sqlite_store(&store_path).build().await;
+
+    // Put some data in the stores.
+    server.sync_joined_room(&client, room_id!("!a:localhost")).await;
+    assert!(store_path.join("matrix-sdk-state.sqlite3").exists());
+    assert!(store_path.join("matrix-sdk-crypto.sqlite3").exists());
+    assert!(store_path.join("matrix-sdk-event-cache.sqlite3").exists());
+
+    server.mock_logout().ok().mock_once().mount().await;
+
+    let summary = client.logout_and_wipe().await.unwrap();
+    assert!(summary.logged_out);
+    for file in
+        ["matrix-sdk-state.sqlite3", "matrix-sdk-crypto.sqlite3", "matrix-sdk-event-cache.sqlite3"]
+    {
+        assert!(summary.deleted_files.contains(&store_path.join(file)));
+    }
+
+    // No file remains.
+    assert!(!store_path.exists());
+    assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+
+    // The stores can't be used anymore.
+    client.store().get_kv_data(StateStoreDataKey::SyncToken).await.unwrap_err();
+}
+
+#[cfg(feature = "sqlite")]
+#[async_test]
+async fn test_logout_and_wipe_when_logout_fails() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let store_path = tmp_dir.path().join("store");
+
+    let server = MatrixMockServer::new().await;
+    let client = server.client_builder().sqlite_store(&store_path).build().await;
+
+    server.mock_logout().error500().mock_once().mount().await;
+
+    // The data is wiped anyway.
+    let summary = client.logout_and_wipe().await.unwrap();
+    assert!(!summary.logged_out);
+    assert!(!summary.deleted_files.is_empty());
+    assert!(!store_path.exists());
+}
+
+#[cfg(feature = "sqlite")]
+#[async_test]
+async fn test_logout_and_wipe_without_sqlite_stores() {
+    let server = MatrixMockServer::new().await;
+    let client = server.client_builder().build().await;
+
+    server.mock_logout().ok().expect(0).mount().await;
+
+    assert_matches!(client.logout_and_wipe().await, Err(Error::StoresNotWipeable));
+}
@@ -932,10 +932,8 @@ async fn test_enable_from_secret_storage() {
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
     let (builder, server) = test_client_builder_with_server().await;
-    let encryption_settings = EncryptionSettings {
-        backup_download_strategy: BackupDownloadStrategy::OneShot,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::OneShot;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
         .with_encryption_settings(encryption_settings)
@@ -1070,10 +1068,8 @@ async fn test_enable_from_secret_storage_no_existing_backup() {
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
     let (builder, server) = test_client_builder_with_server().await;
-    let encryption_settings = EncryptionSettings {
-        backup_download_strategy: BackupDownloadStrategy::OneShot,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::OneShot;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
         .with_encryption_settings(encryption_settings)
@@ -1113,10 +1109,8 @@ async fn test_enable_from_secret_storage_mismatched_key() {
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
     let (builder, server) = test_client_builder_with_server().await;
-    let encryption_settings = EncryptionSettings {
-        backup_download_strategy: BackupDownloadStrategy::OneShot,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::OneShot;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
         .with_encryption_settings(encryption_settings)
@@ -1197,10 +1191,8 @@ async fn test_enable_from_secret_storage_and_manual_download() {
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
     let (builder, server) = test_client_builder_with_server().await;
-    let encryption_settings = EncryptionSettings {
-        backup_download_strategy: BackupDownloadStrategy::Manual,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::Manual;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
         .with_encryption_settings(encryption_settings)
@@ -1319,10 +1311,8 @@ async fn test_enable_from_secret_storage_and_download_after_utd() {
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
     let (builder, server) = test_client_builder_with_server().await;
-    let encryption_settings = EncryptionSettings {
-        backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::AfterDecryptionFailure;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
         .with_encryption_settings(encryption_settings)
@@ -1434,10 +1424,8 @@ async fn test_enable_from_secret_storage_and_download_after_utd_from_old_message
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
     let (builder, server) = test_client_builder_with_server().await;
-    let encryption_settings = EncryptionSettings {
-        backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::AfterDecryptionFailure;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
         .with_encryption_settings(encryption_settings)
@@ -46,14 +46,15 @@ async fn test_client(user_id: &UserId) -> (Client, wiremock::MockServer) {
         tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
     };
 
+    let mut encryption_settings = matrix_sdk::encryption::EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::Manual;
+    encryption_settings.auto_enable_backups = true;
+
     let (builder, server) = test_client_builder_with_server().await;
     let client = builder
         .request_config(RequestConfig::new().disable_retry())
-        .with_encryption_settings(matrix_sdk::encryption::EncryptionSettings {
-            auto_enable_cross_signing: true,
-            backup_download_strategy: BackupDownloadStrategy::Manual,
-            auto_enable_backups: true,
-        })
+        .with_encryption_settings(encryption_settings)
         .build()
         .await
         .unwrap();
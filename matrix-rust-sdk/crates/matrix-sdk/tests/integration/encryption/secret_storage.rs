@@ -1,4 +1,7 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
 
 use assert_matches::assert_matches;
 use matrix_sdk::{
@@ -18,7 +21,7 @@ use ruma::{
     },
     user_id, UserId,
 };
-use serde_json::json;
+use serde_json::{json, Value};
 use wiremock::{
     matchers::{header, method, path, path_regex},
     Mock, MockServer, ResponseTemplate,
@@ -64,6 +67,57 @@ async fn mock_secret_store_key(
         .await;
 }
 
+/// Mock the account data endpoints with a store of the events, so the secret
+/// storage keys and secrets can be followed as they change.
+async fn mock_account_data_store(server: &MockServer) -> Arc<Mutex<BTreeMap<String, Value>>> {
+    let account_data: Arc<Mutex<BTreeMap<String, Value>>> = Default::default();
+
+    fn event_type(request: &wiremock::Request) -> String {
+        request.url.path_segments().and_then(|mut s| s.next_back()).unwrap().to_owned()
+    }
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"_matrix/client/r0/user/.*/account_data/"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with({
+            let account_data = account_data.to_owned();
+
+            move |request: &wiremock::Request| match account_data
+                .lock()
+                .unwrap()
+                .get(&event_type(request))
+            {
+                Some(content) => ResponseTemplate::new(200).set_body_json(content),
+                None => ResponseTemplate::new(404).set_body_json(json!({
+                    "errcode": "M_NOT_FOUND",
+                    "error": "Account data not found"
+                })),
+            }
+        })
+        .named("account data GET")
+        .mount(server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"_matrix/client/r0/user/.*/account_data/"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with({
+            let account_data = account_data.to_owned();
+
+            move |request: &wiremock::Request| {
+                let content = request.body_json().expect("The content should be JSON");
+                account_data.lock().unwrap().insert(event_type(request), content);
+
+                ResponseTemplate::new(200).set_body_json(json!({}))
+            }
+        })
+        .named("account data PUT")
+        .mount(server)
+        .await;
+
+    account_data
+}
+
 #[async_test]
 async fn test_secret_store_create_default_key() {
     let (client, server) = logged_in_client_with_server().await;
@@ -663,3 +717,157 @@ async fn test_is_secret_storage_enabled() {
         );
     }
 }
+
+#[async_test]
+async fn test_secret_store_keys_not_set_up() {
+    let (client, server) = logged_in_client_with_server().await;
+    mock_account_data_store(&server).await;
+
+    let keys = client
+        .encryption()
+        .secret_storage()
+        .fetch_keys()
+        .await
+        .expect("We should be able to fetch the secret storage keys");
+
+    assert!(keys.is_empty(), "There shouldn't be any key if secret storage isn't set up");
+}
+
+#[async_test]
+async fn test_secret_store_move_from_passphrase_to_recovery_key() {
+    let (client, server) = logged_in_client_with_server().await;
+    let account_data = mock_account_data_store(&server).await;
+    let secret_storage = client.encryption().secret_storage();
+
+    // Given a secret store protected by a passphrase, containing some secrets.
+    let old_store = secret_storage
+        .create_secret_store()
+        .with_passphrase("It's a secret to everybody")
+        .await
+        .expect("We should be able to create a new secret store");
+
+    old_store
+        .put_secret(SecretName::CrossSigningMasterKey, "master key")
+        .await
+        .expect("We should be able to store a secret to the secret store");
+    old_store
+        .put_secret(SecretName::RecoveryKey, "backup recovery key")
+        .await
+        .expect("We should be able to store a secret to the secret store");
+
+    // When we add a recovery key and make it the default key.
+    let new_store =
+        old_store.add_key(None).await.expect("We should be able to add a secret storage key");
+    new_store.set_as_default().await.expect("We should be able to set the default key");
+
+    // Then both keys are listed.
+    let keys = secret_storage.fetch_keys().await.expect("We should be able to fetch the keys");
+    assert_eq!(keys.len(), 2);
+
+    let old_key = keys
+        .iter()
+        .find(|key| key.key_id == old_store.key_id())
+        .expect("The old key should be listed");
+    assert!(old_key.passphrase.is_some(), "The old key should be based on a passphrase");
+
+    let new_key = keys
+        .iter()
+        .find(|key| key.key_id == new_store.key_id())
+        .expect("The new key should be listed");
+    assert!(new_key.passphrase.is_none(), "The new key should be a recovery key");
+
+    // And the default key can't be deleted.
+    assert_matches!(
+        secret_storage.delete_key(new_store.key_id()).await,
+        Err(SecretStorageError::DeletingDefaultKey { key_id }) => {
+            assert_eq!(key_id, new_store.key_id());
+        }
+    );
+
+    // When we delete the old key.
+    secret_storage.delete_key(old_store.key_id()).await.expect("We should be able to delete a key");
+
+    // Then only the new key is listed.
+    let keys = secret_storage.fetch_keys().await.expect("We should be able to fetch the keys");
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].key_id, new_store.key_id());
+
+    // And the secrets can only be decrypted with the new key.
+    let secret_store = secret_storage
+        .open_secret_store(&new_store.secret_storage_key())
+        .await
+        .expect("We should be able to open the secret store with the new key");
+
+    let master_key = secret_store
+        .get_secret(SecretName::CrossSigningMasterKey)
+        .await
+        .expect("We should be able to retrieve a secret from the secret store");
+    assert_eq!(master_key.as_deref(), Some("master key"));
+
+    let recovery_key = secret_store
+        .get_secret(SecretName::RecoveryKey)
+        .await
+        .expect("We should be able to retrieve a secret from the secret store");
+    assert_eq!(recovery_key.as_deref(), Some("backup recovery key"));
+
+    let content = account_data.lock().unwrap()["m.cross_signing.master"].to_owned();
+    let content: SecretEventContent = serde_json::from_value(content).unwrap();
+    assert_eq!(content.encrypted.len(), 1);
+    assert!(content.encrypted.contains_key(new_store.key_id()));
+
+    assert_matches!(
+        secret_storage
+            .open_secret_store_with_key_id(old_store.key_id(), "It's a secret to everybody")
+            .await,
+        Err(SecretStorageError::Json(_)),
+        "The info about the old key should have been deleted"
+    );
+}
+
+#[async_test]
+async fn test_secret_store_delete_only_key_of_secret() {
+    let (client, server) = logged_in_client_with_server().await;
+    let account_data = mock_account_data_store(&server).await;
+    let secret_storage = client.encryption().secret_storage();
+
+    // Given a secret store with two keys, and a secret only encrypted with the
+    // old key.
+    let old_store = secret_storage
+        .create_secret_store()
+        .with_passphrase("It's a secret to everybody")
+        .await
+        .expect("We should be able to create a new secret store");
+    let new_store =
+        old_store.add_key(None).await.expect("We should be able to add a secret storage key");
+    new_store.set_as_default().await.expect("We should be able to set the default key");
+
+    old_store
+        .put_secret(SecretName::RecoveryKey, "backup recovery key")
+        .await
+        .expect("We should be able to store a secret to the secret store");
+
+    // When we try to delete the old key, it's refused since the secret would be
+    // lost.
+    assert_matches!(
+        secret_storage.delete_key(old_store.key_id()).await,
+        Err(SecretStorageError::DeletingLastKeyOfSecret { key_id, secret_name }) => {
+            assert_eq!(key_id, old_store.key_id());
+            assert_eq!(secret_name, SecretName::RecoveryKey);
+        }
+    );
+
+    // Then the secret and the old key are left untouched.
+    let content = account_data.lock().unwrap()["m.megolm_backup.v1"].to_owned();
+    let content: SecretEventContent = serde_json::from_value(content).unwrap();
+    assert!(content.encrypted.contains_key(old_store.key_id()));
+
+    let keys = secret_storage.fetch_keys().await.expect("We should be able to fetch the keys");
+    assert_eq!(keys.len(), 2);
+
+    // Once the secret is encrypted with the new key, the old key can be deleted.
+    old_store.migrate_secrets_to(&new_store).await.expect("We should be able to migrate secrets");
+    secret_storage.delete_key(old_store.key_id()).await.expect("We should be able to delete a key");
+
+    let keys = secret_storage.fetch_keys().await.expect("We should be able to fetch the keys");
+    assert_eq!(keys.len(), 1);
+}
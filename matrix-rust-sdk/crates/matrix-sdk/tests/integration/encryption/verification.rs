@@ -8,27 +8,30 @@ use futures_util::FutureExt;
 use imbl::HashSet;
 use matrix_sdk::{
     config::RequestConfig,
-    encryption::VerificationState,
+    encryption::{identities::RequestVerificationError, VerificationState},
     matrix_auth::{MatrixSession, MatrixSessionTokens},
     test_utils::logged_in_client_with_server,
     Client,
 };
 use matrix_sdk_base::SessionMeta;
-use matrix_sdk_test::{async_test, SyncResponseBuilder};
+use matrix_sdk_test::{
+    async_test, mocks::mock_encryption_state, GlobalAccountDataTestEvent, JoinedRoomBuilder,
+    StateTestEvent, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
+};
 use ruma::{
     api::{client::keys::upload_signatures::v3::SignedKeys, MatrixVersion},
     encryption::{CrossSigningKey, DeviceKeys},
-    owned_device_id, owned_user_id,
+    owned_device_id, owned_user_id, room_id,
     serde::Raw,
     user_id, CrossSigningKeyId, DeviceId, OwnedDeviceId, OwnedUserId,
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{body_json, method, path},
+    matchers::{body_json, body_partial_json, method, path, path_regex},
     Mock, MockServer, Request, ResponseTemplate,
 };
 
-use crate::mock_sync_scoped;
+use crate::{mock_sync, mock_sync_scoped};
 
 #[derive(Debug, Default)]
 struct Keys {
@@ -743,3 +746,138 @@ async fn test_request_user_identity() {
     assert_matches!(encryption.request_user_identity(bob_id).await, Ok(Some(_)));
     assert_matches!(encryption.get_user_identity(bob_id).await, Ok(Some(_)));
 }
+
+#[async_test]
+async fn test_request_verification_from_room_member() {
+    let (client, server) = logged_in_client_with_server().await;
+    let bob_id = user_id!("@bob:example.org");
+    let room_id = room_id!("!dm:example.org");
+
+    let bob_member = json!({
+        "content": { "membership": "join" },
+        "event_id": "$bob_join",
+        "origin_server_ts": 151800140,
+        "sender": bob_id,
+        "state_key": bob_id,
+        "type": "m.room.member",
+    });
+
+    // We share a DM with Bob.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder
+        .add_joined_room(
+            JoinedRoomBuilder::new(room_id)
+                .add_state_event(StateTestEvent::Custom(bob_member.clone())),
+        )
+        .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+            "content": { bob_id: [room_id] },
+            "type": "m.direct",
+        })));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(Default::default()).await.unwrap();
+
+    // Bob's cross-signing keys, his devices aren't needed to request a
+    // verification in a room.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/keys/query"))
+        .and(body_json(json!({ "device_keys": { bob_id: []}})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "failures": {},
+            "device_keys": {},
+            "master_keys": {
+                "@bob:example.org": {
+                    "user_id": "@bob:example.org",
+                    "usage": ["master"],
+                    "keys": {
+                        "ed25519:3NZwYz0VjFrhONhYT2iBWCYdEYF266jn/vmZqc6QdDU": "3NZwYz0VjFrhONhYT2iBWCYdEYF266jn/vmZqc6QdDU"
+                    },
+                    "signatures": {
+                        "@bob:example.org": {
+                            "ed25519:3NZwYz0VjFrhONhYT2iBWCYdEYF266jn/vmZqc6QdDU": "TCnq8/vy6lp56cF5J9PmsqTnLjKcvsNYN7qFpD6isYWmEFLFBfml8B2ceBzlu9NLwi0xT9jpQV7SYRQt4ZnICQ",
+                            "ed25519:B0B0B0B0B": "yCQFDN+1sJQ+qhqfubOnmPOu/agHT8k17SaD886QmVDwEXCeFFDSZKY29oBDaCRJZJ2BvE2WSK+GACXv5t2FDw"
+                        }
+                    }
+                },
+            },
+            "self_signing_keys": {
+                "@bob:example.org": {
+                    "user_id": "@bob:example.org",
+                    "usage": ["self_signing"],
+                    "keys": {
+                        "ed25519:5JpU6BNHsBZbf4Y3t0IvpIWa7kKDSGy3b+DjVjUuJmc": "5JpU6BNHsBZbf4Y3t0IvpIWa7kKDSGy3b+DjVjUuJmc"
+                    },
+                    "signatures": {
+                        "@bob:example.org": {
+                            "ed25519:3NZwYz0VjFrhONhYT2iBWCYdEYF266jn/vmZqc6QdDU": "yre3bDdzSYNQweNRRB0BXSaaM8n9IA2puathxXSDGebyF6Bh1+Kd6Q8/tl271LwM4Wdar4vgPwrgExW7k2hLBg"
+                        }
+                    }
+                },
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "chunk": [bob_member] })))
+        .mount(&server)
+        .await;
+
+    mock_encryption_state(&server, false).await;
+
+    // The verification request is sent in the DM.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.message/"))
+        .and(body_partial_json(json!({ "msgtype": "m.key.verification.request", "to": bob_id })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$request" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let member = room.get_member_no_sync(bob_id).await.unwrap().unwrap();
+
+    let request = member.request_verification().await.unwrap();
+
+    assert_eq!(request.other_user_id(), bob_id);
+    assert_eq!(request.room_id(), Some(room_id));
+    assert_eq!(request.flow_id(), "$request");
+    assert!(request.we_started());
+}
+
+#[async_test]
+async fn test_request_verification_from_room_member_without_identity() {
+    let (client, server) = logged_in_client_with_server().await;
+    let bob_id = user_id!("@bob:example.org");
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": { "membership": "join" },
+            "event_id": "$bob_join",
+            "origin_server_ts": 151800140,
+            "sender": bob_id,
+            "state_key": bob_id,
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(Default::default()).await.unwrap();
+
+    // Bob didn't set up cross-signing.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/keys/query"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "device_keys": {} })))
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let member = room.get_member_no_sync(bob_id).await.unwrap().unwrap();
+
+    assert_matches!(
+        member.request_verification().await,
+        Err(RequestVerificationError::MissingIdentity(user_id))
+    );
+    assert_eq!(user_id, bob_id);
+}
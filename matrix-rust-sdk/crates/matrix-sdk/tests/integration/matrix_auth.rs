@@ -469,13 +469,13 @@ async fn test_login_with_cross_signing_bootstrapping() {
             .mount_as_scoped(&server)
             .await;
 
+        let mut encryption_settings = matrix_sdk::encryption::EncryptionSettings::default();
+        encryption_settings.auto_enable_cross_signing = true;
+
         let client = Client::builder()
             .homeserver_url(server.uri())
             .server_versions([MatrixVersion::V1_0])
-            .with_encryption_settings(matrix_sdk::encryption::EncryptionSettings {
-                auto_enable_cross_signing: true,
-                ..Default::default()
-            })
+            .with_encryption_settings(encryption_settings)
             .request_config(RequestConfig::new().disable_retry())
             .build()
             .await
@@ -522,13 +522,13 @@ async fn test_login_with_cross_signing_bootstrapping() {
             .mount_as_scoped(&server)
             .await;
 
+        let mut encryption_settings = matrix_sdk::encryption::EncryptionSettings::default();
+        encryption_settings.auto_enable_cross_signing = true;
+
         let client = Client::builder()
             .homeserver_url(server.uri())
             .server_versions([MatrixVersion::V1_0])
-            .with_encryption_settings(matrix_sdk::encryption::EncryptionSettings {
-                auto_enable_cross_signing: true,
-                ..Default::default()
-            })
+            .with_encryption_settings(encryption_settings)
             .request_config(RequestConfig::new().disable_retry())
             .build()
             .await
@@ -598,13 +598,13 @@ async fn test_login_doesnt_fail_if_cross_signing_bootstrapping_failed() {
         .mount_as_scoped(&server)
         .await;
 
+    let mut encryption_settings = matrix_sdk::encryption::EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
+
     let client = Client::builder()
         .homeserver_url(server.uri())
         .server_versions([MatrixVersion::V1_0])
-        .with_encryption_settings(matrix_sdk::encryption::EncryptionSettings {
-            auto_enable_cross_signing: true,
-            ..Default::default()
-        })
+        .with_encryption_settings(encryption_settings)
         .request_config(RequestConfig::new().disable_retry())
         .build()
         .await
@@ -721,11 +721,11 @@ async fn test_login_with_cross_signing_bootstrapping_already_bootstrapped() {
         .mount(&server)
         .await;
 
+    let mut encryption_settings = matrix_sdk::encryption::EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
+
     let client = builder
-        .with_encryption_settings(matrix_sdk::encryption::EncryptionSettings {
-            auto_enable_cross_signing: true,
-            ..Default::default()
-        })
+        .with_encryption_settings(encryption_settings)
         .request_config(RequestConfig::new().disable_retry())
         .build()
         .await
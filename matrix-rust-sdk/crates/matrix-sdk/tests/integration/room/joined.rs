@@ -3,7 +3,7 @@ use std::{
     time::Duration,
 };
 
-use futures_util::future::join_all;
+use futures_util::{future::join_all, FutureExt as _};
 use matrix_sdk::{
     config::SyncSettings,
     room::{edit::EditedContent, Receipts, ReportedContentScore, RoomMemberRole},
@@ -23,7 +23,10 @@ use ruma::{
     assign, event_id,
     events::{
         receipt::ReceiptThread,
-        room::message::{RoomMessageEventContent, RoomMessageEventContentWithoutRelation},
+        room::{
+            join_rules::JoinRule,
+            message::{RoomMessageEventContent, RoomMessageEventContentWithoutRelation},
+        },
         TimelineEventType,
     },
     int, mxc_uri, owned_event_id, room_id, thirdparty, user_id, OwnedUserId, TransactionId,
@@ -162,6 +165,115 @@ async fn test_unban_user() {
     room.unban_user(user, None).await.unwrap();
 }
 
+fn knock_event(user_id: &str, event_id: &str) -> Value {
+    json!({
+        "content": {
+            "membership": "knock",
+            "displayname": user_id,
+            "reason": "Let me in",
+        },
+        "event_id": event_id,
+        "origin_server_ts": 151800140,
+        "sender": user_id,
+        "state_key": user_id,
+        "type": "m.room.member",
+    })
+}
+
+#[async_test]
+async fn test_knock_requests() {
+    let (client, server) = logged_in_client_with_server().await;
+    let room_id = room_id!("!test:example.org");
+    let alice = user_id!("@alice:example.org");
+    let bob = user_id!("@bob:example.org");
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [knock_event(alice.as_str(), "$alice_knock")],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let (_drop_guard, mut subscriber) = room.subscribe_to_knock_requests().await.unwrap();
+
+    // The pending knock has been loaded from the member list.
+    let knock_requests = subscriber.get();
+    assert_eq!(knock_requests.len(), 1);
+    assert_eq!(knock_requests[0].user_id(), alice);
+    assert_eq!(knock_requests[0].reason(), Some("Let me in"));
+    assert_eq!(knock_requests[0].event_id(), Some(event_id!("$alice_knock")));
+
+    // A new knock is received through the sync.
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(knock_event(bob.as_str(), "$bob_knock"))),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let knock_requests = subscriber.next().now_or_never().flatten().unwrap();
+    assert_eq!(knock_requests.len(), 2);
+
+    // Accepting a knock invites the user, denying it kicks them.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite$"))
+        .and(body_json(json!({ "user_id": alice })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/kick$"))
+        .and(body_json(json!({ "user_id": bob, "reason": "Not today" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    for knock_request in &knock_requests {
+        if knock_request.user_id() == alice {
+            knock_request.accept().await.unwrap();
+        } else {
+            knock_request.deny(Some("Not today")).await.unwrap();
+        }
+    }
+}
+
+#[async_test]
+async fn test_set_join_rule() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.join_rules/$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({
+            "join_rule": "knock",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.set_join_rule(JoinRule::Knock).await.unwrap();
+}
+
 #[async_test]
 async fn test_mark_as_unread() {
     let (client, server) = logged_in_client_with_server().await;
@@ -7,8 +7,8 @@ use matrix_sdk::{
     config::StoreConfig,
     media::{MediaFormat, MediaRequestParameters, MediaThumbnailSettings},
     send_queue::{
-        LocalEcho, LocalEchoContent, RoomSendQueue, RoomSendQueueError, RoomSendQueueStorageError,
-        RoomSendQueueUpdate, SendHandle,
+        LocalEcho, LocalEchoContent, OutboxRequestState, RoomSendQueue, RoomSendQueueError,
+        RoomSendQueueStorageError, RoomSendQueueUpdate, SendHandle, SendQueueUpdate,
     },
     test_utils::mocks::{MatrixMock, MatrixMockServer},
     Client, MemoryStore,
@@ -2869,3 +2869,326 @@ async fn test_update_caption_while_sending_media_event() {
     // That's all, folks!
     assert!(watch.is_empty());
 }
+
+#[async_test]
+async fn test_outbox_lists_requests_across_rooms() {
+    let mock = MatrixMockServer::new().await;
+
+    let client = mock.client_builder().build().await;
+    let room1 = mock.sync_joined_room(&client, room_id!("!a:b.c")).await;
+    let room2 = mock.sync_joined_room(&client, room_id!("!d:e.f")).await;
+
+    // Nothing is sent while the send queue is disabled.
+    client.send_queue().set_enabled(false).await;
+
+    let (entries, mut watch) = client.send_queue().outbox().await.unwrap();
+    assert!(entries.is_empty());
+    assert!(watch.is_empty());
+
+    room1.send_queue().send(RoomMessageEventContent::text_plain("1").into()).await.unwrap();
+    room2.send_queue().send(RoomMessageEventContent::text_plain("2").into()).await.unwrap();
+
+    // The global stream gets the updates from both rooms.
+    assert_let!(
+        Ok(Ok(SendQueueUpdate {
+            room_id,
+            update: RoomSendQueueUpdate::NewLocalEvent(LocalEcho { transaction_id: txn1, .. }),
+        })) = timeout(Duration::from_secs(1), watch.recv()).await
+    );
+    assert_eq!(room_id, room1.room_id());
+
+    assert_let!(
+        Ok(Ok(SendQueueUpdate {
+            room_id,
+            update: RoomSendQueueUpdate::NewLocalEvent(LocalEcho { transaction_id: txn2, .. }),
+        })) = timeout(Duration::from_secs(1), watch.recv()).await
+    );
+    assert_eq!(room_id, room2.room_id());
+
+    // And the outbox lists both requests as pending.
+    let (mut entries, _) = client.send_queue().outbox().await.unwrap();
+    entries.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+    assert_eq!(entries.len(), 2);
+
+    assert_eq!(entries[0].room_id, room1.room_id());
+    assert_eq!(entries[0].local_echo.transaction_id, txn1);
+    assert_matches!(entries[0].state, OutboxRequestState::Pending);
+    assert_eq!(entries[0].priority, Some(0));
+
+    assert_eq!(entries[1].room_id, room2.room_id());
+    assert_eq!(entries[1].local_echo.transaction_id, txn2);
+    assert_matches!(entries[1].state, OutboxRequestState::Pending);
+    assert_eq!(entries[1].priority, Some(0));
+}
+
+#[async_test]
+async fn test_outbox_unwedge_all() {
+    let mock = MatrixMockServer::new().await;
+
+    // Mark the room as joined.
+    let room_id = room_id!("!a:b.c");
+    let client = mock.client_builder().build().await;
+    let room = mock.sync_joined_room(&client, room_id).await;
+
+    let q = room.send_queue();
+    let (_, mut watch) = q.subscribe().await.unwrap();
+
+    mock.mock_room_state_encryption().plain().mount().await;
+
+    // Respond to the first /send with an unrecoverable error.
+    mock.mock_room_send().error_too_large().mock_once().mount().await;
+    // Respond to the second /send with an OK response.
+    mock.mock_room_send().ok(event_id!("$42")).mock_once().mount().await;
+
+    q.send(RoomMessageEventContent::text_plain("i'm too big for ya").into()).await.unwrap();
+
+    let (txn, _) = assert_update!(watch => local echo { body = "i'm too big for ya" });
+    assert_update!(watch => error { recoverable=false, txn=txn });
+
+    // The outbox reports the request as wedged.
+    let (entries, _) = client.send_queue().outbox().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].local_echo.transaction_id, txn);
+    assert_matches!(entries[0].state, OutboxRequestState::Wedged(_));
+
+    // Unwedging all the requests retries it.
+    assert_eq!(client.send_queue().unwedge_all().await.unwrap(), 1);
+
+    assert_update!(watch => retry { txn=txn });
+    assert_update!(watch => sent { txn=txn, event_id=event_id!("$42") });
+
+    // Nothing's left in the outbox.
+    let (entries, _) = client.send_queue().outbox().await.unwrap();
+    assert!(entries.is_empty());
+    assert_eq!(client.send_queue().unwedge_all().await.unwrap(), 0);
+}
+
+#[async_test]
+async fn test_outbox_cancel_all() {
+    let mock = MatrixMockServer::new().await;
+
+    // Mark the room as joined.
+    let room_id = room_id!("!a:b.c");
+    let client = mock.client_builder().build().await;
+    let room = mock.sync_joined_room(&client, room_id).await;
+
+    // Nothing is sent while the send queue is disabled.
+    client.send_queue().set_enabled(false).await;
+
+    let q = room.send_queue();
+    let (_, mut watch) = q.subscribe().await.unwrap();
+
+    q.send(RoomMessageEventContent::text_plain("msg1").into()).await.unwrap();
+    q.send(RoomMessageEventContent::text_plain("msg2").into()).await.unwrap();
+
+    let (txn1, _) = assert_update!(watch => local echo { body = "msg1" });
+    let (txn2, _) = assert_update!(watch => local echo { body = "msg2" });
+
+    assert_eq!(q.cancel_all().await.unwrap(), 2);
+
+    assert_update!(watch => cancelled { txn = txn1 });
+    assert_update!(watch => cancelled { txn = txn2 });
+    assert!(watch.is_empty());
+
+    assert!(q.outbox().await.unwrap().is_empty());
+    assert_eq!(q.cancel_all().await.unwrap(), 0);
+}
+
+#[async_test]
+async fn test_outbox_set_priority() {
+    let mock = MatrixMockServer::new().await;
+
+    // Mark the room as joined.
+    let room_id = room_id!("!a:b.c");
+    let client = mock.client_builder().build().await;
+    let room = mock.sync_joined_room(&client, room_id).await;
+
+    // Nothing is sent while the send queue is disabled.
+    client.send_queue().set_enabled(false).await;
+
+    let q = room.send_queue();
+    let (_, mut watch) = q.subscribe().await.unwrap();
+
+    q.send(RoomMessageEventContent::text_plain("msg1").into()).await.unwrap();
+    let handle2 = q.send(RoomMessageEventContent::text_plain("msg2").into()).await.unwrap();
+
+    let (txn1, _) = assert_update!(watch => local echo { body = "msg1" });
+    let (txn2, _) = assert_update!(watch => local echo { body = "msg2" });
+
+    // Bumping the priority of the second message puts it in front of the first one.
+    assert!(handle2.set_priority(1).await.unwrap());
+
+    let entries = q.outbox().await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].local_echo.transaction_id, txn2);
+    assert_eq!(entries[0].priority, Some(1));
+    assert_eq!(entries[1].local_echo.transaction_id, txn1);
+    assert_eq!(entries[1].priority, Some(0));
+
+    // When the queue is enabled again, the messages are sent in the new order.
+    mock.mock_room_state_encryption().plain().mount().await;
+    mock.mock_room_send().ok(event_id!("$2")).mock_once().mount().await;
+    mock.mock_room_send().ok(event_id!("$1")).mock_once().mount().await;
+
+    client.send_queue().set_enabled(true).await;
+
+    assert_update!(watch => sent { txn = txn2, event_id = event_id!("$2") });
+    assert_update!(watch => sent { txn = txn1, event_id = event_id!("$1") });
+
+    // It's too late to change the priority of a sent event.
+    assert!(!handle2.set_priority(0).await.unwrap());
+}
+
+#[async_test]
+async fn test_identity_remedies_ignore_other_errors() {
+    let mock = MatrixMockServer::new().await;
+
+    // Mark the room as joined.
+    let room_id = room_id!("!a:b.c");
+    let client = mock.client_builder().build().await;
+    let room = mock.sync_joined_room(&client, room_id).await;
+
+    let q = room.send_queue();
+    let (_, mut watch) = q.subscribe().await.unwrap();
+
+    mock.mock_room_state_encryption().plain().mount().await;
+
+    // Respond to the /send with an unrecoverable error unrelated to identities.
+    mock.mock_room_send().error_too_large().mock_once().mount().await;
+
+    let handle =
+        q.send(RoomMessageEventContent::text_plain("i'm too big for ya").into()).await.unwrap();
+
+    let (txn, _) = assert_update!(watch => local echo { body = "i'm too big for ya" });
+    assert_update!(watch => error { recoverable=false, txn=txn });
+
+    // The identity remedies don't apply, so they don't unwedge the event.
+    assert!(handle.withdraw_verification_and_resend().await.unwrap().is_none());
+    assert!(handle.ignore_insecure_devices_and_resend().await.unwrap().is_none());
+    assert!(watch.is_empty());
+
+    let entries = q.outbox().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_matches!(entries[0].state, OutboxRequestState::Wedged(_));
+}
+
+/// Wedge an event sent to a room, and make it look like it was because of the
+/// given error.
+#[cfg(feature = "e2e-encryption")]
+async fn wedge_event_with(
+    mock: &MatrixMockServer,
+    client: &Client,
+    room_id: &ruma::RoomId,
+    error: matrix_sdk_base::store::QueueWedgeError,
+) -> (SendHandle, OwnedTransactionId, Receiver<RoomSendQueueUpdate>) {
+    let room = mock.sync_joined_room(client, room_id).await;
+    let q = room.send_queue();
+    let (_, mut watch) = q.subscribe().await.unwrap();
+
+    mock.mock_room_state_encryption().plain().mount().await;
+    mock.mock_room_send().error_too_large().mock_once().mount().await;
+
+    let handle = q.send(RoomMessageEventContent::text_plain("hello").into()).await.unwrap();
+
+    let (txn, _) = assert_update!(watch => local echo { body = "hello" });
+    assert_update!(watch => error { recoverable=false, txn=txn });
+
+    client.store().update_send_queue_request_status(room_id, &txn, Some(error)).await.unwrap();
+
+    (handle, txn, watch)
+}
+
+#[cfg(feature = "e2e-encryption")]
+#[async_test]
+async fn test_withdraw_verification_and_resend() {
+    use wiremock::{
+        matchers::{method, path_regex},
+        Mock,
+    };
+
+    let mock = MatrixMockServer::new().await;
+    let client = mock.client_builder().build().await;
+    let own_user_id = client.user_id().unwrap().to_owned();
+
+    // Create our own identity, so there's an identity to withdraw the verification
+    // of.
+    Mock::given(method("POST"))
+        .and(path_regex(r"/keys/(device_signing|signatures)/upload$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "failures": {} })))
+        .mount(mock.server())
+        .await;
+    Mock::given(method("POST"))
+        .and(path_regex(r"/keys/upload$"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "one_time_key_counts": {} })),
+        )
+        .mount(mock.server())
+        .await;
+    client.encryption().bootstrap_cross_signing(None).await.unwrap();
+
+    let (handle, txn, mut watch) = wedge_event_with(
+        &mock,
+        &client,
+        room_id!("!a:b.c"),
+        matrix_sdk_base::store::QueueWedgeError::IdentityViolations {
+            users: vec![own_user_id.clone(), owned_user_id!("@bob:b.c")],
+        },
+    )
+    .await;
+
+    // The event is wedged because of identity violations, the other remedy doesn't
+    // apply.
+    assert!(handle.ignore_insecure_devices_and_resend().await.unwrap().is_none());
+
+    mock.mock_room_send().ok(event_id!("$1")).mock_once().mount().await;
+
+    // Only the users with a known identity are reported.
+    let withdrawn = handle.withdraw_verification_and_resend().await.unwrap().unwrap();
+    assert_eq!(withdrawn, [own_user_id]);
+
+    assert_update!(watch => retry { txn=txn });
+    assert_update!(watch => sent { txn=txn, event_id=event_id!("$1") });
+}
+
+#[cfg(feature = "e2e-encryption")]
+#[async_test]
+async fn test_ignore_insecure_devices_and_resend() {
+    use std::collections::BTreeMap;
+
+    let mock = MatrixMockServer::new().await;
+    let client = mock.client_builder().build().await;
+
+    // Our own device is the only one known by the client.
+    let user_id = client.user_id().unwrap().to_owned();
+    let device_id = client.device_id().unwrap().to_owned();
+    let insecure_devices = BTreeMap::from([(user_id.clone(), vec![device_id.clone()])]);
+
+    let device = client.encryption().get_device(&user_id, &device_id).await.unwrap().unwrap();
+    let local_trust = device.local_trust_state();
+
+    let (handle, txn, mut watch) = wedge_event_with(
+        &mock,
+        &client,
+        room_id!("!a:b.c"),
+        matrix_sdk_base::store::QueueWedgeError::InsecureDevices {
+            user_device_map: insecure_devices.clone(),
+        },
+    )
+    .await;
+
+    // The event is wedged because of insecure devices, the other remedy doesn't
+    // apply.
+    assert!(handle.withdraw_verification_and_resend().await.unwrap().is_none());
+
+    mock.mock_room_send().ok(event_id!("$1")).mock_once().mount().await;
+
+    let ignored = handle.ignore_insecure_devices_and_resend().await.unwrap().unwrap();
+    assert_eq!(ignored, insecure_devices);
+
+    assert_update!(watch => retry { txn=txn });
+    assert_update!(watch => sent { txn=txn, event_id=event_id!("$1") });
+
+    // The devices are only ignored for this event: their local trust didn't change.
+    let device = client.encryption().get_device(&user_id, &device_id).await.unwrap().unwrap();
+    assert_eq!(device.local_trust_state(), local_trust);
+}
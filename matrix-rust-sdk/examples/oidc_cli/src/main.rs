@@ -521,7 +521,8 @@ impl OidcCli {
                         if let Some(state) = res {
                             match state {
                                 matrix_sdk_ui::sync_service::State::Idle
-                                | matrix_sdk_ui::sync_service::State::Terminated => {
+                                | matrix_sdk_ui::sync_service::State::Terminated
+                                | matrix_sdk_ui::sync_service::State::Offline => {
                                     num_errors = 0;
                                     num_running = 0;
                                 }
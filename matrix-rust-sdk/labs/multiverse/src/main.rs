@@ -951,6 +951,12 @@ async fn configure_client(server_name: String, config_path: String) -> anyhow::R
     let server_name = ServerName::parse(&server_name)?;
 
     let config_path = PathBuf::from(config_path);
+
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::AfterDecryptionFailure;
+    encryption_settings.auto_enable_backups = true;
+
     let mut client_builder = Client::builder()
         .store_config(
             StoreConfig::new("multiverse".to_owned())
@@ -961,11 +967,7 @@ async fn configure_client(server_name: String, config_path: String) -> anyhow::R
                 ),
         )
         .server_name(&server_name)
-        .with_encryption_settings(EncryptionSettings {
-            auto_enable_cross_signing: true,
-            backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
-            auto_enable_backups: true,
-        });
+        .with_encryption_settings(encryption_settings);
 
     if let Ok(proxy_url) = env::var("PROXY") {
         client_builder = client_builder.proxy(proxy_url).disable_ssl_verification();
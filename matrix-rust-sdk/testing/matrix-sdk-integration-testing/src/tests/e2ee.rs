@@ -34,8 +34,8 @@ use crate::helpers::{SyncTokenAwareClient, TestClientBuilder};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_mutual_sas_verification() -> Result<()> {
-    let encryption_settings =
-        EncryptionSettings { auto_enable_cross_signing: true, ..Default::default() };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
     let alice = SyncTokenAwareClient::new(
         TestClientBuilder::new("alice")
             .use_sqlite()
@@ -296,8 +296,8 @@ async fn test_mutual_sas_verification() -> Result<()> {
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_mutual_qrcode_verification() -> Result<()> {
-    let encryption_settings =
-        EncryptionSettings { auto_enable_cross_signing: true, ..Default::default() };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
     let alice = SyncTokenAwareClient::new(
         TestClientBuilder::new("alice")
             .use_sqlite()
@@ -719,8 +719,8 @@ async fn test_failed_members_response() -> Result<()> {
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_backup_enable_new_user() -> Result<()> {
-    let encryption_settings =
-        EncryptionSettings { auto_enable_backups: true, ..Default::default() };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_backups = true;
 
     let alice = SyncTokenAwareClient::new(
         TestClientBuilder::new("alice")
@@ -748,8 +748,8 @@ async fn test_backup_enable_new_user() -> Result<()> {
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_cross_signing_bootstrap() -> Result<()> {
-    let encryption_settings =
-        EncryptionSettings { auto_enable_cross_signing: true, ..Default::default() };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
 
     let alice = SyncTokenAwareClient::new(
         TestClientBuilder::new("alice")
@@ -785,11 +785,10 @@ async fn test_cross_signing_bootstrap() -> Result<()> {
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_secret_gossip_after_interactive_verification() -> Result<()> {
-    let encryption_settings = EncryptionSettings {
-        auto_enable_cross_signing: true,
-        auto_enable_backups: true,
-        backup_download_strategy: BackupDownloadStrategy::OneShot,
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_cross_signing = true;
+    encryption_settings.auto_enable_backups = true;
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::OneShot;
 
     let first_client = SyncTokenAwareClient::new(
         TestClientBuilder::new("alice_gossip_test")
@@ -444,7 +444,9 @@ async fn decrypt_event(
 
 /// The standard settings for an encrypted room.
 fn encryption_settings() -> EncryptionSettings {
-    EncryptionSettings { auto_enable_cross_signing: true, ..Default::default() }
+    let mut settings = EncryptionSettings::default();
+    settings.auto_enable_cross_signing = true;
+    settings
 }
 
 /// How long to wait before giving up on an operation.
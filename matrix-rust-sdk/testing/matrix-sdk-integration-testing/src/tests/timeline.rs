@@ -310,12 +310,10 @@ async fn test_stale_local_echo_time_abort_edit() {
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_enabling_backups_retries_decryption() {
-    let encryption_settings = EncryptionSettings {
-        auto_enable_backups: true,
-        backup_download_strategy:
-            matrix_sdk::encryption::BackupDownloadStrategy::AfterDecryptionFailure,
-        ..Default::default()
-    };
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.auto_enable_backups = true;
+    encryption_settings.backup_download_strategy =
+        matrix_sdk::encryption::BackupDownloadStrategy::AfterDecryptionFailure;
     let alice = TestClientBuilder::new("alice")
         .use_sqlite()
         .encryption_settings(encryption_settings)
@@ -0,0 +1,58 @@
+// Sync checkpoints, to replay the sync from a known-good point and find which
+// response put the client in a bad state.
+
+use anyhow::Result;
+use matrix_sdk::{
+    sync_checkpoints::{SyncCheckpoint, SyncCheckpointsConfig},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointsConfig {
+    // take a checkpoint every this many sync responses
+    every: usize,
+
+    // how many checkpoints are kept
+    keep: usize,
+
+    // roll back to this checkpoint at startup, before syncing
+    restore: Option<u64>,
+}
+
+impl CheckpointsConfig {
+    pub fn sdk_config(&self) -> SyncCheckpointsConfig {
+        SyncCheckpointsConfig::new()
+            .every(self.every)
+            .keep(self.keep)
+    }
+}
+
+// Log the known checkpoints, and roll back to the configured one if any.
+pub async fn restore_checkpoint(
+    client: &Client,
+    config: Option<&CheckpointsConfig>,
+) -> Result<Option<SyncCheckpoint>> {
+    let Some(config) = config else {
+        return Ok(None);
+    };
+
+    for checkpoint in client.sync_checkpoints().await? {
+        log::info!(
+            "Checkpoint {} taken at {:?}: {:?}",
+            checkpoint.id,
+            checkpoint.created_at,
+            checkpoint.position
+        );
+    }
+
+    let Some(id) = config.restore else {
+        return Ok(None);
+    };
+
+    let checkpoint = client.restore_checkpoint(id).await?;
+    log::info!("Restored checkpoint {}, replaying the sync from there", id);
+
+    Ok(Some(checkpoint))
+}
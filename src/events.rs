@@ -17,6 +17,30 @@ lazy_static! {
         (tx, Mutex::new(rx))
     };
 
+    // We push requests to export the room keys into this channel.
+    pub static ref EXPORT_KEYS: (mpsc::Sender<()>, tokio::sync::Mutex<mpsc::Receiver<()>>) = {
+        let (tx, rx) = mpsc::channel::<()>(10);
+        (tx, Mutex::new(rx))
+    };
+
+    // We push requests to import the room keys into this channel.
+    pub static ref IMPORT_KEYS: (mpsc::Sender<()>, tokio::sync::Mutex<mpsc::Receiver<()>>) = {
+        let (tx, rx) = mpsc::channel::<()>(10);
+        (tx, Mutex::new(rx))
+    };
+
+    // We push requests to inspect the crypto state into this channel.
+    pub static ref INSPECT: (mpsc::Sender<()>, tokio::sync::Mutex<mpsc::Receiver<()>>) = {
+        let (tx, rx) = mpsc::channel::<()>(10);
+        (tx, Mutex::new(rx))
+    };
+
+    // We push requests to verify the configured user into this channel.
+    pub static ref VERIFY_USER: (mpsc::Sender<()>, tokio::sync::Mutex<mpsc::Receiver<()>>) = {
+        let (tx, rx) = mpsc::channel::<()>(10);
+        (tx, Mutex::new(rx))
+    };
+
     // e2e verification state
     pub static ref VERIFIED: Mutex<bool> = Mutex::new(false);
 
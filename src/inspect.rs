@@ -0,0 +1,146 @@
+// Inspector of the local crypto state, to debug decryption issues without
+// reading the crypto store by hand.
+
+use anyhow::{Context, Result};
+use matrix_sdk::{
+    ruma::{OwnedEventId, OwnedRoomId, OwnedUserId},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::events::INSPECT;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InspectConfig {
+    // user whose devices are listed, our own user if not set
+    user: Option<OwnedUserId>,
+
+    // room whose outbound session is shown, timeline_test_room if not set
+    room: Option<OwnedRoomId>,
+
+    // event of the room whose room key is shown
+    event: Option<OwnedEventId>,
+}
+
+pub async fn handle_inspect(
+    client: Client,
+    config: InspectConfig,
+    timeline_test_room: Option<OwnedRoomId>,
+) {
+    loop {
+        let mut rx = INSPECT.1.lock().await;
+        let Some(_) = rx.recv().await else {
+            continue;
+        };
+
+        if let Err(e) = inspect(&client, &config, timeline_test_room.as_ref()).await {
+            log::error!("Failed to inspect the crypto state: {:?}", e);
+        }
+    }
+}
+
+async fn inspect(
+    client: &Client,
+    config: &InspectConfig,
+    timeline_test_room: Option<&OwnedRoomId>,
+) -> Result<()> {
+    let inspector = client.encryption().inspector();
+
+    let user_id = match &config.user {
+        Some(user_id) => user_id.clone(),
+        None => client
+            .user_id()
+            .context("Client isn't logged in")?
+            .to_owned(),
+    };
+    let user = inspector.user(&user_id).await?;
+
+    log::info!("User {}:", user_id);
+    match &user.identity {
+        Some(identity) => log::info!(
+            "  identity: master key {:?}, verified: {}",
+            identity.master_key.map(|k| k.to_base64()),
+            identity.is_verified
+        ),
+        None => log::info!("  no cross-signing identity"),
+    }
+    for device in &user.devices {
+        log::info!(
+            "  device {} ({}): local trust {:?}, cross-signed: {}, verified: {}{}",
+            device.device_id,
+            device.display_name.as_deref().unwrap_or("no name"),
+            device.local_trust,
+            device.is_cross_signed_by_owner,
+            device.is_verified,
+            if device.is_deleted { ", deleted" } else { "" }
+        );
+        log::info!(
+            "    curve25519: {:?}, ed25519: {:?}",
+            device.curve25519_key.map(|k| k.to_base64()),
+            device.ed25519_key.map(|k| k.to_base64())
+        );
+    }
+
+    let Some(room_id) = config.room.as_ref().or(timeline_test_room) else {
+        return Ok(());
+    };
+    let room = inspector.room(room_id).await?;
+
+    log::info!("Room {}:", room_id);
+    match &room.settings {
+        Some(settings) => log::info!(
+            "  settings: {}, only trusted devices: {}, rotation: {:?} / {:?} messages",
+            settings.algorithm,
+            settings.only_allow_trusted_devices,
+            settings.session_rotation_period,
+            settings.session_rotation_period_messages
+        ),
+        None => log::info!("  no settings"),
+    }
+    match &room.outbound_session {
+        Some(session) => {
+            log::info!(
+                "  outbound session {}: created {}, {} messages, shared: {}, expired: {}, invalidated: {}",
+                session.session_id,
+                session.creation_time.get(),
+                session.message_count,
+                session.is_shared,
+                session.is_expired,
+                session.is_invalidated
+            );
+            log::info!(
+                "    rotation: {:?} / {} messages",
+                session.rotation_period,
+                session.rotation_period_msgs
+            );
+            for (user_id, devices) in &session.shared_with {
+                log::info!("    shared with {}: {:?}", user_id, devices);
+            }
+        }
+        None => log::info!("  no outbound session"),
+    }
+
+    let Some(event_id) = &config.event else {
+        return Ok(());
+    };
+
+    log::info!("Event {}:", event_id);
+    match inspector.event(room_id, event_id).await? {
+        Some(event) => match &event.inbound_session {
+            Some(session) => log::info!(
+                "  session {}: sender key {}, first index {}, backed up: {}, imported: {}, sender data {:?}",
+                event.session_id,
+                session.sender_key.to_base64(),
+                session.first_known_index,
+                session.is_backed_up,
+                session.is_imported,
+                session.sender_data
+            ),
+            None => log::info!("  session {}: missing room key", event.session_id),
+        },
+        None => log::info!("  not encrypted with megolm"),
+    }
+
+    Ok(())
+}
@@ -8,7 +8,16 @@ use crate::events::PAGINATE_BACKWARDS;
 
 use crate::events::LIST_ROOMS;
 
-async fn process_events() -> anyhow::Result<()> {
+use crate::events::{EXPORT_KEYS, IMPORT_KEYS, INSPECT, VERIFY_USER};
+
+/// Why the input event loop stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Quit,
+    LogoutAndWipe,
+}
+
+async fn process_events() -> anyhow::Result<Exit> {
     loop {
         let Event::Key(event) = read()? else {
             log::error!("Failed to read input event");
@@ -19,6 +28,13 @@ async fn process_events() -> anyhow::Result<()> {
             break;
         }
 
+        if event.code == KeyCode::Char('L') {
+            if confirm_wipe()? {
+                return Ok(Exit::LogoutAndWipe);
+            }
+            continue;
+        }
+
         if event.code == KeyCode::Enter {
             println!("\r");
         } else if event.code == KeyCode::Char('p') {
@@ -30,16 +46,46 @@ async fn process_events() -> anyhow::Result<()> {
         if event.code == KeyCode::Char('R') {
             let _ = LIST_ROOMS.0.send(()).await;
         }
+
+        if event.code == KeyCode::Char('E') {
+            let _ = EXPORT_KEYS.0.send(()).await;
+        } else if event.code == KeyCode::Char('I') {
+            let _ = IMPORT_KEYS.0.send(()).await;
+        }
+
+        if event.code == KeyCode::Char('C') {
+            let _ = INSPECT.0.send(()).await;
+        }
+
+        if event.code == KeyCode::Char('V') {
+            let _ = VERIFY_USER.0.send(()).await;
+        }
+    }
+
+    Ok(Exit::Quit)
+}
+
+/// Asks for a confirmation before logging out and wiping the data, since it
+/// can't be undone.
+fn confirm_wipe() -> anyhow::Result<bool> {
+    println!("Log out and delete all the data of this device? (y/N)\r");
+
+    let confirmed = matches!(
+        read()?,
+        Event::Key(event) if event.code == KeyCode::Char('y')
+    );
+    if !confirmed {
+        println!("Cancelled\r");
     }
 
-    Ok(())
+    Ok(confirmed)
 }
 
 /// Starts the input event loop.
-pub async fn start() -> anyhow::Result<()> {
+pub async fn start() -> anyhow::Result<Exit> {
     enable_raw_mode()?;
-    process_events().await?;
+    let exit = process_events().await?;
     disable_raw_mode()?;
 
-    Ok(())
+    Ok(exit)
 }
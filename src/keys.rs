@@ -0,0 +1,218 @@
+// Offline room key export and import, to move room keys between test accounts
+// without going through a key backup.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+use matrix_sdk::{
+    ruma::{
+        api::client::message::get_message_events,
+        events::{room::encrypted::EncryptedEventScheme, AnyMessageLikeEvent, AnyTimelineEvent},
+        serde::Raw,
+        uint, MilliSecondsSinceUnixEpoch, OwnedRoomId, UInt,
+    },
+    Client, Room,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::events::{EXPORT_KEYS, IMPORT_KEYS};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyExportConfig {
+    // file the keys are exported to, and imported from
+    path: PathBuf,
+    passphrase: String,
+
+    // only export the keys of these rooms
+    #[serde(default)]
+    rooms: Vec<OwnedRoomId>,
+
+    // only export the keys of the messages sent since this date (YYYY-MM-DD)
+    since: Option<String>,
+}
+
+pub async fn handle_export_keys(client: Client, config: Option<KeyExportConfig>) {
+    loop {
+        let mut rx = EXPORT_KEYS.1.lock().await;
+        let Some(_) = rx.recv().await else {
+            continue;
+        };
+
+        let Some(config) = &config else {
+            log::error!("Can't export room keys, no key_export in config.yaml");
+            continue;
+        };
+
+        if let Err(e) = export_keys(&client, config).await {
+            log::error!("Failed to export room keys: {:?}", e);
+        }
+    }
+}
+
+pub async fn handle_import_keys(client: Client, config: Option<KeyExportConfig>) {
+    loop {
+        let mut rx = IMPORT_KEYS.1.lock().await;
+        let Some(_) = rx.recv().await else {
+            continue;
+        };
+
+        let Some(config) = &config else {
+            log::error!("Can't import room keys, no key_export in config.yaml");
+            continue;
+        };
+
+        if let Err(e) = import_keys(&client, config).await {
+            log::error!("Failed to import room keys: {:?}", e);
+        }
+    }
+}
+
+async fn export_keys(client: &Client, config: &KeyExportConfig) -> Result<()> {
+    // Room keys don't know when they were created, so to filter them by date we
+    // collect the sessions used by the messages sent since that date.
+    let session_ids = match &config.since {
+        Some(since) => {
+            let since = parse_date(since)?;
+            let rooms = match config.rooms.is_empty() {
+                true => client.joined_rooms(),
+                false => config
+                    .rooms
+                    .iter()
+                    .filter_map(|id| client.get_room(id))
+                    .collect(),
+            };
+
+            let mut session_ids = HashSet::new();
+            for room in rooms {
+                session_ids.extend(session_ids_since(client, &room, since).await?);
+            }
+            log::info!(
+                "Found {} sessions used since {}",
+                session_ids.len(),
+                since.0
+            );
+            Some(session_ids)
+        }
+        None => None,
+    };
+
+    log::info!("Exporting room keys to {}", config.path.display());
+    client
+        .encryption()
+        .export_room_keys(config.path.clone(), &config.passphrase, |session| {
+            (config.rooms.is_empty() || config.rooms.iter().any(|id| id == session.room_id()))
+                && session_ids
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(session.session_id()))
+        })
+        .await?;
+    log::info!("Room keys exported");
+
+    Ok(())
+}
+
+async fn import_keys(client: &Client, config: &KeyExportConfig) -> Result<()> {
+    log::info!("Importing room keys from {}", config.path.display());
+    let result = client
+        .encryption()
+        .import_room_keys(config.path.clone(), &config.passphrase)
+        .await?;
+
+    log::info!(
+        "Imported {} room keys out of {}",
+        result.imported_count,
+        result.total_count
+    );
+    for (room_id, sessions) in &result.keys {
+        let count: usize = sessions.values().map(|ids| ids.len()).sum();
+        log::info!("  {}: {} keys", room_id, count);
+    }
+
+    Ok(())
+}
+
+// Paginate backwards through the room history until `since`, collecting the
+// megolm sessions of the encrypted events.
+async fn session_ids_since(
+    client: &Client,
+    room: &Room,
+    since: MilliSecondsSinceUnixEpoch,
+) -> Result<HashSet<String>> {
+    let mut session_ids = HashSet::new();
+    let mut from = None;
+
+    loop {
+        let mut request = get_message_events::v3::Request::backward(room.room_id().to_owned());
+        request.from = from;
+        request.limit = uint!(100);
+        let response = client.send(request, None).await?;
+
+        let mut reached_since = false;
+        for event in &response.chunk {
+            match event.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts") {
+                Ok(Some(ts)) if ts < since => {
+                    reached_since = true;
+                    break;
+                }
+                _ => {}
+            }
+            if let Some(session_id) = megolm_session_id(event) {
+                session_ids.insert(session_id);
+            }
+        }
+
+        if reached_since || response.end.is_none() {
+            break;
+        }
+        from = response.end;
+    }
+
+    Ok(session_ids)
+}
+
+fn megolm_session_id(event: &Raw<AnyTimelineEvent>) -> Option<String> {
+    let event = event.deserialize().ok()?;
+    let AnyTimelineEvent::MessageLike(event) = event else {
+        return None;
+    };
+    let AnyMessageLikeEvent::RoomEncrypted(event) = event else {
+        return None;
+    };
+    match &event.as_original()?.content.scheme {
+        EncryptedEventScheme::MegolmV1AesSha2(content) => Some(content.session_id.clone()),
+        _ => None,
+    }
+}
+
+// Parse a YYYY-MM-DD date, at midnight UTC.
+fn parse_date(date: &str) -> Result<MilliSecondsSinceUnixEpoch> {
+    let mut parts = date.splitn(3, '-').map(str::parse::<i64>);
+    let (Some(Ok(y)), Some(Ok(m)), Some(Ok(d))) = (parts.next(), parts.next(), parts.next()) else {
+        anyhow::bail!("Invalid date {}, expected YYYY-MM-DD", date);
+    };
+    let is_leap_year = y % 4 == 0 && (y % 100 != 0 || y % 400 == 0);
+    let days_in_month = match m {
+        2 if is_leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&m) || !(1..=days_in_month).contains(&d) {
+        anyhow::bail!("Invalid date {}, expected YYYY-MM-DD", date);
+    }
+
+    // Days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let ms = u64::try_from(days * 86_400_000)
+        .ok()
+        .and_then(UInt::new)
+        .context("Date is before 1970")?;
+    Ok(MilliSecondsSinceUnixEpoch(ms))
+}
@@ -4,30 +4,38 @@ use std::{io::Write, sync::Arc};
 use anyhow::{Context, Result};
 use futures_util::{pin_mut, StreamExt as _};
 use matrix_sdk::{
-    config::SyncSettings,
     encryption::{BackupDownloadStrategy, EncryptionSettings},
     matrix_auth::MatrixSession,
     ruma::{
         events::{
             key::verification::request::ToDeviceKeyVerificationRequestEvent,
-            room::message::OriginalSyncRoomMessageEvent,
+            room::message::{MessageType, OriginalSyncRoomMessageEvent},
         },
-        OwnedRoomId,
+        OwnedRoomId, OwnedUserId,
     },
-    Client,
+    sliding_sync::VersionBuilder,
+    Client, ClientBuildError, ClientBuilder,
+};
+use matrix_sdk_ui::{
+    room_list_service::{filters::new_filter_non_left, State as RoomListState},
+    sync_service::{State as SyncServiceState, SyncService},
 };
-use matrix_sdk_ui::room_list_service::filters::new_filter_non_left;
 use serde::{Deserialize, Serialize};
 use serde_yaml;
 use std::{cmp::min, path::PathBuf};
+use tokio::sync::oneshot;
 use tracing::{Event, Subscriber};
 use tracing_log::LogTracer;
 use tracing_subscriber::{layer::SubscriberExt, Layer, Registry};
 
 use rooms::ROOM_LIST;
 
+mod checkpoints;
 mod events;
+mod inspect;
 mod keyboard;
+mod keys;
+mod network;
 mod rooms;
 mod timeline;
 mod verification;
@@ -48,12 +56,65 @@ struct Config {
     // wait for e2e verification before constructing timeline
     #[serde(default = "default_true")]
     timeline_wait_verification: bool,
+
+    // where and how to export/import room keys
+    key_export: Option<keys::KeyExportConfig>,
+
+    // what to inspect in the crypto state
+    #[serde(default)]
+    inspect: inspect::InspectConfig,
+
+    // member of timeline_test_room to request a verification with
+    verify_user: Option<OwnedUserId>,
+
+    // take sync checkpoints, and optionally replay the sync from one of them
+    checkpoints: Option<checkpoints::CheckpointsConfig>,
+
+    // proxy, certificates and DNS overrides of the HTTP client
+    #[serde(default)]
+    network: network::NetworkConfig,
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn client_builder(config: &Config) -> Result<ClientBuilder> {
+    let mut encryption_settings = EncryptionSettings::default();
+    encryption_settings.backup_download_strategy = BackupDownloadStrategy::AfterDecryptionFailure;
+
+    let builder = Client::builder()
+        .homeserver_url(config.homeserver_url.clone())
+        .sqlite_store(config.db_path.clone(), None)
+        .with_encryption_settings(encryption_settings);
+
+    let builder = config.network.apply(builder)?;
+
+    Ok(match &config.checkpoints {
+        Some(checkpoints) => builder.sync_checkpoints(checkpoints.sdk_config()),
+        None => builder,
+    })
+}
+
+// Discover whether the homeserver supports the native sliding sync. If it
+// doesn't, the sync service falls back on /sync instead.
+async fn build_client(config: &Config) -> Result<Client> {
+    let builder =
+        client_builder(config)?.sliding_sync_version_builder(VersionBuilder::DiscoverNative);
+
+    match builder.build().await {
+        Ok(client) => Ok(client),
+        Err(ClientBuildError::SlidingSyncVersion(err)) => {
+            log::info!("No native sliding sync ({err}), falling back on /sync");
+            Ok(client_builder(config)?
+                .sliding_sync_version_builder(VersionBuilder::None)
+                .build()
+                .await?)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
 async fn login(config: &Config) -> Result<Client> {
     log::info!(
         "Connecting: homeserver={} username={}",
@@ -64,17 +125,7 @@ async fn login(config: &Config) -> Result<Client> {
     let client = match config.session_path.exists() {
         true => {
             log::info!("Restoring login from session.");
-            let client = Client::builder()
-                .homeserver_url(config.homeserver_url.clone())
-                .sqlite_store(config.db_path.clone(), None)
-                .sliding_sync_version_builder(matrix_sdk::sliding_sync::VersionBuilder::DiscoverNative)
-                .with_encryption_settings(EncryptionSettings {
-                    auto_enable_cross_signing: false,
-                    backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
-                    auto_enable_backups: false,
-                })
-                .build()
-                .await?;
+            let client = build_client(config).await?;
 
             let session_file =
                 std::fs::File::open(&config.session_path).context("Unable to open session file")?;
@@ -86,16 +137,7 @@ async fn login(config: &Config) -> Result<Client> {
         }
         false => {
             log::info!("Logging in with username/password.");
-            let client = Client::builder()
-                .homeserver_url(config.homeserver_url.clone())
-                .sqlite_store(config.db_path.clone(), None)
-                .with_encryption_settings(EncryptionSettings {
-                    auto_enable_cross_signing: false,
-                    backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
-                    auto_enable_backups: false,
-                })
-                .build()
-                .await?;
+            let client = build_client(config).await?;
             client
                 .matrix_auth()
                 .login_username(config.username.clone(), config.password.as_str())
@@ -119,7 +161,39 @@ async fn login(config: &Config) -> Result<Client> {
     Ok(client)
 }
 
-async fn start_matrix(config: Config, client: Client) -> Result<()> {
+// Log out, and delete the stores and the session file, so that nothing of this
+// device remains on the disk, e.g. on a shared machine.
+async fn logout_and_wipe(client: &Client, config: &Config) -> Result<()> {
+    log::info!("Logging out and wiping the data of this device");
+
+    let summary = client.logout_and_wipe().await?;
+    if !summary.logged_out {
+        log::warn!("Unable to log out, remove the device from another session");
+    }
+    for file in &summary.deleted_files {
+        log::info!("Deleted {}", file.display());
+    }
+
+    match std::fs::remove_file(&config.session_path) {
+        Ok(()) => log::info!("Deleted {}", config.session_path.display()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error).context("Unable to delete session file"),
+    }
+
+    // The stores directory is only removed if nothing else was in it.
+    if config.db_path.exists() {
+        log::warn!(
+            "{} still exists, it contains files that aren't from the stores",
+            config.db_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Syncs until the sync service stops on its own, or until `stop` fires, in
+/// which case the sync service is stopped before returning.
+async fn start_matrix(config: Config, client: Client, stop: oneshot::Receiver<()>) -> Result<()> {
     client.add_event_handler(|ev: OriginalSyncRoomMessageEvent, _: Client| async move {
         let msg = format!("{}", ev.content.body().replace(|c: char| !c.is_ascii(), ""));
         log::info!("Message: {}...", &msg[0..min(60, msg.len())]);
@@ -136,51 +210,123 @@ async fn start_matrix(config: Config, client: Client) -> Result<()> {
         },
     );
 
-    let sync_settings = SyncSettings::default();
-    let sync_service = matrix_sdk_ui::sync_service::SyncService::builder(client.clone())
+    // Verification requests from other users are sent in a DM, as a message.
+    client.add_event_handler(
+        |ev: OriginalSyncRoomMessageEvent, client: Client| async move {
+            let MessageType::VerificationRequest(content) = &ev.content.msgtype else {
+                return;
+            };
+            if client.user_id() != Some(&*content.to) {
+                return;
+            }
+            match client
+                .encryption()
+                .get_verification_request(&ev.sender, &ev.event_id)
+                .await
+            {
+                Some(request) => {
+                    tokio::spawn(verification::request_verification_handler(request));
+                }
+                None => log::info!("Ignoring stale verification request from {}", ev.sender),
+            }
+        },
+    );
+
+    // Roll back before the sync service loads anything from the store.
+    let checkpoint = checkpoints::restore_checkpoint(&client, config.checkpoints.as_ref()).await?;
+
+    // With the offline mode, the sync service recovers from network errors on
+    // its own, instead of us having to call start() again. On homeservers
+    // without sliding sync, it falls back to /sync on its own too.
+    let sync_service = SyncService::builder(client.clone())
+        .with_offline_mode()
         .build()
         .await?;
+    log::info!("Using classic /sync: {}", sync_service.uses_classic_sync());
 
     let mut state_sub = sync_service.state();
-    tokio::spawn(async move {
-        loop {
-            let state = state_sub.next().await;
-            match state {
-                Some(state) => {
-                    log::info!("sync_service state: {:?}", state);
-                }
-                None => {
-                    log::info!("sync_service state: None");
-                    break;
-                }
-            }
-        }
-    });
 
     let room_list_service = sync_service.room_list_service();
+    let mut room_list_state_sub = room_list_service.state();
+
+    // The sync token is restored with the store, but each sliding sync
+    // connection must be told about its position.
+    if let Some(checkpoint) = &checkpoint {
+        if !sync_service.uses_classic_sync()
+            && !sync_service.restore_checkpoint_position(checkpoint).await
+        {
+            log::warn!("The checkpoint has no position for some syncs, starting new sessions");
+        }
+    }
+
     let _ = tokio::spawn(watch_room_list(room_list_service));
     let _ = tokio::spawn(rooms::log_room_list());
+    tokio::spawn(keys::handle_export_keys(
+        client.clone(),
+        config.key_export.clone(),
+    ));
+    tokio::spawn(keys::handle_import_keys(
+        client.clone(),
+        config.key_export.clone(),
+    ));
+    tokio::spawn(inspect::handle_inspect(
+        client.clone(),
+        config.inspect.clone(),
+        config.timeline_test_room.clone(),
+    ));
+    tokio::spawn(verification::handle_verify_user(
+        client.clone(),
+        config.verify_user.clone(),
+        config.timeline_test_room.clone(),
+    ));
 
     sync_service.start().await;
 
-    log::info!("First sync");
-    client.sync_once(sync_settings.clone()).await?;
-
-    // if timeline_test_room is set, listen to its timeline
-    if let Some(room_id) = config.timeline_test_room {
-        let Some(room) = client.get_room(&room_id) else {
-            anyhow::bail!("Unable to find room: {}", room_id);
-        };
-        let _ = tokio::spawn(timeline::watch_timeline(
-            room,
-            config.timeline_wait_verification,
-        ));
-    }
+    let sync = async {
+        log::info!("First sync");
+        while let Some(state) = room_list_state_sub.next().await {
+            match state {
+                RoomListState::SettingUp | RoomListState::Running => break,
+                RoomListState::Error { .. } => anyhow::bail!("First sync failed"),
+                _ => {}
+            }
+        }
 
-    log::info!("Sync forever");
-    client.sync(sync_settings).await?;
+        // if timeline_test_room is set, listen to its timeline
+        if let Some(room_id) = config.timeline_test_room {
+            let Some(room) = client.get_room(&room_id) else {
+                anyhow::bail!("Unable to find room: {}", room_id);
+            };
+            tokio::spawn(timeline::watch_timeline(
+                room,
+                config.timeline_wait_verification,
+            ));
+        }
 
-    Ok(())
+        log::info!("Sync forever");
+        while let Some(state) = state_sub.next().await {
+            log::info!("sync_service state: {:?}", state);
+            if state == SyncServiceState::Error {
+                sync_service.start().await;
+            }
+        }
+
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let result = tokio::select! {
+        result = sync => result,
+        _ = stop => {
+            log::info!("Stopping the sync");
+            Ok(())
+        }
+    };
+
+    // Make sure nothing is written to the stores anymore, e.g. before they
+    // are wiped.
+    sync_service.stop().await?;
+
+    result
 }
 
 // Watch verification state and update global VERIFIED state.
@@ -294,20 +440,36 @@ async fn main() -> Result<()> {
     println!("p -- paginate timeline backwards");
     println!("R -- list rooms");
     println!("SPACE -- print timeline");
+    println!("E -- export room keys");
+    println!("I -- import room keys");
+    println!("C -- inspect crypto state");
+    println!("V -- request verification of verify_user");
+    println!("L -- log out and wipe all the data of this device");
     println!("");
 
     let client = login(&config).await?;
 
     let _ = tokio::spawn(watch_verification_state(client.clone()));
 
+    let (stop_matrix, stop) = oneshot::channel();
     let matrix_handle = {
         let config = config.clone();
-        tokio::spawn(start_matrix(config, client))
+        let client = client.clone();
+        tokio::spawn(start_matrix(config, client, stop))
     };
 
-    keyboard::start().await?;
+    let exit = keyboard::start().await?;
+
+    // The sync must be stopped before wiping, otherwise it would keep writing
+    // to the stores while they're deleted.
+    let _ = stop_matrix.send(());
+    if let Err(error) = matrix_handle.await? {
+        log::error!("The sync stopped with an error: {error:?}");
+    }
+
+    if exit == keyboard::Exit::LogoutAndWipe {
+        logout_and_wipe(&client, &config).await?;
+    }
 
-    matrix_handle.abort();
-    let _ = matrix_handle.await?;
     Ok(())
 }
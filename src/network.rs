@@ -0,0 +1,79 @@
+// Network settings of the HTTP client, to test against homeservers behind a
+// proxy, a self-signed certificate or a domain that isn't in the DNS.
+
+use std::{collections::BTreeMap, net::IpAddr, path::PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use matrix_sdk::{reqwest::Certificate, CertificatePin, ClientBuilder};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    // HTTP proxy all the requests go through
+    proxy: Option<String>,
+
+    // PEM files of root certificates to trust, in addition to the system ones
+    #[serde(default)]
+    root_certificates: Vec<PathBuf>,
+
+    // only trust root_certificates, not the system ones
+    #[serde(default)]
+    disable_built_in_root_certificates: bool,
+
+    // only trust the certificates of these hosts matching one of their pins,
+    // either "sha256/<base64>" of the public key, or the SHA-256 fingerprint of
+    // the certificate; can't be combined with root_certificates
+    #[serde(default)]
+    pins: BTreeMap<String, Vec<String>>,
+
+    // resolve these domains to these addresses, instead of querying the DNS
+    #[serde(default)]
+    hosts: BTreeMap<String, Vec<IpAddr>>,
+}
+
+impl NetworkConfig {
+    pub fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        ensure!(
+            self.pins.is_empty() || self.root_certificates.is_empty(),
+            "network.pins can't be combined with network.root_certificates"
+        );
+
+        if let Some(proxy) = &self.proxy {
+            log::info!("Using proxy {}", proxy);
+            builder = builder.proxy(proxy);
+        }
+
+        if !self.root_certificates.is_empty() {
+            let mut certificates = Vec::new();
+            for path in &self.root_certificates {
+                let pem = std::fs::read(path)
+                    .with_context(|| format!("Unable to read certificate {}", path.display()))?;
+                certificates.push(
+                    Certificate::from_pem(&pem)
+                        .with_context(|| format!("Invalid certificate {}", path.display()))?,
+                );
+            }
+            builder = builder.add_root_certificates(certificates);
+        }
+
+        if self.disable_built_in_root_certificates {
+            builder = builder.disable_built_in_root_certificates();
+        }
+
+        for (host, pins) in &self.pins {
+            let pins = pins
+                .iter()
+                .map(|pin| pin.parse::<CertificatePin>())
+                .collect::<Result<Vec<_>, _>>()?;
+            builder = builder.pin_certificates(host, pins);
+        }
+
+        for (domain, addresses) in &self.hosts {
+            log::info!("Resolving {} to {:?}", domain, addresses);
+            builder = builder.resolve(domain, addresses.clone());
+        }
+
+        Ok(builder)
+    }
+}
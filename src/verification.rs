@@ -1,12 +1,91 @@
+use anyhow::{Context, Result};
 use futures_util::StreamExt;
 use matrix_sdk::{
     crypto::SasState,
     encryption::verification::{
         SasVerification, Verification, VerificationRequest, VerificationRequestState,
     },
+    ruma::{OwnedRoomId, OwnedUserId},
+    Client,
 };
 use std::time::Duration;
 
+use crate::events::VERIFY_USER;
+
+pub async fn handle_verify_user(
+    client: Client,
+    user_id: Option<OwnedUserId>,
+    timeline_test_room: Option<OwnedRoomId>,
+) {
+    loop {
+        let mut rx = VERIFY_USER.1.lock().await;
+        let Some(_) = rx.recv().await else {
+            continue;
+        };
+
+        let Some(user_id) = &user_id else {
+            log::error!("Can't verify a user, no verify_user in config.yaml");
+            continue;
+        };
+
+        if let Err(e) = verify_user(&client, user_id, timeline_test_room.as_ref()).await {
+            log::error!("Failed to request verification of {}: {:?}", user_id, e);
+        }
+    }
+}
+
+// Request the verification of a member of timeline_test_room, in our DM with
+// them.
+async fn verify_user(
+    client: &Client,
+    user_id: &OwnedUserId,
+    timeline_test_room: Option<&OwnedRoomId>,
+) -> Result<()> {
+    let room_id = timeline_test_room.context("No timeline_test_room in config.yaml")?;
+    let room = client
+        .get_room(room_id)
+        .with_context(|| format!("Unable to find room: {}", room_id))?;
+    let member = room
+        .get_member(user_id)
+        .await?
+        .with_context(|| format!("{} isn't a member of {}", user_id, room_id))?;
+
+    log::info!("Requesting verification of {}", user_id);
+    let request = member.request_verification().await?;
+    log::info!("Sent verification request in {:?}", request.room_id());
+    tokio::spawn(outgoing_verification_handler(request));
+
+    Ok(())
+}
+
+// Wait for the other user to accept our request, and start a SAS verification.
+async fn outgoing_verification_handler(request: VerificationRequest) {
+    let mut stream = request.changes();
+
+    while let Some(state) = stream.next().await {
+        match state {
+            VerificationRequestState::Created { .. }
+            | VerificationRequestState::Requested { .. } => (),
+            VerificationRequestState::Ready { .. } => match request.start_sas().await {
+                Ok(Some(sas)) => {
+                    tokio::spawn(sas_verification_handler(sas));
+                    break;
+                }
+                Ok(None) => log::info!("The other user doesn't support SAS verification"),
+                Err(e) => log::error!("Failed to start SAS verification: {:?}", e),
+            },
+            VerificationRequestState::Transitioned { verification } => {
+                if let Verification::SasV1(s) = verification {
+                    tokio::spawn(sas_verification_handler(s));
+                    break;
+                }
+            }
+            VerificationRequestState::Done | VerificationRequestState::Cancelled(_) => break,
+        }
+    }
+    log::info!("Verification request finished");
+}
+
 pub async fn request_verification_handler(request: VerificationRequest) {
     log::info!(
         "Accepting verification request from {}",